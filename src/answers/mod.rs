@@ -0,0 +1,224 @@
+//! Answering simple interactive prompts ("Are you sure? [y/N]") without an
+//! expect script. Commands with answers run on a remote terminal
+//! (`ssh -tt`), their output is watched for each answer's prompt, and the
+//! response is typed in. Every answer is shown in the output and recorded
+//! in the audit log; responses marked `secret` only as `***`.
+
+use crate::audit;
+use crate::errors::{AppError, ExecutionError, Result};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Answers typed into a single command at most, so a prompt that keeps
+/// coming back can't loop forever.
+pub const MAX_ANSWERS: usize = 32;
+
+/// Output kept for matching prompts; only the tail since the last answer.
+const WINDOW: usize = 4096;
+
+/// An answer as written in the config.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AnswerRule {
+    /// Regex matched against the output since the last answer.
+    pub prompt: String,
+    pub response: String,
+    /// Regex a command has to match for the answer to apply; all commands
+    /// when unset.
+    #[serde(default)]
+    pub command: Option<String>,
+    /// Keep the response out of logs.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub secret: bool,
+}
+
+#[derive(Clone, Debug)]
+pub struct Answer {
+    prompt: Regex,
+    command: Option<Regex>,
+    response: String,
+    secret: bool,
+}
+
+impl PartialEq for Answer {
+    fn eq(&self, other: &Answer) -> bool {
+        self.prompt.as_str() == other.prompt.as_str()
+            && self.command.as_ref().map(Regex::as_str) == other.command.as_ref().map(Regex::as_str)
+            && self.response == other.response
+            && self.secret == other.secret
+    }
+}
+
+fn regex(option: &'static str, pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|e| {
+        ExecutionError::InvalidOption {
+            option,
+            reason: format!("invalid regex '{}': {}", pattern, e),
+        }
+        .into()
+    })
+}
+
+impl Answer {
+    pub fn from_rule(rule: &AnswerRule) -> Result<Answer> {
+        Ok(Answer {
+            prompt: regex("answers.prompt", &rule.prompt)?,
+            command: rule
+                .command
+                .as_deref()
+                .map(|pattern| regex("answers.command", pattern))
+                .transpose()?,
+            response: rule.response.clone(),
+            secret: rule.secret,
+        })
+    }
+
+    /// What the output and the audit log show for the response.
+    pub fn shown_response(&self) -> &str {
+        if self.secret {
+            "***"
+        } else {
+            &self.response
+        }
+    }
+
+    /// The line written to the command's terminal.
+    pub fn typed(&self) -> String {
+        format!("{}\n", self.response)
+    }
+
+    /// The output line recording that this answer was given.
+    pub fn log_line(&self) -> String {
+        format!(
+            "[russh] answered prompt /{}/ with '{}'",
+            self.prompt.as_str(),
+            self.shown_response()
+        )
+    }
+
+    /// Records the answer in the audit log.
+    pub fn audit(&self, host: &str, command: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct Answered<'a> {
+            host: &'a str,
+            command: &'a str,
+            prompt: &'a str,
+            response: &'a str,
+        }
+        audit::record(
+            "prompt_answered",
+            Answered {
+                host,
+                command,
+                prompt: self.prompt.as_str(),
+                response: self.shown_response(),
+            },
+        )
+    }
+}
+
+/// `--answer 'PROMPT=RESPONSE'`, for all of the run's commands. The prompt
+/// regex ends at the last `=`.
+impl FromStr for Answer {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Answer> {
+        let Some((prompt, response)) = spec.rsplit_once('=') else {
+            return Err(ExecutionError::InvalidOption {
+                option: "--answer",
+                reason: format!("expected PROMPT=RESPONSE, got '{}'", spec),
+            }
+            .into());
+        };
+        Ok(Answer {
+            prompt: regex("--answer", prompt)?,
+            command: None,
+            response: response.to_string(),
+            secret: false,
+        })
+    }
+}
+
+/// The answers that apply to `command`.
+pub fn for_command(answers: &[Answer], command: &str) -> Vec<Answer> {
+    answers
+        .iter()
+        .filter(|answer| answer.command.as_ref().is_none_or(|c| c.is_match(command)))
+        .cloned()
+        .collect()
+}
+
+/// Watches a command's output and says when to answer.
+pub struct Responder {
+    answers: Vec<Answer>,
+    window: String,
+    answered: usize,
+}
+
+impl Responder {
+    pub fn new(answers: Vec<Answer>) -> Responder {
+        Responder {
+            answers,
+            window: String::new(),
+            answered: 0,
+        }
+    }
+
+    /// Takes the next piece of output and returns the answer to give, if
+    /// it completed a prompt.
+    pub fn feed(&mut self, output: &str) -> Option<&Answer> {
+        if self.answered >= MAX_ANSWERS {
+            return None;
+        }
+        self.window.push_str(output);
+        if self.window.len() > WINDOW {
+            let mut cut = self.window.len() - WINDOW;
+            while !self.window.is_char_boundary(cut) {
+                cut += 1;
+            }
+            self.window.drain(..cut);
+        }
+        let answer = self
+            .answers
+            .iter()
+            .find(|answer| answer.prompt.is_match(&self.window))?;
+        self.window.clear();
+        self.answered += 1;
+        Some(answer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_responder() {
+        let confirm: Answer = r"Are you sure\? \[y/N\]=y".parse().unwrap();
+        let token = Answer::from_rule(&AnswerRule {
+            prompt: "Token:".to_string(),
+            response: "hunter2".to_string(),
+            command: Some("^vault".to_string()),
+            secret: true,
+        })
+        .unwrap();
+        let answers = vec![confirm, token];
+        assert_eq!(for_command(&answers, "apt-get remove nginx").len(), 1);
+        assert_eq!(for_command(&answers, "vault login").len(), 2);
+
+        let mut responder = Responder::new(answers);
+        assert!(responder.feed("Removing nginx\nAre you su").is_none());
+        let answer = responder.feed("re? [y/N] ").unwrap();
+        assert_eq!(answer.typed(), "y\n");
+        // The echoed answer doesn't trigger it again.
+        assert!(responder.feed("y\r\n").is_none());
+        let answer = responder.feed("Token: ").unwrap();
+        assert_eq!(
+            answer.log_line(),
+            "[russh] answered prompt /Token:/ with '***'"
+        );
+        assert!("no-equals-sign".parse::<Answer>().is_err());
+        assert!("([=y".parse::<Answer>().is_err());
+    }
+}
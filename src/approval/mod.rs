@@ -0,0 +1,387 @@
+use crate::audit::{self, current_user, now};
+use crate::config::Host;
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::i18n::{tr, trf, Msg};
+use crate::lock::shared_dir;
+use crate::prompt;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Hosts carrying this tag need a second operator's approval before a run.
+pub const PROTECTED_TAG: &str = "protected";
+
+/// How long an issued approval token stays valid.
+const APPROVAL_TTL_SECS: u64 = 3600;
+
+/// A run against protected hosts waiting for a second operator.
+#[derive(Debug, Serialize, Deserialize)]
+struct ApprovalRequest {
+    id: String,
+    requester: String,
+    hosts: Vec<String>,
+    commands: Vec<String>,
+    created: u64,
+}
+
+/// The second operator's sign-off for a request.
+#[derive(Debug, Serialize, Deserialize)]
+struct Approval {
+    id: String,
+    requester: String,
+    approver: String,
+    token: String,
+    expires: u64,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Identifies a run by what it does, so an approval only covers exactly the
+/// approved hosts and commands.
+fn request_id(hosts: &[String], commands: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for host in hosts {
+        hasher.update(host.as_bytes());
+        hasher.update([0]);
+    }
+    hasher.update([1]);
+    for command in commands {
+        hasher.update(command.as_bytes());
+        hasher.update([0]);
+    }
+    hex(&hasher.finalize()[..8])
+}
+
+fn new_token(id: &str, approver: &str) -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    let seed = format!("{}:{}:{}:{}", id, approver, nanos, std::process::id());
+    hex(&Sha256::digest(seed.as_bytes())[..6])
+}
+
+/// The approvals' directory under the system temp dir.
+pub const STORE: &str = "russh-approvals";
+
+/// The store is shared by every local user, so it must be sticky: otherwise
+/// anyone could remove or swap another user's approval.
+fn store() -> Result<PathBuf> {
+    let dir = shared_dir(STORE)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let metadata = fs::symlink_metadata(&dir)?;
+        if !metadata.is_dir() || metadata.permissions().mode() & 0o1000 == 0 {
+            return Err(AppError::Generic(format!(
+                "{} is not a sticky directory; remove it and retry",
+                dir.display()
+            )));
+        }
+    }
+    Ok(dir)
+}
+
+/// Who owns `path`. Unlike a name written into the file, the owner can't be
+/// made up by whoever wrote it.
+#[cfg(unix)]
+fn owner(path: &Path) -> Option<String> {
+    use std::os::unix::fs::MetadataExt;
+    let uid = fs::symlink_metadata(path).ok()?.uid();
+    Some(audit::user_name(uid).unwrap_or_else(|| uid.to_string()))
+}
+
+/// There is no file owner to go by; the recorded names are all there is.
+#[cfg(not(unix))]
+fn owner(_path: &Path) -> Option<String> {
+    None
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    if !fs::symlink_metadata(path).ok()?.is_file() {
+        return None;
+    }
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+/// Writes a file owned by the current user, never following a link. A file
+/// of ours already at `path` is replaced; another user's can't be removed
+/// from the sticky store and is refused.
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let json = serde_json::to_vec_pretty(value).map_err(|e| AppError::Generic(e.to_string()))?;
+    let _ = fs::remove_file(path);
+    let mut file = match OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(AppError::Generic(format!(
+                "{} belongs to another user",
+                path.display()
+            )))
+        }
+        Err(e) => return Err(e.into()),
+    };
+    file.write_all(&json)?;
+    Ok(())
+}
+
+/// The approvals issued for request `id`, one file per approver.
+fn approvals(dir: &Path, id: &str) -> Vec<PathBuf> {
+    let prefix = format!("{}.", id);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with(&prefix) && name.ends_with(".approval"))
+        })
+        .collect()
+}
+
+/// What an approval covers of the `input` a run feeds its commands, like
+/// the script `russh script` pipes to `sh -s`: all of it, so the approver
+/// reads what will run and a changed script needs a new approval.
+pub fn input(input: &str) -> String {
+    format!(
+        "stdin (sha256 {}):\n{}",
+        hex(&Sha256::digest(input.as_bytes())),
+        input.trim_end()
+    )
+}
+
+pub fn protected_hosts(hosts: &[Host]) -> Vec<String> {
+    let mut protected: Vec<String> = hosts
+        .iter()
+        .filter(|h| h.tags.iter().any(|t| t == PROTECTED_TAG))
+        .map(|h| h.host.clone())
+        .collect();
+    protected.sort();
+    protected
+}
+
+/// Gate for runs touching protected hosts. Without a valid `token` the run is
+/// refused and a request is filed for a second operator to approve.
+pub fn check(hosts: &[Host], commands: &[String], token: Option<&str>) -> Result<()> {
+    let protected = protected_hosts(hosts);
+    if protected.is_empty() {
+        return Ok(());
+    }
+
+    let id = request_id(&protected, commands);
+    let dir = store()?;
+
+    let Some(token) = token else {
+        let request = ApprovalRequest {
+            id: id.clone(),
+            requester: current_user(),
+            hosts: protected.clone(),
+            commands: commands.to_vec(),
+            created: now(),
+        };
+        write_json(&dir.join(format!("{}.request", id)), &request)?;
+        audit::record("approval_requested", &request)?;
+        return Err(ExecutionError::Refused(trf(
+            Msg::ApprovalNeeded,
+            &[&protected.join(", "), &id],
+        ))
+        .into());
+    };
+
+    let candidates = approvals(&dir, &id);
+    if candidates.is_empty() {
+        return Err(ExecutionError::Refused(trf(Msg::ApprovalNotIssued, &[&id])).into());
+    }
+    let (approval_path, approval) = candidates
+        .into_iter()
+        .filter_map(|path| read_json::<Approval>(&path).map(|approval| (path, approval)))
+        .find(|(_, approval)| approval.id == id && approval.token == token)
+        .ok_or_else(|| ExecutionError::Refused(tr(Msg::ApprovalTokenMismatch).to_string()))?;
+    if approval.expires < now() {
+        let _ = fs::remove_file(&approval_path);
+        return Err(ExecutionError::Refused(trf(Msg::ApprovalExpired, &[&id])).into());
+    }
+    let user = current_user();
+    let approver = owner(&approval_path).unwrap_or_else(|| approval.approver.clone());
+    if approver != approval.approver {
+        return Err(ExecutionError::Refused(trf(
+            Msg::ApprovalWrongOwner,
+            &[&id, &approval.approver, &approver],
+        ))
+        .into());
+    }
+    if approver == user {
+        return Err(ExecutionError::Refused(tr(Msg::ApprovalSelfApproved).to_string()).into());
+    }
+    if approval.requester != user {
+        return Err(ExecutionError::Refused(trf(
+            Msg::ApprovalOtherRequester,
+            &[&id, &approval.requester],
+        ))
+        .into());
+    }
+
+    // Approvals are single use. The approval belongs to the approver and
+    // can't be removed from the sticky store by us, so using it leaves a
+    // marker of ours behind instead.
+    let used = dir.join(format!("{}.{}.used", id, approval.token));
+    match OpenOptions::new().write(true).create_new(true).open(&used) {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+            return Err(ExecutionError::Refused(trf(Msg::ApprovalUsed, &[&id])).into())
+        }
+        Err(e) => return Err(e.into()),
+    }
+    let _ = fs::remove_file(&approval_path);
+    let _ = fs::remove_file(dir.join(format!("{}.request", id)));
+    audit::record(
+        "approved_run",
+        json!({
+            "id": id,
+            "approver": approval.approver,
+            "hosts": protected,
+            "commands": commands,
+        }),
+    )
+}
+
+/// `russh approve <id>`: shows a pending request and issues a token for it.
+pub fn approve(id: &str, assume_yes: bool) -> Result<()> {
+    let dir = store()?;
+    let request_path = dir.join(format!("{}.request", id));
+    let mut request: ApprovalRequest = read_json(&request_path)
+        .ok_or_else(|| AppError::Generic(format!("no pending approval request {}", id)))?;
+    if request.id != id || request.id != request_id(&request.hosts, &request.commands) {
+        return Err(AppError::Generic(format!(
+            "approval request {} doesn't match its hosts and commands",
+            id
+        )));
+    }
+    if let Some(requester) = owner(&request_path) {
+        request.requester = requester;
+    }
+
+    let approver = current_user();
+    if request.requester == approver {
+        return Err(AppError::Generic(
+            "you can't approve your own run; a second operator must approve it".to_string(),
+        ));
+    }
+
+    println!(
+        "{}",
+        trf(Msg::ApprovalRequest, &[&request.id, &request.requester])
+    );
+    println!("  hosts:    {}", request.hosts.join(", "));
+    for command in &request.commands {
+        println!("  command:  {}", command.replace('\n', "\n            "));
+    }
+    if !assume_yes {
+        match prompt::confirm(tr(Msg::ApprovePrompt))? {
+            Some(true) => {}
+            Some(false) => {
+                println!("{}", tr(Msg::NotApproved));
+                return Ok(());
+            }
+            None => {
+                return Err(ExecutionError::Refused(
+                    "no terminal to confirm the approval on; pass --yes".to_string(),
+                )
+                .into())
+            }
+        }
+    }
+
+    let approval = Approval {
+        id: request.id.clone(),
+        requester: request.requester.clone(),
+        approver: approver.clone(),
+        token: new_token(&request.id, &request.requester),
+        expires: now() + APPROVAL_TTL_SECS,
+    };
+    write_json(
+        &dir.join(format!("{}.{}.approval", request.id, approver)),
+        &approval,
+    )?;
+    audit::record(
+        "approval_issued",
+        json!({
+            "id": request.id,
+            "requester": request.requester,
+            "hosts": request.hosts,
+            "commands": request.commands,
+        }),
+    )?;
+    println!(
+        "{}",
+        trf(Msg::Approved, &[&(APPROVAL_TTL_SECS / 60), &approval.token])
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_id_covers_hosts_and_commands() {
+        let hosts = vec!["db01".to_string()];
+        let a = request_id(&hosts, &["uptime".to_string()]);
+        assert_eq!(a, request_id(&hosts, &["uptime".to_string()]));
+        assert_ne!(a, request_id(&hosts, &["reboot".to_string()]));
+        assert_ne!(
+            a,
+            request_id(&["db02".to_string()], &["uptime".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_input_is_covered() {
+        let hosts = vec!["db01".to_string()];
+        let approved = vec!["sh -s".to_string(), input("echo hi\n")];
+        assert!(approved[1].ends_with(":\necho hi"));
+        assert_ne!(
+            request_id(&hosts, &approved),
+            request_id(&hosts, &["sh -s".to_string(), input("rm -rf /srv\n")])
+        );
+    }
+
+    #[test]
+    fn test_protected_hosts() {
+        let db: Host = "db01 tag=protected".parse().unwrap();
+        let web: Host = "web01 tag=canary".parse().unwrap();
+        assert_eq!(protected_hosts(&[web.clone(), db]), vec!["db01"]);
+        assert!(check(&[web], &["uptime".to_string()], None).is_ok());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_refuses_an_approval_written_by_the_requester() {
+        let db: Host = "db-approval-test tag=protected".parse().unwrap();
+        let commands = vec![format!("true {}", std::process::id())];
+        let id = request_id(&protected_hosts(std::slice::from_ref(&db)), &commands);
+        let dir = store().unwrap();
+        let forged = Approval {
+            id: id.clone(),
+            requester: current_user(),
+            approver: "someone-else".to_string(),
+            token: "t0ken".to_string(),
+            expires: now() + 60,
+        };
+        let path = dir.join(format!("{}.someone-else.approval", id));
+        write_json(&path, &forged).unwrap();
+
+        let err = check(&[db], &commands, Some("t0ken")).unwrap_err();
+        let _ = fs::remove_file(&path);
+        assert!(err.to_string().contains("written by"), "{}", err);
+    }
+}
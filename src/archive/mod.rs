@@ -0,0 +1,281 @@
+//! Off-box copies of every run: with an `[archive]` section, each run's log
+//! directory (host logs and `report.json`, see [`crate::logs`]) is uploaded
+//! to an S3-compatible bucket under `<prefix><timestamp>/`, and runs older
+//! than `retention_days` are deleted from it.
+//!
+//! Requests are signed by curl (`--aws-sigv4`), with the credentials from
+//! `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and, for temporary ones,
+//! `AWS_SESSION_TOKEN`.
+
+use crate::audit::now;
+use crate::errors::{AppError, Result};
+use crate::history::Labels;
+use crate::http;
+use crate::logs;
+use crate::paths;
+use crate::ssh::ServerResult;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where runs wait for their upload when there is no `log_dir`; a run
+/// that couldn't be uploaded is retried after the next one.
+const STAGING: &str = "archive";
+
+fn default_region() -> String {
+    "us-east-1".to_string()
+}
+
+/// `[archive]` config section: the bucket runs are uploaded to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ArchiveConfig {
+    /// e.g. `https://s3.eu-central-1.amazonaws.com` or a MinIO server.
+    pub endpoint: String,
+    pub bucket: String,
+    #[serde(default = "default_region")]
+    pub region: String,
+    /// Prepended to every key, e.g. `russh/prod/`.
+    #[serde(default)]
+    pub prefix: String,
+    /// Runs older than this are deleted from the bucket; kept forever
+    /// without it.
+    #[serde(default)]
+    pub retention_days: Option<u64>,
+}
+
+impl ArchiveConfig {
+    /// The prefix as a directory: empty or ending in `/`.
+    fn prefix(&self) -> String {
+        match self.prefix.is_empty() || self.prefix.ends_with('/') {
+            true => self.prefix.clone(),
+            false => format!("{}/", self.prefix),
+        }
+    }
+
+    /// The path-style URL of `key`, which every S3-compatible store takes.
+    fn url(&self, key: &str) -> String {
+        format!(
+            "{}/{}/{}",
+            self.endpoint.trim_end_matches('/'),
+            self.bucket,
+            encode(key, false)
+        )
+    }
+}
+
+/// Percent-encodes `text` for a URL; `/` is kept unless `slash`.
+fn encode(text: &str, slash: bool) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            b'/' if !slash => "/".to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The curl options signing a request, and the credentials for its stdin.
+struct Signing {
+    args: Vec<String>,
+    secrets: String,
+}
+
+impl Signing {
+    fn from_env(region: &str) -> Result<Signing> {
+        let var = |name: &str| {
+            env::var(name)
+                .ok()
+                .filter(|v| !v.is_empty())
+                .ok_or_else(|| AppError::Generic(format!("{} is not set", name)))
+        };
+        let quote = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        let mut secrets = format!(
+            "user = \"{}:{}\"\n",
+            quote(&var("AWS_ACCESS_KEY_ID")?),
+            quote(&var("AWS_SECRET_ACCESS_KEY")?)
+        );
+        if let Ok(token) = var("AWS_SESSION_TOKEN") {
+            secrets.push_str(&format!(
+                "header = \"x-amz-security-token: {}\"\n",
+                quote(&token)
+            ));
+        }
+        Ok(Signing {
+            args: vec!["--aws-sigv4".to_string(), format!("aws:amz:{}:s3", region)],
+            secrets,
+        })
+    }
+
+    fn request(&self, method: &str, url: &str, args: &[&str]) -> Result<Vec<u8>> {
+        let args: Vec<&str> = self
+            .args
+            .iter()
+            .map(String::as_str)
+            .chain(args.iter().copied())
+            .collect();
+        http::request(method, url, &args, &self.secrets)
+    }
+}
+
+/// Uploads every file of the run directory `dir` under
+/// `<prefix><dir name>/`. Returns how many files were uploaded.
+fn upload(config: &ArchiveConfig, signing: &Signing, dir: &Path) -> Result<usize> {
+    let run = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let mut uploaded = 0;
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let Some(name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else {
+            continue;
+        };
+        let key = format!("{}{}/{}", config.prefix(), run, name);
+        let file = path.to_string_lossy();
+        signing.request("PUT", &config.url(&key), &["--upload-file", &file])?;
+        uploaded += 1;
+    }
+    Ok(uploaded)
+}
+
+/// The `<Key>`s of a ListObjectsV2 response, and its continuation token.
+fn parse_listing(xml: &str) -> (Vec<String>, Option<String>) {
+    let unescape = |text: &str| {
+        text.replace("&lt;", "<")
+            .replace("&gt;", ">")
+            .replace("&quot;", "\"")
+            .replace("&apos;", "'")
+            .replace("&amp;", "&")
+    };
+    let keys = Regex::new(r"<Key>([^<]*)</Key>").expect("valid regex");
+    let token =
+        Regex::new(r"<NextContinuationToken>([^<]*)</NextContinuationToken>").expect("valid regex");
+    (
+        keys.captures_iter(xml).map(|c| unescape(&c[1])).collect(),
+        token.captures(xml).map(|c| unescape(&c[1])),
+    )
+}
+
+/// Whether `key` belongs to a run from before the `cutoff` timestamp. Run
+/// directories are named by [`logs::timestamp`], which sorts by time.
+fn expired(key: &str, prefix: &str, cutoff: &str) -> bool {
+    key.strip_prefix(prefix)
+        .and_then(|rest| rest.get(..cutoff.len()))
+        .is_some_and(|stamp| stamp.as_bytes()[..8].iter().all(u8::is_ascii_digit) && stamp < cutoff)
+}
+
+/// Deletes the runs older than `days` from the bucket. Returns how many
+/// objects were deleted.
+fn prune(config: &ArchiveConfig, signing: &Signing, days: u64) -> Result<usize> {
+    let prefix = config.prefix();
+    let cutoff = logs::timestamp(now().saturating_sub(days * 86_400));
+    let mut deleted = 0;
+    let mut token: Option<String> = None;
+    loop {
+        let mut url = format!(
+            "{}/{}?list-type=2&prefix={}",
+            config.endpoint.trim_end_matches('/'),
+            config.bucket,
+            encode(&prefix, true)
+        );
+        if let Some(token) = &token {
+            url.push_str(&format!("&continuation-token={}", encode(token, true)));
+        }
+        let body = signing.request("GET", &url, &[])?;
+        let (keys, next) = parse_listing(&String::from_utf8_lossy(&body));
+        for key in keys.iter().filter(|key| expired(key, &prefix, &cutoff)) {
+            signing.request("DELETE", &config.url(key), &[])?;
+            deleted += 1;
+        }
+        match next {
+            Some(next) => token = Some(next),
+            None => return Ok(deleted),
+        }
+    }
+}
+
+/// Writes the run's logs, into `log_dir` if given, and uploads them with
+/// any earlier runs still waiting in the staging directory. Returns the
+/// number of files uploaded.
+pub fn store(
+    config: &ArchiveConfig,
+    log_dir: Option<&Path>,
+    results: &[(String, ServerResult)],
+    labels: &Labels,
+) -> Result<usize> {
+    let staging = paths::state_path(STAGING)?;
+    let dir = logs::write(log_dir.unwrap_or(&staging), results, labels)?;
+    let signing = Signing::from_env(&config.region)?;
+
+    let mut runs: Vec<PathBuf> = match fs::read_dir(&staging) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.is_dir())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    if !runs.contains(&dir) {
+        runs.push(dir);
+    }
+    runs.sort();
+    let mut uploaded = 0;
+    for run in runs {
+        uploaded += upload(config, &signing, &run)?;
+        if run.starts_with(&staging) {
+            fs::remove_dir_all(&run)?;
+        }
+    }
+
+    if let Some(days) = config.retention_days {
+        prune(config, &signing, days)?;
+    }
+    Ok(uploaded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_and_retention() {
+        let config = ArchiveConfig {
+            endpoint: "https://minio.example.com:9000/".to_string(),
+            bucket: "evidence".to_string(),
+            region: default_region(),
+            prefix: "russh/prod".to_string(),
+            retention_days: Some(30),
+        };
+        assert_eq!(config.prefix(), "russh/prod/");
+        assert_eq!(
+            config.url("russh/prod/20261016T143000Z/web 01.log"),
+            "https://minio.example.com:9000/evidence/russh/prod/20261016T143000Z/web%2001.log"
+        );
+        assert_eq!(encode("a/b c", true), "a%2Fb%20c");
+
+        let (keys, token) = parse_listing(
+            "<ListBucketResult><Contents><Key>russh/prod/20250101T000000Z/web01.log</Key>\
+             </Contents><Contents><Key>russh/prod/20261016T143000Z/a&amp;b.log</Key></Contents>\
+             <NextContinuationToken>1/abc=</NextContinuationToken></ListBucketResult>",
+        );
+        assert_eq!(
+            keys,
+            [
+                "russh/prod/20250101T000000Z/web01.log",
+                "russh/prod/20261016T143000Z/a&b.log"
+            ]
+        );
+        assert_eq!(token.as_deref(), Some("1/abc="));
+
+        let cutoff = "20260916T143000Z";
+        assert!(expired(&keys[0], "russh/prod/", cutoff));
+        assert!(!expired(&keys[1], "russh/prod/", cutoff));
+        assert!(!expired("russh/prod/notes.txt", "russh/prod/", cutoff));
+        assert!(!expired("other/20250101T000000Z/x", "russh/prod/", cutoff));
+    }
+}
@@ -0,0 +1,87 @@
+//! `--ask-pass`: one login password, asked once and given to every
+//! connection of the run, for appliances that only take passwords. The
+//! native backend uses it directly. ssh, scp and rsync get it through
+//! `SSH_ASKPASS` (OpenSSH 8.4 or later), with russh itself as the helper
+//! program. ssh runs the helper with the password in its environment,
+//! which only the same user can read. That avoids ssh asking on the
+//! terminal once per host, which breaks parallel runs.
+
+use crate::auth::AuthHandler;
+use crate::errors::{AppError, Result};
+use std::env;
+use std::io::{self, Write};
+use std::process::Command;
+use std::sync::OnceLock;
+
+/// Set for the helper: the password to answer with.
+const PASSWORD_VAR: &str = "RUSSH_ASKPASS_PASSWORD";
+
+static PASSWORD: OnceLock<String> = OnceLock::new();
+
+/// Asks `auth` for the login password, once per run.
+pub fn ask(auth: &dyn AuthHandler) -> Result<()> {
+    if PASSWORD.get().is_some() {
+        return Ok(());
+    }
+    let password = auth
+        .password("SSH password: ")
+        .ok_or_else(|| AppError::Generic("--ask-pass needs a terminal to ask on".to_string()))?;
+    let _ = PASSWORD.set(password);
+    Ok(())
+}
+
+/// The password from `--ask-pass`, if it was given.
+pub fn password() -> Option<&'static str> {
+    PASSWORD.get().map(String::as_str)
+}
+
+/// Whether ssh's `prompt` asks for the login password, rather than a key
+/// passphrase or whether to trust a host key.
+pub fn asks_password(prompt: &str) -> bool {
+    prompt.to_lowercase().contains("password")
+}
+
+/// Makes the ssh, scp or rsync `command` answer password prompts with the
+/// `--ask-pass` password instead of asking on the terminal.
+pub fn apply(command: &mut Command) {
+    let (Some(password), Ok(helper)) = (password(), env::current_exe()) else {
+        return;
+    };
+    command
+        .env("SSH_ASKPASS", helper)
+        .env("SSH_ASKPASS_REQUIRE", "force")
+        .env(PASSWORD_VAR, password);
+}
+
+/// When this process was started by ssh as its askpass helper, answers
+/// the prompt in its arguments and returns the exit code; `None` for a
+/// normal run. Anything but a password prompt is declined.
+pub fn answer() -> Option<i32> {
+    let password = env::var(PASSWORD_VAR).ok()?;
+    let prompt = env::args().nth(1).unwrap_or_default();
+    if !asks_password(&prompt) {
+        return Some(1);
+    }
+    let mut stdout = io::stdout();
+    match writeln!(stdout, "{}", password).and_then(|_| stdout.flush()) {
+        Ok(()) => Some(0),
+        Err(_) => Some(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_asks_password() {
+        assert!(asks_password("admin@switch01's password: "));
+        assert!(asks_password("Password:"));
+        assert!(!asks_password(
+            "Enter passphrase for key '/home/a/.ssh/id_ed25519': "
+        ));
+        assert!(!asks_password(
+            "Are you sure you want to continue connecting (yes/no/[fingerprint])?"
+        ));
+    }
+}
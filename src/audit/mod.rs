@@ -0,0 +1,101 @@
+use crate::errors::{AppError, Result};
+use crate::paths;
+use serde::Serialize;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One line of the audit log.
+#[derive(Serialize)]
+struct Entry<'a, T: Serialize> {
+    time: u64,
+    user: String,
+    event: &'a str,
+    #[serde(flatten)]
+    details: T,
+}
+
+/// The audit log's file name in the state directory.
+pub const AUDIT_LOG: &str = "audit.log";
+
+pub fn audit_log_path() -> Result<PathBuf> {
+    paths::state_path(AUDIT_LOG)
+}
+
+/// The login name of the real uid. `$USER` can be set to anything, so it's
+/// only trusted where there is no uid to go by.
+pub fn current_user() -> String {
+    #[cfg(unix)]
+    {
+        let uid = current_uid();
+        user_name(uid).unwrap_or_else(|| uid.to_string())
+    }
+    #[cfg(not(unix))]
+    {
+        std::env::var("USER")
+            .or_else(|_| std::env::var("USERNAME"))
+            .unwrap_or_else(|_| "unknown".to_string())
+    }
+}
+
+#[cfg(unix)]
+pub fn current_uid() -> u32 {
+    // SAFETY: getuid has no preconditions and can't fail.
+    unsafe { libc::getuid() }
+}
+
+/// Looks `uid` up in the password database.
+#[cfg(unix)]
+pub fn user_name(uid: u32) -> Option<String> {
+    use std::ffi::CStr;
+
+    let mut buf = vec![0 as libc::c_char; 4096];
+    loop {
+        let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut found: *mut libc::passwd = std::ptr::null_mut();
+        // SAFETY: every pointer is valid for the call and `buf.len()` is the
+        // buffer's real size; on success `pwd`'s strings point into `buf`.
+        let rc =
+            unsafe { libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut found) };
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if rc != 0 || found.is_null() {
+            return None;
+        }
+        // SAFETY: pw_name is a NUL-terminated string inside `buf`.
+        let name = unsafe { CStr::from_ptr(pwd.pw_name) };
+        return Some(name.to_string_lossy().into_owned());
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Appends an event to the audit log as a JSON line.
+pub fn record<T: Serialize>(event: &str, details: T) -> Result<()> {
+    let path = audit_log_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let entry = Entry {
+        time: now(),
+        user: current_user(),
+        event,
+        details,
+    };
+    let mut line = serde_json::to_vec(&entry).map_err(|e| AppError::Generic(e.to_string()))?;
+    line.push(b'\n');
+    let mut log = OpenOptions::new().create(true).append(true).open(&path)?;
+    // Another process appending at the same time must not interleave with
+    // this line.
+    log.lock()?;
+    log.write_all(&line)?;
+    Ok(())
+}
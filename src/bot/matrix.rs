@@ -0,0 +1,193 @@
+//! The Matrix client-server API: `/sync` long-polling for the room's
+//! messages and threaded `m.notice` replies.
+
+use super::{call, encode, Chat, Message};
+use crate::errors::{AppError, Result};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How long a `/sync` waits for new events, in milliseconds.
+const SYNC_TIMEOUT: u64 = 30_000;
+
+pub struct Matrix {
+    api: String,
+    room: String,
+    token: String,
+    /// The bot's own user id, whose messages are skipped.
+    user: String,
+    /// Where the next `/sync` continues from.
+    since: Mutex<String>,
+    transactions: AtomicU64,
+}
+
+/// A sync filter for just the timeline of `room`.
+fn filter(room: &str) -> String {
+    let filter = json!({
+        "presence": { "types": [] },
+        "account_data": { "types": [] },
+        "room": {
+            "rooms": [room],
+            "state": { "types": [] },
+            "ephemeral": { "types": [] },
+            "account_data": { "types": [] },
+            "timeline": { "types": ["m.room.message"] },
+        },
+    });
+    encode(&filter.to_string())
+}
+
+/// The text messages in `room` of a `/sync` response, except `own` ones.
+fn messages(sync: &Value, room: &str, own: &str) -> Vec<Message> {
+    let events = sync["rooms"]["join"][room]["timeline"]["events"].as_array();
+    events
+        .into_iter()
+        .flatten()
+        .filter(|event| event["type"] == "m.room.message" && event["sender"] != own)
+        .filter(|event| event["content"]["msgtype"] == "m.text")
+        .filter_map(|event| {
+            let relation = &event["content"]["m.relates_to"];
+            Some(Message {
+                id: event["event_id"].as_str()?.to_string(),
+                thread: match relation["rel_type"] == "m.thread" {
+                    true => relation["event_id"].as_str().map(str::to_string),
+                    false => None,
+                },
+                sender: event["sender"].as_str()?.to_string(),
+                text: event["content"]["body"].as_str()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+impl Matrix {
+    /// Logs in with `token` and skips the room's history, so only messages
+    /// from now on are answered.
+    pub fn connect(homeserver: &str, room: &str, token: String) -> Result<Matrix> {
+        let api = format!("{}/_matrix/client/v3", homeserver.trim_end_matches('/'));
+        let whoami = call("GET", &format!("{}/account/whoami", api), &token, None)?;
+        let user = whoami["user_id"]
+            .as_str()
+            .ok_or_else(|| AppError::Generic(format!("Matrix login failed: {}", whoami)))?
+            .to_string();
+        let matrix = Matrix {
+            api,
+            room: room.to_string(),
+            token,
+            user,
+            since: Mutex::new(String::new()),
+            transactions: AtomicU64::new(0),
+        };
+        let sync = matrix.sync(None, 0)?;
+        matrix.advance(&sync)?;
+        Ok(matrix)
+    }
+
+    fn sync(&self, since: Option<&str>, timeout: u64) -> Result<Value> {
+        let mut url = format!(
+            "{}/sync?timeout={}&filter={}",
+            self.api,
+            timeout,
+            filter(&self.room)
+        );
+        if let Some(since) = since {
+            url.push_str(&format!("&since={}", encode(since)));
+        }
+        call("GET", &url, &self.token, None)
+    }
+
+    fn advance(&self, sync: &Value) -> Result<()> {
+        let next = sync["next_batch"]
+            .as_str()
+            .ok_or_else(|| AppError::Generic(format!("unexpected Matrix sync: {}", sync)))?;
+        *self.since.lock().unwrap() = next.to_string();
+        Ok(())
+    }
+}
+
+impl Chat for Matrix {
+    fn poll(&self) -> Result<Vec<Message>> {
+        let since = self.since.lock().unwrap().clone();
+        let sync = self.sync(Some(&since), SYNC_TIMEOUT)?;
+        self.advance(&sync)?;
+        Ok(messages(&sync, &self.room, &self.user))
+    }
+
+    fn reply(&self, to: &Message, text: &str) -> Result<()> {
+        let transaction = format!(
+            "russh{}.{}",
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis())
+                .unwrap_or_default(),
+            self.transactions.fetch_add(1, Ordering::Relaxed)
+        );
+        let url = format!(
+            "{}/rooms/{}/send/m.room.message/{}",
+            self.api,
+            encode(&self.room),
+            transaction
+        );
+        let body = json!({
+            "msgtype": "m.notice",
+            "body": text,
+            "m.relates_to": {
+                "rel_type": "m.thread",
+                "event_id": to.thread.as_deref().unwrap_or(&to.id),
+                "is_falling_back": true,
+                "m.in_reply_to": { "event_id": to.id },
+            },
+        });
+        let sent = call("PUT", &url, &self.token, Some(&body))?;
+        match sent["event_id"].is_string() {
+            true => Ok(()),
+            false => Err(AppError::Generic(format!(
+                "Matrix refused the reply: {}",
+                sent
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_messages() {
+        let room = "!ops:example.org";
+        let sync = json!({
+            "next_batch": "s2",
+            "rooms": { "join": { room: { "timeline": { "events": [
+                {
+                    "type": "m.room.message",
+                    "event_id": "$1",
+                    "sender": "@ana:example.org",
+                    "content": { "msgtype": "m.text", "body": "!russh run web uptime" },
+                },
+                {
+                    "type": "m.room.message",
+                    "event_id": "$2",
+                    "sender": "@ana:example.org",
+                    "content": {
+                        "msgtype": "m.text",
+                        "body": "!russh targets",
+                        "m.relates_to": { "rel_type": "m.thread", "event_id": "$1" },
+                    },
+                },
+                {
+                    "type": "m.room.message",
+                    "event_id": "$3",
+                    "sender": "@russh:example.org",
+                    "content": { "msgtype": "m.notice", "body": "1 ok, 0 failed" },
+                },
+            ] } } } },
+        });
+        let messages = messages(&sync, room, "@russh:example.org");
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].thread, None);
+        assert_eq!(messages[0].text, "!russh run web uptime");
+        assert_eq!(messages[1].thread.as_deref(), Some("$1"));
+    }
+}
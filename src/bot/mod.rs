@@ -0,0 +1,480 @@
+//! `russh bot`: chat-ops from a Matrix room or a Slack channel. The bot
+//! reads the room, and messages starting with its prefix
+//! (`!russh run web uptime`) run allowlisted commands on allowlisted
+//! targets for allowlisted users; the results are posted back in the
+//! message's thread. Runs go through the same checks as any other: the
+//! run lock, approval for protected hosts (`approval=TOKEN`) and the audit
+//! log. Destructive commands (see [`crate::queue::is_destructive`]) and
+//! ones using shell operators need an allowlist entry spelling them out, a
+//! pattern is not enough.
+//!
+//! Both platforms are polled over HTTP with curl, Matrix with long-polling
+//! `/sync` and Slack with `conversations.history`, so the bot needs no
+//! public endpoint.
+
+mod matrix;
+mod slack;
+
+use crate::cli::{load_config, require_client, Cli};
+use crate::config::{Config, Host};
+use crate::errors::{AppError, ExecutionError, InventoryError, Result};
+use crate::executor::parallelism;
+use crate::filter::HostPattern;
+use crate::http;
+use crate::queue::is_destructive;
+use crate::report::{self, RunReport};
+use crate::serve::{admit, batch_executor};
+use crate::ssh::{Backend, ServerResult};
+use crate::summary::Status;
+use crate::warnings::{warn, WarningKind};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::env;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Longest reply posted; longer output is cut.
+const MAX_REPLY: usize = 3500;
+
+/// Characters that make one command several, or run others inside it.
+const SHELL_OPERATORS: &[char] = &[';', '&', '|', '$', '`', '<', '>', '(', ')', '\n'];
+
+/// Pause after a failed poll before trying again.
+const RETRY_PAUSE: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Platform {
+    Matrix,
+    Slack,
+}
+
+fn default_token_env() -> String {
+    "RUSSH_BOT_TOKEN".to_string()
+}
+
+fn default_prefix() -> String {
+    "!russh".to_string()
+}
+
+/// `[bot]` config section.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BotConfig {
+    pub platform: Platform,
+    /// The Matrix homeserver, e.g. `https://matrix.example.org`.
+    #[serde(default)]
+    pub homeserver: Option<String>,
+    /// The Matrix room id (`!abc:example.org`) or Slack channel id.
+    pub room: String,
+    /// The environment variable holding the bot's access token.
+    #[serde(default = "default_token_env")]
+    pub token_env: String,
+    /// What messages for the bot start with.
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+    /// Who may use the bot: Matrix user ids or Slack member ids.
+    #[serde(default)]
+    pub users: Vec<String>,
+    /// Groups and hosts it may run on.
+    #[serde(default)]
+    pub targets: Vec<String>,
+    /// Commands it may run, as globs over the whole command or `/regex/`.
+    #[serde(default)]
+    pub commands: Vec<String>,
+}
+
+/// A message in the room.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Message {
+    pub id: String,
+    /// The thread it is in, if it is a reply.
+    pub thread: Option<String>,
+    pub sender: String,
+    pub text: String,
+}
+
+/// A chat platform: reading the room and answering in threads.
+trait Chat: Send + Sync {
+    /// The messages posted since the last call, oldest first; may wait a
+    /// while for new ones.
+    fn poll(&self) -> Result<Vec<Message>>;
+
+    /// Answers `to` in its thread.
+    fn reply(&self, to: &Message, text: &str) -> Result<()>;
+}
+
+/// `value` as a curl config string.
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Calls a chat API with the bearer `token` and an optional JSON `body`,
+/// both given to curl on stdin so they stay out of the process list.
+fn call(method: &str, url: &str, token: &str, body: Option<&Value>) -> Result<Value> {
+    let mut secrets = format!(
+        "header = {}\n",
+        quote(&format!("Authorization: Bearer {}", token))
+    );
+    if let Some(body) = body {
+        secrets.push_str(&format!("data-binary = {}\n", quote(&body.to_string())));
+    }
+    let args = [
+        "--header",
+        "Content-Type: application/json; charset=utf-8",
+        "--max-time",
+        "60",
+    ];
+    let response = http::request(method, url, &args, &secrets)?;
+    serde_json::from_slice(&response)
+        .map_err(|e| AppError::Generic(format!("unexpected response from {}: {}", url, e)))
+}
+
+/// Percent-encodes `text` for a URL path segment or query value.
+fn encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// What a message asks for.
+#[derive(Debug, PartialEq)]
+enum Order {
+    Help,
+    Targets,
+    Run {
+        target: String,
+        approval: Option<String>,
+        command: String,
+    },
+}
+
+/// The first word of `text` and the rest, without leading whitespace.
+fn next_word(text: &str) -> (&str, &str) {
+    let text = text.trim_start();
+    match text.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/// What a message starting with `prefix` asks for; `None` for other
+/// messages, `Err` with the usage for ones it can't make out.
+fn parse(prefix: &str, text: &str) -> Option<std::result::Result<Order, String>> {
+    let (first, rest) = next_word(text);
+    if first != prefix {
+        return None;
+    }
+    let usage = format!(
+        "usage: {p} run TARGET [approval=TOKEN] COMMAND, {p} targets, {p} help",
+        p = prefix
+    );
+    let (order, rest) = next_word(rest);
+    Some(match order {
+        "" | "help" => Ok(Order::Help),
+        "targets" => Ok(Order::Targets),
+        "run" => {
+            let (target, rest) = next_word(rest);
+            let (approval, command) = match next_word(rest) {
+                (word, command) if word.starts_with("approval=") => {
+                    (Some(word["approval=".len()..].to_string()), command)
+                }
+                _ => (None, rest),
+            };
+            match (target, command.trim()) {
+                ("", _) | (_, "") => Err(usage),
+                (target, command) => Ok(Order::Run {
+                    target: target.to_string(),
+                    approval,
+                    command: command.to_string(),
+                }),
+            }
+        }
+        _ => Err(usage),
+    })
+}
+
+pub struct Bot {
+    config: Config,
+    bot: BotConfig,
+    commands: Vec<HostPattern>,
+    parallel: usize,
+    chat: Box<dyn Chat>,
+}
+
+impl Bot {
+    /// Whether `sender` may run `command` on `target`.
+    fn allowed(
+        &self,
+        sender: &str,
+        target: &str,
+        command: &str,
+    ) -> std::result::Result<(), String> {
+        if !self.bot.targets.iter().any(|t| t == target) {
+            return Err(format!(
+                "{} is not a target; `{} targets` lists them",
+                target, self.bot.prefix
+            ));
+        }
+        let exact = self.bot.commands.iter().any(|c| c == command);
+        if !exact && !self.commands.iter().any(|pattern| pattern.matches(command)) {
+            return Err(format!("`{}` is not an allowed command", command));
+        }
+        // A pattern like `systemctl status *` would otherwise let anything
+        // through after a `;`.
+        if !exact && (is_destructive(command) || command.contains(SHELL_OPERATORS)) {
+            return Err(format!(
+                "`{}` needs an exact entry in [bot] commands, a pattern is not enough",
+                command
+            ));
+        }
+        match self.bot.users.iter().any(|user| user == sender) {
+            true => Ok(()),
+            false => Err(format!("{} may not run commands", sender)),
+        }
+    }
+
+    /// The hosts of `target`: a group, or a configured host.
+    fn hosts(&self, target: &str) -> Result<Vec<Host>> {
+        if self.config.groups.contains_key(target) {
+            return self.config.group_hosts(&[target.to_string()]);
+        }
+        match self
+            .config
+            .servers
+            .iter()
+            .any(|server| server.host == target)
+        {
+            true => Ok(vec![self.config.host(target)]),
+            false => Err(InventoryError::NoneSelected(format!("target {}", target)).into()),
+        }
+    }
+
+    fn answer(&self, message: &Message, text: &str) {
+        if let Err(e) = self.chat.reply(message, text) {
+            warn(
+                WarningKind::Delivery,
+                format!("could not answer {}: {}", message.sender, e),
+            );
+        }
+    }
+
+    fn handle(self: &Arc<Self>, message: Message) {
+        let Some(order) = parse(&self.bot.prefix, &message.text) else {
+            return;
+        };
+        if !self.bot.users.contains(&message.sender) {
+            return self.answer(
+                &message,
+                &format!("{} may not use this bot", message.sender),
+            );
+        }
+        match order {
+            Err(usage) => self.answer(&message, &usage),
+            Ok(Order::Help) => self.answer(
+                &message,
+                &format!(
+                    "{p} run TARGET [approval=TOKEN] COMMAND runs an allowed command on a target, \
+                     {p} targets lists the targets",
+                    p = self.bot.prefix
+                ),
+            ),
+            Ok(Order::Targets) => self.answer(
+                &message,
+                &format!("Targets: {}", self.bot.targets.join(", ")),
+            ),
+            Ok(Order::Run {
+                target,
+                approval,
+                command,
+            }) => {
+                if let Err(refused) = self.allowed(&message.sender, &target, &command) {
+                    return self.answer(&message, &refused);
+                }
+                let bot = Arc::clone(self);
+                thread::spawn(move || {
+                    let reply = bot
+                        .run(&message, &target, approval.as_deref(), command)
+                        .unwrap_or_else(|e| format!("Not run: {}", e));
+                    bot.answer(&message, &reply);
+                });
+            }
+        }
+    }
+
+    fn run(
+        &self,
+        message: &Message,
+        target: &str,
+        approval: Option<&str>,
+        command: String,
+    ) -> Result<String> {
+        let hosts = self.hosts(target)?;
+        let commands = vec![command];
+        let _lock = admit(&self.config, &hosts, &commands, approval, "bot_run")?;
+        self.answer(
+            message,
+            &format!(
+                "Running `{}` on {} ({} host(s))...",
+                commands[0],
+                target,
+                hosts.len()
+            ),
+        );
+        let results = batch_executor(&self.config, &hosts, commands, self.parallel).run();
+        if let Err(e) = report::save(&RunReport::new(&hosts, &results)) {
+            warn(
+                WarningKind::Delivery,
+                format!("could not save the run report: {}", e),
+            );
+        }
+        Ok(summarize(&results))
+    }
+}
+
+/// The reply for a finished run: a line per host and their output, cut
+/// at `MAX_REPLY`.
+fn summarize(results: &[(String, ServerResult)]) -> String {
+    let failed = results
+        .iter()
+        .filter(|(_, r)| Status::of(r) == Status::Failed)
+        .count();
+    let mut reply = format!("{} ok, {} failed\n", results.len() - failed, failed);
+    for (_, result) in results {
+        let status = match (Status::of(result), result.exit_code) {
+            (Status::Failed, Some(code)) => format!("FAILED (exit {})", code),
+            (status, _) => format!("{:?}", status).to_lowercase(),
+        };
+        reply.push_str(&format!(
+            "{}: {} in {:.1}s\n",
+            result.server, status, result.duration
+        ));
+        let output = format!("{}{}", result.output, result.error.as_deref().unwrap_or(""));
+        if !output.trim().is_empty() {
+            reply.push_str(&format!("```\n{}\n```\n", output.trim_end()));
+        }
+    }
+    if reply.len() > MAX_REPLY {
+        let mut end = MAX_REPLY;
+        while !reply.is_char_boundary(end) {
+            end -= 1;
+        }
+        reply.truncate(end);
+        reply.push_str("\n... (cut, see the run report)");
+    }
+    reply
+}
+
+/// `russh bot`: answers the `[bot]` room until killed.
+pub fn serve(config: Config, parallel: usize) -> Result<()> {
+    let bot = config.bot.clone().ok_or_else(|| {
+        AppError::Generic("russh bot needs a [bot] section in the config".to_string())
+    })?;
+    let commands = bot
+        .commands
+        .iter()
+        .map(|pattern| pattern.parse())
+        .collect::<Result<Vec<HostPattern>>>()?;
+    let token = env::var(&bot.token_env)
+        .ok()
+        .filter(|token| !token.is_empty())
+        .ok_or_else(|| ExecutionError::InvalidOption {
+            option: "[bot] token_env",
+            reason: format!("{} is not set", bot.token_env),
+        })?;
+    let chat: Box<dyn Chat> = match bot.platform {
+        Platform::Matrix => {
+            let homeserver = bot.homeserver.as_deref().ok_or_else(|| {
+                AppError::Generic("[bot] needs a homeserver for Matrix".to_string())
+            })?;
+            Box::new(matrix::Matrix::connect(homeserver, &bot.room, token)?)
+        }
+        Platform::Slack => Box::new(slack::Slack::connect(&bot.room, token)?),
+    };
+    eprintln!(
+        "Listening for {} in {}, {} target(s) for {} user(s)",
+        bot.prefix,
+        bot.room,
+        bot.targets.len(),
+        bot.users.len()
+    );
+    let bot = Arc::new(Bot {
+        config,
+        bot,
+        commands,
+        parallel,
+        chat,
+    });
+    loop {
+        match bot.chat.poll() {
+            Ok(messages) => messages.into_iter().for_each(|message| bot.handle(message)),
+            Err(e) => {
+                warn(
+                    WarningKind::Delivery,
+                    format!("could not read the room: {}", e),
+                );
+                thread::sleep(RETRY_PAUSE);
+            }
+        }
+    }
+}
+
+/// `russh bot`: answers the configured chat room until killed.
+pub fn command(cli: &Cli) -> Result<()> {
+    let mut config = load_config(&cli.config_file, false)?;
+    config.expand_groups();
+    let parallel = parallelism(cli.parallel, Some(&config))?;
+    require_client(Backend::OpenSsh)?;
+    serve(config, parallel)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse("!russh", "hello there"), None);
+        assert_eq!(parse("!russh", "!russhx run"), None);
+        assert_eq!(parse("!russh", "!russh"), Some(Ok(Order::Help)));
+        assert_eq!(parse("!russh", " !russh targets"), Some(Ok(Order::Targets)));
+        assert_eq!(
+            parse("!russh", "!russh run web  systemctl status  nginx"),
+            Some(Ok(Order::Run {
+                target: "web".to_string(),
+                approval: None,
+                command: "systemctl status  nginx".to_string(),
+            }))
+        );
+        assert_eq!(
+            parse("!russh", "!russh run db approval=1f2e reboot"),
+            Some(Ok(Order::Run {
+                target: "db".to_string(),
+                approval: Some("1f2e".to_string()),
+                command: "reboot".to_string(),
+            }))
+        );
+        assert!(matches!(parse("!russh", "!russh run web"), Some(Err(_))));
+        assert!(matches!(parse("!russh", "!russh deploy"), Some(Err(_))));
+
+        let results = [(
+            "uptime".to_string(),
+            ServerResult {
+                server: "web01".to_string(),
+                output: "x".repeat(MAX_REPLY),
+                duration: 0.25,
+                exit_code: Some(2),
+                ..Default::default()
+            },
+        )];
+        let reply = summarize(&results);
+        assert!(reply.starts_with("0 ok, 1 failed\nweb01: FAILED (exit 2) in 0.2s\n```\nxxx"));
+        assert!(reply.ends_with("(cut, see the run report)"));
+    }
+}
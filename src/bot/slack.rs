@@ -0,0 +1,112 @@
+//! The Slack Web API: polling `conversations.history` for the channel's
+//! messages and replying with `chat.postMessage` in the thread.
+
+use super::{call, encode, Chat, Message};
+use crate::errors::{AppError, Result};
+use serde_json::{json, Value};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const API: &str = "https://slack.com/api";
+
+/// Pause between polls; `conversations.history` allows about one a second.
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+
+pub struct Slack {
+    channel: String,
+    token: String,
+    /// The bot's own member id, whose messages are skipped.
+    user: String,
+    /// The timestamp of the newest message seen.
+    oldest: Mutex<String>,
+}
+
+/// `response` if Slack says it is `ok`.
+fn ok(response: Value) -> Result<Value> {
+    match response["ok"].as_bool() {
+        Some(true) => Ok(response),
+        _ => Err(AppError::Generic(format!(
+            "Slack API error: {}",
+            response["error"].as_str().unwrap_or("unexpected response")
+        ))),
+    }
+}
+
+/// Slack escapes these three in message text.
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+impl Slack {
+    /// Checks `token` and starts from now, so only new messages are
+    /// answered.
+    pub fn connect(channel: &str, token: String) -> Result<Slack> {
+        let auth = ok(call("POST", &format!("{}/auth.test", API), &token, None)?)?;
+        let user = auth["user_id"].as_str().unwrap_or_default().to_string();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        Ok(Slack {
+            channel: channel.to_string(),
+            token,
+            user,
+            oldest: Mutex::new(format!("{}.000000", now)),
+        })
+    }
+}
+
+impl Chat for Slack {
+    fn poll(&self) -> Result<Vec<Message>> {
+        thread::sleep(POLL_INTERVAL);
+        let oldest = self.oldest.lock().unwrap().clone();
+        let url = format!(
+            "{}/conversations.history?channel={}&oldest={}&limit=100",
+            API,
+            encode(&self.channel),
+            encode(&oldest)
+        );
+        let history = ok(call("GET", &url, &self.token, None)?)?;
+        // Newest first; `ts` is the message's id and sorts as text.
+        let mut messages: Vec<&Value> = history["messages"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .collect();
+        messages.sort_by_key(|message| message["ts"].as_str().unwrap_or_default().to_string());
+        if let Some(newest) = messages.last().and_then(|m| m["ts"].as_str()) {
+            *self.oldest.lock().unwrap() = newest.to_string();
+        }
+        Ok(messages
+            .into_iter()
+            .filter(|message| message["subtype"].is_null() && message["bot_id"].is_null())
+            .filter(|message| message["user"] != self.user.as_str())
+            .filter_map(|message| {
+                Some(Message {
+                    id: message["ts"].as_str()?.to_string(),
+                    thread: message["thread_ts"].as_str().map(str::to_string),
+                    sender: message["user"].as_str()?.to_string(),
+                    text: unescape(message["text"].as_str()?),
+                })
+            })
+            .collect())
+    }
+
+    fn reply(&self, to: &Message, text: &str) -> Result<()> {
+        let body = json!({
+            "channel": self.channel,
+            "thread_ts": to.thread.as_deref().unwrap_or(&to.id),
+            "text": text,
+        });
+        ok(call(
+            "POST",
+            &format!("{}/chat.postMessage", API),
+            &self.token,
+            Some(&body),
+        )?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+//! Central switch for everything that talks to the network other than SSH.
+//! With `--offline` every such feature must ask here first and fail closed,
+//! so nothing leaves the machine by accident in an air-gapped environment.
+
+use crate::errors::{ExecutionError, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+
+/// A network feature that `--offline` turns off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Capability {
+    /// Any outgoing HTTP request; checked by the `http` module itself.
+    Http,
+    Webhooks,
+    SelfUpdate,
+    Archive,
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Capability::Http => "HTTP access",
+            Capability::Webhooks => "webhook delivery",
+            Capability::SelfUpdate => "self-update",
+            Capability::Archive => "run archiving",
+        })
+    }
+}
+
+pub fn set_offline(offline: bool) {
+    OFFLINE.store(offline, Ordering::Relaxed);
+}
+
+pub fn is_offline() -> bool {
+    OFFLINE.load(Ordering::Relaxed)
+}
+
+/// Fails when `capability` has been disabled by `--offline`.
+pub fn check(capability: Capability) -> Result<()> {
+    if is_offline() {
+        return Err(ExecutionError::Offline(capability.to_string()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_offline_fails_closed() {
+        assert!(check(Capability::Webhooks).is_ok());
+        set_offline(true);
+        let err = check(Capability::SelfUpdate).unwrap_err();
+        assert_eq!(err.to_string(), "self-update is disabled by --offline");
+        set_offline(false);
+    }
+}
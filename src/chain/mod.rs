@@ -0,0 +1,255 @@
+//! `--chain`: a host's commands run one after the other in a single remote
+//! shell instead of an ssh session each, stopping at the first one that
+//! fails. The script marks where each command ends, with its exit code and
+//! the time, on stdout and stderr; the markers are kept out of the live
+//! output and the host's result is split at them into one result per
+//! command afterwards.
+
+use crate::ssh::{Cut, ServerResult};
+
+/// Starts the marker lines, followed by `start <epoch>` once, then
+/// `<step> <exit code> <epoch>` on stdout and `<step> <exit code>` on
+/// stderr after each command.
+pub const STEP_MARKER: &str = "__RUSSH_STEP__";
+
+/// The POSIX shell script running `commands` in order, stopping with the
+/// exit code of the first one that fails.
+pub fn script(commands: &[String]) -> String {
+    let mut script = format!("echo \"{} start $(date +%s)\"\n", STEP_MARKER);
+    for (step, command) in commands.iter().enumerate() {
+        let step = step + 1;
+        script.push_str(&format!(
+            "{{ {}\n}}; __russh_rc=$?; echo \"{m} {s} $__russh_rc $(date +%s)\"; \
+             echo \"{m} {s} $__russh_rc\" >&2; [ $__russh_rc -eq 0 ] || exit $__russh_rc\n",
+            command,
+            m = STEP_MARKER,
+            s = step
+        ));
+    }
+    script
+}
+
+/// What of `line` to show live: all of it, the output in front of a
+/// marker when a command's last line had no newline, or nothing for a
+/// marker line.
+pub fn shown(line: &str) -> Option<&str> {
+    match line.find(STEP_MARKER) {
+        None => Some(line),
+        Some(0) => None,
+        Some(at) => Some(&line[..at]),
+    }
+}
+
+#[derive(Default)]
+struct Step {
+    output: String,
+    error: String,
+    exit_code: Option<i32>,
+    ended: Option<u64>,
+}
+
+fn push_line(text: &mut String, line: &str) {
+    text.push_str(line);
+    text.push('\n');
+}
+
+/// Sorts `text`'s lines into `steps` at the markers; returns the start
+/// time if the script got to print it.
+fn sort_lines(text: &str, steps: &mut [Step], stdout: bool) -> Option<u64> {
+    let mut start = None;
+    let mut current = 0;
+    for line in text.lines() {
+        let last = steps.len() - 1;
+        let (before, marker) = match line.find(STEP_MARKER) {
+            Some(at) => (&line[..at], Some(&line[at + STEP_MARKER.len()..])),
+            None => (line, None),
+        };
+        if marker.is_none() || !before.is_empty() {
+            let step = &mut steps[current.min(last)];
+            push_line(
+                if stdout {
+                    &mut step.output
+                } else {
+                    &mut step.error
+                },
+                before,
+            );
+        }
+        let fields: Vec<&str> = marker.unwrap_or_default().split_whitespace().collect();
+        match fields.as_slice() {
+            ["start", at] => start = at.parse().ok(),
+            [step, exit_code, rest @ ..] => {
+                let Some(step) = step
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|s| (1..=last + 1).contains(s))
+                else {
+                    continue;
+                };
+                if stdout {
+                    steps[step - 1].exit_code = exit_code.parse().ok();
+                    steps[step - 1].ended = rest.first().and_then(|at| at.parse().ok());
+                }
+                current = step;
+            }
+            _ => {}
+        }
+    }
+    start
+}
+
+/// Splits the result of running `commands` chained into one result per
+/// command. The command that ended the shell some other way (`exit`, a
+/// timeout) gets the session's exit code and whatever came after the last
+/// marker; the ones after a failure are cut. When the script never
+/// started, every command gets the session's result.
+pub fn split(commands: &[String], result: ServerResult) -> Vec<(String, ServerResult)> {
+    if commands.is_empty() {
+        return Vec::new();
+    }
+    let mut steps: Vec<Step> = commands.iter().map(|_| Step::default()).collect();
+    let start = sort_lines(&result.output, &mut steps, true);
+    sort_lines(
+        result.error.as_deref().unwrap_or_default(),
+        &mut steps,
+        false,
+    );
+    let Some(start) = start else {
+        return commands
+            .iter()
+            .map(|command| (command.clone(), result.clone()))
+            .collect();
+    };
+
+    let mut split: Vec<(String, ServerResult)> = Vec::new();
+    let mut previous = start;
+    let mut stopped = false;
+    for (command, step) in commands.iter().zip(steps) {
+        if stopped {
+            let skipped = ServerResult::skipped(&result.server, Cut::AfterFailure);
+            split.push((command.clone(), skipped));
+            continue;
+        }
+        let (exit_code, success, duration) = match (step.exit_code, step.ended) {
+            (Some(code), Some(ended)) => (Some(code), code == 0, ended.saturating_sub(previous)),
+            _ => (
+                result.exit_code,
+                result.success,
+                (result.duration as u64).saturating_sub(previous.saturating_sub(start)),
+            ),
+        };
+        stopped = !success || step.exit_code.is_none();
+        previous = step.ended.unwrap_or(previous);
+        split.push((
+            command.clone(),
+            ServerResult {
+                output: step.output,
+                error: Some(step.error).filter(|text| !text.is_empty()),
+                duration: duration as f64,
+                success,
+                changed: false,
+                exit_code,
+                remote_time: None,
+                ..result.clone()
+            },
+        ));
+    }
+    // The session's change and timing reports go to the last command that
+    // ran, as they can't be told apart.
+    if let Some((_, last)) = split.iter_mut().rev().find(|(_, r)| r.cut.is_none()) {
+        last.changed = result.changed;
+        last.remote_time = result.remote_time;
+    }
+    split
+}
+
+/// Splits the results of the chained `commands` in `results`, leaving any
+/// other job's result as it is.
+pub fn split_results(
+    commands: &[String],
+    results: Vec<(String, ServerResult)>,
+) -> Vec<(String, ServerResult)> {
+    let chained = script(commands);
+    results
+        .into_iter()
+        .flat_map(|(command, result)| match command == chained {
+            true => split(commands, result),
+            false => vec![(command, result)],
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(output: &str, error: &str, exit_code: i32) -> ServerResult {
+        ServerResult {
+            server: "web01".to_string(),
+            output: output.to_string(),
+            error: Some(error.to_string()).filter(|e| !e.is_empty()),
+            duration: 7.5,
+            success: exit_code == 0,
+            changed: true,
+            exit_code: Some(exit_code),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_split() {
+        let commands: Vec<String> = ["apt-get update", "apt-get upgrade -y", "reboot"]
+            .map(String::from)
+            .to_vec();
+        let script = script(&commands);
+        assert!(script.contains("{ apt-get upgrade -y\n}; __russh_rc=$?; echo \"__RUSSH_STEP__ 2 "));
+
+        let run = result(
+            "__RUSSH_STEP__ start 100\n\
+             Hit:1 http://deb.debian.org\n\
+             __RUSSH_STEP__ 1 0 103\n\
+             no newline__RUSSH_STEP__ 2 100 110\n",
+            "__RUSSH_STEP__ 1 0\nE: dpkg was interrupted\n__RUSSH_STEP__ 2 100\n",
+            100,
+        );
+        let steps = split_results(&commands, vec![(script.clone(), run)]);
+        let summary: Vec<(&str, Option<i32>, f64, bool)> = steps
+            .iter()
+            .map(|(c, r)| (c.as_str(), r.exit_code, r.duration, r.changed))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("apt-get update", Some(0), 3.0, false),
+                ("apt-get upgrade -y", Some(100), 7.0, true),
+                ("reboot", None, 0.0, false)
+            ]
+        );
+        assert_eq!(steps[0].1.output, "Hit:1 http://deb.debian.org\n");
+        assert_eq!(steps[0].1.error, None);
+        assert_eq!(steps[1].1.output, "no newline\n");
+        assert_eq!(
+            steps[1].1.error.as_deref(),
+            Some("E: dpkg was interrupted\n")
+        );
+        assert_eq!(steps[2].1.cut, Some(Cut::AfterFailure));
+
+        // The second command exits the shell itself.
+        let exited = result(
+            "__RUSSH_STEP__ start 100\n__RUSSH_STEP__ 1 0 101\nbye\n",
+            "",
+            3,
+        );
+        let steps = split(&commands, exited);
+        assert_eq!(steps[1].1.exit_code, Some(3));
+        assert_eq!(steps[1].1.output, "bye\n");
+        assert_eq!(steps[2].1.cut, Some(Cut::AfterFailure));
+
+        let unreachable = result("", "ssh: connect to host web01 port 22\n", 255);
+        assert!(split(&commands, unreachable)
+            .iter()
+            .all(|(_, r)| r.unreachable()));
+        assert_eq!(shown("__RUSSH_STEP__ 1 0 103"), None);
+        assert_eq!(shown("done__RUSSH_STEP__ 1 0 103"), Some("done"));
+    }
+}
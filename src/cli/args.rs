@@ -0,0 +1,747 @@
+use crate::answers::Answer;
+use crate::clipboard::CopyTarget;
+use crate::du;
+use crate::filter::{HostPattern, TagExpr};
+use crate::history::Label;
+use crate::output::OutputFormat;
+use crate::preflight::Requirement;
+use crate::report::ReportSpec;
+use crate::ssh::{Backend, EnvVar, IoPriority};
+use argh::FromArgs;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+/// executes SSH commands on multiple servers.
+/// This is the main configuration for the command line interface.
+#[derive(FromArgs, PartialEq, Debug)]
+pub struct Cli {
+    /// specify the commands that should be executed on the remote servers.
+    /// These are the actual SSH commands that will be run on each server.
+    #[argh(positional)]
+    pub commands: Vec<String>,
+
+    /// optional: specify the relative path to the russh.json file.
+    /// If not provided, a default path or other logic will be used.
+    #[argh(option, short = 'c')]
+    pub config_file: Option<String>,
+
+    /// optional: where russh writes its state (run reports, queue, audit
+    /// log, caches); defaults to $RUSSH_STATE_DIR or the XDG data dir.
+    #[argh(option)]
+    pub state_dir: Option<PathBuf>,
+
+    /// optional: write each host's output, exit code and duration to
+    /// <dir>/<timestamp>/<host>.log; overrides log_dir in the config.
+    #[argh(option)]
+    pub log_dir: Option<PathBuf>,
+
+    /// optional: run against an ad-hoc host instead of the configured servers.
+    /// Accepts inline attributes, e.g. -H 'web01 user=deploy port=2222 tag=canary'.
+    /// Can be repeated.
+    #[argh(option, short = 'H', long = "host")]
+    pub hosts: Vec<String>,
+
+    /// optional: run against the hosts listed in this file, one per line
+    /// and written as for -H, or `-` to read them from stdin.
+    #[argh(option)]
+    pub hosts_file: Option<PathBuf>,
+
+    /// optional: run against the hosts of this set saved with `russh set
+    /// save` (repeatable).
+    #[argh(option, long = "set")]
+    pub sets: Vec<String>,
+
+    /// optional: only run against the members of this config group
+    /// (repeatable).
+    #[argh(option, short = 'g', long = "group")]
+    pub groups: Vec<String>,
+
+    /// optional: only run against hosts whose name matches this glob
+    /// (`web-*`, `db-0[1-3]`) or `/regex/` (repeatable).
+    #[argh(option, long = "hosts")]
+    pub only: Vec<HostPattern>,
+
+    /// optional: skip hosts whose name matches this glob or `/regex/`
+    /// (repeatable).
+    #[argh(option)]
+    pub exclude: Vec<HostPattern>,
+
+    /// optional: only run against hosts whose tags satisfy this expression,
+    /// e.g. 'prod & gpu' or '(web | api) & !canary' (repeatable, all must
+    /// hold).
+    #[argh(option)]
+    pub tags: Vec<TagExpr>,
+
+    /// optional: before running, tick the hosts to run on in a checklist of
+    /// the selected ones.
+    #[argh(switch)]
+    pub pick: bool,
+
+    /// optional: language for messages and prompts (en, de, es).
+    /// Defaults to RUSSH_LANG or the system locale.
+    #[argh(option)]
+    pub lang: Option<String>,
+
+    /// optional: print the exact ssh command russh would run for the given
+    /// host instead of running anything.
+    #[argh(option)]
+    pub print_command: Option<String>,
+
+    /// optional: print the ssh invocation for every host and command,
+    /// after templating, instead of running anything.
+    #[argh(switch)]
+    pub dry_run: bool,
+
+    /// optional: give every command its own connection instead of sharing
+    /// one per host.
+    #[argh(switch)]
+    pub no_multiplex: bool,
+
+    /// optional: run a host's commands in order in one remote shell,
+    /// stopping at the first that fails.
+    #[argh(switch)]
+    pub chain: bool,
+
+    /// optional: don't take the run lock that keeps concurrent runs against
+    /// the same inventory apart.
+    #[argh(switch)]
+    pub no_lock: bool,
+
+    /// optional: remove a stale run lock left behind by another run.
+    #[argh(switch)]
+    pub break_lock: bool,
+
+    /// optional: approval token from a second operator, required for runs
+    /// against hosts tagged `protected`.
+    #[argh(option)]
+    pub approval: Option<String>,
+
+    /// optional: run commands that couldn't reach their host once more,
+    /// after every other host is done.
+    #[argh(switch)]
+    pub retry_unreachable: bool,
+
+    /// optional: remember commands for hosts that couldn't be reached so
+    /// they can be retried later with `russh flush-queue`.
+    #[argh(switch)]
+    pub queue_unreachable: bool,
+
+    /// optional: time the command on each host and report its real/user/sys
+    /// time next to the round-trip duration.
+    #[argh(switch)]
+    pub remote_timing: bool,
+
+    /// optional: preflight check each host must pass before the command
+    /// runs, e.g. 'disk_free("/var") > 2GB' (repeatable).
+    #[argh(option)]
+    pub require: Vec<Requirement>,
+
+    /// optional: SSH transport, `openssh` (default, runs the ssh binary) or
+    /// `native` (built-in libssh2 client, needs the `native` feature).
+    #[argh(option, default = "Backend::OpenSsh")]
+    pub backend: Backend,
+
+    /// optional: run remote commands under `nice -n N` (-20 to 19).
+    #[argh(option)]
+    pub nice: Option<i32>,
+
+    /// optional: run remote commands under `ionice` with this class
+    /// (idle, best-effort[:0-7] or realtime[:0-7]).
+    #[argh(option)]
+    pub ionice: Option<IoPriority>,
+
+    /// optional: run commands (and push/pull file operations) as root, or
+    /// each server's `become_user`, through its `become_method` (default
+    /// sudo).
+    #[argh(switch, long = "become")]
+    pub escalate: bool,
+
+    /// optional: answer an interactive prompt, as `PROMPT=RESPONSE` with
+    /// PROMPT a regex; the command runs on a remote terminal. Repeatable.
+    #[argh(option)]
+    pub answer: Vec<Answer>,
+
+    /// optional: export a variable on every host before the command runs,
+    /// as `KEY=VALUE`; overrides the config's [env]. Repeatable.
+    #[argh(option)]
+    pub env: Vec<EnvVar>,
+
+    /// optional: drive an interactive session on every host with this
+    /// expect script (send/expect steps); without a command it talks to the
+    /// host's login shell or CLI.
+    #[argh(option)]
+    pub expect: Option<PathBuf>,
+
+    /// optional: ask once for the login password and give it to every
+    /// connection, for hosts that only allow password authentication.
+    #[argh(switch)]
+    pub ask_pass: bool,
+
+    /// optional: like --become, but ask once for the password and pass it
+    /// to `sudo -S` on every server.
+    #[argh(switch)]
+    pub sudo: bool,
+
+    /// optional: kill commands still running after this many seconds and
+    /// report them as failed; a server's own `timeout` takes precedence.
+    #[argh(option)]
+    pub timeout: Option<u64>,
+
+    /// optional: retry a failed command up to N times with exponential
+    /// backoff before reporting it (default: `retries` from the config, or 0).
+    #[argh(option)]
+    pub retries: Option<u32>,
+
+    /// optional: seconds to wait before the first retry, doubling for every
+    /// further one (default: `retry_delay` from the config, or 1).
+    #[argh(option)]
+    pub retry_delay: Option<f64>,
+
+    /// optional: show a full-screen dashboard with live output per server
+    /// instead of interleaved lines (needs the `tui` feature).
+    #[argh(switch)]
+    pub tui: bool,
+
+    /// optional: instead of streaming output, print each distinct output
+    /// once with the hosts that produced it.
+    #[argh(switch)]
+    pub group_output: bool,
+
+    /// optional: show every output line while streaming, instead of runs
+    /// of identical lines from a host as `line (xN)`.
+    #[argh(switch)]
+    pub no_collapse: bool,
+
+    /// optional: show how many hosts are done, how many failed and an ETA
+    /// below the output; a line on stderr every 10s when not on a terminal.
+    #[argh(switch)]
+    pub progress: bool,
+
+    /// optional: run at most N jobs at once (default: `max_parallel` from
+    /// the config, or 64).
+    #[argh(option)]
+    pub parallel: Option<usize>,
+
+    /// optional: rolling mode, work through N servers at a time (1 for one
+    /// by one); each server runs its commands in order, and a batch
+    /// finishes before the next starts.
+    #[argh(option)]
+    pub serial: Option<usize>,
+
+    /// optional: start nothing new once a command has failed; jobs already
+    /// running are let finish.
+    #[argh(switch)]
+    pub fail_fast: bool,
+
+    /// optional: also exit non-zero (4) when a selected host or job never
+    /// ran, e.g. dropped by --require or cut by --deadline.
+    #[argh(switch)]
+    pub all_must_succeed: bool,
+
+    /// optional: budget for the whole run in seconds; jobs not started by
+    /// then are skipped, and running ones are cancelled after a grace period.
+    #[argh(option)]
+    pub deadline: Option<u64>,
+
+    /// optional: seconds running jobs may go on past --deadline before they
+    /// are cancelled (default 30).
+    #[argh(option)]
+    pub deadline_grace: Option<u64>,
+
+    /// optional: `text` (default), `json`, which prints one JSON array of
+    /// results and nothing else on stdout, or `json-hosts`, the same grouped
+    /// by host.
+    #[argh(option, default = "OutputFormat::Text")]
+    pub output: OutputFormat,
+
+    /// optional: also write a per-host pass/fail report for CI, as
+    /// `junit:PATH` (JUnit XML) or `md:PATH` (Markdown). Repeatable.
+    #[argh(option)]
+    pub report: Vec<ReportSpec>,
+
+    /// optional: attach a `KEY=VALUE` label to the run, e.g. ticket=OPS-1234,
+    /// kept in the history, logs and reports (repeatable).
+    #[argh(option)]
+    pub label: Vec<Label>,
+
+    /// optional: after the run, copy `failed-hosts` (comma-separated) or the
+    /// `summary` to the system clipboard.
+    #[argh(option)]
+    pub copy: Option<CopyTarget>,
+
+    /// optional: gather host facts again even where the cache is still
+    /// fresh.
+    #[argh(switch)]
+    pub refresh_facts: bool,
+
+    /// optional: disable every network feature except SSH itself
+    /// (webhooks, self-update); fails if the config asks for one.
+    #[argh(switch)]
+    pub offline: bool,
+
+    /// print the russh version and exit.
+    #[argh(switch)]
+    pub version: bool,
+
+    /// with --version, also print build and environment details.
+    #[argh(switch)]
+    pub verbose: bool,
+
+    #[argh(subcommand)]
+    pub subcommand: Option<Subcommand>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum Subcommand {
+    SelfUpdate(SelfUpdateArgs),
+    Info(InfoArgs),
+    Approve(ApproveArgs),
+    FlushQueue(FlushQueueArgs),
+    Push(PushArgs),
+    Pull(PullArgs),
+    RerunFailed(RerunFailedArgs),
+    Monitor(MonitorArgs),
+    Import(ImportArgs),
+    Deploy(DeployArgs),
+    Task(TaskArgs),
+    Facts(FactsArgs),
+    Paths(PathsArgs),
+    Decommission(DecommissionArgs),
+    Script(ScriptArgs),
+    Runbook(RunbookArgs),
+    Gate(GateArgs),
+    KnownHosts(KnownHostsArgs),
+    Serve(ServeArgs),
+    List(ListArgs),
+    Bot(BotArgs),
+    Config(ConfigArgs),
+    Ping(PingArgs),
+    Du(DuArgs),
+    History(HistoryArgs),
+    Set(SetArgs),
+}
+
+/// update russh to the latest GitHub release.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "self-update")]
+pub struct SelfUpdateArgs {
+    /// only check whether a newer release is available.
+    #[argh(switch)]
+    pub check: bool,
+}
+
+/// print version, config and environment details for bug reports.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "info")]
+pub struct InfoArgs {
+    /// print the details as JSON.
+    #[argh(switch)]
+    pub json: bool,
+}
+
+/// approve another operator's run against protected hosts.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "approve")]
+pub struct ApproveArgs {
+    /// the request id printed by the refused run.
+    #[argh(positional)]
+    pub request_id: String,
+
+    /// approve without asking for confirmation.
+    #[argh(switch)]
+    pub yes: bool,
+}
+
+/// copy a local file or directory to every selected host in parallel.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "push")]
+pub struct PushArgs {
+    /// the local file or directory to copy.
+    #[argh(positional)]
+    pub local: PathBuf,
+
+    /// where to put it on each host.
+    #[argh(positional)]
+    pub remote: String,
+}
+
+/// upload an artifact to a temporary directory on every selected host,
+/// verify it, run the install command and roll back on failure.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "deploy")]
+pub struct DeployArgs {
+    /// the local file (or directory) to deploy.
+    #[argh(positional)]
+    pub artifact: PathBuf,
+
+    /// the command that installs it; the uploaded path is in $ARTIFACT.
+    #[argh(option)]
+    pub install_cmd: String,
+
+    /// optional: run if the install fails, with the same $ARTIFACT.
+    #[argh(option)]
+    pub rollback_cmd: Option<String>,
+}
+
+/// run a task saved in the config's [tasks]: its commands in order on each
+/// host, stopping a host at its first failure. Lists the tasks without a
+/// name.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "task")]
+pub struct TaskArgs {
+    /// the task to run.
+    #[argh(positional)]
+    pub name: Option<String>,
+}
+
+/// show what each selected host is (OS, kernel, architecture, CPUs,
+/// memory), from the facts cache or gathered over ssh.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "facts")]
+pub struct FactsArgs {
+    /// print the facts as JSON.
+    #[argh(switch)]
+    pub json: bool,
+}
+
+/// scan the selected hosts' keys with ssh-keyscan and pin them in russh's
+/// known_hosts file, so first connections don't stop to ask.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "known-hosts")]
+pub struct KnownHostsArgs {
+    /// pin a host's new key even if it differs from the pinned one.
+    #[argh(switch)]
+    pub replace: bool,
+}
+
+/// serve an HTTP API on a local port for listing the inventory, starting
+/// runs and following them.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "serve")]
+pub struct ServeArgs {
+    /// address to listen on (default 127.0.0.1:7878).
+    #[argh(option)]
+    pub listen: Option<SocketAddr>,
+}
+
+/// print the selected hosts after config resolution: user, port, groups,
+/// tags and the ssh options they get.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+pub struct ListArgs {}
+
+/// answer allowlisted commands from the Matrix room or Slack channel in
+/// the config's [bot] section.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "bot")]
+pub struct BotArgs {}
+
+/// change the config file's inventory without hand-editing it.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "config")]
+pub struct ConfigArgs {
+    #[argh(subcommand)]
+    pub action: ConfigAction,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum ConfigAction {
+    AddServer(AddServerArgs),
+    RemoveServer(RemoveServerArgs),
+    Edit(EditArgs),
+    Schema(SchemaArgs),
+}
+
+/// add a [[servers]] entry, keeping the rest of the file as it is.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "add-server")]
+pub struct AddServerArgs {
+    /// the host name or address.
+    #[argh(positional)]
+    pub host: String,
+    /// user to log in as.
+    #[argh(option)]
+    pub user: Option<String>,
+    /// ssh port.
+    #[argh(option)]
+    pub port: Option<u16>,
+    /// group to add it to, created if missing; repeatable.
+    #[argh(option)]
+    pub group: Vec<String>,
+    /// tag to give it; repeatable.
+    #[argh(option)]
+    pub tag: Vec<String>,
+    /// host(s) to reach it through, as for ssh -J.
+    #[argh(option)]
+    pub jump_host: Option<String>,
+}
+
+/// remove a server and every mention of it (tags, group memberships,
+/// per-host settings) from the config.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "remove-server")]
+pub struct RemoveServerArgs {
+    /// the server to remove.
+    #[argh(positional)]
+    pub host: String,
+}
+
+/// open the config in $VISUAL or $EDITOR, saving it only once it loads.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "edit")]
+pub struct EditArgs {}
+
+/// print a JSON Schema of the config format, for editors and validators.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "schema")]
+pub struct SchemaArgs {}
+
+/// save, list and delete named host sets for `--set`.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "set")]
+pub struct SetArgs {
+    #[argh(subcommand)]
+    pub action: SetAction,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum SetAction {
+    Save(SaveSetArgs),
+    List(ListSetsArgs),
+    Delete(DeleteSetArgs),
+}
+
+/// save the hosts picked by -H, --group, --set, --hosts, --exclude and
+/// --tags (given before `set`), or those that failed in the last run, as
+/// a named set.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "save")]
+pub struct SaveSetArgs {
+    /// the set's name; saving under an existing name replaces that set.
+    #[argh(positional)]
+    pub name: String,
+    /// save the hosts that failed in the last run.
+    #[argh(switch)]
+    pub from_last_failed: bool,
+}
+
+/// list the saved host sets.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "list")]
+pub struct ListSetsArgs {
+    /// print the sets as JSON.
+    #[argh(switch)]
+    pub json: bool,
+}
+
+/// delete a saved host set.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "delete")]
+pub struct DeleteSetArgs {
+    /// the set to delete.
+    #[argh(positional)]
+    pub name: String,
+}
+
+/// list earlier runs with their labels, newest last.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "history")]
+pub struct HistoryArgs {
+    /// only list runs with this `KEY=VALUE` label (repeatable, all must
+    /// match).
+    #[argh(option)]
+    pub label: Vec<Label>,
+
+    /// how many runs to list (default 20).
+    #[argh(option, default = "20")]
+    pub limit: usize,
+
+    /// print the runs as JSON.
+    #[argh(switch)]
+    pub json: bool,
+}
+
+/// check that every selected host can be logged into, with how long it
+/// takes.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ping")]
+pub struct PingArgs {
+    /// seconds a host gets to answer (default 10).
+    #[argh(option)]
+    pub timeout: Option<u64>,
+}
+
+/// show how full the disks of every selected host are.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "du")]
+pub struct DuArgs {
+    /// the path to measure with du; its filesystem is the one shown
+    /// (default: / and no du).
+    #[argh(positional)]
+    pub path: Option<String>,
+
+    /// percent full at which a host is flagged (default 90).
+    #[argh(option, default = "du::DEFAULT_THRESHOLD")]
+    pub threshold: u8,
+}
+
+/// print where russh reads its config and writes its state.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "paths")]
+pub struct PathsArgs {
+    /// print the locations as JSON.
+    #[argh(switch)]
+    pub json: bool,
+}
+
+/// run a local script on every selected host, piped to its interpreter
+/// (the script's #! line, or sh) on the host's stdin.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "script")]
+pub struct ScriptArgs {
+    /// the local script to run.
+    #[argh(positional)]
+    pub path: PathBuf,
+
+    /// arguments for the script, as $1, $2...
+    #[argh(positional, greedy)]
+    pub args: Vec<String>,
+}
+
+/// run the steps of a runbook file on every selected host, each step once
+/// the steps it needs have succeeded on that host.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "runbook")]
+pub struct RunbookArgs {
+    /// the runbook: [[steps]] tables with a name, a command and the steps
+    /// it needs.
+    #[argh(positional)]
+    pub path: PathBuf,
+
+    /// print the steps by stage, the hosts and the estimated duration from
+    /// the last run, without running anything.
+    #[argh(switch)]
+    pub plan: bool,
+}
+
+/// let a runbook waiting at a manual gate continue, or list the gates
+/// runs are waiting at.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "gate")]
+pub struct GateArgs {
+    /// the gate id printed by the waiting run.
+    #[argh(positional)]
+    pub id: Option<String>,
+
+    /// skip the gated step (and the steps after it) instead.
+    #[argh(switch)]
+    pub abort: bool,
+}
+
+/// retire a host: run the farewell command on it, remove it from the
+/// config, forget its host key and archive what the state directory holds
+/// on it.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "decommission")]
+pub struct DecommissionArgs {
+    /// the host to retire, as named in the config.
+    #[argh(positional)]
+    pub host: String,
+
+    /// the command to run on the host first (default: the config's
+    /// decommission_command).
+    #[argh(option)]
+    pub farewell: Option<String>,
+
+    /// don't run a farewell command, e.g. because the host is already gone.
+    #[argh(switch)]
+    pub no_farewell: bool,
+
+    /// decommission without asking for confirmation.
+    #[argh(switch)]
+    pub yes: bool,
+}
+
+/// fetch a file or directory from every selected host into
+/// <local-dir>/<hostname>/.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "pull")]
+pub struct PullArgs {
+    /// the path to fetch on each host.
+    #[argh(positional)]
+    pub remote: String,
+
+    /// the local directory to collect the per-host copies in.
+    #[argh(positional)]
+    pub local_dir: PathBuf,
+}
+
+/// run the previous run's failed commands again on the hosts they failed on.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "rerun-failed")]
+pub struct RerunFailedArgs {}
+
+/// keep checking that every selected host is reachable over SSH and report
+/// hosts that go down or recover.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "monitor")]
+pub struct MonitorArgs {
+    /// seconds between checks (default 60).
+    #[argh(option, default = "60")]
+    pub interval: u64,
+
+    /// seconds to wait for each connection (default 10).
+    #[argh(option, default = "10")]
+    pub connect_timeout: u64,
+
+    /// also send desktop notifications for hosts going down or recovering.
+    #[argh(switch)]
+    pub desktop: bool,
+
+    /// check once, print the table and exit.
+    #[argh(switch)]
+    pub once: bool,
+}
+
+/// add hosts defined elsewhere to the russh config.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "import")]
+pub struct ImportArgs {
+    #[argh(subcommand)]
+    pub source: ImportSource,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+pub enum ImportSource {
+    SshConfig(ImportSshConfigArgs),
+}
+
+/// add the Host entries of an OpenSSH client config as [[servers]].
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "ssh-config")]
+pub struct ImportSshConfigArgs {
+    /// the ssh config to read (default ~/.ssh/config).
+    #[argh(option)]
+    pub path: Option<PathBuf>,
+}
+
+/// retry commands queued for hosts that were unreachable.
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand, name = "flush-queue")]
+pub struct FlushQueueArgs {
+    /// skip entries queued more than this many hours ago (default 24).
+    #[argh(option, default = "24")]
+    pub max_age: u64,
+
+    /// also run entries older than --max-age.
+    #[argh(switch)]
+    pub include_stale: bool,
+
+    /// run destructive commands (reboot, rm -rf, ...) without asking.
+    #[argh(switch)]
+    pub yes: bool,
+}
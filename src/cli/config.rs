@@ -0,0 +1,87 @@
+use super::{config_path, load_config, Cli, ConfigAction, ConfigArgs, ImportArgs, ImportSource};
+use crate::config::{self, ssh_config, Host};
+use crate::errors::{AppError, ConfigError, ExecutionError, Result};
+
+/// `russh config`: edits the config file in place.
+pub fn manage(cli: &Cli, args: &ConfigArgs) -> Result<()> {
+    if let ConfigAction::Schema(_) = args.action {
+        let schema = serde_json::to_string_pretty(&config::schema())
+            .map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", schema);
+        return Ok(());
+    }
+    let path = config_path(&cli.config_file)?;
+    match &args.action {
+        ConfigAction::AddServer(args) => {
+            let host = Host {
+                user: args.user.clone(),
+                port: args.port,
+                tags: args.tag.clone(),
+                jump_host: args.jump_host.clone(),
+                ..Host::new(&args.host)
+            };
+            if config::add_servers(&path, &[host])?.is_empty() {
+                return Err(ExecutionError::Refused(format!(
+                    "{} is already in {}",
+                    args.host,
+                    path.display()
+                ))
+                .into());
+            }
+            if !args.group.is_empty() {
+                config::add_to_groups(&path, &args.host, &args.group)?;
+            }
+            println!("Added {} to {}", args.host, path.display());
+        }
+        ConfigAction::RemoveServer(args) => {
+            if !config::remove_server(&path, &args.host)? {
+                return Err(ExecutionError::Refused(format!(
+                    "{} is not in {}",
+                    args.host,
+                    path.display()
+                ))
+                .into());
+            }
+            println!("Removed {} from {}", args.host, path.display());
+        }
+        ConfigAction::Edit(_) => match config::edit(&path)? {
+            true => println!("Saved {}", path.display()),
+            false => println!("No changes to {}", path.display()),
+        },
+        ConfigAction::Schema(_) => {}
+    }
+    Ok(())
+}
+
+/// `russh import ssh-config`: writes the hosts of an ssh config into the
+/// russh config file.
+pub fn import(cli: &Cli, args: &ImportArgs) -> Result<()> {
+    let ImportSource::SshConfig(source) = &args.source;
+    let ssh_config = source
+        .path
+        .clone()
+        .or_else(ssh_config::default_path)
+        .ok_or_else(|| {
+            AppError::Generic("no home directory to find ~/.ssh/config in".to_string())
+        })?;
+    let hosts = ssh_config::load(&ssh_config)?;
+    let config = load_config(&cli.config_file, false)?;
+    let path = config.path.ok_or(ConfigError::NotFound)?;
+    let added = config::add_servers(&path, &hosts)?;
+    if added.is_empty() {
+        println!(
+            "All {} hosts in {} are already in {}",
+            hosts.len(),
+            ssh_config.display(),
+            path.display()
+        );
+    } else {
+        println!(
+            "Imported {} hosts into {}: {}",
+            added.len(),
+            path.display(),
+            added.join(", ")
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,501 @@
+//! The command-line interface: its arguments, and what the subcommands
+//! share to pick hosts and set up a run. Each subcommand's body lives with
+//! the module it drives (`russh deploy` in `deploy`, and so on); the
+//! `russh` binary only parses the arguments and dispatches.
+
+use crate::answers::Answer;
+use crate::capability::{self, Capability};
+use crate::config::{
+    check_host_name, find_config_in_cwd, find_config_in_user_dir, host_specs,
+    prompt_create_default_config, read_config, Config, Host,
+};
+use crate::errors::{AppError, ConfigError, ExecutionError, InventoryError, Result};
+use crate::executor::{parallelism, Display, Schedule};
+use crate::i18n::{tr, trf, Msg};
+use crate::lock::{self, RunLock};
+use crate::output::OutputFormat;
+use crate::sink::{self, ResultSink, Sinks};
+use crate::ssh::{client, Backend, EnvVar, ExecOptions, ServerResult};
+use crate::warnings::{warn, WarningKind};
+use crate::webhook::Webhook;
+use crate::{approval, askpass, auth, escalation, expect, filter, history, pick, sets};
+use ansi_term::Color::{Blue, Green, Red};
+use std::fs;
+use std::io::{self, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod args;
+pub mod config;
+pub mod run;
+pub mod task;
+
+pub use args::*;
+
+/// Loads the configuration. Ad-hoc `-H` runs don't need a config file, so
+/// with `optional` set a missing file yields an empty config instead of a prompt.
+pub(crate) fn load_config(config_file: &Option<String>, optional: bool) -> Result<Config> {
+    if optional
+        && config_file.is_none()
+        && find_config_in_cwd().is_none()
+        && find_config_in_user_dir().is_none()
+    {
+        return Ok(Config::default());
+    }
+
+    let config_path = config_path(config_file)?;
+    let config_path_str = config_path
+        .to_str()
+        .ok_or_else(|| ConfigError::InvalidPath(config_path.clone()))?;
+
+    read_config(config_path_str)
+}
+
+/// The config file to use: `-c`, else `russh.toml` in the current
+/// directory, else the user's, offering to create that one.
+pub(crate) fn config_path(config_file: &Option<String>) -> Result<PathBuf> {
+    Ok(match config_file {
+        Some(path) => PathBuf::from(path),
+        None => find_config_in_cwd()
+            .or_else(|| {
+                let path = find_config_in_user_dir()?;
+                warn(
+                    WarningKind::ConfigFallback,
+                    format!(
+                        "no russh.toml in the current directory, using {}",
+                        path.display()
+                    ),
+                );
+                Some(path)
+            })
+            .or_else(|| match prompt_create_default_config() {
+                Ok(Some(path)) => Some(path),
+                Ok(None) => None, // User chose not to create a config
+                Err(e) => {
+                    eprintln!("{}", trf(Msg::ConfigCreationError, &[&e]));
+                    None
+                }
+            })
+            .ok_or(ConfigError::NotFound)?,
+    })
+}
+
+/// The host specs in a `--hosts-file`, or on stdin for `-`. A file without
+/// any is an error rather than an empty selection, which would mean every
+/// configured server, and so is a host ssh would take for an option.
+pub fn read_hosts_file(path: &Path) -> Result<Vec<String>> {
+    let text = if path == Path::new("-") {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            file: path.to_path_buf(),
+            source,
+        })?
+    };
+    let specs = host_specs(&text);
+    if specs.is_empty() {
+        return Err(ExecutionError::Refused(format!(
+            "{} lists no hosts; not running on every configured server instead",
+            match path == Path::new("-") {
+                true => "stdin".to_string(),
+                false => path.display().to_string(),
+            }
+        ))
+        .into());
+    }
+    for spec in &specs {
+        check_host_name(spec.split_whitespace().next().unwrap_or_default())?;
+    }
+    Ok(specs)
+}
+
+/// Loads the config and picks the hosts selected on the command line.
+pub(crate) fn inventory(cli: &Cli) -> Result<(Config, Vec<Host>)> {
+    let optional = !cli.hosts.is_empty() || !cli.sets.is_empty();
+    let mut config = load_config(&cli.config_file, optional)?;
+    config.expand_groups();
+    let hosts = filter_hosts(
+        cli,
+        resolve_hosts(&config, &cli.hosts, &cli.groups, &cli.sets)?,
+    )?;
+    Ok((config, hosts))
+}
+
+/// Applies `--hosts`, `--exclude` and `--tags`, then asks which of the
+/// remaining hosts to keep with `--pick`.
+pub(crate) fn filter_hosts(cli: &Cli, hosts: Vec<Host>) -> Result<Vec<Host>> {
+    let hosts = narrow_hosts(cli, hosts)?;
+    match cli.pick {
+        true => pick::pick(hosts),
+        false => Ok(hosts),
+    }
+}
+
+fn narrow_hosts(cli: &Cli, hosts: Vec<Host>) -> Result<Vec<Host>> {
+    if cli.only.is_empty() && cli.exclude.is_empty() && cli.tags.is_empty() {
+        return Ok(hosts);
+    }
+    let mut hosts = filter::apply(hosts, &cli.only, &cli.exclude);
+    hosts.retain(|host| cli.tags.iter().all(|expr| expr.matches(&host.tags)));
+    if hosts.is_empty() {
+        let flags: Vec<&str> = [
+            (!cli.only.is_empty(), "--hosts"),
+            (!cli.exclude.is_empty(), "--exclude"),
+            (!cli.tags.is_empty(), "--tags"),
+        ]
+        .into_iter()
+        .filter_map(|(used, flag)| used.then_some(flag))
+        .collect();
+        let flags = match flags.split_last() {
+            Some((last, [])) => last.to_string(),
+            Some((last, rest)) => format!("{} and {}", rest.join(", "), last),
+            None => String::new(),
+        };
+        return Err(InventoryError::NoneSelected(flags).into());
+    }
+    Ok(hosts)
+}
+
+/// Picks the hosts to run against: ad-hoc `-H` specs, groups and saved sets
+/// when given, otherwise every configured server. Inline attributes
+/// override the configured ones.
+pub(crate) fn resolve_hosts(
+    config: &Config,
+    specs: &[String],
+    groups: &[String],
+    set_names: &[String],
+) -> Result<Vec<Host>> {
+    let candidates = if specs.is_empty() && groups.is_empty() && set_names.is_empty() {
+        config.hosts()
+    } else {
+        let mut candidates = specs
+            .iter()
+            .map(|spec| {
+                let host: Host = spec.parse()?;
+                let base = config.host(&host.host);
+                Ok(host.with_defaults(&base))
+            })
+            .collect::<Result<Vec<Host>>>()?;
+        candidates.extend(config.group_hosts(groups)?);
+        candidates.extend(sets::hosts(config, set_names)?);
+        candidates
+    };
+
+    let mut hosts: Vec<Host> = Vec::with_capacity(candidates.len());
+    for host in candidates {
+        if hosts.iter().any(|h| h.host == host.host) {
+            warn(
+                WarningKind::HostSkipped,
+                format!("{} is listed more than once, running it once", host.host),
+            );
+            continue;
+        }
+        hosts.push(host);
+    }
+    Ok(hosts)
+}
+
+/// Retry pause when neither `--retry-delay` nor `retry_delay` is set.
+const DEFAULT_RETRY_DELAY: f64 = 1.0;
+
+/// Seconds running jobs get past `--deadline` without `--deadline-grace`.
+const DEFAULT_DEADLINE_GRACE: u64 = 30;
+
+/// When `--deadline` passes, counted from now.
+fn deadline(cli: &Cli) -> Result<Option<Instant>> {
+    match cli.deadline {
+        Some(0) => Err(ExecutionError::InvalidOption {
+            option: "--deadline",
+            reason: "must be at least 1 second".to_string(),
+        }
+        .into()),
+        deadline => Ok(deadline.map(|secs| Instant::now() + Duration::from_secs(secs))),
+    }
+}
+
+/// The config's `[env]`, sorted by name, with `--env` on top.
+fn env(cli: &Cli, config: &Config) -> Result<Vec<EnvVar>> {
+    let mut env = config
+        .env
+        .iter()
+        .map(|(name, value)| EnvVar::new(name, value))
+        .collect::<Result<Vec<EnvVar>>>()?;
+    env.sort_by(|a, b| a.name.cmp(&b.name));
+    for var in &cli.env {
+        env.retain(|v| v.name != var.name);
+        env.push(var.clone());
+    }
+    Ok(env)
+}
+
+pub(crate) fn exec_options(cli: &Cli, config: &Config) -> Result<ExecOptions> {
+    let retry_delay = cli
+        .retry_delay
+        .or(config.retry_delay)
+        .unwrap_or(DEFAULT_RETRY_DELAY);
+    let retry_delay =
+        Duration::try_from_secs_f64(retry_delay).map_err(|_| ExecutionError::InvalidOption {
+            option: "--retry-delay",
+            reason: format!(
+                "must be a non-negative number of seconds, got {}",
+                retry_delay
+            ),
+        })?;
+    let auth = auth::Handler::default();
+    if cli.ask_pass {
+        askpass::ask(&*auth)?;
+    }
+    let options = ExecOptions {
+        backend: cli.backend,
+        nice: cli.nice,
+        ionice: cli.ionice,
+        remote_timing: cli.remote_timing,
+        timeout: cli.timeout,
+        escalate: cli.escalate || cli.sudo,
+        become_password: if cli.sudo || config.servers.iter().any(|s| s.escalate == Some(true)) {
+            escalation::ask_password(&*auth)
+        } else {
+            None
+        },
+        answers: config
+            .answers
+            .iter()
+            .map(Answer::from_rule)
+            .chain(cli.answer.iter().cloned().map(Ok))
+            .collect::<Result<_>>()?,
+        expect: cli
+            .expect
+            .as_deref()
+            .map(expect::Script::load)
+            .transpose()?
+            .map(Arc::new),
+        retries: cli.retries.or(config.retries).unwrap_or_default(),
+        retry_delay,
+        cutoff: deadline(cli)?.map(|deadline| {
+            deadline + Duration::from_secs(cli.deadline_grace.unwrap_or(DEFAULT_DEADLINE_GRACE))
+        }),
+        auth,
+        stdin: None,
+        env: env(cli, config)?,
+        requires: None,
+    };
+    if options.expect.is_some() && !options.answers.is_empty() {
+        warn(
+            WarningKind::IgnoredOption,
+            "answers are not used with --expect, the script does the typing".to_string(),
+        );
+    }
+    if options.become_password.is_some() && !options.answers.is_empty() {
+        warn(
+            WarningKind::IgnoredOption,
+            "answers can't reach commands run with a sudo password, their stdin is closed"
+                .to_string(),
+        );
+    }
+    options.validate()?;
+    require_client(options.backend)?;
+    Ok(options)
+}
+
+/// What every subcommand acting on hosts goes through first: takes the run
+/// lock unless `--no-lock`, held until the returned guard is dropped, and
+/// refuses protected hosts without a matching `--approval`.
+pub(crate) fn admit(
+    cli: &Cli,
+    config: &Config,
+    hosts: &[Host],
+    commands: &[String],
+) -> Result<Option<RunLock>> {
+    let lock = if cli.no_lock {
+        None
+    } else {
+        Some(RunLock::acquire(
+            &lock::target(config, hosts),
+            cli.break_lock,
+        )?)
+    };
+    approval::check(hosts, commands, cli.approval.as_deref())?;
+    Ok(lock)
+}
+
+/// Checks once that there is an ssh to run, for the openssh backend.
+pub(crate) fn require_client(backend: Backend) -> Result<()> {
+    match backend {
+        Backend::OpenSsh => client::require(),
+        Backend::Native => Ok(()),
+    }
+}
+
+pub(crate) fn schedule(cli: &Cli, config: &Config) -> Result<Schedule> {
+    if cli.serial == Some(0) {
+        return Err(ExecutionError::InvalidOption {
+            option: "--serial",
+            reason: "must be at least 1".to_string(),
+        }
+        .into());
+    }
+    Ok(Schedule {
+        parallel: parallelism(cli.parallel, Some(config))?,
+        serial: cli.serial,
+        fail_fast: cli.fail_fast,
+        deadline: deadline(cli)?,
+        in_order: false,
+    })
+}
+
+/// The run's result sinks: `log_dir` or the `[archive]`, the `--report`
+/// files, the `[webhook]`, every one in `[sinks]` and, last, the console.
+pub(crate) fn sinks(cli: &Cli, config: &Config) -> Result<Sinks> {
+    let mut sinks: Vec<(String, Box<dyn ResultSink>)> = Vec::new();
+    let log_dir = cli.log_dir.as_ref().or(config.log_dir.as_ref()).cloned();
+    if let Some(archive) = &config.archive {
+        capability::check(Capability::Archive)?;
+        let archive = sink::Archive {
+            config: archive.clone(),
+            log_dir,
+        };
+        sinks.push(("archive".to_string(), Box::new(archive)));
+    } else if let Some(log_dir) = log_dir {
+        sinks.push(("log_dir".to_string(), Box::new(sink::LogDir(log_dir))));
+    }
+    for spec in &cli.report {
+        let name = format!("--report {}", spec.path.display());
+        sinks.push((name, Box::new(sink::ReportFile(spec.clone()))));
+    }
+    if let Some(webhook) = &config.webhook {
+        capability::check(Capability::Webhooks)?;
+        sinks.push((
+            "webhook".to_string(),
+            Box::new(Webhook::new(webhook.clone())),
+        ));
+    }
+    let mut names: Vec<&String> = config.sinks.keys().collect();
+    names.sort();
+    for name in names {
+        sinks.push((name.clone(), config.sinks[name].open()?));
+    }
+    let console = sink::Console {
+        format: cli.output,
+        group: cli.group_output,
+    };
+    sinks.push(("console".to_string(), Box::new(console)));
+    Ok(Sinks::start(sinks))
+}
+
+/// Records the run in the history and hands the results to the sinks,
+/// which print them last.
+pub(crate) fn report(cli: &Cli, results: &[(String, ServerResult)], sinks: Sinks) {
+    let labels = history::labels(&cli.label);
+    if let Err(e) = history::record(&labels, results) {
+        warn(
+            WarningKind::Delivery,
+            format!("could not record the run in the history: {}", e),
+        );
+    }
+    sinks.finish(results, &labels);
+}
+
+/// Picks the live display from `--output` and `--tui`.
+pub(crate) fn display_mode(cli: &Cli, config: &Config) -> Result<Display> {
+    if cli.progress && (cli.group_output || cli.tui || cli.output != OutputFormat::Text) {
+        warn(
+            WarningKind::IgnoredOption,
+            "--progress only shows with streamed text output".to_string(),
+        );
+    }
+    if cli.group_output {
+        if cli.tui || cli.output != OutputFormat::Text {
+            return Err(ExecutionError::InvalidOption {
+                option: "--group-output",
+                reason: "can't be combined with --tui or --output json".to_string(),
+            }
+            .into());
+        }
+        return Ok(Display::Quiet);
+    }
+    if !cli.tui {
+        return Ok(match cli.output {
+            OutputFormat::Text => Display::Lines {
+                collapse: !cli.no_collapse && config.collapse_repeats.unwrap_or(true),
+                progress: cli.progress,
+            },
+            OutputFormat::Json | OutputFormat::JsonHosts => Display::Quiet,
+        });
+    }
+    if !cfg!(feature = "tui") {
+        return Err(ExecutionError::InvalidOption {
+            option: "--tui",
+            reason: "this russh was built without the `tui` feature".to_string(),
+        }
+        .into());
+    }
+    if cli.output != OutputFormat::Text {
+        return Err(ExecutionError::InvalidOption {
+            option: "--tui",
+            reason: "can't be combined with --output json".to_string(),
+        }
+        .into());
+    }
+    if !io::stdout().is_terminal() {
+        return Err(AppError::Generic(tr(Msg::TerminalRequired).to_string()));
+    }
+    Ok(Display::Dashboard)
+}
+
+fn print_banner() {
+    println!("{}", Blue.paint("russh - Multi-Host SSH Client"));
+    println!("-----------------------------");
+    println!("{}", Green.paint("Author: Eric Tossell"));
+    println!(
+        "{}",
+        Red.paint("GitHub: https://github.com/erictossell/russh")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use argh::FromArgs;
+
+    #[test]
+    fn test_resolve_hosts_runs_each_host_once() {
+        let specs = vec![
+            "web01".to_string(),
+            "web02".to_string(),
+            "web01".to_string(),
+        ];
+        let hosts = resolve_hosts(&Config::default(), &specs, &[], &[]).unwrap();
+        let names: Vec<&str> = hosts.iter().map(|host| host.host.as_str()).collect();
+        assert_eq!(names, ["web01", "web02"]);
+    }
+
+    #[test]
+    fn test_read_hosts_file() {
+        let path = std::env::temp_dir().join(format!("russh-hosts-{}", std::process::id()));
+        fs::write(&path, "# fleet\nweb01 user=deploy\n/dc1/web02\n").unwrap();
+        assert_eq!(
+            read_hosts_file(&path).unwrap(),
+            ["web01 user=deploy", "web02"]
+        );
+        fs::write(&path, "web01\n-oProxyCommand=touch /tmp/pwned\n").unwrap();
+        assert!(read_hosts_file(&path).is_err());
+        fs::write(&path, "# nothing\n").unwrap();
+        assert!(read_hosts_file(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_parse() {
+        let cli = Cli::from_args(&["russh"], &["-H", "web01", "uptime"]).unwrap();
+        assert_eq!(cli.hosts, ["web01"]);
+        assert_eq!(cli.commands, ["uptime"]);
+        assert!(cli.subcommand.is_none());
+
+        let cli = Cli::from_args(&["russh"], &["ping", "--timeout", "3"]).unwrap();
+        assert_eq!(
+            cli.subcommand,
+            Some(Subcommand::Ping(PingArgs { timeout: Some(3) }))
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use super::{
+    admit, display_mode, exec_options, inventory, load_config, print_banner, report, schedule,
+    sinks, Cli,
+};
+use crate::config::{Config, Host};
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::executor::{execute_jobs, Job, Schedule};
+use crate::i18n::{tr, trf, Msg};
+use crate::output::OutputFormat;
+use crate::queue::{self, QueueEntry};
+use crate::report::{self, RunReport};
+use crate::runbook::Tracker;
+use crate::ssh::{format_command, run_ssh_command, Backend, Cut, ExecOptions, ServerResult};
+use crate::warnings::{warn, WarningKind};
+use crate::{approval, chain, clipboard, estimate, exit, history, interrupt, monitor, preflight};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, IsTerminal};
+use std::sync::Arc;
+
+/// `russh [COMMAND...]`: runs the commands on every selected host.
+pub fn commands(cli: &Cli) -> Result<()> {
+    let (config, hosts) = inventory(cli)?;
+    let options = exec_options(cli, &config)?;
+
+    if let Some(name) = &cli.print_command {
+        return print_command(&config, &hosts, name, &cli.commands, &options);
+    }
+
+    // Every job shares its host and command instead of owning copies. An
+    // expect script without a command gets the login shell.
+    let mut commands: Vec<Arc<str>> = cli.commands.iter().map(|c| c.as_str().into()).collect();
+    if commands.is_empty() && options.expect.is_some() {
+        commands.push("".into());
+    }
+    if cli.chain {
+        check_chain(cli, &hosts, &options)?;
+        commands = vec![chain::script(&cli.commands).into()];
+    }
+    let jobs = hosts
+        .into_iter()
+        .map(Arc::new)
+        .flat_map(|host| {
+            commands
+                .iter()
+                .map(move |command| (Arc::clone(&host), Arc::clone(command)))
+        })
+        .collect();
+    let schedule = schedule(cli, &config)?;
+    run(cli, &config, jobs, &options, schedule, None)
+}
+
+/// Runs ssh jobs with everything around them: lock, approval, preflight
+/// checks, the offline queue, the run report and the result sinks.
+pub fn run(
+    cli: &Cli,
+    config: &Config,
+    jobs: Vec<Job>,
+    options: &ExecOptions,
+    schedule: Schedule,
+    runbook: Option<Arc<Tracker>>,
+) -> Result<()> {
+    let mut hosts: Vec<Host> = Vec::new();
+    let mut commands: Vec<String> = Vec::new();
+    let mut seen = HashSet::new();
+    for (host, command) in &jobs {
+        if seen.insert(host.host.as_str()) {
+            hosts.push(Host::clone(host));
+        }
+        if !commands.iter().any(|c| c.as_str() == &**command) {
+            commands.push(command.to_string());
+        }
+    }
+
+    if cli.dry_run {
+        dry_run(&jobs, options, &schedule);
+        return Ok(());
+    }
+
+    let text = cli.output == OutputFormat::Text;
+    let display = display_mode(cli, config)?;
+    // Cron mail and CI logs get the output and summary, not the banner.
+    if text && io::stdout().is_terminal() {
+        print_banner();
+    }
+
+    // The approval covers what the commands are fed as well, e.g. a script.
+    commands.extend(options.stdin.as_deref().map(approval::input));
+    let _lock = admit(cli, config, &hosts, &commands)?;
+
+    let posix_only = options.posix_only_flags();
+    if !posix_only.is_empty() {
+        for host in hosts.iter().filter(|host| host.shell().is_windows()) {
+            warn(
+                WarningKind::IgnoredOption,
+                format!(
+                    "{} ignored for Windows host {}",
+                    posix_only.join(", "),
+                    host.host
+                ),
+            );
+        }
+    }
+
+    let selected = hosts.len();
+    let hosts = preflight::filter_hosts(hosts, &cli.require, options);
+    exit::record_left_out(selected - hosts.len());
+    let remaining: HashSet<&str> = hosts.iter().map(|h| h.host.as_str()).collect();
+    let jobs: Vec<Job> = jobs
+        .into_iter()
+        .filter(|(host, _)| remaining.contains(host.host.as_str()))
+        .collect();
+    if let Some(runbook) = &runbook {
+        runbook.retain_hosts(&remaining);
+    }
+
+    let sinks = sinks(cli, config)?;
+
+    if text {
+        if let Some(estimate) = estimated(&jobs, &schedule) {
+            println!("{}", estimate);
+        }
+        println!("{}", tr(Msg::ProcessingCommands));
+    }
+    let run_options = Arc::new(options.clone());
+    let retry = cli.retry_unreachable && runbook.is_none();
+    if cli.retry_unreachable && runbook.is_some() {
+        warn(
+            WarningKind::IgnoredOption,
+            "--retry-unreachable is not used for runbooks".to_string(),
+        );
+    }
+    let job = move |host: Arc<Host>, command: Arc<str>, tx| {
+        let options = Arc::clone(&run_options);
+        let runbook = runbook.clone();
+        async move {
+            let job = run_ssh_command(&host, &command, &options, tx);
+            match runbook {
+                Some(runbook) => runbook.run(&host, &command, job).await,
+                None => job.await,
+            }
+        }
+    };
+    let mut results = execute_jobs(jobs, job.clone(), Some(&sinks), display.clone(), schedule);
+    if retry {
+        let retries = unreachable_jobs(&hosts, &results, schedule);
+        if !retries.is_empty() && !interrupt::interrupted() && !options.cut_off() {
+            if text {
+                println!(
+                    "Retrying {} command(s) on hosts that couldn't be reached...",
+                    retries.len()
+                );
+            }
+            let retried = execute_jobs(retries, job, Some(&sinks), display, schedule);
+            merge_retries(&mut results, retried);
+        }
+    }
+    if cli.chain {
+        results = chain::split_results(&cli.commands, results);
+    }
+
+    if cli.queue_unreachable {
+        queue_unreachable(&hosts, &results, options)?;
+    }
+    let run_report = RunReport::new(&hosts, &results).with_labels(history::labels(&cli.label));
+    if let Err(e) = report::save(&run_report) {
+        warn(
+            WarningKind::Delivery,
+            format!("could not save the run report: {}", e),
+        );
+    }
+    if let Err(e) = estimate::record(&results) {
+        warn(
+            WarningKind::Delivery,
+            format!("could not record the run's durations: {}", e),
+        );
+    }
+
+    // Final summary or any other post-processing can be done here
+    report(cli, &results, sinks);
+    if let Some(target) = cli.copy {
+        if let Err(e) = clipboard::copy(&clipboard::render(target, &results)) {
+            warn(
+                WarningKind::Delivery,
+                format!("could not copy to the clipboard: {}", e),
+            );
+        }
+    }
+    if text {
+        println!("{}", tr(Msg::ExecutionCompleted));
+    }
+
+    Ok(())
+}
+
+/// `russh rerun-failed`: runs the last run's failed commands again, on the
+/// hosts they failed on.
+pub fn rerun_failed(cli: &Cli) -> Result<()> {
+    let jobs: Vec<Job> = report::load_last()?
+        .failed_jobs()
+        .into_iter()
+        .map(|(host, command)| (Arc::new(host), command.into()))
+        .collect();
+    if jobs.is_empty() {
+        println!("Nothing failed in the last run.");
+        return Ok(());
+    }
+    let config = load_config(&cli.config_file, true)?;
+    let options = exec_options(cli, &config)?;
+    run(cli, &config, jobs, &options, schedule(cli, &config)?, None)
+}
+
+/// Prints the ssh invocation for one host so it can be reproduced by hand.
+fn print_command(
+    config: &Config,
+    hosts: &[Host],
+    name: &str,
+    commands: &[String],
+    options: &ExecOptions,
+) -> Result<()> {
+    let host = hosts
+        .iter()
+        .find(|h| h.host == name)
+        .cloned()
+        .unwrap_or_else(|| config.host(name));
+    if commands.is_empty() {
+        return Err(AppError::Generic(
+            "no command given to print the ssh invocation for".to_string(),
+        ));
+    }
+    for command in commands {
+        println!(
+            "{}",
+            format_command("ssh", &options.ssh_args(&host, command))
+        );
+    }
+    Ok(())
+}
+
+/// How long `jobs` should take with `schedule`, from earlier runs, as the
+/// line shown before a run.
+fn estimated(jobs: &[Job], schedule: &Schedule) -> Option<String> {
+    let history = estimate::History::load().ok()?;
+    let estimate = estimate::estimate(jobs, schedule, &history)?;
+    let settings = match schedule.serial {
+        Some(size) => format!("--serial {}", size),
+        None => format!("--parallel {}", schedule.parallel),
+    };
+    Some(format!(
+        "Estimated run time: about {} with {} (from earlier runs of {} of {} jobs)",
+        monitor::format_age(estimate.seconds.ceil() as u64),
+        settings,
+        estimate.known,
+        estimate.jobs
+    ))
+}
+
+/// `--dry-run`: prints the ssh invocation of every job, in the order the
+/// jobs would start.
+fn dry_run(jobs: &[Job], options: &ExecOptions, schedule: &Schedule) {
+    if options.backend != Backend::OpenSsh {
+        eprintln!("The native backend runs these over libssh2, not the ssh client.");
+    }
+    if let Some(stdin) = &options.stdin {
+        eprintln!("Each command is given {} bytes on stdin.", stdin.len());
+    }
+    for (host, command) in jobs {
+        println!(
+            "{}",
+            format_command("ssh", &options.ssh_args(host, command))
+        );
+    }
+    if let Some(estimate) = estimated(jobs, schedule) {
+        eprintln!("{}", estimate);
+    }
+}
+
+/// Whether `--chain` can run: its script needs a POSIX shell and its
+/// output, which an expect script would be typing into.
+fn check_chain(cli: &Cli, hosts: &[Host], options: &ExecOptions) -> Result<()> {
+    let reason = if options.expect.is_some() {
+        "can't be combined with --expect".to_string()
+    } else if let Some(host) = hosts.iter().find(|host| host.shell().is_windows()) {
+        format!("needs a POSIX shell, {} is a Windows host", host.host)
+    } else if cli.commands.is_empty() {
+        "needs commands to run".to_string()
+    } else {
+        return Ok(());
+    };
+    Err(ExecutionError::InvalidOption {
+        option: "--chain",
+        reason,
+    }
+    .into())
+}
+
+/// `--retry-unreachable`: the jobs to run once more at the end, those that
+/// couldn't reach their host and, in a task, the ones skipped after them.
+fn unreachable_jobs(
+    hosts: &[Host],
+    results: &[(String, ServerResult)],
+    schedule: Schedule,
+) -> Vec<Job> {
+    let unreachable: HashSet<&str> = results
+        .iter()
+        .filter(|(_, result)| result.unreachable())
+        .map(|(_, result)| result.server.as_str())
+        .collect();
+    let hosts: HashMap<&str, Arc<Host>> = hosts
+        .iter()
+        .filter(|host| unreachable.contains(host.host.as_str()))
+        .map(|host| (host.host.as_str(), Arc::new(host.clone())))
+        .collect();
+    results
+        .iter()
+        .filter(|(_, result)| {
+            result.unreachable()
+                || (schedule.in_order
+                    && result.cut == Some(Cut::AfterFailure)
+                    && unreachable.contains(result.server.as_str()))
+        })
+        .filter_map(|(command, result)| {
+            let host = hosts.get(result.server.as_str())?;
+            Some((Arc::clone(host), command.as_str().into()))
+        })
+        .collect()
+}
+
+/// Puts the results of retried jobs in place of their first attempts.
+fn merge_retries(results: &mut [(String, ServerResult)], retried: Vec<(String, ServerResult)>) {
+    let mut replaced = HashSet::new();
+    for (command, mut result) in retried {
+        let first = (0..results.len()).find(|i| {
+            let (c, r) = &results[*i];
+            !replaced.contains(i)
+                && *c == command
+                && r.server == result.server
+                && (r.unreachable() || r.cut == Some(Cut::AfterFailure))
+        });
+        if let Some(i) = first {
+            result.attempts += results[i].1.attempts;
+            results[i].1 = result;
+            replaced.insert(i);
+        }
+    }
+}
+
+/// Records the commands that failed because their host was unreachable.
+fn queue_unreachable(
+    hosts: &[Host],
+    results: &[(String, ServerResult)],
+    options: &ExecOptions,
+) -> Result<()> {
+    let entries: Vec<QueueEntry> = results
+        .iter()
+        .filter(|(_, result)| result.unreachable())
+        .filter_map(|(command, result)| {
+            let host = hosts.iter().find(|h| h.host == result.server)?;
+            Some(QueueEntry::new(host, command, options))
+        })
+        .collect();
+    if !entries.is_empty() {
+        println!("{}", trf(Msg::QueuedUnreachable, &[&entries.len()]));
+    }
+    queue::enqueue(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::SSH_CONNECTION_FAILED;
+
+    fn result(server: &str, exit_code: i32, attempts: u32) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            success: exit_code == 0,
+            exit_code: Some(exit_code),
+            attempts,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_merge_retries() {
+        let mut results = vec![
+            ("uptime".to_string(), result("web01", 0, 1)),
+            (
+                "uptime".to_string(),
+                result("web02", SSH_CONNECTION_FAILED, 2),
+            ),
+        ];
+        merge_retries(
+            &mut results,
+            vec![("uptime".to_string(), result("web02", 0, 1))],
+        );
+        assert!(results.iter().all(|(_, result)| result.success));
+        // The retry's attempts add to the first run's.
+        assert_eq!(results[1].1.attempts, 3);
+        assert_eq!(results[0].1.attempts, 1);
+    }
+}
@@ -0,0 +1,111 @@
+use super::run::run;
+use super::{exec_options, filter_hosts, load_config, resolve_hosts, schedule, Cli, TaskArgs};
+use crate::config::{Config, Host, Task};
+use crate::errors::{ExecutionError, InventoryError, Result};
+use crate::executor::Schedule;
+use crate::warnings::{warn, WarningKind};
+use std::sync::Arc;
+
+/// `russh task`: runs a saved task, or lists them.
+pub fn command(cli: &Cli, args: &TaskArgs) -> Result<()> {
+    let mut config = load_config(&cli.config_file, false)?;
+    config.expand_groups();
+    let Some(name) = &args.name else {
+        print_tasks(&config);
+        return Ok(());
+    };
+    let task = config.task(name)?;
+    if task.commands.is_empty() {
+        return Err(ExecutionError::Refused(format!("task '{}' has no commands", name)).into());
+    }
+    let hosts = task_hosts(cli, &config, name, task)?;
+    let commands: Vec<Arc<str>> = task.commands.iter().map(|c| c.as_str().into()).collect();
+    let jobs = hosts
+        .into_iter()
+        .map(Arc::new)
+        .flat_map(|host| {
+            commands
+                .iter()
+                .map(move |command| (Arc::clone(&host), Arc::clone(command)))
+        })
+        .collect();
+    let options = exec_options(cli, &config)?;
+    let schedule = Schedule {
+        in_order: true,
+        ..schedule(cli, &config)?
+    };
+    run(cli, &config, jobs, &options, schedule, None)
+}
+
+/// The hosts a task runs on: those picked on the command line, or else its
+/// own `hosts` and `groups`, which it never leaves.
+fn task_hosts(cli: &Cli, config: &Config, name: &str, task: &Task) -> Result<Vec<Host>> {
+    if task.hosts.is_empty() && task.groups.is_empty() {
+        return filter_hosts(
+            cli,
+            resolve_hosts(config, &cli.hosts, &cli.groups, &cli.sets)?,
+        );
+    }
+    let allowed = resolve_hosts(config, &task.hosts, &task.groups, &[])?;
+    if cli.hosts.is_empty() && cli.groups.is_empty() && cli.sets.is_empty() {
+        return filter_hosts(cli, allowed);
+    }
+    let picked = filter_hosts(
+        cli,
+        resolve_hosts(config, &cli.hosts, &cli.groups, &cli.sets)?,
+    )?;
+    let (inside, outside): (Vec<Host>, Vec<Host>) = picked
+        .into_iter()
+        .partition(|host| allowed.iter().any(|a| a.host == host.host));
+    for host in outside {
+        warn(
+            WarningKind::HostSkipped,
+            format!("task '{}' doesn't run on {}, skipping it", name, host.host),
+        );
+    }
+    if inside.is_empty() {
+        return Err(InventoryError::NoneSelected(format!(
+            "the hosts and groups of task '{}'",
+            name
+        ))
+        .into());
+    }
+    Ok(inside)
+}
+
+fn print_tasks(config: &Config) {
+    if config.tasks.is_empty() {
+        println!("No tasks configured; add them as [tasks.NAME] in russh.toml.");
+        return;
+    }
+    let mut names: Vec<&String> = config.tasks.keys().collect();
+    names.sort();
+    let width = names
+        .iter()
+        .map(|name| name.len())
+        .max()
+        .unwrap_or_default();
+    for name in names {
+        let task = &config.tasks[name];
+        let mut limits = Vec::new();
+        if !task.hosts.is_empty() {
+            limits.push(format!("hosts: {}", task.hosts.join(", ")));
+        }
+        if !task.groups.is_empty() {
+            limits.push(format!("groups: {}", task.groups.join(", ")));
+        }
+        let limits = match limits.is_empty() {
+            true => String::new(),
+            false => format!(" [{}]", limits.join("; ")),
+        };
+        println!(
+            "{:<width$}  {}{}",
+            name,
+            task.description
+                .clone()
+                .unwrap_or_else(|| task.commands.join(" && ")),
+            limits,
+            width = width
+        );
+    }
+}
@@ -0,0 +1,123 @@
+//! `--copy`: puts the failed hosts or the run summary on the system
+//! clipboard through whichever clipboard tool the platform has.
+
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::ssh::ServerResult;
+use crate::summary::{self, Status};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+/// What `--copy` puts on the clipboard.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyTarget {
+    FailedHosts,
+    Summary,
+}
+
+impl FromStr for CopyTarget {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "failed-hosts" => Ok(CopyTarget::FailedHosts),
+            "summary" => Ok(CopyTarget::Summary),
+            _ => Err(ExecutionError::InvalidOption {
+                option: "--copy",
+                reason: format!(
+                    "unknown target '{}', expected failed-hosts or summary",
+                    name
+                ),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Failed hosts as a comma-separated list, ready to paste into another run.
+pub fn failed_hosts(results: &[(String, ServerResult)]) -> String {
+    let mut hosts: Vec<&str> = Vec::new();
+    for (_, result) in results {
+        if Status::of(result) == Status::Failed && !hosts.contains(&result.server.as_str()) {
+            hosts.push(&result.server);
+        }
+    }
+    hosts.join(",")
+}
+
+pub fn render(target: CopyTarget, results: &[(String, ServerResult)]) -> String {
+    match target {
+        CopyTarget::FailedHosts => failed_hosts(results),
+        CopyTarget::Summary => summary::format_summary(results, false),
+    }
+}
+
+/// Clipboard tools to try, in order, as program and arguments.
+fn tools() -> &'static [(&'static str, &'static [&'static str])] {
+    if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(target_os = "windows") {
+        &[("clip", &[])]
+    } else {
+        &[
+            ("wl-copy", &[]),
+            ("xclip", &["-selection", "clipboard"]),
+            ("xsel", &["--clipboard", "--input"]),
+        ]
+    }
+}
+
+pub fn copy(text: &str) -> Result<()> {
+    for (program, args) in tools() {
+        let Ok(mut child) = Command::new(program)
+            .args(*args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        if child.wait()?.success() {
+            return Ok(());
+        }
+    }
+    let names: Vec<&str> = tools().iter().map(|(program, _)| *program).collect();
+    Err(AppError::Generic(format!(
+        "no working clipboard tool found (tried {})",
+        names.join(", ")
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(server: &str, success: bool) -> (String, ServerResult) {
+        (
+            "uptime".to_string(),
+            ServerResult {
+                server: server.to_string(),
+                duration: 0.1,
+                success,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_failed_hosts() {
+        let results = vec![
+            result("web01", true),
+            result("web02", false),
+            result("db01", false),
+            result("web02", false),
+        ];
+        assert_eq!(render(CopyTarget::FailedHosts, &results), "web02,db01");
+        assert!(render(CopyTarget::Summary, &results).contains("1 ok, 0 changed, 3 failed"));
+        assert!("everything".parse::<CopyTarget>().is_err());
+    }
+}
@@ -1,41 +1,1114 @@
-use serde::{Deserialize, Serialize};
+pub mod ssh_config;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
 
 // If you're using a custom Result type or error types from main.rs
-use crate::{AppError, Result};
+use crate::answers::AnswerRule;
+use crate::archive::ArchiveConfig;
+use crate::bot::BotConfig;
+use crate::crypto::CryptoPolicy;
+use crate::errors::{AppError, ConfigError, ExecutionError, InventoryError, Result};
+use crate::escalation::BecomeMethod;
+use crate::i18n::{is_yes, tr, trf, Msg};
+use crate::known_hosts::HostKeyPolicy;
+use crate::paths;
+use crate::prompt;
+use crate::sink::SinkConfig;
+use crate::warnings::{warn, WarningKind};
+use crate::webhook::WebhookConfig;
+use toml_edit::{ArrayOfTables, DocumentMut, Item, Table, Value};
+
+#[derive(Default, Serialize, Deserialize, JsonSchema)]
+pub struct Config {
+    /// `[[servers]]` tables; plain hostnames from the old
+    /// `servers = ["..."]` format are accepted as well.
+    #[serde(default, deserialize_with = "deserialize_servers")]
+    #[schemars(with = "Vec<ServerEntry>")]
+    pub servers: Vec<Host>,
+    /// Deprecated: set `options` on the `[[servers]]` entry instead.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub ssh_options: HashMap<String, String>,
+    /// Deprecated: set `user` on the `[[servers]]` entry instead.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub users: HashMap<String, String>,
+    #[serde(default)]
+    pub tags: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    pub groups: HashMap<String, Group>,
+    /// Per-host shell snippets run before every command, e.g.
+    /// `source /opt/app/env.sh`.
+    #[serde(default)]
+    pub pre_commands: HashMap<String, String>,
+    #[serde(default)]
+    pub webhook: Option<WebhookConfig>,
+    /// `[archive]`: upload every run's logs to an S3-compatible bucket.
+    #[serde(default)]
+    pub archive: Option<ArchiveConfig>,
+    /// `[bot]`: chat-ops from a Matrix room or Slack channel, see
+    /// `russh bot`.
+    #[serde(default)]
+    pub bot: Option<BotConfig>,
+    /// Jump host for every server without its own `jump_host`.
+    #[serde(default)]
+    pub bastion: Option<Bastion>,
+    /// `[auth]`: the key and agent use of servers without their own.
+    #[serde(default)]
+    pub auth: Option<AuthConfig>,
+    /// `[[answers]]` typed into interactive prompts; `--answer` adds more.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub answers: Vec<AnswerRule>,
+    /// `[tasks.NAME]`: named lists of commands for `russh task NAME`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub tasks: HashMap<String, Task>,
+    /// `[sinks.NAME]`: more places every run's results go to, on top of
+    /// `log_dir`, `[webhook]` and `[archive]`.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub sinks: HashMap<String, SinkConfig>,
+    /// Upper bound on jobs running at once; `--parallel` overrides it.
+    #[serde(default)]
+    pub max_parallel: Option<usize>,
+    /// Show runs of identical lines from a host as `line (xN)` while
+    /// streaming (default true); `--no-collapse` turns it off.
+    #[serde(default)]
+    pub collapse_repeats: Option<bool>,
+    /// Default `clean_env` for every host: start ssh with only the local
+    /// environment variables in `env_passthrough`.
+    #[serde(default)]
+    pub clean_env: Option<bool>,
+    /// Local variables kept by hosts with `clean_env`, on top of their own.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_passthrough: Vec<String>,
+    /// Default `host_key_policy` for every host: `strict`, `accept-new`,
+    /// `ask` or `insecure`.
+    #[serde(default)]
+    pub host_key_policy: Option<HostKeyPolicy>,
+    /// Default `crypto_policy` for every host: `modern`, `compat` or `fips`.
+    #[serde(default)]
+    pub crypto_policy: Option<CryptoPolicy>,
+    /// Seconds cached host facts stay valid.
+    #[serde(default)]
+    pub facts_ttl: Option<u64>,
+    /// Retries for a failed command; `--retries` overrides it.
+    #[serde(default)]
+    pub retries: Option<u32>,
+    /// Seconds before the first retry, doubling after that; `--retry-delay`
+    /// overrides it.
+    #[serde(default)]
+    pub retry_delay: Option<f64>,
+    /// Also read the hosts in `~/.ssh/config` into `servers`.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub import_ssh_config: bool,
+    /// Run on a host by `russh decommission` before it is removed, e.g. to
+    /// deregister it from monitoring; `--farewell` overrides it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decommission_command: Option<String>,
+    /// Where each run writes a directory of per-host logs; `--log-dir`
+    /// overrides it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_dir: Option<PathBuf>,
+    /// `[env]`: variables exported on every host before each command;
+    /// `--env` adds to and overrides them.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub env: HashMap<String, String>,
+    /// Where this config was read from, if it came from a file.
+    #[serde(skip)]
+    pub path: Option<PathBuf>,
+    // Add other configuration fields here
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum ServerEntry {
+    Name(String),
+    Table(Box<Host>),
+}
+
+fn deserialize_servers<'de, D>(deserializer: D) -> std::result::Result<Vec<Host>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let entries = Vec::<ServerEntry>::deserialize(deserializer)?;
+    Ok(entries
+        .into_iter()
+        .map(|entry| match entry {
+            ServerEntry::Name(name) => Host::new(&name),
+            ServerEntry::Table(host) => *host,
+        })
+        .collect())
+}
+
+/// A named set of servers. Membership is the static `members` list plus
+/// whatever `members_command` prints (one host per line) at run start.
+/// A plain list (`web = ["web01", "web02"]`) is shorthand for `members`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(from = "GroupSpec")]
+pub struct Group {
+    #[serde(default)]
+    pub members: Vec<String>,
+    pub members_command: Option<String>,
+    /// Prepended to every command run on the group's members.
+    pub pre_command: Option<String>,
+}
+
+/// A saved list of commands, run in order on each host by `russh task`.
+/// `hosts` and `groups` limit where it may run; without them it runs
+/// wherever the command line points it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Task {
+    pub description: Option<String>,
+    pub commands: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hosts: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<String>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum GroupSpec {
+    List(Vec<String>),
+    Table {
+        #[serde(default)]
+        members: Vec<String>,
+        members_command: Option<String>,
+        pre_command: Option<String>,
+    },
+}
 
-impl From<toml::de::Error> for AppError {
-    fn from(err: toml::de::Error) -> Self {
-        AppError::TomlDeserializationError(err)
+impl From<GroupSpec> for Group {
+    fn from(spec: GroupSpec) -> Self {
+        match spec {
+            GroupSpec::List(members) => Group {
+                members,
+                ..Default::default()
+            },
+            GroupSpec::Table {
+                members,
+                members_command,
+                pre_command,
+            } => Group {
+                members,
+                members_command,
+                pre_command,
+            },
+        }
     }
 }
 
-impl From<toml::ser::Error> for AppError {
-    fn from(err: toml::ser::Error) -> Self {
-        AppError::TomlSerializationError(err)
+/// Runs a group's `members_command` locally and returns the hosts it printed.
+fn run_members_command(group: &str, command: &str) -> Result<Vec<String>> {
+    let failed = |reason: String| InventoryError::MembersCommand {
+        group: group.to_string(),
+        reason,
+    };
+    let output = if cfg!(target_os = "windows") {
+        Command::new("cmd").args(["/C", command]).output()
+    } else {
+        Command::new("sh").args(["-c", command]).output()
     }
+    .map_err(|e| failed(format!("could not be started: {}", e)))?;
+    if !output.status.success() {
+        return Err(failed(format!(
+            "exited with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ))
+        .into());
+    }
+    let members: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    for member in &members {
+        if let Err(reason) = host_name_error(member) {
+            return Err(failed(format!("printed '{}', which {}", member, reason)).into());
+        }
+    }
+    Ok(members)
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct Config {
-    pub servers: Vec<String>,
-    pub ssh_options: HashMap<String, String>,
-    pub users: HashMap<String, String>,
-    // Add other configuration fields here
+/// Why `name` can't be a host name, if it can't. ssh reads a name starting
+/// with `-` as an option (`-oProxyCommand=...` would run a local command),
+/// and whitespace or control characters never belong in one.
+fn host_name_error(name: &str) -> std::result::Result<(), &'static str> {
+    if name.starts_with('-') {
+        Err("can't start with '-'")
+    } else if name.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        Err("can't contain whitespace or control characters")
+    } else {
+        Ok(())
+    }
+}
+
+/// Checks a host name that came from outside the config, e.g. a
+/// `--hosts-file`, before it gets anywhere near ssh's command line.
+pub fn check_host_name(name: &str) -> Result<()> {
+    host_name_error(name).map_err(|reason| {
+        InventoryError::HostSpec {
+            spec: name.to_string(),
+            reason: reason.to_string(),
+        }
+        .into()
+    })
+}
+
+/// `[bastion]`: the jump box servers are reached through by default.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Bastion {
+    pub host: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl Bastion {
+    /// `[user@]host[:port]`, with IPv6 addresses bracketed.
+    pub fn spec(&self) -> String {
+        let mut spec = String::new();
+        if let Some(user) = self.user.as_ref().filter(|u| !u.is_empty()) {
+            spec.push_str(user);
+            spec.push('@');
+        }
+        match self.port {
+            Some(port) if self.host.contains(':') => {
+                spec.push_str(&format!("[{}]:{}", self.host, port))
+            }
+            Some(port) => spec.push_str(&format!("{}:{}", self.host, port)),
+            None => spec.push_str(&self.host),
+        }
+        spec
+    }
+}
+
+/// `[auth]`: defaults for how servers authenticate.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct AuthConfig {
+    /// Private key for every server without its own `identity_file`.
+    pub identity_file: Option<PathBuf>,
+    /// Default `use_agent` for every server.
+    pub use_agent: Option<bool>,
+}
+
+/// A single target host with everything needed to connect to it.
+///
+/// Hosts are resolved from the configuration file or parsed from an inline
+/// spec on the command line, e.g. `web01 user=deploy port=2222 tag=canary`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct Host {
+    pub host: String,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    /// Private key to authenticate with instead of ssh's defaults.
+    pub identity_file: Option<PathBuf>,
+    /// Offer the keys in the SSH agent (the default); with `false` only
+    /// `identity_file` and ssh's default keys are tried.
+    pub use_agent: Option<bool>,
+    /// Extra ssh arguments as one string, split the way a shell would:
+    /// `-o ServerAliveInterval=30 -4`.
+    pub options: Option<String>,
+    /// Extra ssh arguments, one per element, passed as they are:
+    /// `["-o", "ServerAliveInterval=30"]`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub extra_options: Vec<String>,
+    /// Host(s) to reach this one through, as for `ssh -J`:
+    /// `[user@]host[:port]`, comma-separated for a chain, or `none` to
+    /// bypass the `[bastion]`.
+    pub jump_host: Option<String>,
+    /// Seconds a command may run on this host before it is killed.
+    pub timeout: Option<u64>,
+    /// What the host's sshd runs commands with; `cmd` or `powershell` for
+    /// Windows hosts.
+    pub shell: Option<Shell>,
+    /// Always escalate on this host (`become = true`), or never with
+    /// `become = false`, whatever `--become` says.
+    #[serde(rename = "become")]
+    pub escalate: Option<bool>,
+    /// How to escalate privileges on this host with `--become`.
+    pub become_method: Option<BecomeMethod>,
+    /// Who to become; root when unset.
+    pub become_user: Option<String>,
+    /// Start ssh for this host with only the local environment variables
+    /// in `env_passthrough` (and `HOME`, `PATH`, `USER`, `LOGNAME`).
+    pub clean_env: Option<bool>,
+    /// Local variables kept with `clean_env`; `LC_*` matches a prefix.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env_passthrough: Vec<String>,
+    /// What to do when this host's key isn't known yet.
+    pub host_key_policy: Option<HostKeyPolicy>,
+    /// The algorithms this host is offered: `modern`, `compat` or `fips`.
+    pub crypto_policy: Option<CryptoPolicy>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Snippets run, in order, before every command on this host.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub pre_commands: Vec<String>,
+    // Kept last: TOML needs plain values before nested tables.
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    pub vars: HashMap<String, String>,
+}
+
+impl Host {
+    pub fn new(host: &str) -> Self {
+        Host {
+            host: host.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Fills in anything not set on this host from `base`, so inline
+    /// attributes win over the configured values.
+    pub fn with_defaults(mut self, base: &Host) -> Host {
+        if self.user.is_none() {
+            self.user = base.user.clone();
+        }
+        if self.port.is_none() {
+            self.port = base.port;
+        }
+        if self.identity_file.is_none() {
+            self.identity_file = base.identity_file.clone();
+        }
+        if self.use_agent.is_none() {
+            self.use_agent = base.use_agent;
+        }
+        if self.options.is_none() {
+            self.options = base.options.clone();
+        }
+        if self.extra_options.is_empty() {
+            self.extra_options = base.extra_options.clone();
+        }
+        if self.jump_host.is_none() {
+            self.jump_host = base.jump_host.clone();
+        }
+        if self.timeout.is_none() {
+            self.timeout = base.timeout;
+        }
+        if self.shell.is_none() {
+            self.shell = base.shell;
+        }
+        if self.escalate.is_none() {
+            self.escalate = base.escalate;
+        }
+        if self.become_method.is_none() {
+            self.become_method = base.become_method;
+        }
+        if self.become_user.is_none() {
+            self.become_user = base.become_user.clone();
+        }
+        if self.clean_env.is_none() {
+            self.clean_env = base.clean_env;
+        }
+        if self.host_key_policy.is_none() {
+            self.host_key_policy = base.host_key_policy;
+        }
+        if self.crypto_policy.is_none() {
+            self.crypto_policy = base.crypto_policy;
+        }
+        for name in &base.env_passthrough {
+            if !self.env_passthrough.contains(name) {
+                self.env_passthrough.push(name.clone());
+            }
+        }
+        for tag in &base.tags {
+            if !self.tags.contains(tag) {
+                self.tags.push(tag.clone());
+            }
+        }
+        for (key, value) in &base.vars {
+            self.vars
+                .entry(key.clone())
+                .or_insert_with(|| value.clone());
+        }
+        if self.pre_commands.is_empty() {
+            self.pre_commands = base.pre_commands.clone();
+        }
+        self
+    }
+
+    pub fn shell(&self) -> Shell {
+        self.shell.unwrap_or_default()
+    }
+
+    /// The extra ssh arguments: `options` split like a shell would, then
+    /// `extra_options` as they are. Options with unbalanced quotes are split
+    /// on whitespace, with a warning.
+    pub fn option_args(&self) -> Vec<String> {
+        let mut args = match self.options.as_deref().map(shell_words::split) {
+            Some(Ok(words)) => words,
+            Some(Err(e)) => {
+                warn(
+                    WarningKind::ConfigFallback,
+                    format!(
+                        "options '{}' for {}: {}, splitting on whitespace instead",
+                        self.options.as_deref().unwrap_or_default(),
+                        self.host,
+                        e
+                    ),
+                );
+                self.options
+                    .iter()
+                    .flat_map(|o| o.split_whitespace())
+                    .map(str::to_string)
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        args.extend(self.extra_options.iter().cloned());
+        args
+    }
+
+    /// The `-J` argument for this host, if it is reached through a jump host.
+    pub fn jump(&self) -> Option<&str> {
+        self.jump_host
+            .as_deref()
+            .map(str::trim)
+            .filter(|jump| !jump.is_empty() && *jump != "none")
+    }
+
+    /// `command` with this host's pre-commands in front; the command only
+    /// runs if they all succeed.
+    pub fn command(&self, command: &str) -> String {
+        if self.pre_commands.is_empty() {
+            return command.to_string();
+        }
+        let mut parts = self.pre_commands.clone();
+        parts.push(command.to_string());
+        // Windows PowerShell 5 has no `&&`.
+        match self.shell() {
+            Shell::Powershell => parts.join("; if (-not $?) { exit 1 }; "),
+            Shell::Posix | Shell::Cmd => parts.join(" && "),
+        }
+    }
+
+    /// Applies a single `key=value` attribute. Unknown keys become host vars.
+    pub fn set_attribute(&mut self, key: &str, value: &str) -> Result<()> {
+        match key {
+            "user" => self.user = Some(value.to_string()),
+            "port" => {
+                let port = value.parse().map_err(|_| InventoryError::HostSpec {
+                    spec: format!("{} {}={}", self.host, key, value),
+                    reason: format!("invalid port '{}'", value),
+                })?;
+                self.port = Some(port);
+            }
+            "timeout" => {
+                let timeout = value.parse().map_err(|_| InventoryError::HostSpec {
+                    spec: format!("{} {}={}", self.host, key, value),
+                    reason: format!("invalid timeout '{}'", value),
+                })?;
+                self.timeout = Some(timeout);
+            }
+            "shell" => self.shell = Some(value.parse()?),
+            "become" => {
+                let escalate = value.parse().map_err(|_| InventoryError::HostSpec {
+                    spec: format!("{} {}={}", self.host, key, value),
+                    reason: format!("become must be true or false, got '{}'", value),
+                })?;
+                self.escalate = Some(escalate);
+            }
+            "become_method" => self.become_method = Some(value.parse()?),
+            "become_user" => self.become_user = Some(value.to_string()),
+            "clean_env" => {
+                let clean = value.parse().map_err(|_| InventoryError::HostSpec {
+                    spec: format!("{} {}={}", self.host, key, value),
+                    reason: format!("clean_env must be true or false, got '{}'", value),
+                })?;
+                self.clean_env = Some(clean);
+            }
+            "host_key_policy" => self.host_key_policy = Some(value.parse()?),
+            "crypto_policy" => self.crypto_policy = Some(value.parse()?),
+            "env_passthrough" => {
+                for name in value.split(',').filter(|n| !n.is_empty()) {
+                    if !self.env_passthrough.iter().any(|n| n == name) {
+                        self.env_passthrough.push(name.to_string());
+                    }
+                }
+            }
+            "options" => self.options = Some(value.to_string()),
+            "jump_host" => self.jump_host = Some(value.to_string()),
+            "identity_file" => self.identity_file = Some(PathBuf::from(value)),
+            "use_agent" => {
+                let use_agent = value.parse().map_err(|_| InventoryError::HostSpec {
+                    spec: format!("{} {}={}", self.host, key, value),
+                    reason: format!("use_agent must be true or false, got '{}'", value),
+                })?;
+                self.use_agent = Some(use_agent);
+            }
+            "tag" | "tags" => {
+                for tag in value.split(',').filter(|t| !t.is_empty()) {
+                    if !self.tags.iter().any(|t| t == tag) {
+                        self.tags.push(tag.to_string());
+                    }
+                }
+            }
+            _ => {
+                self.vars.insert(key.to_string(), value.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The shell a host's sshd hands commands to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// `sh` and friends on Unix hosts.
+    #[default]
+    Posix,
+    /// `cmd.exe`, the default of the Windows OpenSSH server.
+    Cmd,
+    /// Windows PowerShell or PowerShell 7.
+    Powershell,
+}
+
+impl Shell {
+    pub fn is_windows(self) -> bool {
+        self != Shell::Posix
+    }
+}
+
+impl FromStr for Shell {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "posix" | "sh" => Ok(Shell::Posix),
+            "cmd" => Ok(Shell::Cmd),
+            "powershell" | "pwsh" => Ok(Shell::Powershell),
+            _ => Err(InventoryError::HostSpec {
+                spec: format!("shell={}", name),
+                reason: "shell must be posix, cmd or powershell".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+impl FromStr for Host {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let mut parts = spec.split_whitespace();
+        let invalid = |reason: String| InventoryError::HostSpec {
+            spec: spec.to_string(),
+            reason,
+        };
+        let name = parts
+            .next()
+            .ok_or_else(|| invalid("empty host spec".to_string()))?;
+        if name.contains('=') {
+            return Err(invalid("must start with a hostname".to_string()).into());
+        }
+
+        let mut host = Host::new(name);
+        for attribute in parts {
+            let (key, value) = attribute.split_once('=').ok_or_else(|| {
+                invalid(format!(
+                    "invalid attribute '{}', expected key=value",
+                    attribute
+                ))
+            })?;
+            host.set_attribute(key, value)?;
+        }
+        Ok(host)
+    }
+}
+
+impl Config {
+    /// Evaluates every group's `members_command` and adds all group members
+    /// to the inventory. A failing command only loses its dynamic members.
+    pub fn expand_groups(&mut self) {
+        let mut names: Vec<String> = self.groups.keys().cloned().collect();
+        names.sort();
+        for name in names {
+            let group = self.groups.get_mut(&name).expect("group exists");
+            if let Some(command) = &group.members_command {
+                match run_members_command(&name, command) {
+                    Ok(members) => {
+                        for member in members {
+                            if !group.members.contains(&member) {
+                                group.members.push(member);
+                            }
+                        }
+                    }
+                    Err(e) => warn(
+                        WarningKind::HostSkipped,
+                        format!("{}, using static members only", e),
+                    ),
+                }
+            }
+            for member in &group.members {
+                if !self.servers.iter().any(|server| &server.host == member) {
+                    self.servers.push(Host::new(member));
+                }
+            }
+        }
+    }
+
+    /// The task called `name`.
+    pub fn task(&self, name: &str) -> Result<&Task> {
+        self.tasks.get(name).ok_or_else(|| {
+            let mut known: Vec<&str> = self.tasks.keys().map(String::as_str).collect();
+            known.sort();
+            ExecutionError::UnknownTask {
+                name: name.to_string(),
+                known: if known.is_empty() {
+                    "none".to_string()
+                } else {
+                    known.join(", ")
+                },
+            }
+            .into()
+        })
+    }
+
+    /// Resolves the members of the named groups into `Host` entries, in the
+    /// order the groups are given.
+    pub fn group_hosts(&self, names: &[String]) -> Result<Vec<Host>> {
+        let mut hosts = Vec::new();
+        for name in names {
+            let group = self.groups.get(name).ok_or_else(|| {
+                let mut known: Vec<&str> = self.groups.keys().map(String::as_str).collect();
+                known.sort();
+                InventoryError::UnknownGroup {
+                    name: name.clone(),
+                    known: if known.is_empty() {
+                        "none".to_string()
+                    } else {
+                        known.join(", ")
+                    },
+                }
+            })?;
+            hosts.extend(group.members.iter().map(|member| self.host(member)));
+        }
+        Ok(hosts)
+    }
+
+    /// Resolves the configured servers into `Host` entries.
+    pub fn hosts(&self) -> Vec<Host> {
+        self.servers
+            .iter()
+            .map(|server| self.host(&server.host))
+            .collect()
+    }
+
+    fn has_server(&self, name: &str) -> bool {
+        self.servers.iter().any(|server| server.host == name)
+    }
+
+    /// Adds `hosts` to `servers`. A host that is already configured keeps
+    /// its own settings and only takes the ones it lacks from the import.
+    pub fn merge_hosts(&mut self, hosts: Vec<Host>) {
+        for host in hosts {
+            match self.servers.iter_mut().find(|s| s.host == host.host) {
+                Some(server) => *server = server.clone().with_defaults(&host),
+                None => self.servers.push(host),
+            }
+        }
+    }
+
+    /// Per-server settings for hosts missing from `servers` are never used,
+    /// which is almost always a typo in the hostname.
+    fn warn_unknown_servers(&self) {
+        let tables = [
+            ("ssh_options", self.ssh_options.keys().collect::<Vec<_>>()),
+            ("users", self.users.keys().collect()),
+            ("tags", self.tags.keys().collect()),
+        ];
+        for (table, servers) in tables {
+            let mut unknown: Vec<&String> = servers
+                .into_iter()
+                .filter(|server| !self.has_server(server))
+                .collect();
+            unknown.sort();
+            for server in unknown {
+                warn(
+                    WarningKind::HostSkipped,
+                    format!(
+                        "[{}] has an entry for '{}' which is not in servers, ignoring it",
+                        table, server
+                    ),
+                );
+            }
+        }
+    }
+
+    /// Resolves a single server name, using whatever the config knows about it.
+    pub fn host(&self, server: &str) -> Host {
+        let mut host = self
+            .servers
+            .iter()
+            .find(|entry| entry.host == server)
+            .cloned()
+            .unwrap_or_else(|| Host::new(server));
+        if host.user.is_none() {
+            host.user = self.users.get(server).cloned();
+        }
+        if host.options.is_none() {
+            host.options = self.ssh_options.get(server).cloned();
+        }
+        if host.jump_host.is_none() {
+            host.jump_host = self
+                .bastion
+                .as_ref()
+                .filter(|bastion| bastion.host != server)
+                .map(Bastion::spec);
+        }
+        for tag in self.tags.get(server).into_iter().flatten() {
+            if !host.tags.contains(tag) {
+                host.tags.push(tag.clone());
+            }
+        }
+        if host.clean_env.is_none() {
+            host.clean_env = self.clean_env;
+        }
+        if host.host_key_policy.is_none() {
+            host.host_key_policy = self.host_key_policy;
+        }
+        if host.crypto_policy.is_none() {
+            host.crypto_policy = self.crypto_policy;
+        }
+        if let Some(auth) = &self.auth {
+            if host.identity_file.is_none() {
+                host.identity_file = auth.identity_file.clone();
+            }
+            if host.use_agent.is_none() {
+                host.use_agent = auth.use_agent;
+            }
+        }
+        for name in &self.env_passthrough {
+            if !host.env_passthrough.contains(name) {
+                host.env_passthrough.push(name.clone());
+            }
+        }
+
+        let mut groups: Vec<(&String, &Group)> = self
+            .groups
+            .iter()
+            .filter(|(_, group)| group.members.iter().any(|m| m == server))
+            .collect();
+        groups.sort_by_key(|(name, _)| name.as_str());
+        // `{group}` in commands: the first group the host is in.
+        if let Some((name, _)) = groups.first() {
+            host.vars
+                .entry("group".to_string())
+                .or_insert_with(|| name.to_string());
+        }
+        let own = std::mem::take(&mut host.pre_commands);
+        host.pre_commands = groups
+            .into_iter()
+            .filter_map(|(_, group)| group.pre_command.clone())
+            .chain(self.pre_commands.get(server).cloned())
+            .chain(own)
+            .collect();
+        host
+    }
 }
 
 pub fn read_config(file_path: &str) -> Result<Config> {
-    let file = fs::read_to_string(file_path)?;
-    let config: Config = toml::from_str(&file)?;
+    let file = fs::read_to_string(file_path).map_err(|source| ConfigError::Read {
+        file: PathBuf::from(file_path),
+        source,
+    })?;
+    let mut config: Config = toml::from_str(&file).map_err(|e| ConfigError::parse(file_path, e))?;
+    config.path = Some(fs::canonicalize(file_path).unwrap_or_else(|_| PathBuf::from(file_path)));
+    if config.import_ssh_config {
+        match ssh_config::default_path().filter(|path| path.exists()) {
+            Some(path) => config.merge_hosts(ssh_config::load(&path)?),
+            None => warn(
+                WarningKind::HostSkipped,
+                "import_ssh_config is set but there is no ~/.ssh/config",
+            ),
+        }
+    }
+    config.warn_unknown_servers();
+    if !config.ssh_options.is_empty() || !config.users.is_empty() {
+        warn(
+            WarningKind::DeprecatedKey,
+            "[ssh_options] and [users] are deprecated, set `options` and `user` in [[servers]] tables instead",
+        );
+    }
     Ok(config)
 }
 
+/// Appends `hosts` as `[[servers]]` tables to the config file at `path`,
+/// leaving everything else in the file (comments included) as it is.
+/// Hosts already listed are skipped; returns the names that were added.
+pub fn add_servers(path: &Path, hosts: &[Host]) -> Result<Vec<String>> {
+    let mut doc = read_document(path)?;
+
+    // The legacy `servers = ["name", ...]` list becomes tables, since the
+    // two forms can't be mixed. Comments above it move along.
+    let comment = doc
+        .as_table()
+        .key("servers")
+        .and_then(|key| key.leaf_decor().prefix().cloned());
+    let mut servers = match doc.remove("servers") {
+        None => ArrayOfTables::new(),
+        Some(Item::ArrayOfTables(tables)) => tables,
+        Some(item) => {
+            let mut tables = ArrayOfTables::new();
+            for value in item.as_array().into_iter().flatten() {
+                let mut table = Table::new();
+                match value {
+                    Value::String(name) => {
+                        table.insert("host", toml_edit::value(name.value().as_str()));
+                    }
+                    Value::InlineTable(inline) => table = inline.clone().into_table(),
+                    _ => continue,
+                }
+                tables.push(table);
+            }
+            if let (Some(first), Some(comment)) = (tables.get_mut(0), comment) {
+                first.decor_mut().set_prefix(comment);
+            }
+            tables
+        }
+    };
+
+    let mut added = Vec::new();
+    for host in hosts {
+        let listed = servers
+            .iter()
+            .any(|t| t.get("host").and_then(Item::as_str) == Some(host.host.as_str()));
+        if listed || added.contains(&host.host) {
+            continue;
+        }
+        let entry = toml::to_string(host)?;
+        let entry: DocumentMut = entry
+            .parse()
+            .map_err(|e: toml_edit::TomlError| AppError::Generic(e.to_string()))?;
+        servers.push(entry.as_table().clone());
+        added.push(host.host.clone());
+    }
+    doc.insert("servers", Item::ArrayOfTables(servers));
+    fs::write(path, doc.to_string())?;
+    Ok(added)
+}
+
+/// A JSON Schema of the config file format, derived from the types it is
+/// read into.
+pub fn schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(Config);
+    schema.insert("title".to_string(), "russh.toml".into());
+    schema.to_value()
+}
+
+/// The config file at `path`, parsed for editing.
+fn read_document(path: &Path) -> Result<DocumentMut> {
+    let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        file: path.to_path_buf(),
+        source,
+    })?;
+    text.parse().map_err(|e: toml_edit::TomlError| {
+        ConfigError::Parse {
+            file: path.to_path_buf(),
+            line: e
+                .span()
+                .map(|span| text[..span.start].matches('\n').count() + 1),
+            message: e.message().to_string(),
+        }
+        .into()
+    })
+}
+
+/// Removes every mention of the server `name` from the config file at
+/// `path`: its `servers` entry, its `[tags]`, `[pre_commands]`,
+/// `[ssh_options]` and `[users]` keys and its group memberships. Returns
+/// whether it was listed in `servers`.
+pub fn remove_server(path: &Path, name: &str) -> Result<bool> {
+    let mut doc = read_document(path)?;
+    let other = |value: &Value| match value {
+        Value::String(host) => host.value() != name,
+        Value::InlineTable(table) => table.get("host").and_then(Value::as_str) != Some(name),
+        _ => true,
+    };
+
+    let mut listed = false;
+    match doc.get_mut("servers") {
+        Some(Item::ArrayOfTables(tables)) => {
+            let before = tables.len();
+            tables.retain(|t| t.get("host").and_then(Item::as_str) != Some(name));
+            listed = tables.len() < before;
+        }
+        Some(item) => {
+            if let Some(servers) = item.as_array_mut() {
+                let before = servers.len();
+                retain_values(servers, other);
+                listed = servers.len() < before;
+            }
+        }
+        None => {}
+    }
+    for key in ["tags", "pre_commands", "ssh_options", "users"] {
+        if let Some(table) = doc.get_mut(key).and_then(Item::as_table_like_mut) {
+            table.remove(name);
+        }
+    }
+    if let Some(groups) = doc.get_mut("groups").and_then(Item::as_table_like_mut) {
+        for (_, group) in groups.iter_mut() {
+            let members = match group.as_table_like_mut() {
+                Some(table) => table.get_mut("members"),
+                None => Some(group),
+            };
+            if let Some(members) = members.and_then(Item::as_array_mut) {
+                retain_values(members, other);
+            }
+        }
+    }
+    paths::rewrite(path, doc.to_string().as_bytes())?;
+    Ok(listed)
+}
+
+/// `Array::retain`, without leaving `[ "b"]` behind when `"a"` goes from
+/// `["a", "b"]`.
+fn retain_values(array: &mut toml_edit::Array, keep: impl FnMut(&Value) -> bool) {
+    let first = array.get(0).map(|value| value.decor().clone());
+    array.retain(keep);
+    if let (Some(decor), Some(value)) = (first, array.get_mut(0)) {
+        *value.decor_mut() = decor;
+    }
+}
+
+/// Adds the server `name` to the `members` of each of `groups` in the
+/// config file at `path`, creating the groups that don't exist yet as
+/// plain lists.
+pub fn add_to_groups(path: &Path, name: &str, groups: &[String]) -> Result<()> {
+    let mut doc = read_document(path)?;
+    let not_a_list = |group: &str| {
+        AppError::from(ConfigError::Parse {
+            file: path.to_path_buf(),
+            line: None,
+            message: format!("group '{}' has no members list to add to", group),
+        })
+    };
+    let table = doc
+        .entry("groups")
+        .or_insert(toml_edit::table())
+        .as_table_like_mut()
+        .ok_or_else(|| not_a_list("groups"))?;
+    for group in groups {
+        let item = table
+            .entry(group)
+            .or_insert(toml_edit::value(toml_edit::Array::new()));
+        if let Some(table) = item.as_table_like_mut() {
+            if table.get("members").is_none() {
+                table.insert("members", toml_edit::value(toml_edit::Array::new()));
+            }
+        }
+        let members = match item.as_table_like_mut() {
+            Some(table) => table.get_mut("members"),
+            None => Some(item),
+        };
+        let members = members
+            .and_then(Item::as_array_mut)
+            .ok_or_else(|| not_a_list(group))?;
+        if !members.iter().any(|member| member.as_str() == Some(name)) {
+            members.push(name);
+        }
+    }
+    paths::rewrite(path, doc.to_string().as_bytes())
+}
+
+/// Opens the config file at `path` in `$VISUAL` or `$EDITOR` (`vi` without
+/// either). The edit is made on a copy that only replaces the file once it
+/// loads; a broken one is offered for another round. Returns whether the
+/// file changed.
+pub fn edit(path: &Path) -> Result<bool> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| "vi".to_string());
+    let editor = shell_words::split(&editor).map_err(|e| ExecutionError::InvalidOption {
+        option: "$EDITOR",
+        reason: e.to_string(),
+    })?;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let draft = path.with_file_name(format!(".{}.edit.toml", name.trim_end_matches(".toml")));
+    let original = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        file: path.to_path_buf(),
+        source,
+    })?;
+    fs::copy(path, &draft)?;
+    let outcome = loop {
+        let status = Command::new(&editor[0])
+            .args(&editor[1..])
+            .arg(&draft)
+            .status()
+            .map_err(|e| ExecutionError::InvalidOption {
+                option: "$EDITOR",
+                reason: format!("could not start {}: {}", editor[0], e),
+            });
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => {
+                break Err(ExecutionError::Refused(format!(
+                    "the editor exited with {}, {} is unchanged",
+                    status,
+                    path.display()
+                ))
+                .into())
+            }
+            Err(e) => break Err(e.into()),
+        }
+        let error = match draft.to_str() {
+            Some(draft) => read_config(draft).err(),
+            None => Some(ConfigError::InvalidPath(path.to_path_buf()).into()),
+        };
+        let Some(error) = error else {
+            let edited = fs::read_to_string(&draft)?;
+            if edited == original {
+                break Ok(false);
+            }
+            paths::rewrite(path, edited.as_bytes())?;
+            break Ok(true);
+        };
+        eprintln!("{}", error);
+        if !prompt::interactive() {
+            break Err(ExecutionError::Refused(format!("{} is unchanged", path.display())).into());
+        }
+        eprintln!("{}", tr(Msg::EditAgainPrompt));
+        let mut response = String::new();
+        io::stdin().read_line(&mut response)?;
+        if !response.trim().is_empty() && !is_yes(&response) {
+            break Err(ExecutionError::Refused(format!("{} is unchanged", path.display())).into());
+        }
+    };
+    let _ = fs::remove_file(&draft);
+    outcome
+}
+
+/// The host specs in a hosts file: one per line, written as for `-H`, with
+/// blank lines and `#` comments skipped. A `kind/` prefix, as in `kubectl
+/// get nodes -o name` output (`node/worker-1`), is dropped.
+pub fn host_specs(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let name_end = line.find(char::is_whitespace).unwrap_or(line.len());
+            match line[..name_end].rsplit_once('/') {
+                Some((_, name)) => format!("{}{}", name, &line[name_end..]),
+                None => line.to_string(),
+            }
+        })
+        .collect()
+}
+
 pub fn find_config_in_cwd() -> Option<PathBuf> {
-    let cwd = env::current_dir().expect("Failed to get current working directory");
+    let cwd = env::current_dir().ok()?;
     let config_path = cwd.join("russh.toml");
     if config_path.exists() {
         Some(config_path)
@@ -64,6 +1137,10 @@ pub fn find_config_in_user_dir() -> Option<PathBuf> {
 }
 
 pub fn prompt_create_default_config() -> Result<Option<PathBuf>> {
+    // Under cron or in CI a missing config is an error, not a question.
+    if !prompt::interactive() {
+        return Ok(None);
+    }
     let default_path = dirs::config_dir()
         .ok_or(AppError::File(std::io::Error::new(
             std::io::ErrorKind::NotFound,
@@ -72,15 +1149,15 @@ pub fn prompt_create_default_config() -> Result<Option<PathBuf>> {
         .join("russh/russh.toml");
 
     println!(
-        "Configuration file not found. Do you want to create a default user file at {:?}? [Y/n]",
-        default_path
+        "{}",
+        trf(Msg::CreateConfigPrompt, &[&format!("{:?}", default_path)])
     );
     let mut response = String::new();
     io::stdin()
         .read_line(&mut response)
         .map_err(AppError::File)?;
 
-    if response.trim().to_lowercase().starts_with('y') {
+    if is_yes(&response) {
         create_default_config(default_path.to_str().ok_or(AppError::File(
             std::io::Error::new(
                 std::io::ErrorKind::InvalidData,
@@ -99,9 +1176,38 @@ pub fn create_default_config(file_path: &str) -> Result<()> {
         fs::create_dir_all(dir)?;
     }
     let example_config = Config {
-        servers: vec!["example.server.com".to_string()],
-        ssh_options: HashMap::from([("example.server.com".to_string(), "-p 22".to_string())]),
-        users: HashMap::from([("example.server.com".to_string(), "example".to_string())]),
+        servers: vec![Host {
+            user: Some("example".to_string()),
+            port: Some(22),
+            ..Host::new("example.server.com")
+        }],
+        ssh_options: HashMap::new(),
+        users: HashMap::new(),
+        tags: HashMap::new(),
+        answers: Vec::new(),
+        tasks: HashMap::new(),
+        sinks: HashMap::new(),
+        bastion: None,
+        auth: None,
+        groups: HashMap::new(),
+        pre_commands: HashMap::new(),
+        webhook: None,
+        archive: None,
+        bot: None,
+        max_parallel: None,
+        collapse_repeats: None,
+        clean_env: None,
+        env_passthrough: Vec::new(),
+        host_key_policy: None,
+        crypto_policy: None,
+        facts_ttl: None,
+        retries: None,
+        retry_delay: None,
+        import_ssh_config: false,
+        decommission_command: None,
+        log_dir: None,
+        env: HashMap::new(),
+        path: None,
     };
     let example_config_bytes = toml::to_string_pretty(&example_config)?;
     fs::write(file_path, example_config_bytes)?;
@@ -112,11 +1218,10 @@ pub fn create_default_config(file_path: &str) -> Result<()> {
 mod config_tests {
     use super::*;
     use std::fs;
-    use std::path::Path;
 
     fn create_temp_config(file_name: &str, content: &str) -> String {
-        let path = Path::new(file_name);
-        fs::write(path, content).expect("Failed to write temp config file");
+        let path = env::temp_dir().join(format!("russh-{}-{}", std::process::id(), file_name));
+        fs::write(&path, content).expect("Failed to write temp config file");
         path.to_str().unwrap().to_string()
     }
 
@@ -131,10 +1236,120 @@ mod config_tests {
         "#;
         let file_path = create_temp_config("russh.toml", config_content);
         let config = read_config(&file_path).expect("Failed to read config");
-        assert_eq!(config.servers, vec!["test.server.com"]);
+        assert_eq!(config.servers, vec![Host::new("test.server.com")]);
         assert_eq!(config.ssh_options["test.server.com"], "-p 22");
         assert_eq!(config.users["test.server.com"], "user");
     }
+
+    #[test]
+    fn test_server_tables() {
+        let config: Config = toml::from_str(
+            r#"
+            [[servers]]
+            host = "web01"
+            user = "deploy"
+            port = 2222
+            identity_file = "~/.ssh/deploy"
+
+            [[servers]]
+            host = "db01"
+            options = "-4"
+            "#,
+        )
+        .expect("Failed to parse [[servers]] tables");
+        let web = config.host("web01");
+        assert_eq!(web.user.as_deref(), Some("deploy"));
+        assert_eq!(web.port, Some(2222));
+        assert_eq!(web.identity_file, Some(PathBuf::from("~/.ssh/deploy")));
+        assert_eq!(config.host("db01").options.as_deref(), Some("-4"));
+
+        let written = toml::to_string_pretty(&config).expect("Failed to serialize");
+        let reread: Config = toml::from_str(&written).expect("Failed to re-read");
+        assert_eq!(reread.servers, config.servers);
+    }
+
+    #[test]
+    fn test_auth_defaults() {
+        let config: Config = toml::from_str(
+            r#"
+            [auth]
+            identity_file = "~/.ssh/fleet"
+            use_agent = false
+
+            [[servers]]
+            host = "web01"
+
+            [[servers]]
+            host = "ci01"
+            identity_file = "~/.ssh/ci"
+            use_agent = true
+            "#,
+        )
+        .expect("Failed to parse [auth]");
+        let web = config.host("web01");
+        assert_eq!(web.identity_file, Some(PathBuf::from("~/.ssh/fleet")));
+        assert_eq!(web.use_agent, Some(false));
+        let ci = config.host("ci01");
+        assert_eq!(ci.identity_file, Some(PathBuf::from("~/.ssh/ci")));
+        assert_eq!(ci.use_agent, Some(true));
+
+        let host: Host = "web02 use_agent=false".parse().unwrap();
+        assert_eq!(host.use_agent, Some(false));
+        assert!("web02 use_agent=never".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn test_bastion() {
+        let config: Config = toml::from_str(
+            r#"
+            servers = ["web01", "jump.example.com", "lan01"]
+
+            [ssh_options]
+            lan01 = "-4"
+
+            [bastion]
+            host = "jump.example.com"
+            user = "ops"
+            port = 2222
+            "#,
+        )
+        .expect("Failed to parse [bastion]");
+        let web = config.host("web01");
+        assert_eq!(web.jump(), Some("ops@jump.example.com:2222"));
+        assert_eq!(config.host("jump.example.com").jump(), None);
+
+        let direct: Host = "lan01 jump_host=none".parse().unwrap();
+        let direct = direct.with_defaults(&config.host("lan01"));
+        assert_eq!(direct.jump(), None);
+        let bastion = Bastion {
+            host: "fd00::1".to_string(),
+            user: None,
+            port: Some(22),
+        };
+        assert_eq!(bastion.spec(), "[fd00::1]:22");
+    }
+
+    #[test]
+    fn test_tasks() {
+        let config: Config = toml::from_str(
+            r#"
+            servers = ["web01"]
+
+            [tasks.deploy]
+            description = "Pull and restart the app"
+            commands = ["git pull", "systemctl restart app"]
+            groups = ["web"]
+            "#,
+        )
+        .expect("Failed to parse [tasks]");
+        let deploy = config.task("deploy").unwrap();
+        assert_eq!(deploy.commands.len(), 2);
+        assert_eq!(deploy.groups, ["web"]);
+        assert!(deploy.hosts.is_empty());
+        let err = config.task("deplyo").unwrap_err().to_string();
+        assert_eq!(err, "unknown task 'deplyo' (configured tasks: deploy)");
+    }
+
     #[test]
     fn test_find_config_in_cwd() {
         let config_content = r#"
@@ -144,9 +1359,260 @@ servers = ["test.server.com"]
 [users]
 "test.server.com" = "user"
 "#;
-        let _ = create_temp_config("russh.toml", config_content);
+        fs::write("russh.toml", config_content).expect("Failed to write config file");
 
         let config_path = find_config_in_cwd().expect("Failed to find config in CWD");
         assert!(config_path.exists());
+        fs::remove_file("russh.toml").unwrap();
+    }
+
+    #[test]
+    fn test_parse_inline_host() {
+        let host: Host = "web01 user=deploy port=2222 tag=canary datacenter=fra1"
+            .parse()
+            .expect("Failed to parse host spec");
+        assert_eq!(host.host, "web01");
+        assert_eq!(host.user.as_deref(), Some("deploy"));
+        assert_eq!(host.port, Some(2222));
+        assert_eq!(host.tags, vec!["canary"]);
+        assert_eq!(host.vars["datacenter"], "fra1");
+
+        assert!("web01 port=abc".parse::<Host>().is_err());
+        assert!("web01 user".parse::<Host>().is_err());
+        assert!("user=deploy".parse::<Host>().is_err());
+    }
+
+    #[test]
+    fn test_inline_host_overrides_config() {
+        let config = Config {
+            servers: vec![Host::new("web01")],
+            ssh_options: HashMap::from([("web01".to_string(), "-4".to_string())]),
+            users: HashMap::from([("web01".to_string(), "admin".to_string())]),
+            tags: HashMap::new(),
+            groups: HashMap::new(),
+            pre_commands: HashMap::new(),
+            webhook: None,
+            archive: None,
+            bot: None,
+            answers: Vec::new(),
+            tasks: HashMap::new(),
+            sinks: HashMap::new(),
+            bastion: None,
+            auth: None,
+            max_parallel: None,
+            collapse_repeats: None,
+            clean_env: None,
+            env_passthrough: Vec::new(),
+            host_key_policy: None,
+            crypto_policy: None,
+            facts_ttl: None,
+            retries: None,
+            retry_delay: None,
+            import_ssh_config: false,
+            decommission_command: None,
+            log_dir: None,
+            env: HashMap::new(),
+            path: None,
+        };
+        let inline: Host = "web01 user=deploy".parse().unwrap();
+        let host = inline.with_defaults(&config.host("web01"));
+        assert_eq!(host.user.as_deref(), Some("deploy"));
+        assert_eq!(host.options.as_deref(), Some("-4"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expand_groups_with_members_command() {
+        let mut config: Config = toml::from_str(
+            r#"
+            servers = ["web01"]
+            [ssh_options]
+            [users]
+            [groups.web]
+            members = ["web01", "web02"]
+            members_command = "printf 'web02\\nweb03\\n'"
+            [groups.broken]
+            members_command = "exit 3"
+            [groups.hostile]
+            members = ["db01"]
+            members_command = "printf 'db02\\n-oProxyCommand=touch /tmp/pwned\\n'"
+        "#,
+        )
+        .expect("Failed to parse config");
+        config.expand_groups();
+        assert_eq!(
+            config.groups["web"].members,
+            vec!["web01", "web02", "web03"]
+        );
+        let servers: Vec<&str> = config.servers.iter().map(|s| s.host.as_str()).collect();
+        assert_eq!(servers, vec!["web01", "db01", "web02", "web03"]);
+        assert!(config.groups["broken"].members.is_empty());
+        // A member ssh would take for an option loses the command's members.
+        assert_eq!(config.groups["hostile"].members, vec!["db01"]);
+        assert!(check_host_name("-oProxyCommand=id").is_err());
+        assert!(check_host_name("web 01").is_err());
+        assert!(check_host_name("web01\u{1b}").is_err());
+        assert!(check_host_name("web-01.example.com").is_ok());
+    }
+
+    #[test]
+    fn test_group_list_shorthand() {
+        let config: Config = toml::from_str(
+            r#"
+            servers = []
+            [ssh_options]
+            [users]
+            [groups]
+            web = ["web01", "web02"]
+            db = { members = ["db01"] }
+            "#,
+        )
+        .expect("Failed to parse groups");
+        assert_eq!(config.groups["web"].members, vec!["web01", "web02"]);
+
+        let hosts = config
+            .group_hosts(&["db".to_string(), "web".to_string()])
+            .unwrap();
+        let names: Vec<&str> = hosts.iter().map(|h| h.host.as_str()).collect();
+        assert_eq!(names, vec!["db01", "web01", "web02"]);
+        assert!(config.group_hosts(&["cache".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_pre_commands() {
+        let config: Config = toml::from_str(
+            r#"
+            servers = ["app01", "web01"]
+            [ssh_options]
+            [users]
+            [pre_commands]
+            app01 = "cd /srv/app"
+            [groups.app]
+            members = ["app01"]
+            pre_command = "source /opt/app/env.sh"
+            "#,
+        )
+        .expect("Failed to parse pre_commands");
+        assert_eq!(
+            config.host("app01").command("rake db:migrate"),
+            "source /opt/app/env.sh && cd /srv/app && rake db:migrate"
+        );
+        assert_eq!(config.host("web01").command("uptime"), "uptime");
+    }
+
+    #[test]
+    fn test_add_servers_keeps_the_file() {
+        let path = env::temp_dir().join(format!("russh-add-servers-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "# fleet\nservers = [\"web01\"]\nmax_parallel = 4 # tuned\n",
+        )
+        .unwrap();
+        let mut db = Host::new("db01");
+        db.user = Some("postgres".to_string());
+        db.port = Some(2222);
+
+        let added = add_servers(&path, &[Host::new("web01"), db]).unwrap();
+        assert_eq!(added, ["db01"]);
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("# fleet"));
+        assert!(text.contains("max_parallel = 4 # tuned"));
+
+        let config = read_config(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.host("db01").port, Some(2222));
+        assert_eq!(config.max_parallel, Some(4));
+    }
+
+    #[test]
+    fn test_remove_server() {
+        let path = env::temp_dir().join(format!("russh-remove-server-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "[[servers]]\nhost = \"web01\"\n\n# the old box\n[[servers]]\nhost = \"web02\"\n\n\
+             [tags]\nweb02 = [\"web\"]\n\n[groups]\nweb = [\"web01\", \"web02\"]\n\n\
+             [groups.all]\nmembers = [\"web02\"]\n",
+        )
+        .unwrap();
+
+        assert!(remove_server(&path, "web02").unwrap());
+        assert!(!remove_server(&path, "web03").unwrap());
+        let config = read_config(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        assert_eq!(config.servers.len(), 1);
+        assert_eq!(config.servers[0].host, "web01");
+        assert!(config.tags.is_empty());
+        assert_eq!(config.groups["web"].members, ["web01"]);
+        assert!(config.groups["all"].members.is_empty());
+    }
+
+    #[test]
+    fn test_host_specs() {
+        let text = "# from the load balancer\nweb01\n\n  web02 user=deploy port=2222 # new\n\
+                    node/ip-10-0-1-7.ec2.internal\n";
+        assert_eq!(
+            host_specs(text),
+            [
+                "web01",
+                "web02 user=deploy port=2222",
+                "ip-10-0-1-7.ec2.internal"
+            ]
+        );
+        assert!(host_specs("\n# nothing yet\n").is_empty());
+    }
+
+    #[test]
+    fn test_schema() {
+        let schema = schema();
+        let properties = schema["properties"].as_object().unwrap();
+        assert!(properties.contains_key("servers"));
+        assert!(properties.contains_key("groups"));
+        assert!(!properties.contains_key("path"));
+        let host = &schema["$defs"]["Host"]["properties"];
+        assert!(host["become"].is_object());
+        assert!(host["escalate"].is_null());
+        // Both forms of a group are allowed.
+        assert_eq!(
+            schema["$defs"]["Group"]["anyOf"].as_array().unwrap().len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_add_to_groups() {
+        let path = env::temp_dir().join(format!("russh-add-groups-{}.toml", std::process::id()));
+        fs::write(
+            &path,
+            "servers = [\"web01\", \"web07\"]\n\n[groups]\n# the frontends\nweb = [\"web01\"]\n\n\
+             [groups.canary]\nmembers_command = \"echo web01\"\n",
+        )
+        .unwrap();
+
+        let groups = ["web", "canary", "eu"].map(String::from);
+        add_to_groups(&path, "web07", &groups).unwrap();
+        add_to_groups(&path, "web07", &groups[..1]).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let config = read_config(path.to_str().unwrap()).unwrap();
+        let _ = fs::remove_file(&path);
+        assert!(text.contains("# the frontends\nweb = [\"web01\", \"web07\"]"));
+        assert_eq!(config.groups["canary"].members, ["web07"]);
+        assert!(config.groups["canary"].members_command.is_some());
+        assert_eq!(config.groups["eu"].members, ["web07"]);
+    }
+
+    #[test]
+    fn test_merge_hosts() {
+        let mut config = Config {
+            servers: vec!["web01 user=deploy".parse().unwrap()],
+            ..Default::default()
+        };
+        config.merge_hosts(ssh_config::parse(
+            "Host web01\n  User root\n  Port 2200\nHost web02\n",
+        ));
+        assert_eq!(config.servers.len(), 2);
+        assert_eq!(config.servers[0].user.as_deref(), Some("deploy"));
+        assert_eq!(config.servers[0].port, Some(2200));
+        assert_eq!(config.servers[1].host, "web02");
     }
 }
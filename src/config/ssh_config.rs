@@ -0,0 +1,127 @@
+//! Reading hosts out of an OpenSSH client config (`~/.ssh/config`), so an
+//! inventory kept there doesn't have to be repeated in `russh.toml`.
+
+use super::Host;
+use crate::errors::{ConfigError, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn default_path() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh").join("config"))
+}
+
+pub fn load(path: &Path) -> Result<Vec<Host>> {
+    let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+        file: path.to_path_buf(),
+        source,
+    })?;
+    Ok(parse(&text))
+}
+
+/// One host per concrete alias on a `Host` line, with the `User`, `Port`
+/// and `IdentityFile` set in its stanza. The alias stays the host name, so
+/// ssh still applies everything else from its config (`HostName`,
+/// `ProxyJump`, ...). Wildcard patterns and `Match` blocks are skipped.
+pub fn parse(text: &str) -> Vec<Host> {
+    let mut hosts: Vec<Host> = Vec::new();
+    // Indices into `hosts` that the current stanza applies to.
+    let mut current: Vec<usize> = Vec::new();
+    for line in text.lines() {
+        let Some((keyword, value)) = split_line(line) else {
+            continue;
+        };
+        match keyword.to_ascii_lowercase().as_str() {
+            "host" => {
+                current.clear();
+                for alias in value.split_whitespace().map(unquote) {
+                    if alias.contains(['*', '?', '!']) {
+                        continue;
+                    }
+                    match hosts.iter().position(|h| h.host == alias) {
+                        Some(index) => current.push(index),
+                        None => {
+                            current.push(hosts.len());
+                            hosts.push(Host::new(alias));
+                        }
+                    }
+                }
+            }
+            "match" => current.clear(),
+            key => {
+                // ssh uses the first value it finds for a setting.
+                for &index in &current {
+                    let host = &mut hosts[index];
+                    match key {
+                        "user" if host.user.is_none() => host.user = Some(value.to_string()),
+                        "port" if host.port.is_none() => host.port = value.parse().ok(),
+                        "identityfile" if host.identity_file.is_none() => {
+                            host.identity_file = Some(PathBuf::from(value))
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+    hosts
+}
+
+/// Splits `Keyword value` or `Keyword=value`, ignoring blanks and comments.
+fn split_line(line: &str) -> Option<(&str, &str)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let end = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(end);
+    let value = rest.trim_start().strip_prefix('=').unwrap_or(rest).trim();
+    Some((keyword, unquote(value)))
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssh_config() {
+        let hosts = parse(
+            r#"
+# jump hosts
+Host bastion
+    HostName 203.0.113.10
+    User ops
+
+Host web01 web02
+  User=deploy
+  Port 2222
+  IdentityFile "~/.ssh/web key"
+
+Host *.internal !legacy
+    User nobody
+
+Match host web01
+    User ignored
+
+Host web01
+    User second
+    Port 22
+"#,
+        );
+        let names: Vec<&str> = hosts.iter().map(|h| h.host.as_str()).collect();
+        assert_eq!(names, ["bastion", "web01", "web02"]);
+        assert_eq!(hosts[0].user.as_deref(), Some("ops"));
+        assert_eq!(hosts[1].user.as_deref(), Some("deploy"));
+        assert_eq!(hosts[1].port, Some(2222));
+        assert_eq!(
+            hosts[2].identity_file,
+            Some(PathBuf::from("~/.ssh/web key"))
+        );
+    }
+}
@@ -0,0 +1,127 @@
+//! `crypto_policy` presets: the key exchange, cipher, MAC and host key
+//! algorithms a host is offered, instead of spelling them out in raw
+//! `options`. `modern` allows only current algorithms, `fips` only
+//! FIPS 140-approved ones, and `compat` adds the legacy ones old
+//! appliances still need to ssh's defaults. A host's own `options` win
+//! over its preset, since ssh takes the first value it sees.
+
+use crate::config::Host;
+use crate::errors::{AppError, InventoryError, Result};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum CryptoPolicy {
+    Modern,
+    Compat,
+    Fips,
+}
+
+/// What a preset allows, as ssh config values; a leading `+` adds to
+/// ssh's defaults instead of replacing them.
+pub struct Algorithms {
+    pub kex: &'static str,
+    pub ciphers: &'static str,
+    pub macs: &'static str,
+    pub host_keys: &'static str,
+}
+
+const MODERN: Algorithms = Algorithms {
+    kex: "curve25519-sha256,curve25519-sha256@libssh.org,diffie-hellman-group18-sha512,diffie-hellman-group16-sha512",
+    ciphers: "chacha20-poly1305@openssh.com,aes256-gcm@openssh.com,aes128-gcm@openssh.com,aes256-ctr,aes128-ctr",
+    macs: "hmac-sha2-512-etm@openssh.com,hmac-sha2-256-etm@openssh.com",
+    host_keys: "ssh-ed25519,ecdsa-sha2-nistp256,ecdsa-sha2-nistp384,rsa-sha2-512,rsa-sha2-256",
+};
+
+const COMPAT: Algorithms = Algorithms {
+    kex: "+diffie-hellman-group14-sha1,diffie-hellman-group1-sha1",
+    ciphers: "+aes256-cbc,aes128-cbc,3des-cbc",
+    macs: "+hmac-sha1",
+    host_keys: "+ssh-rsa",
+};
+
+const FIPS: Algorithms = Algorithms {
+    kex: "ecdh-sha2-nistp256,ecdh-sha2-nistp384,ecdh-sha2-nistp521,diffie-hellman-group16-sha512,diffie-hellman-group14-sha256",
+    ciphers: "aes256-gcm@openssh.com,aes128-gcm@openssh.com,aes256-ctr,aes192-ctr,aes128-ctr",
+    macs: "hmac-sha2-512-etm@openssh.com,hmac-sha2-256-etm@openssh.com,hmac-sha2-512,hmac-sha2-256",
+    host_keys: "ecdsa-sha2-nistp256,ecdsa-sha2-nistp384,ecdsa-sha2-nistp521,rsa-sha2-512,rsa-sha2-256",
+};
+
+impl CryptoPolicy {
+    pub fn algorithms(self) -> &'static Algorithms {
+        match self {
+            CryptoPolicy::Modern => &MODERN,
+            CryptoPolicy::Compat => &COMPAT,
+            CryptoPolicy::Fips => &FIPS,
+        }
+    }
+}
+
+impl fmt::Display for CryptoPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            CryptoPolicy::Modern => "modern",
+            CryptoPolicy::Compat => "compat",
+            CryptoPolicy::Fips => "fips",
+        })
+    }
+}
+
+impl FromStr for CryptoPolicy {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "modern" => Ok(CryptoPolicy::Modern),
+            "compat" => Ok(CryptoPolicy::Compat),
+            "fips" => Ok(CryptoPolicy::Fips),
+            _ => Err(InventoryError::HostSpec {
+                spec: format!("crypto_policy={}", name),
+                reason: "crypto_policy must be modern, compat or fips".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// The `-o` options for `host`'s preset, understood by ssh and scp alike.
+pub fn ssh_options(host: &Host) -> Vec<String> {
+    let Some(policy) = host.crypto_policy else {
+        return Vec::new();
+    };
+    let algorithms = policy.algorithms();
+    [
+        ("KexAlgorithms", algorithms.kex),
+        ("Ciphers", algorithms.ciphers),
+        ("MACs", algorithms.macs),
+        ("HostKeyAlgorithms", algorithms.host_keys),
+    ]
+    .into_iter()
+    .flat_map(|(option, value)| ["-o".to_string(), format!("{}={}", option, value)])
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ssh_options() {
+        assert!(ssh_options(&Host::new("web01")).is_empty());
+        let host: Host = "switch01 crypto_policy=compat".parse().unwrap();
+        let options = ssh_options(&host);
+        assert_eq!(options.len(), 8);
+        assert_eq!(
+            options[..2],
+            [
+                "-o",
+                "KexAlgorithms=+diffie-hellman-group14-sha1,diffie-hellman-group1-sha1"
+            ]
+        );
+        assert!(options.contains(&"HostKeyAlgorithms=+ssh-rsa".to_string()));
+        assert!("legacy".parse::<CryptoPolicy>().is_err());
+    }
+}
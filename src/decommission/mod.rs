@@ -0,0 +1,238 @@
+//! `russh decommission HOST`: retires a host for good. A farewell command
+//! (`decommission_command`, or `--farewell`) runs on it first, e.g. to
+//! deregister it from monitoring; then it leaves the config, its key leaves
+//! `~/.ssh/known_hosts`, and what the state directory holds on it (cached
+//! facts, queued commands, its part of the last run's report) moves into
+//! one archive file under `<state dir>/decommissioned/`.
+
+use crate::audit::{current_user, now};
+use crate::cli::{exec_options, load_config, Cli, DecommissionArgs};
+use crate::config;
+use crate::config::Host;
+use crate::errors::{AppError, ConfigError, ExecutionError, Result};
+use crate::facts::{self, Facts};
+use crate::i18n::{tr, trf, Msg};
+use crate::paths;
+use crate::queue::{self, QueueEntry};
+use crate::report::{self, HostReport};
+use crate::{audit, prompt};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::mpsc;
+
+/// The archive's directory in the state directory.
+pub const ARCHIVE_DIR: &str = "decommissioned";
+
+/// Everything russh knew about a host when it was decommissioned.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Archive {
+    pub host: Host,
+    pub decommissioned_at: u64,
+    pub decommissioned_by: String,
+    pub facts: Option<Facts>,
+    pub queued: Vec<QueueEntry>,
+    pub last_run: Option<HostReport>,
+}
+
+impl Archive {
+    /// What the state directory holds on `host`.
+    pub fn collect(host: &Host) -> Result<Archive> {
+        let last_run = match report::last_run_path()?.exists() {
+            true => report::load_last()?
+                .hosts
+                .into_iter()
+                .find(|h| h.host.host == host.host),
+            false => None,
+        };
+        Ok(Archive {
+            host: host.clone(),
+            decommissioned_at: now(),
+            decommissioned_by: current_user(),
+            facts: facts::load_cached(&host.host),
+            queued: queue::load()?
+                .into_iter()
+                .filter(|entry| entry.host.host == host.host)
+                .collect(),
+            last_run,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.facts.is_none() && self.queued.is_empty() && self.last_run.is_none()
+    }
+
+    /// Writes the archive, then removes the host from the stores it was
+    /// collected from. Returns where the archive went.
+    pub fn store(&self) -> Result<PathBuf> {
+        let path = paths::state_path(ARCHIVE_DIR)?.join(format!(
+            "{}-{}.json",
+            paths::file_name(&self.host.host),
+            self.decommissioned_at
+        ));
+        let json = serde_json::to_vec_pretty(self).map_err(|e| AppError::Generic(e.to_string()))?;
+        paths::write_atomic(&path, &json)?;
+
+        facts::forget(&self.host.host)?;
+        queue::remove(&self.queued)?;
+        report::forget(&self.host.host)?;
+        Ok(path)
+    }
+}
+
+/// The names `host`'s key can be pinned under in `known_hosts`: the bare
+/// name for port 22, `[name]:port` otherwise.
+pub fn known_host_names(host: &Host) -> Vec<String> {
+    let mut names = vec![host.host.clone()];
+    if let Some(port) = host.port.filter(|&port| port != 22) {
+        names.push(format!("[{}]:{}", host.host, port));
+    }
+    names
+}
+
+/// Removes `host`'s keys from `known_hosts` with `ssh-keygen -R`, which
+/// finds hashed entries as well. Returns the names that had a key.
+pub fn forget_host_key(host: &Host, known_hosts: &Path) -> Result<Vec<String>> {
+    if !known_hosts.exists() {
+        return Ok(Vec::new());
+    }
+    let mut removed = Vec::new();
+    for name in known_host_names(host) {
+        let output = Command::new("ssh-keygen")
+            .arg("-R")
+            .arg(&name)
+            .arg("-f")
+            .arg(known_hosts)
+            .output()
+            .map_err(|e| AppError::Generic(format!("could not run ssh-keygen: {}", e)))?;
+        if !output.status.success() {
+            return Err(AppError::Generic(format!(
+                "ssh-keygen -R {} failed: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        if String::from_utf8_lossy(&output.stdout).contains("found") {
+            removed.push(name);
+        }
+    }
+    Ok(removed)
+}
+
+/// `russh decommission`: retires a host, farewell command first so a
+/// failing one leaves everything in place.
+pub fn command(cli: &Cli, args: &DecommissionArgs) -> Result<()> {
+    let config = load_config(&cli.config_file, false)?;
+    let path = config.path.clone().ok_or(ConfigError::NotFound)?;
+    let host = config.host(&args.host);
+    let listed = config.servers.iter().any(|s| s.host == host.host);
+    let archive = Archive::collect(&host)?;
+    if !listed && archive.is_empty() {
+        return Err(AppError::Generic(format!(
+            "{} is neither in {} nor in the state directory",
+            host.host,
+            path.display()
+        )));
+    }
+    let farewell = match args.no_farewell {
+        true => None,
+        false => args
+            .farewell
+            .clone()
+            .or_else(|| config.decommission_command.clone()),
+    };
+
+    if !args.yes {
+        println!("{}", trf(Msg::DecommissionPrompt, &[&host.host]));
+        if let Some(command) = &farewell {
+            println!("  run:      {}", command);
+        }
+        if listed {
+            println!("  remove:   from {}", path.display());
+        }
+        println!("  forget:   its host key");
+        if !archive.is_empty() {
+            println!("  archive:  its facts, queued commands and last run");
+        }
+        match prompt::confirm(tr(Msg::ConfirmPrompt))? {
+            Some(true) => {}
+            Some(false) => {
+                println!("{}", tr(Msg::NotDecommissioned));
+                return Ok(());
+            }
+            None => {
+                return Err(ExecutionError::Refused(
+                    "no terminal to confirm the decommission on; pass --yes".to_string(),
+                )
+                .into())
+            }
+        }
+    }
+
+    if let Some(command) = &farewell {
+        let options = exec_options(cli, &config)?;
+        let (tx, rx) = mpsc::channel();
+        let result = crate::ssh::run_ssh_command_blocking(&host, command, &options, tx);
+        for line in rx
+            .try_iter()
+            .filter_map(|event| event.line().map(str::to_string))
+        {
+            println!("[{}] {}", host.host, line);
+        }
+        if !result.success {
+            return Err(ExecutionError::Refused(format!(
+                "the farewell command failed on {} (exit code {}), nothing was removed; \
+                 pass --no-farewell to decommission it anyway",
+                host.host,
+                result.exit_code.map_or("?".to_string(), |c| c.to_string())
+            ))
+            .into());
+        }
+    }
+
+    if listed {
+        config::remove_server(&path, &host.host)?;
+        println!("Removed {} from {}", host.host, path.display());
+    }
+    let known_hosts = dirs::home_dir()
+        .map(|home| home.join(".ssh/known_hosts"))
+        .unwrap_or_default();
+    let keys = forget_host_key(&host, &known_hosts)?;
+    if !keys.is_empty() {
+        println!(
+            "Removed the host key of {} from {}",
+            keys.join(", "),
+            known_hosts.display()
+        );
+    }
+    let stored = match archive.is_empty() {
+        true => None,
+        false => Some(archive.store()?),
+    };
+    if let Some(stored) = &stored {
+        println!("Archived its state in {}", stored.display());
+    }
+    audit::record(
+        "decommissioned",
+        serde_json::json!({
+            "host": host.host,
+            "farewell": farewell,
+            "host_keys": keys,
+            "archive": stored,
+        }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_host_names() {
+        assert_eq!(known_host_names(&Host::new("web01")), ["web01"]);
+        let db: Host = "db01 port=2222".parse().unwrap();
+        assert_eq!(known_host_names(&db), ["db01", "[db01]:2222"]);
+        let ssh: Host = "db02 port=22".parse().unwrap();
+        assert_eq!(known_host_names(&ssh), ["db02"]);
+    }
+}
@@ -0,0 +1,165 @@
+//! `russh deploy`: the usual upload-then-install done as one step per host.
+//! The artifact goes to a fresh temporary directory through the verified
+//! push, the install command runs against it, and on failure the optional
+//! rollback command runs before the directory is removed.
+
+use crate::cli::{
+    admit, display_mode, exec_options, inventory, report, schedule, sinks, Cli, DeployArgs,
+};
+use crate::config::Host;
+use crate::errors::Result;
+use crate::executor::execute_blocking_jobs;
+use crate::ssh::{run_ssh_command_blocking, shell_quote, Event, ExecOptions, ServerResult};
+use crate::transfer::{self, Source};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+
+/// What to run on each host once the artifact is in place. Both commands
+/// see the uploaded file's path in `$ARTIFACT` and run from its directory.
+#[derive(Clone, Debug)]
+pub struct Plan {
+    pub install: String,
+    pub rollback: Option<String>,
+}
+
+/// `command` with `$ARTIFACT` set, run from the artifact's directory.
+fn with_artifact(dir: &str, artifact: &str, command: &str) -> String {
+    format!(
+        "ARTIFACT={}; export ARTIFACT; cd {} && {}",
+        shell_quote(artifact),
+        shell_quote(dir),
+        command
+    )
+}
+
+/// Deploys `source` to `host`: upload, verify, install, and roll back if
+/// any of it fails. The result is the install's, or the first failed step's,
+/// with the time of all of them.
+pub fn deploy(
+    host: &Host,
+    source: &Source,
+    plan: &Plan,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let login = transfer::login_user(host);
+    let (quiet, _) = mpsc::channel();
+    let staged = run_ssh_command_blocking(&login, "mktemp -d", options, quiet);
+    let dir = staged.output.trim().to_string();
+    if !staged.success || dir.is_empty() {
+        return failed(staged, "could not create a temporary directory");
+    }
+    let mut duration = staged.duration;
+    let cleanup = |tx: Sender<Event>| {
+        // Escalated, since the install may have left root-owned files.
+        run_ssh_command_blocking(host, &format!("rm -rf {}", shell_quote(&dir)), options, tx)
+    };
+
+    let uploaded = transfer::push(&login, source, &dir, options, tx.clone());
+    duration += uploaded.duration;
+    if !uploaded.success {
+        duration += cleanup(tx).duration;
+        return ServerResult {
+            duration,
+            ..failed(uploaded, "upload failed, nothing was installed")
+        };
+    }
+
+    let artifact = format!("{}/{}", dir, source.name());
+    let install = run_ssh_command_blocking(
+        host,
+        &with_artifact(&dir, &artifact, &plan.install),
+        options,
+        tx.clone(),
+    );
+    duration += install.duration;
+    let mut result = ServerResult {
+        changed: install.success,
+        transfer: uploaded.transfer,
+        ..install
+    };
+    if !result.success {
+        let note = match &plan.rollback {
+            Some(rollback) => {
+                let rolled_back = run_ssh_command_blocking(
+                    host,
+                    &with_artifact(&dir, &artifact, rollback),
+                    options,
+                    tx.clone(),
+                );
+                duration += rolled_back.duration;
+                if rolled_back.success {
+                    "install failed, rolled back"
+                } else {
+                    "install failed, and so did the rollback"
+                }
+            }
+            None => "install failed, no rollback command given",
+        };
+        result = failed(result, note);
+    }
+    duration += cleanup(tx).duration;
+    ServerResult { duration, ..result }
+}
+
+/// `result` marked as failed, with `note` after its error output.
+fn failed(mut result: ServerResult, note: &str) -> ServerResult {
+    let error = result.error.get_or_insert_with(String::new);
+    if !error.is_empty() && !error.ends_with('\n') {
+        error.push('\n');
+    }
+    error.push_str(note);
+    error.push('\n');
+    result.success = false;
+    result.changed = false;
+    result
+}
+
+/// `russh deploy`: uploads, verifies and installs an artifact on every
+/// selected host, rolling back where the install fails.
+pub fn command(cli: &Cli, args: &DeployArgs) -> Result<()> {
+    let (config, hosts) = inventory(cli)?;
+    let schedule = schedule(cli, &config)?;
+    let label: Arc<str> = format!("deploy {}", args.artifact.display()).into();
+    let source = Arc::new(Source::open(&args.artifact)?);
+    // The approval covers the artifact's content and what runs on the
+    // hosts, not just the artifact's path.
+    let mut commands = vec![
+        format!("{} (sha256 {})", label, source.checksum()),
+        args.install_cmd.clone(),
+    ];
+    commands.extend(args.rollback_cmd.clone());
+    let _lock = admit(cli, &config, &hosts, &commands)?;
+    let jobs = hosts
+        .into_iter()
+        .map(|host| (Arc::new(host), Arc::clone(&label)))
+        .collect();
+    let plan = Plan {
+        install: args.install_cmd.clone(),
+        rollback: args.rollback_cmd.clone(),
+    };
+    let options = exec_options(cli, &config)?;
+    let sinks = sinks(cli, &config)?;
+    let results = execute_blocking_jobs(
+        jobs,
+        move |host, _, tx| deploy(host, &source, &plan, &options, tx),
+        Some(&sinks),
+        display_mode(cli, &config)?,
+        schedule,
+    );
+    report(cli, &results, sinks);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_with_artifact() {
+        assert_eq!(
+            with_artifact("/tmp/tmp.x1", "/tmp/tmp.x1/app 1.deb", "dpkg -i \"$ARTIFACT\""),
+            "ARTIFACT='/tmp/tmp.x1/app 1.deb'; export ARTIFACT; cd /tmp/tmp.x1 && dpkg -i \"$ARTIFACT\""
+        );
+    }
+}
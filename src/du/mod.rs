@@ -0,0 +1,338 @@
+//! `russh du [PATH]`: how full the disks of every selected host are. Each
+//! host reports the filesystem holding PATH (`/` without one) from `df`,
+//! and with a PATH also its size from `du`. Hosts are listed fullest first,
+//! and those at or above the threshold are flagged and count as failed for
+//! the exit code, so a cron job can alert on them.
+
+use crate::cli::{exec_options, inventory, require_client, Cli, DuArgs};
+use crate::config::Host;
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::exit;
+use crate::output::OutputFormat;
+use crate::preflight::format_bytes;
+use crate::runtime;
+use crate::ssh::{run_ssh_command, shell_quote, Event, ExecOptions, ServerResult};
+use crate::summary::color_enabled;
+use ansi_term::Color::Red;
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::sync::mpsc;
+
+/// How full a filesystem may be, in percent, before its host is flagged.
+pub const DEFAULT_THRESHOLD: u8 = 90;
+
+/// Prefix of the probe's output lines, so login banners are never taken
+/// for sizes.
+const PREFIX: &str = "russh-du.";
+
+/// A filesystem as `df` sees it, sizes in KiB.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Disk {
+    pub filesystem: String,
+    pub mount: String,
+    pub size_kb: u64,
+    pub used_kb: u64,
+    pub avail_kb: u64,
+    pub percent: u8,
+}
+
+/// One host's answer.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Usage {
+    pub host: String,
+    pub disk: Option<Disk>,
+    /// What `du` counted under the path, in KiB.
+    pub path_kb: Option<u64>,
+    /// Whether the filesystem is at or above the threshold.
+    pub over: bool,
+    /// Why nothing was measured.
+    pub error: Option<String>,
+}
+
+/// The remote command. Fields are picked apart with `read` so hosts
+/// without awk answer too; `du` errors for unreadable directories are
+/// dropped, its total still counts what could be read.
+fn probe(path: Option<&str>) -> String {
+    let target = shell_quote(path.unwrap_or("/"));
+    let mut probe = format!(
+        "df -Pk {} | {{ read -r _; read -r fs size used avail pct mount; \
+         echo \"{}df=$fs $size $used $avail $pct $mount\"; }}",
+        target, PREFIX
+    );
+    if path.is_some() {
+        probe.push_str(&format!(
+            "; du -sk {} 2>/dev/null | {{ read -r size _; echo \"{}du=$size\"; }}",
+            target, PREFIX
+        ));
+    }
+    probe
+}
+
+fn parse_disk(line: &str) -> Option<Disk> {
+    let mut fields = line.splitn(6, ' ');
+    let mut next = || fields.next().filter(|field| !field.is_empty());
+    let filesystem = next()?.to_string();
+    let size_kb = next()?.parse().ok()?;
+    let used_kb = next()?.parse().ok()?;
+    let avail_kb = next()?.parse().ok()?;
+    let percent = next()?.trim_end_matches('%').parse().ok()?;
+    Some(Disk {
+        filesystem,
+        size_kb,
+        used_kb,
+        avail_kb,
+        percent,
+        mount: next()?.to_string(),
+    })
+}
+
+fn usage(result: &ServerResult, lines: &[String], threshold: u8) -> Usage {
+    let value = |name: &str| {
+        lines.iter().find_map(|line| {
+            line.trim_end()
+                .strip_prefix(PREFIX)?
+                .strip_prefix(name)?
+                .strip_prefix('=')
+        })
+    };
+    let disk = value("df").and_then(parse_disk);
+    let error = match disk {
+        Some(_) => None,
+        None => Some(
+            result
+                .error
+                .as_deref()
+                .and_then(|error| error.lines().map(str::trim).find(|l| !l.is_empty()))
+                .unwrap_or("df printed nothing")
+                .to_string(),
+        ),
+    };
+    Usage {
+        host: result.server.clone(),
+        over: disk.as_ref().is_some_and(|disk| disk.percent >= threshold),
+        path_kb: value("du").and_then(|size| size.parse().ok()),
+        disk,
+        error,
+    }
+}
+
+/// Fullest first; hosts that couldn't be measured last.
+fn sort(usages: &mut [Usage]) {
+    usages.sort_by_key(|usage| {
+        (
+            Reverse(usage.disk.as_ref().map(|disk| disk.percent)),
+            usage.host.clone(),
+        )
+    });
+}
+
+/// Measures every host in parallel and records hosts that couldn't be
+/// measured or are too full as failed.
+pub fn gather(
+    hosts: &[Host],
+    path: Option<&str>,
+    options: &ExecOptions,
+    threshold: u8,
+) -> Vec<Usage> {
+    let command = probe(path);
+    let measured = runtime::block_on(async {
+        let probes: Vec<_> = hosts
+            .iter()
+            .filter(|host| !host.shell().is_windows())
+            .cloned()
+            .map(|host| {
+                let options = options.clone();
+                let command = command.clone();
+                tokio::spawn(async move {
+                    let (tx, rx) = mpsc::channel();
+                    let result = run_ssh_command(&host, &command, &options, tx).await;
+                    let lines: Vec<String> =
+                        rx.into_iter().filter_map(Event::into_stdout).collect();
+                    (result, lines)
+                })
+            })
+            .collect();
+        let mut done = Vec::with_capacity(probes.len());
+        for probe in probes {
+            done.push(probe.await);
+        }
+        done
+    });
+    let mut usages = Vec::with_capacity(hosts.len());
+    let mut results = Vec::with_capacity(hosts.len());
+    for (mut result, lines) in measured.into_iter().filter_map(|probe| probe.ok()) {
+        let usage = usage(&result, &lines, threshold);
+        result.success = usage.disk.is_some() && !usage.over;
+        results.push(("du".to_string(), result));
+        usages.push(usage);
+    }
+    usages.extend(
+        hosts
+            .iter()
+            .filter(|host| host.shell().is_windows())
+            .map(|host| Usage {
+                host: host.host.clone(),
+                disk: None,
+                path_kb: None,
+                over: false,
+                error: Some("disk usage is only gathered on POSIX hosts".to_string()),
+            }),
+    );
+    exit::record(&results);
+    sort(&mut usages);
+    usages
+}
+
+fn kb(size: u64) -> String {
+    format_bytes(size as f64 * 1024.0)
+}
+
+pub fn print(usages: &[Usage], path: Option<&str>, threshold: u8, json: bool) -> Result<()> {
+    if json {
+        let json =
+            serde_json::to_string_pretty(usages).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let width = |column: &dyn Fn(&Usage) -> usize, title: &str| {
+        usages
+            .iter()
+            .map(column)
+            .max()
+            .unwrap_or(0)
+            .max(title.len())
+    };
+    let host_width = width(&|u| u.host.len(), "HOST");
+    let mount_width = width(&|u| u.disk.as_ref().map_or(0, |d| d.mount.len()), "MOUNT");
+    let mut header = format!(
+        "{:<host_width$}  {:<mount_width$}  {:>8}  {:>8}  {:>8}  {:>4}",
+        "HOST", "MOUNT", "SIZE", "USED", "AVAIL", "USE%"
+    );
+    let path_width = path.map_or(0, |path| path.len().max(8));
+    if let Some(path) = path {
+        header.push_str(&format!("  {:>path_width$}", path));
+    }
+    println!("{}", header.trim_end());
+    let color = color_enabled();
+    for usage in usages {
+        let Some(disk) = &usage.disk else {
+            println!(
+                "{:<host_width$}  {}",
+                usage.host,
+                usage.error.as_deref().unwrap_or_default()
+            );
+            continue;
+        };
+        let mut line = format!(
+            "{:<host_width$}  {:<mount_width$}  {:>8}  {:>8}  {:>8}  {:>3}%",
+            usage.host,
+            disk.mount,
+            kb(disk.size_kb),
+            kb(disk.used_kb),
+            kb(disk.avail_kb),
+            disk.percent
+        );
+        if path.is_some() {
+            let size = usage.path_kb.map_or("-".to_string(), kb);
+            line.push_str(&format!("  {:>path_width$}", size));
+        }
+        if usage.over {
+            let flag = format!("over {}%", threshold);
+            line.push_str("  ");
+            match color {
+                true => line.push_str(&Red.paint(flag).to_string()),
+                false => line.push_str(&flag),
+            }
+        }
+        println!("{}", line);
+    }
+    let over = usages.iter().filter(|usage| usage.over).count();
+    let measured = usages.iter().filter(|usage| usage.disk.is_some()).count();
+    println!("{}/{} host(s) at or above {}%", over, measured, threshold);
+    Ok(())
+}
+
+/// `russh du`: prints how full the selected hosts' disks are.
+pub fn command(cli: &Cli, args: &DuArgs) -> Result<()> {
+    if args.threshold > 100 {
+        return Err(ExecutionError::InvalidOption {
+            option: "--threshold",
+            reason: format!("must be a percentage up to 100, got {}", args.threshold),
+        }
+        .into());
+    }
+    let (config, hosts) = inventory(cli)?;
+    require_client(cli.backend)?;
+    // Run like any command, under --sudo, --nice and --ionice, but untimed
+    // and with nothing to type.
+    let options = ExecOptions {
+        remote_timing: false,
+        expect: None,
+        ..exec_options(cli, &config)?
+    };
+    let path = args.path.as_deref();
+    let usages = gather(&hosts, path, &options, args.threshold);
+    print(
+        &usages,
+        path,
+        args.threshold,
+        cli.output != OutputFormat::Text,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage() {
+        let result = |server: &str, error: &str| ServerResult {
+            server: server.to_string(),
+            error: Some(error.to_string()),
+            duration: 0.2,
+            success: true,
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        let lines = |lines: &[&str]| lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+
+        let full = usage(
+            &result("web01", ""),
+            &lines(&[
+                "Welcome to web01",
+                "russh-du.df=/dev/sda1 41152736 39000000 2152736 95% /var/lib/docker data\r",
+                "russh-du.du=1048576",
+            ]),
+            DEFAULT_THRESHOLD,
+        );
+        let disk = full.disk.as_ref().unwrap();
+        assert_eq!(disk.percent, 95);
+        assert_eq!(disk.mount, "/var/lib/docker data");
+        assert_eq!(full.path_kb, Some(1048576));
+        assert!(full.over);
+
+        let roomy = usage(
+            &result("web02", ""),
+            &lines(&["russh-du.df=overlay 1000 420 580 42% /"]),
+            DEFAULT_THRESHOLD,
+        );
+        assert!(!roomy.over);
+        assert_eq!(roomy.path_kb, None);
+
+        let missing = usage(
+            &result("web03", "df: /srv: No such file or directory\n"),
+            &lines(&["russh-du.du="]),
+            DEFAULT_THRESHOLD,
+        );
+        assert_eq!(missing.disk, None);
+        assert_eq!(
+            missing.error.as_deref(),
+            Some("df: /srv: No such file or directory")
+        );
+
+        let mut usages = vec![missing, roomy, full];
+        sort(&mut usages);
+        let hosts: Vec<&str> = usages.iter().map(|u| u.host.as_str()).collect();
+        assert_eq!(hosts, ["web01", "web02", "web03"]);
+    }
+}
@@ -0,0 +1,124 @@
+//! Error types. `AppError` is what every fallible function returns; the
+//! domain-specific enums underneath carry the context (file, line, host)
+//! needed to tell the user exactly what went wrong and where.
+
+use crate::i18n::{tr, trf, Msg};
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+pub type Result<T> = std::result::Result<T, AppError>;
+
+#[derive(Debug, Error)]
+pub enum AppError {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Inventory(#[from] InventoryError),
+    #[error(transparent)]
+    Transport(#[from] TransportError),
+    #[error(transparent)]
+    Execution(#[from] ExecutionError),
+    #[error("{}", trf(Msg::FileError, &[.0]))]
+    File(#[from] io::Error),
+    #[error("{}", trf(Msg::TomlError, &[.0]))]
+    TomlSerializationError(#[from] toml::ser::Error),
+    #[error("{0}")]
+    Generic(String),
+}
+
+/// Finding, reading and parsing the configuration file.
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("{}", tr(Msg::ConfigNotFound))]
+    NotFound,
+    #[error("{}: {}", tr(Msg::InvalidConfigPath), .0.display())]
+    InvalidPath(PathBuf),
+    #[error("{}: {}: {source}", tr(Msg::ConfigReadFailed), .file.display())]
+    Read { file: PathBuf, source: io::Error },
+    #[error("{}:{}: {message}", .file.display(), .line.map_or(String::from("?"), |l| l.to_string()))]
+    Parse {
+        file: PathBuf,
+        line: Option<usize>,
+        message: String,
+    },
+}
+
+impl ConfigError {
+    pub fn parse(file: impl Into<PathBuf>, err: toml::de::Error) -> ConfigError {
+        ConfigError::Parse {
+            file: file.into(),
+            line: err.line_col().map(|(line, _)| line + 1),
+            message: err.to_string(),
+        }
+    }
+}
+
+/// Which hosts to run against: host specs, groups and their members.
+#[derive(Debug, Error)]
+pub enum InventoryError {
+    #[error("{}", trf(Msg::InvalidHostSpec, &[.spec, .reason]))]
+    HostSpec { spec: String, reason: String },
+    #[error("{}", trf(Msg::UnknownGroup, &[.name, .known]))]
+    UnknownGroup { name: String, known: String },
+    #[error("{}", trf(Msg::UnknownSet, &[.name, .known]))]
+    UnknownSet { name: String, known: String },
+    #[error("{}", trf(Msg::MembersCommand, &[.group, .reason]))]
+    MembersCommand { group: String, reason: String },
+    #[error("{}", trf(Msg::NoneSelected, &[.0]))]
+    NoneSelected(String),
+}
+
+/// Getting bytes to and from a host or URL.
+#[derive(Debug, Error)]
+pub enum TransportError {
+    #[error("{}", trf(Msg::SpawnFailed, &[.program, .source]))]
+    Spawn {
+        program: &'static str,
+        source: io::Error,
+    },
+    #[error(
+        "{}{}",
+        tr(Msg::SshMissing),
+        if cfg!(feature = "native") { tr(Msg::SshMissingNative) } else { "" }
+    )]
+    SshMissing,
+    #[error("{host}: {message}")]
+    Connection { host: String, message: String },
+    #[error("{}", trf(Msg::RequestFailed, &[.url, .message]))]
+    Http { url: String, message: String },
+}
+
+/// Deciding whether and how to run: options, locks and approvals.
+#[derive(Debug, Error)]
+pub enum ExecutionError {
+    #[error("{option}: {reason}")]
+    InvalidOption {
+        option: &'static str,
+        reason: String,
+    },
+    #[error("{0}")]
+    Refused(String),
+    #[error("{host}: {message}")]
+    Host { host: String, message: String },
+    #[error("{}", trf(Msg::Offline, &[.0]))]
+    Offline(String),
+    #[error("{}", trf(Msg::UnknownTask, &[.name, .known]))]
+    UnknownTask { name: String, known: String },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_error_has_file_and_line() {
+        let err = toml::from_str::<toml::Value>("a = 1\nb = ").unwrap_err();
+        let err = ConfigError::parse("/etc/russh.toml", err);
+        match &err {
+            ConfigError::Parse { line, .. } => assert_eq!(*line, Some(2)),
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert!(err.to_string().starts_with("/etc/russh.toml:2: "));
+    }
+}
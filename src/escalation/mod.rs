@@ -0,0 +1,317 @@
+//! Running remote commands as another user (root by default) through the
+//! host's privilege escalation tool. Everything that runs commands with a
+//! run's `ExecOptions` goes through here, so `--become` and `--sudo` mean
+//! the same for commands, file transfers and rerun-failed.
+
+use crate::audit;
+use crate::auth::AuthHandler;
+use crate::errors::{AppError, InventoryError, Result};
+use crate::ssh::shell_quote;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// How a host escalates privileges. BSDs tend to have `doas`, older
+/// estates `su` or PowerBroker's `pbrun`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum BecomeMethod {
+    #[default]
+    Sudo,
+    Doas,
+    Su,
+    Pbrun,
+}
+
+impl fmt::Display for BecomeMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            BecomeMethod::Sudo => "sudo",
+            BecomeMethod::Doas => "doas",
+            BecomeMethod::Su => "su",
+            BecomeMethod::Pbrun => "pbrun",
+        })
+    }
+}
+
+impl FromStr for BecomeMethod {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "sudo" => Ok(BecomeMethod::Sudo),
+            "doas" => Ok(BecomeMethod::Doas),
+            "su" => Ok(BecomeMethod::Su),
+            "pbrun" => Ok(BecomeMethod::Pbrun),
+            _ => Err(InventoryError::HostSpec {
+                spec: format!("become_method={}", name),
+                reason: "become_method must be sudo, doas, su or pbrun".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// The prompt russh asks escalation tools to print, so a password request
+/// can be recognised in the output.
+pub const PASSWORD_PROMPT: &str = "[russh-become-password]";
+
+/// The escalation password entered for `--sudo`. Only ever written to the
+/// stdin of `sudo -S`, and kept out of `Debug` output.
+#[derive(Clone, PartialEq)]
+pub struct Password(Arc<str>);
+
+impl Password {
+    /// The line fed to `sudo -S`.
+    pub fn stdin_line(&self) -> String {
+        format!("{}\n", self.0)
+    }
+}
+
+impl fmt::Debug for Password {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Password(..)")
+    }
+}
+
+/// Asks `auth` for the escalation password once; the terminal asks
+/// without echoing it, and only when there is one. An empty answer means
+/// none is needed.
+pub fn ask_password(auth: &dyn AuthHandler) -> Option<Password> {
+    auth.password("become password (Enter for none): ")
+        .filter(|p| !p.is_empty())
+        .map(|p| Password(p.into()))
+}
+
+/// Written after the sudo password when the command reads stdin too: the
+/// command skips everything up to it, so the password never reaches the
+/// command, whether sudo read it or not.
+pub const STDIN_MARKER: &str = "__RUSSH_STDIN__";
+
+/// `command` run through `method` as `user` (root when unset). sudo and
+/// doas are told not to prompt, so a missing password fails at once
+/// instead of hanging; su and pbrun have no such switch and fail for want
+/// of a terminal instead. With `password`, sudo reads it from stdin
+/// instead, and the command itself gets none, so the password can't leak
+/// into it when sudo doesn't ask. A command that reads `stdin` (a script
+/// for `sh -s`) gets what follows [`STDIN_MARKER`] instead.
+pub fn wrap(
+    method: BecomeMethod,
+    user: Option<&str>,
+    command: &str,
+    password: bool,
+    stdin: bool,
+) -> String {
+    let user = user.unwrap_or("root");
+    if password && method == BecomeMethod::Sudo {
+        // `read` never takes more than its line from a pipe, so the rest
+        // is left for the command.
+        let skip = match stdin {
+            true => format!(
+                "while IFS= read -r line && [ \"$line\" != {} ]; do :; done",
+                STDIN_MARKER
+            ),
+            false => "exec </dev/null".to_string(),
+        };
+        return format!(
+            "sudo -S -k -p '' -u {} -- sh -c {}",
+            shell_quote(user),
+            shell_quote(&format!("{}; {}", skip, command))
+        );
+    }
+    let command = shell_quote(command);
+    match method {
+        BecomeMethod::Sudo => format!(
+            "sudo -n -p {} -u {} -- sh -c {}",
+            shell_quote(PASSWORD_PROMPT),
+            shell_quote(user),
+            command
+        ),
+        BecomeMethod::Doas => format!("doas -n -u {} sh -c {}", shell_quote(user), command),
+        BecomeMethod::Su => format!("su {} -c {}", shell_quote(user), command),
+        BecomeMethod::Pbrun => format!("pbrun -u {} sh -c {}", shell_quote(user), command),
+    }
+}
+
+/// Exit code of ssh itself failing, before anything ran on the host.
+const SSH_FAILED: i32 = 255;
+
+/// How an escalation went on one host, as results and the audit log
+/// record it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Escalation {
+    pub method: BecomeMethod,
+    /// Who the command was to run as.
+    pub become_user: String,
+    /// Whether the escalation tool ran the command.
+    pub granted: bool,
+    /// The tool's own words when it refused, e.g. sudo's
+    /// `3 incorrect password attempts`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl Escalation {
+    /// Records the escalation in the audit log.
+    pub fn audit(&self, host: &str, command: &str) -> Result<()> {
+        #[derive(Serialize)]
+        struct Escalated<'a> {
+            host: &'a str,
+            command: &'a str,
+            #[serde(flatten)]
+            escalation: &'a Escalation,
+        }
+        audit::record(
+            "escalation",
+            Escalated {
+                host,
+                command,
+                escalation: self,
+            },
+        )
+    }
+}
+
+/// What the escalation tools print when they won't run a command.
+const REFUSALS: [&str; 13] = [
+    PASSWORD_PROMPT,
+    "a password is required",
+    "incorrect password attempt",
+    "is not in the sudoers file",
+    "is not allowed to run sudo",
+    "is not allowed to execute",
+    "may not run sudo",
+    "doas: Authentication failed",
+    "doas: Authentication required",
+    "doas: Operation not permitted",
+    "su: Authentication failure",
+    "su: must be run from a terminal",
+    "Password:",
+];
+
+/// The line of `stderr` in which the escalation tool refused, if it did.
+fn refusal(stderr: &str) -> Option<&str> {
+    stderr
+        .lines()
+        .find(|line| REFUSALS.iter().any(|sign| line.contains(sign)))
+        .map(str::trim)
+}
+
+/// How an escalated command's escalation went, judged by its exit code and
+/// stderr. Unknown when ssh failed or the command was killed before the
+/// escalation tool said anything.
+pub fn outcome(
+    method: BecomeMethod,
+    user: Option<&str>,
+    exit_code: Option<i32>,
+    stderr: &str,
+) -> Option<Escalation> {
+    let refused = refusal(stderr);
+    let ran = exit_code.is_some_and(|code| code != SSH_FAILED);
+    if refused.is_none() && !ran {
+        return None;
+    }
+    Some(Escalation {
+        method,
+        become_user: user.unwrap_or("root").to_string(),
+        granted: refused.is_none(),
+        message: refused.map(|line| {
+            line.strip_prefix(PASSWORD_PROMPT)
+                .unwrap_or(line)
+                .trim()
+                .to_string()
+        }),
+    })
+}
+
+/// Whether `stderr` shows the escalation tool wanted a password.
+pub fn password_required(stderr: &str) -> bool {
+    const SIGNS: [&str; 7] = [
+        PASSWORD_PROMPT,
+        "a password is required",
+        "incorrect password attempt",
+        "doas: Authentication failed",
+        "doas: Authentication required",
+        "su: must be run from a terminal",
+        "Password:",
+    ];
+    SIGNS.iter().any(|sign| stderr.contains(sign))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap() {
+        assert_eq!(
+            wrap(BecomeMethod::Sudo, None, "apt-get update", false, false),
+            "sudo -n -p '[russh-become-password]' -u root -- sh -c 'apt-get update'"
+        );
+        assert_eq!(
+            wrap(BecomeMethod::Doas, Some("www"), "id -un", true, false),
+            "doas -n -u www sh -c 'id -un'"
+        );
+        assert_eq!(
+            wrap(BecomeMethod::Su, None, "echo 'hi'", false, false),
+            "su root -c 'echo '\\''hi'\\'''"
+        );
+        assert_eq!(
+            wrap(BecomeMethod::Sudo, Some("app"), "whoami", true, false),
+            "sudo -S -k -p '' -u app -- sh -c 'exec </dev/null; whoami'"
+        );
+        assert_eq!(
+            wrap(BecomeMethod::Sudo, None, "sh -s", true, true),
+            "sudo -S -k -p '' -u root -- sh -c 'while IFS= read -r line && \
+             [ \"$line\" != __RUSSH_STDIN__ ]; do :; done; sh -s'"
+        );
+        assert_eq!(format!("{:?}", Password("hunter2".into())), "Password(..)");
+        assert_eq!(
+            "pbrun".parse::<BecomeMethod>().unwrap(),
+            BecomeMethod::Pbrun
+        );
+        assert!("runas".parse::<BecomeMethod>().is_err());
+        assert!(password_required("sudo: a password is required\n"));
+        assert!(!password_required("permission denied\n"));
+    }
+
+    #[test]
+    fn test_outcome() {
+        let denied = outcome(
+            BecomeMethod::Sudo,
+            None,
+            Some(1),
+            "Sorry, try again.\nsudo: 3 incorrect password attempts\n",
+        )
+        .unwrap();
+        assert!(!denied.granted);
+        assert_eq!(denied.become_user, "root");
+        assert_eq!(
+            denied.message.as_deref(),
+            Some("sudo: 3 incorrect password attempts")
+        );
+
+        let not_allowed = outcome(
+            BecomeMethod::Sudo,
+            Some("app"),
+            Some(1),
+            "deploy is not in the sudoers file.  This incident will be reported.\n",
+        )
+        .unwrap();
+        assert!(!not_allowed.granted);
+
+        // The command itself failing still means sudo let it run.
+        let granted = outcome(
+            BecomeMethod::Doas,
+            None,
+            Some(2),
+            "ls: /nope: No such file\n",
+        );
+        assert_eq!(granted.map(|e| (e.granted, e.message)), Some((true, None)));
+        // Nothing is known when ssh never got through.
+        assert_eq!(outcome(BecomeMethod::Sudo, None, Some(255), ""), None);
+        assert_eq!(outcome(BecomeMethod::Sudo, None, None, ""), None);
+    }
+}
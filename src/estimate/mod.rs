@@ -0,0 +1,211 @@
+//! How long a run will take, from how long its commands took before. Every
+//! run's durations are kept per command and host; before the next run the
+//! jobs are laid out the way the executor will run them (`--parallel`
+//! slots, `--serial` batches, one host's commands in a row for tasks) to
+//! estimate its wall time.
+
+use crate::audit::now;
+use crate::errors::{AppError, Result};
+use crate::executor::{Job, Schedule};
+use crate::paths;
+use crate::ssh::ServerResult;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+
+/// The history's file in the state directory.
+const FILE: &str = "durations.json";
+
+/// Durations kept per command and host.
+const KEEP_RUNS: usize = 5;
+
+/// Commands kept; the ones unused the longest go first.
+const KEEP_COMMANDS: usize = 500;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Command {
+    /// When it last ran.
+    used: u64,
+    /// Seconds it took on each host, latest last.
+    hosts: BTreeMap<String, Vec<f64>>,
+}
+
+/// Earlier durations, by command.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    commands: HashMap<String, Command>,
+}
+
+fn median(durations: &[f64]) -> Option<f64> {
+    let mut sorted = durations.to_vec();
+    sorted.sort_by(f64::total_cmp);
+    sorted.get(sorted.len() / 2).copied()
+}
+
+impl History {
+    pub fn load() -> Result<History> {
+        match fs::read(paths::state_path(FILE)?) {
+            Ok(json) => serde_json::from_slice(&json).map_err(|e| {
+                AppError::Generic(format!("corrupt duration history {}: {}", FILE, e))
+            }),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(History::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Adds the durations of the jobs that ran in `results`.
+    fn add(&mut self, results: &[(String, ServerResult)], at: u64) {
+        for (command, result) in results.iter().filter(|(_, r)| r.cut.is_none()) {
+            let entry = self.commands.entry(command.clone()).or_default();
+            entry.used = at;
+            let durations = entry.hosts.entry(result.server.clone()).or_default();
+            durations.push(result.duration);
+            let extra = durations.len().saturating_sub(KEEP_RUNS);
+            durations.drain(..extra);
+        }
+        if self.commands.len() > KEEP_COMMANDS {
+            let mut used: Vec<u64> = self.commands.values().map(|c| c.used).collect();
+            used.sort_unstable();
+            let oldest_kept = used[used.len() - KEEP_COMMANDS];
+            self.commands.retain(|_, c| c.used >= oldest_kept);
+        }
+    }
+
+    /// How long `command` should take on `host`: its median there, else
+    /// the median over the hosts it ran on.
+    fn expected(&self, host: &str, command: &str) -> Option<f64> {
+        let entry = self.commands.get(command)?;
+        if let Some(durations) = entry.hosts.get(host) {
+            return median(durations);
+        }
+        let medians: Vec<f64> = entry.hosts.values().filter_map(|d| median(d)).collect();
+        median(&medians)
+    }
+}
+
+/// Adds the run's durations to the history.
+pub fn record(results: &[(String, ServerResult)]) -> Result<()> {
+    let path = paths::state_path(FILE)?;
+    let _lock = paths::lock(&path)?;
+    let mut history = History::load()?;
+    history.add(results, now());
+    let json = serde_json::to_vec(&history).map_err(|e| AppError::Generic(e.to_string()))?;
+    paths::write_atomic(&path, &json)
+}
+
+/// A run's expected wall time.
+#[derive(Debug, PartialEq)]
+pub struct Estimate {
+    pub seconds: f64,
+    /// Jobs with a history; the others are assumed to take as long as the
+    /// average of those.
+    pub known: usize,
+    pub jobs: usize,
+}
+
+/// When `units` finish, started in order as one of `slots` frees up.
+fn wall_time(units: &[f64], slots: usize) -> f64 {
+    let mut free = vec![0.0_f64; slots.clamp(1, units.len().max(1))];
+    for unit in units {
+        let (slot, _) = free
+            .iter()
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .expect("at least one slot");
+        free[slot] += unit;
+    }
+    free.into_iter().fold(0.0, f64::max)
+}
+
+/// Estimates `jobs` run with `schedule`; `None` without any history.
+pub fn estimate(jobs: &[Job], schedule: &Schedule, history: &History) -> Option<Estimate> {
+    let expected: Vec<Option<f64>> = jobs
+        .iter()
+        .map(|(host, command)| history.expected(&host.host, command))
+        .collect();
+    let known: Vec<f64> = expected.iter().flatten().copied().collect();
+    if known.is_empty() {
+        return None;
+    }
+    let average = known.iter().sum::<f64>() / known.len() as f64;
+
+    // Units as the executor forms them: single jobs, or each host's jobs.
+    let mut units: Vec<(&str, f64)> = Vec::new();
+    for ((host, _), expected) in jobs.iter().zip(&expected) {
+        let seconds = expected.unwrap_or(average);
+        match units.iter_mut().find(|(h, _)| *h == host.host) {
+            Some((_, total)) if schedule.serial.is_some() || schedule.in_order => *total += seconds,
+            _ => units.push((&host.host, seconds)),
+        }
+    }
+    let units: Vec<f64> = units.into_iter().map(|(_, seconds)| seconds).collect();
+    let seconds = match schedule.serial {
+        Some(size) => units
+            .chunks(size.max(1))
+            .map(|batch| wall_time(batch, batch.len()))
+            .sum(),
+        None => wall_time(&units, schedule.parallel),
+    };
+    Some(Estimate {
+        seconds,
+        known: known.len(),
+        jobs: jobs.len(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Host;
+    use std::sync::Arc;
+
+    fn result(server: &str, duration: f64) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            duration,
+            success: true,
+            exit_code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_estimate() {
+        let mut history = History::default();
+        let upgrade = "apt-get upgrade -y".to_string();
+        history.add(
+            &[
+                (upgrade.clone(), result("web01", 60.0)),
+                (upgrade.clone(), result("web02", 20.0)),
+                ("df -h".to_string(), result("web01", 1.0)),
+            ],
+            1,
+        );
+        history.add(&[(upgrade.clone(), result("web01", 40.0))], 2);
+        assert_eq!(history.expected("web01", &upgrade), Some(60.0));
+        assert_eq!(history.expected("web03", &upgrade), Some(60.0));
+        assert_eq!(history.expected("web03", "uptime"), None);
+
+        let jobs: Vec<Job> = ["web01", "web02", "web03", "web04"]
+            .into_iter()
+            .map(|host| (Arc::new(Host::new(host)), upgrade.as_str().into()))
+            .collect();
+        let at = |parallel, serial| {
+            let schedule = Schedule {
+                serial,
+                ..Schedule::parallel(parallel)
+            };
+            estimate(&jobs, &schedule, &history).unwrap().seconds
+        };
+        // web01 60s, web02 20s; web03 and web04 never ran it: the median
+        // over the hosts that did, 60s.
+        assert_eq!(at(1, None), 200.0);
+        assert_eq!(at(2, None), 120.0);
+        assert_eq!(at(10, None), 60.0);
+        assert_eq!(at(10, Some(1)), 200.0);
+        assert_eq!(at(10, Some(3)), 120.0);
+
+        let unknown: Vec<Job> = vec![(Arc::new(Host::new("web01")), "uptime".into())];
+        assert_eq!(estimate(&unknown, &Schedule::parallel(4), &history), None);
+    }
+}
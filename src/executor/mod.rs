@@ -0,0 +1,873 @@
+//! Running commands on many hosts at once: the worker pool behind every
+//! russh run, and [`Executor`], the way to drive it from other programs.
+//!
+//! ```no_run
+//! use russh::{Executor, Host};
+//!
+//! let results = Executor::new()
+//!     .hosts(["web01", "web02"].map(Host::new))
+//!     .command("uptime")
+//!     .parallel(8)
+//!     .timeout(30)
+//!     .run();
+//! for (command, result) in &results {
+//!     println!("{} {}: {}", result.server, command, result.success);
+//! }
+//! ```
+
+mod prefix;
+mod progress;
+mod repeats;
+
+use self::prefix::Prefixer;
+use self::progress::Progress;
+use self::repeats::Repeats;
+use crate::auth::{self, AuthHandler};
+use crate::config::{Config, Host};
+use crate::errors::{ExecutionError, Result};
+use crate::i18n::{trf, Msg};
+use crate::interrupt;
+use crate::runtime;
+use crate::sink::Sinks;
+use crate::ssh::{run_ssh_command, Cut, Event, ExecOptions, ServerResult};
+use crate::summary::color_enabled;
+#[cfg(feature = "tui")]
+use crate::tui;
+use crate::warnings::{warn, WarningKind};
+use std::future::Future;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+use tokio::sync::Semaphore;
+
+/// Jobs running at once when neither `--parallel` nor `max_parallel` is set.
+pub const DEFAULT_PARALLEL: usize = 64;
+
+/// How many jobs may run at once: `--parallel`, then the config's
+/// `max_parallel`, then the default.
+pub fn parallelism(flag: Option<usize>, config: Option<&Config>) -> Result<usize> {
+    let parallel = flag
+        .or_else(|| config.and_then(|c| c.max_parallel))
+        .unwrap_or(DEFAULT_PARALLEL);
+    if parallel == 0 {
+        return Err(ExecutionError::InvalidOption {
+            option: "--parallel",
+            reason: "must be at least 1".to_string(),
+        }
+        .into());
+    }
+    Ok(parallel)
+}
+
+/// How jobs are scheduled.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Schedule {
+    /// Jobs running at once.
+    pub parallel: usize,
+    /// `--serial`: servers done at a time, batch after batch.
+    pub serial: Option<usize>,
+    /// `--fail-fast`: start nothing new after a failure.
+    pub fail_fast: bool,
+    /// `--deadline`: start nothing new after this.
+    pub deadline: Option<Instant>,
+    /// Each server runs its commands one after another and stops at the
+    /// first that fails, as tasks do.
+    pub in_order: bool,
+}
+
+impl Schedule {
+    pub fn parallel(parallel: usize) -> Schedule {
+        Schedule {
+            parallel,
+            serial: None,
+            fail_fast: false,
+            deadline: None,
+            in_order: false,
+        }
+    }
+}
+
+/// How live output is shown while jobs run.
+#[derive(Clone, Debug)]
+pub enum Display {
+    /// Nothing, e.g. for `--output json`.
+    Quiet,
+    /// `[server] line`, as lines arrive; with `collapse`, runs of identical
+    /// lines from a host are shown as `line (xN)`, and with `progress`, how
+    /// many hosts are done below them.
+    Lines { collapse: bool, progress: bool },
+    /// The `--tui` dashboard.
+    #[cfg_attr(not(feature = "tui"), allow(dead_code))]
+    Dashboard,
+    /// Every event handed to the caller, as `Executor::start` does.
+    Forward(mpsc::Sender<Event>),
+}
+
+/// One command on one host. Hosts and commands are shared between the jobs
+/// that use them, so a large inventory doesn't copy them for every task.
+pub type Job = (Arc<Host>, Arc<str>);
+
+/// Runs the jobs as tasks on the shared runtime, at most `parallel` at
+/// once, while a display thread prints output. `run` does the actual work
+/// for one host, e.g. an ssh command; jobs that block go through
+/// [`execute_blocking_jobs`]. Results come back in job order.
+pub fn execute_jobs<F, Fut>(
+    jobs: Vec<Job>,
+    run: F,
+    sinks: Option<&Sinks>,
+    display: Display,
+    schedule: Schedule,
+) -> Vec<(String, ServerResult)>
+where
+    F: Fn(Arc<Host>, Arc<str>, mpsc::Sender<Event>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ServerResult> + Send + 'static,
+{
+    runtime::block_on(execute_jobs_async(jobs, run, sinks, display, schedule))
+}
+
+/// `execute_jobs` for callers already running async code.
+pub async fn execute_jobs_async<F, Fut>(
+    jobs: Vec<Job>,
+    run: F,
+    sinks: Option<&Sinks>,
+    display: Display,
+    schedule: Schedule,
+) -> Vec<(String, ServerResult)>
+where
+    F: Fn(Arc<Host>, Arc<str>, mpsc::Sender<Event>) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ServerResult> + Send + 'static,
+{
+    let _running = interrupt::Running::start();
+    let (tx, rx): (mpsc::Sender<Event>, Receiver<Event>) = mpsc::channel();
+    let rx = match sinks.filter(|sinks| sinks.streams()) {
+        Some(sinks) => sinks.sender().tee(rx),
+        None => rx,
+    };
+    // Finished jobs, for the dashboard's progress bar and summary.
+    let (done_tx, done_rx) = mpsc::channel::<(String, ServerResult)>();
+    let done_tx = matches!(display, Display::Dashboard).then_some(done_tx);
+
+    let progress = match display {
+        Display::Lines { progress: true, .. } => Some(Arc::new(Progress::new(
+            &jobs,
+            io::stdout().is_terminal() && io::stderr().is_terminal(),
+        ))),
+        _ => None,
+    };
+
+    // Start a thread for displaying outputs
+    let servers: Vec<String> = jobs.iter().map(|(host, _)| host.host.clone()).collect();
+    let shown = progress.clone();
+    let display = thread::spawn(move || match display {
+        Display::Quiet => rx.into_iter().for_each(drop),
+        Display::Lines { collapse, .. } => {
+            display_outputs(rx, &servers, collapse, shown.as_deref())
+        }
+        Display::Forward(out) => rx.into_iter().for_each(|event| {
+            let _ = out.send(event);
+        }),
+        #[cfg(feature = "tui")]
+        Display::Dashboard => {
+            let mut dashboard = tui::Dashboard::new(servers.clone());
+            match tui::run(&mut dashboard, &rx, &done_rx) {
+                Ok(true) => {}
+                Ok(false) => display_outputs(rx, &servers, false, None),
+                Err(e) => {
+                    warn(
+                        WarningKind::Delivery,
+                        format!("could not start the dashboard: {}", e),
+                    );
+                    display_outputs(rx, &servers, false, None);
+                }
+            }
+        }
+        #[cfg(not(feature = "tui"))]
+        Display::Dashboard => {
+            drop(done_rx);
+            display_outputs(rx, &servers, false, None)
+        }
+    });
+
+    let total = jobs.len();
+    let batches = batches(jobs, schedule);
+    let run = Arc::new(run);
+    let failed = Arc::new(AtomicBool::new(false));
+    let mut results = Vec::new();
+    // Why a job about to start shouldn't, if it shouldn't.
+    let stop = {
+        let failed = Arc::clone(&failed);
+        move || {
+            if interrupt::interrupted() {
+                Some(Cut::Interrupted)
+            } else if schedule.fail_fast && failed.load(Ordering::SeqCst) {
+                Some(Cut::FailFast)
+            } else if schedule.deadline.is_some_and(|d| Instant::now() >= d) {
+                Some(Cut::NotStarted)
+            } else {
+                None
+            }
+        }
+    };
+    for batch in batches {
+        if let Some(cut) = stop() {
+            results.extend(batch.into_iter().flatten().map(|(index, (host, command))| {
+                let result = ServerResult::skipped(&host.host, cut);
+                if let Some(progress) = &progress {
+                    progress.finish(&result);
+                }
+                (index, (command.to_string(), result))
+            }));
+            continue;
+        }
+        let slots = match schedule.serial {
+            Some(_) => batch.len(),
+            None => schedule.parallel.clamp(1, batch.len().max(1)),
+        };
+        // Units start in order as slots free up; each holds its slot until
+        // its last job is done.
+        let slots = Arc::new(Semaphore::new(slots));
+        let mut units = Vec::with_capacity(batch.len());
+        for unit in batch {
+            let Ok(slot) = Arc::clone(&slots).acquire_owned().await else {
+                break;
+            };
+            let failed = Arc::clone(&failed);
+            let stop = stop.clone();
+            let tx = tx.clone();
+            let sink_sender = sinks.map(Sinks::sender);
+            let done_tx = done_tx.clone();
+            let progress = progress.clone();
+            let run = Arc::clone(&run);
+
+            units.push(tokio::spawn(async move {
+                let _slot = slot;
+                let mut done = Vec::new();
+                let mut unit_failed = false;
+                let finish = |result: &ServerResult| {
+                    if let Some(progress) = &progress {
+                        progress.finish(result);
+                    }
+                };
+                for (index, (host, command)) in unit {
+                    let cut =
+                        stop().or((schedule.in_order && unit_failed).then_some(Cut::AfterFailure));
+                    if let Some(cut) = cut {
+                        let result = ServerResult::skipped(&host.host, cut);
+                        finish(&result);
+                        done.push((index, (command.to_string(), result)));
+                        continue;
+                    }
+                    // A panicking job fails its host instead of taking the
+                    // unit and its finished results down with it.
+                    let job = run(Arc::clone(&host), Arc::clone(&command), tx.clone());
+                    // Ctrl-C aborts the job, which kills its ssh process.
+                    let job = tokio::spawn(job);
+                    let abort = job.abort_handle();
+                    let Some(joined) = interrupt::unless_interrupted(job).await else {
+                        abort.abort();
+                        let result = ServerResult::skipped(&host.host, Cut::Interrupted);
+                        finish(&result);
+                        done.push((index, (command.to_string(), result)));
+                        continue;
+                    };
+                    let result = joined.unwrap_or_else(|_| ServerResult {
+                        server: host.host.clone(),
+                        error: Some("job panicked".to_string()),
+                        ..Default::default()
+                    });
+                    if !result.success {
+                        failed.store(true, Ordering::SeqCst);
+                        unit_failed = true;
+                    }
+                    let _ = tx.send(Event::completed(&result));
+                    if let Some(sender) = &sink_sender {
+                        sender.result(&command, &result);
+                    }
+                    if let Some(done) = &done_tx {
+                        let _ = done.send((command.to_string(), result.clone()));
+                    }
+                    finish(&result);
+                    done.push((index, (command.to_string(), result)));
+                }
+                done
+            }));
+        }
+
+        // Wait for the batch's units to complete
+        for unit in units {
+            match unit.await {
+                Ok(done) => results.extend(done),
+                Err(e) => eprintln!("{}", trf(Msg::ThreadJoinFailed, &[&e.to_string()])),
+            }
+        }
+    }
+    drop(tx);
+    drop(done_tx);
+    let cut = |kind| {
+        results
+            .iter()
+            .filter(|(_, (_, result))| result.cut == Some(kind))
+            .count()
+    };
+    let (not_started, cancelled) = (cut(Cut::NotStarted), cut(Cut::Cancelled));
+    if not_started + cancelled > 0 {
+        warn(
+            WarningKind::HostSkipped,
+            format!(
+                "--deadline: {} of {} job(s) not started, {} cancelled while running",
+                not_started, total, cancelled
+            ),
+        );
+    }
+    let interrupted = cut(Cut::Interrupted);
+    if interrupted > 0 {
+        warn(
+            WarningKind::HostSkipped,
+            format!(
+                "interrupted: {} of {} job(s) cancelled or not started",
+                interrupted, total
+            ),
+        );
+    }
+    let stopped = cut(Cut::FailFast);
+    if stopped > 0 {
+        warn(
+            WarningKind::HostSkipped,
+            format!(
+                "--fail-fast: stopped after a failure, {} of {} job(s) not started",
+                stopped, total
+            ),
+        );
+    }
+    let _ = tokio::task::spawn_blocking(move || display.join()).await;
+    if let Some(progress) = &progress {
+        progress.close();
+    }
+    results.sort_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// `execute_jobs` for jobs that block, like copies, deploys and probes:
+/// each runs on a thread of the runtime's blocking pool, so it doesn't hold
+/// up the async tasks.
+pub fn execute_blocking_jobs<F>(
+    jobs: Vec<Job>,
+    run: F,
+    sinks: Option<&Sinks>,
+    display: Display,
+    schedule: Schedule,
+) -> Vec<(String, ServerResult)>
+where
+    F: Fn(&Host, &str, mpsc::Sender<Event>) -> ServerResult + Send + Sync + 'static,
+{
+    let run = Arc::new(run);
+    let job = move |host: Arc<Host>, command: Arc<str>, tx| {
+        let run = Arc::clone(&run);
+        async move {
+            tokio::task::spawn_blocking(move || run(&host, &command, tx))
+                .await
+                .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()))
+        }
+    };
+    execute_jobs(jobs, job, sinks, display, schedule)
+}
+
+/// A group of jobs run in order by one task.
+type Unit = Vec<(usize, Job)>;
+
+/// Splits jobs into batches that run one after another. Normally that is a
+/// single batch of single jobs; with `--serial N` (or for a task) each unit
+/// is one server's jobs, and with `--serial N` each batch holds N servers.
+fn batches(jobs: Vec<Job>, schedule: Schedule) -> Vec<Vec<Unit>> {
+    let jobs = jobs.into_iter().enumerate();
+    if schedule.serial.is_none() && !schedule.in_order {
+        return vec![jobs.map(|job| vec![job]).collect()];
+    }
+    let mut units: Vec<Unit> = Vec::new();
+    for (index, (host, command)) in jobs {
+        match units.iter_mut().find(|unit| unit[0].1 .0.host == host.host) {
+            Some(unit) => unit.push((index, (host, command))),
+            None => units.push(vec![(index, (host, command))]),
+        }
+    }
+    let Some(size) = schedule.serial else {
+        return vec![units];
+    };
+    let mut batches = Vec::new();
+    let mut units = units.into_iter().peekable();
+    while units.peek().is_some() {
+        batches.push(units.by_ref().take(size).collect());
+    }
+    batches
+}
+
+/// Prints output lines tagged with their server as they arrive, holding
+/// the stdout lock for each burst and flushing only once the channel is
+/// drained. Lines are only ever written whole, so output from different
+/// servers never mixes within a line.
+fn display_outputs(
+    rx: Receiver<Event>,
+    servers: &[String],
+    collapse: bool,
+    progress: Option<&Progress>,
+) {
+    let mut prefixer = Prefixer::new(servers.iter().map(String::as_str), color_enabled());
+    let mut repeats = collapse.then(Repeats::default);
+    let stdout = io::stdout();
+    let mut burst = |first: Event| {
+        let mut out = stdout.lock();
+        let mut next = Some(first);
+        while let Some(event) = next {
+            let server = event.server();
+            let lines = match (&mut repeats, event.line()) {
+                (_, None) => Vec::new(),
+                (Some(repeats), Some(line)) => repeats.line(server, line),
+                (None, Some(line)) => vec![line.to_string()],
+            };
+            for line in lines {
+                let _ = writeln!(out, "{}", prefixer.line(server, &line));
+            }
+            next = rx.try_recv().ok();
+        }
+        let _ = out.flush();
+    };
+    while let Ok(first) = rx.recv() {
+        match progress {
+            Some(progress) => progress.suspend(|| burst(first)),
+            None => burst(first),
+        }
+    }
+    let mut out = stdout.lock();
+    for (server, line) in repeats.iter_mut().flat_map(Repeats::finish) {
+        let _ = writeln!(out, "{}", prefixer.line(&server, &line));
+    }
+    let _ = out.flush();
+}
+
+/// Runs commands over ssh on a set of hosts, every command on every host,
+/// with the same worker pool as the `russh` command.
+#[derive(Clone, Debug)]
+pub struct Executor {
+    hosts: Vec<Host>,
+    commands: Vec<String>,
+    options: ExecOptions,
+    schedule: Schedule,
+}
+
+impl Default for Executor {
+    fn default() -> Executor {
+        Executor::new()
+    }
+}
+
+impl Executor {
+    pub fn new() -> Executor {
+        Executor {
+            hosts: Vec::new(),
+            commands: Vec::new(),
+            options: ExecOptions::default(),
+            schedule: Schedule::parallel(DEFAULT_PARALLEL),
+        }
+    }
+
+    /// Every configured server of `config`, with the config's
+    /// `max_parallel`, `retries` and `retry_delay`.
+    pub fn from_config(config: &Config) -> Executor {
+        let mut executor = Executor::new().hosts(config.hosts());
+        if let Some(parallel) = config.max_parallel {
+            executor = executor.parallel(parallel);
+        }
+        executor.options.retries = config.retries.unwrap_or_default();
+        if let Some(delay) = config.retry_delay {
+            executor.options.retry_delay = std::time::Duration::from_secs_f64(delay);
+        }
+        executor
+    }
+
+    pub fn host(mut self, host: Host) -> Executor {
+        self.hosts.push(host);
+        self
+    }
+
+    pub fn hosts(mut self, hosts: impl IntoIterator<Item = Host>) -> Executor {
+        self.hosts.extend(hosts);
+        self
+    }
+
+    pub fn command(mut self, command: impl Into<String>) -> Executor {
+        self.commands.push(command.into());
+        self
+    }
+
+    pub fn commands<S: Into<String>>(mut self, commands: impl IntoIterator<Item = S>) -> Executor {
+        self.commands.extend(commands.into_iter().map(Into::into));
+        self
+    }
+
+    /// Jobs running at once; at least one.
+    pub fn parallel(mut self, parallel: usize) -> Executor {
+        self.schedule.parallel = parallel.max(1);
+        self
+    }
+
+    /// Seconds a command may run before it is killed, on hosts without
+    /// their own `timeout`.
+    pub fn timeout(mut self, secs: u64) -> Executor {
+        self.options.timeout = Some(secs.max(1));
+        self
+    }
+
+    /// Runs each host's commands one after another, stopping a host at its
+    /// first failure.
+    pub fn in_order(mut self, in_order: bool) -> Executor {
+        self.schedule.in_order = in_order;
+        self
+    }
+
+    /// Starts nothing new once a command has failed.
+    pub fn fail_fast(mut self, fail_fast: bool) -> Executor {
+        self.schedule.fail_fast = fail_fast;
+        self
+    }
+
+    /// Answers authentication questions (passwords, passphrases, new host
+    /// keys) instead of the terminal.
+    pub fn auth(mut self, handler: impl AuthHandler + 'static) -> Executor {
+        self.options.auth = auth::Handler::new(handler);
+        self
+    }
+
+    /// Everything else about how commands run: backend, retries, escalation
+    /// and so on. Replaces what `timeout` and `auth` set.
+    pub fn options(mut self, options: ExecOptions) -> Executor {
+        self.options = options;
+        self
+    }
+
+    fn jobs(&self) -> Vec<Job> {
+        let commands: Vec<Arc<str>> = self.commands.iter().map(|c| c.as_str().into()).collect();
+        self.hosts
+            .iter()
+            .flat_map(|host| {
+                let host = Arc::new(host.clone());
+                commands
+                    .iter()
+                    .map(move |command| (Arc::clone(&host), Arc::clone(command)))
+            })
+            .collect()
+    }
+
+    /// Runs everything and returns each `(command, result)`, in host order
+    /// and then command order.
+    pub fn run(self) -> Vec<(String, ServerResult)> {
+        runtime::block_on(self.execute(Display::Quiet))
+    }
+
+    /// `run` for callers on a tokio runtime of their own, which must have
+    /// I/O and time enabled.
+    pub async fn run_async(self) -> Vec<(String, ServerResult)> {
+        self.execute(Display::Quiet).await
+    }
+
+    /// Starts running in the background. The returned [`Running`] yields
+    /// events as they arrive; [`Running::finish`] waits for the results.
+    pub fn start(self) -> Running {
+        let (tx, events) = mpsc::channel();
+        let handle = thread::spawn(move || runtime::block_on(self.execute(Display::Forward(tx))));
+        Running { events, handle }
+    }
+
+    /// Runs everything, handing each event (output lines, finished jobs)
+    /// to `on_event` as it arrives, and returns the results as `run` does.
+    pub fn run_with(self, mut on_event: impl FnMut(&Event)) -> Vec<(String, ServerResult)> {
+        let mut running = self.start();
+        for event in running.by_ref() {
+            on_event(&event);
+        }
+        running.finish()
+    }
+
+    async fn execute(self, display: Display) -> Vec<(String, ServerResult)> {
+        let jobs = self.jobs();
+        let options = Arc::new(self.options);
+        execute_jobs_async(
+            jobs,
+            move |host, command, tx| {
+                let options = Arc::clone(&options);
+                async move { run_ssh_command(&host, &command, &options, tx).await }
+            },
+            None,
+            display,
+            self.schedule,
+        )
+        .await
+    }
+}
+
+/// An [`Executor`] run in progress. Iterating it gives the [`Event`]s:
+/// output lines, tagged with their stream, and each job's completion.
+pub struct Running {
+    events: Receiver<Event>,
+    handle: thread::JoinHandle<Vec<(String, ServerResult)>>,
+}
+
+impl Iterator for Running {
+    type Item = Event;
+
+    fn next(&mut self) -> Option<Event> {
+        self.events.recv().ok()
+    }
+}
+
+impl Running {
+    /// Waits for every job and returns the results, as `Executor::run`.
+    pub fn finish(self) -> Vec<(String, ServerResult)> {
+        drop(self.events);
+        self.handle.join().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn test_execute_jobs_is_bounded_and_ordered() {
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let jobs: Vec<Job> = (0..12)
+            .map(|i| (Arc::new(Host::new(&format!("host{:02}", i))), "true".into()))
+            .collect();
+
+        let (running_job, peak_job) = (Arc::clone(&running), Arc::clone(&peak));
+        let results = execute_jobs(
+            jobs,
+            move |host, _, _| {
+                let (running_job, peak_job) = (Arc::clone(&running_job), Arc::clone(&peak_job));
+                async move {
+                    let now = running_job.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak_job.fetch_max(now, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                    running_job.fetch_sub(1, Ordering::SeqCst);
+                    ServerResult {
+                        server: host.host.clone(),
+                        success: true,
+                        exit_code: Some(0),
+                        ..Default::default()
+                    }
+                }
+            },
+            None,
+            Display::Quiet,
+            Schedule::parallel(3),
+        );
+
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        let servers: Vec<String> = results.into_iter().map(|(_, r)| r.server).collect();
+        let expected: Vec<String> = (0..12).map(|i| format!("host{:02}", i)).collect();
+        assert_eq!(servers, expected);
+        assert!(parallelism(Some(0), None).is_err());
+        assert_eq!(parallelism(None, None).unwrap(), DEFAULT_PARALLEL);
+    }
+
+    #[test]
+    fn test_forwarded_events() {
+        let (tx, events) = mpsc::channel();
+        let jobs: Vec<Job> = vec![(Arc::new(Host::new("web01")), "uptime".into())];
+        execute_jobs(
+            jobs,
+            |host, _, tx| async move {
+                let _ = tx.send(Event::stdout(&host.host, "up 3 days"));
+                let _ = tx.send(Event::stderr(&host.host, "warning: clock skew"));
+                ServerResult::skipped(&host.host, Cut::NotStarted)
+            },
+            None,
+            Display::Forward(tx),
+            Schedule::parallel(1),
+        );
+        let events: Vec<Event> = events.try_iter().collect();
+        assert_eq!(
+            events,
+            [
+                Event::stdout("web01", "up 3 days"),
+                Event::stderr("web01", "warning: clock skew"),
+                Event::Completed {
+                    server: "web01".to_string(),
+                    exit_code: None,
+                    success: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_thousands_of_jobs_at_once() {
+        let jobs: Vec<Job> = (0..5000)
+            .map(|i| {
+                (
+                    Arc::new(Host::new(&format!("host{:04}", i))),
+                    "sleep".into(),
+                )
+            })
+            .collect();
+        let start = Instant::now();
+        let results = execute_jobs(
+            jobs,
+            |host, _, _| async move {
+                tokio::time::sleep(Duration::from_millis(300)).await;
+                ServerResult::skipped(&host.host, Cut::NotStarted)
+            },
+            None,
+            Display::Quiet,
+            Schedule::parallel(5000),
+        );
+        assert_eq!(results.len(), 5000);
+        // All waiting at once, not in waves of a thread pool's size.
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_executor_jobs() {
+        let executor = Executor::new()
+            .hosts(["web01", "web02"].map(Host::new))
+            .commands(["uptime", "df -h"])
+            .parallel(0)
+            .timeout(30);
+        let jobs: Vec<String> = executor
+            .jobs()
+            .iter()
+            .map(|(host, command)| format!("{} {}", host.host, command))
+            .collect();
+        assert_eq!(
+            jobs,
+            ["web01 uptime", "web01 df -h", "web02 uptime", "web02 df -h"]
+        );
+        assert_eq!(executor.schedule.parallel, 1);
+        assert_eq!(executor.options.timeout, Some(30));
+    }
+
+    #[test]
+    fn test_serial_fail_fast() {
+        let jobs: Vec<Job> = ["web01", "web02", "web03"]
+            .iter()
+            .flat_map(|name| {
+                let host = Arc::new(Host::new(name));
+                ["check", "restart"].map(|command| (Arc::clone(&host), command.into()))
+            })
+            .collect();
+        let schedule = Schedule {
+            parallel: 64,
+            serial: Some(2),
+            fail_fast: true,
+            deadline: None,
+            in_order: false,
+        };
+        let sizes: Vec<Vec<usize>> = batches(jobs.clone(), schedule)
+            .iter()
+            .map(|batch| batch.iter().map(Vec::len).collect())
+            .collect();
+        assert_eq!(sizes, [vec![2, 2], vec![2]]);
+
+        // One at a time, so the failure on web02 stops everything after it.
+        let schedule = Schedule {
+            serial: Some(1),
+            ..schedule
+        };
+        let results = execute_jobs(
+            jobs,
+            |host, command, _| async move {
+                ServerResult {
+                    server: host.host.clone(),
+                    success: !(host.host == "web02" && &*command == "check"),
+                    exit_code: Some(0),
+                    ..Default::default()
+                }
+            },
+            None,
+            Display::Quiet,
+            schedule,
+        );
+        let ran: Vec<String> = results
+            .iter()
+            .filter(|(_, r)| r.cut.is_none())
+            .map(|(command, r)| format!("{} {}", r.server, command))
+            .collect();
+        assert_eq!(ran, ["web01 check", "web01 restart", "web02 check"]);
+        // The rest are still reported, as stopped by --fail-fast.
+        assert_eq!(results.len(), 6);
+        assert_eq!(results[5].1.cut, Some(Cut::FailFast));
+    }
+
+    #[test]
+    fn test_in_order_stops_a_host_at_its_failure() {
+        let jobs: Vec<Job> = ["web01", "web02"]
+            .iter()
+            .flat_map(|name| {
+                let host = Arc::new(Host::new(name));
+                ["git pull", "make", "systemctl restart app"]
+                    .map(|command| (Arc::clone(&host), command.into()))
+            })
+            .collect();
+        let schedule = Schedule {
+            in_order: true,
+            ..Schedule::parallel(64)
+        };
+        let results = execute_jobs(
+            jobs,
+            |host, command, _| async move {
+                ServerResult {
+                    server: host.host.clone(),
+                    success: !(host.host == "web01" && &*command == "make"),
+                    exit_code: Some(0),
+                    ..Default::default()
+                }
+            },
+            None,
+            Display::Quiet,
+            schedule,
+        );
+        let cuts: Vec<Option<Cut>> = results.iter().map(|(_, r)| r.cut).collect();
+        assert_eq!(
+            cuts,
+            [None, None, Some(Cut::AfterFailure), None, None, None]
+        );
+    }
+
+    #[test]
+    fn test_deadline_skips_unstarted_jobs() {
+        let jobs: Vec<Job> = ["web01", "web02", "web03"]
+            .iter()
+            .map(|name| (Arc::new(Host::new(name)), "upgrade".into()))
+            .collect();
+        let schedule = Schedule {
+            serial: Some(1),
+            deadline: Some(Instant::now() + Duration::from_millis(100)),
+            ..Schedule::parallel(64)
+        };
+        let results = execute_jobs(
+            jobs,
+            |host, _, _| async move {
+                // The first host runs past the deadline.
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                ServerResult {
+                    server: host.host.clone(),
+                    duration: 0.2,
+                    success: true,
+                    exit_code: Some(0),
+                    ..Default::default()
+                }
+            },
+            None,
+            Display::Quiet,
+            schedule,
+        );
+        let cuts: Vec<Option<Cut>> = results.iter().map(|(_, r)| r.cut).collect();
+        assert_eq!(cuts, [None, Some(Cut::NotStarted), Some(Cut::NotStarted)]);
+        assert!(results[0].1.success && !results[2].1.success);
+    }
+}
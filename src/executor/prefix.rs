@@ -0,0 +1,80 @@
+//! Live output from many hosts at once, one whole line at a time, each
+//! behind its host's `[name]` tag: padded to the longest name so the
+//! output lines up, and colored per host on a terminal, like pssh or GNU
+//! parallel's `--tag`.
+
+use ansi_term::Colour;
+use std::collections::HashMap;
+
+/// Tag colors, handed out to hosts in order.
+const PALETTE: [Colour; 6] = [
+    Colour::Cyan,
+    Colour::Green,
+    Colour::Yellow,
+    Colour::Blue,
+    Colour::Purple,
+    Colour::Red,
+];
+
+pub struct Prefixer {
+    /// Width of the widest tag, brackets included.
+    width: usize,
+    colors: HashMap<String, Colour>,
+    color: bool,
+}
+
+impl Prefixer {
+    /// A prefixer for output from `servers`, in job order; repeated names
+    /// keep their first color.
+    pub fn new<'a>(servers: impl IntoIterator<Item = &'a str>, color: bool) -> Prefixer {
+        let mut colors = HashMap::new();
+        let mut width = 0;
+        for server in servers {
+            width = width.max(server.chars().count() + 2);
+            let next = PALETTE[colors.len() % PALETTE.len()];
+            colors.entry(server.to_string()).or_insert(next);
+        }
+        Prefixer {
+            width,
+            colors,
+            color,
+        }
+    }
+
+    /// `line` from `server`, tagged.
+    pub fn line(&mut self, server: &str, line: &str) -> String {
+        let tag = format!("[{}]", server);
+        let padding = " ".repeat(self.width.saturating_sub(tag.chars().count()));
+        if !self.color {
+            return format!("{}{} {}", tag, padding, line);
+        }
+        let next = PALETTE[self.colors.len() % PALETTE.len()];
+        let color = *self.colors.entry(server.to_string()).or_insert(next);
+        format!("{}{} {}", color.bold().paint(tag), padding, line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tags_line_up() {
+        let mut prefixer = Prefixer::new(["web01", "db-primary", "web01"], false);
+        assert_eq!(
+            prefixer.line("web01", "up 3 days"),
+            "[web01]      up 3 days"
+        );
+        assert_eq!(
+            prefixer.line("db-primary", "up 9 days"),
+            "[db-primary] up 9 days"
+        );
+        // Names missing from the job list still get a tag.
+        assert_eq!(prefixer.line("cache-server-01", "x"), "[cache-server-01] x");
+
+        let mut colored = Prefixer::new(["web01", "web02"], true);
+        let web01 = colored.line("web01", "ok");
+        assert!(web01.starts_with("\u{1b}[1;36m[web01]\u{1b}[0m ok"));
+        assert_ne!(colored.line("web02", "ok"), web01.replace("web01", "web02"));
+    }
+}
@@ -0,0 +1,180 @@
+//! `--progress`: how many hosts are done out of how many, how many failed
+//! and about how long the rest will take. On a terminal it is a bar kept
+//! below the output; otherwise, as in CI logs, a plain line on stderr every
+//! `LOG_INTERVAL` and once everything is done.
+
+use super::Job;
+use crate::ssh::ServerResult;
+use crate::summary::Status;
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time between plain progress lines.
+const LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+struct State {
+    /// Jobs not yet done, by host.
+    remaining: HashMap<String, usize>,
+    failed: HashSet<String>,
+    hosts: usize,
+    done: usize,
+    started: Instant,
+    logged: Instant,
+}
+
+pub struct Progress {
+    bar: Option<ProgressBar>,
+    state: Mutex<State>,
+}
+
+/// The time left at the rate hosts finished so far.
+fn eta(elapsed: Duration, done: usize, total: usize) -> Option<Duration> {
+    (done > 0).then(|| elapsed.mul_f64((total - done) as f64 / done as f64))
+}
+
+/// `1h02m`, `2m10s` or `45s`.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{}s", s),
+        (0, m, s) => format!("{}m{:02}s", m, s),
+        (h, m, _) => format!("{}h{:02}m", h, m),
+    }
+}
+
+fn line(done: usize, total: usize, failed: usize, eta: Option<Duration>) -> String {
+    let mut line = format!(
+        "progress: {}/{} host(s) done, {} failed",
+        done, total, failed
+    );
+    match eta {
+        _ if done == total => {}
+        Some(eta) => line.push_str(&format!(", about {} left", format_duration(eta))),
+        None => {}
+    }
+    line
+}
+
+impl Progress {
+    /// Progress over the hosts of `jobs`; a bar when `terminal`.
+    pub fn new(jobs: &[Job], terminal: bool) -> Progress {
+        let mut remaining: HashMap<String, usize> = HashMap::new();
+        for (host, _) in jobs {
+            *remaining.entry(host.host.clone()).or_default() += 1;
+        }
+        let hosts = remaining.len();
+        let bar = terminal.then(|| {
+            let bar =
+                ProgressBar::with_draw_target(Some(hosts as u64), ProgressDrawTarget::stderr());
+            let style = ProgressStyle::with_template(
+                "{bar:30.cyan/blue} {pos}/{len} host(s), {msg}, ETA {eta}",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar());
+            bar.set_style(style.progress_chars("=> "));
+            bar.set_message("0 failed");
+            bar.enable_steady_tick(Duration::from_secs(1));
+            bar
+        });
+        let now = Instant::now();
+        Progress {
+            bar,
+            state: Mutex::new(State {
+                remaining,
+                failed: HashSet::new(),
+                hosts,
+                done: 0,
+                started: now,
+                logged: now,
+            }),
+        }
+    }
+
+    /// Counts one job as done; its host is done with its last job.
+    pub fn finish(&self, result: &ServerResult) {
+        let mut state = self.state.lock().unwrap();
+        if Status::of(result) == Status::Failed {
+            state.failed.insert(result.server.clone());
+        }
+        let Some(left) = state.remaining.get_mut(&result.server) else {
+            return;
+        };
+        *left = left.saturating_sub(1);
+        if *left > 0 {
+            return;
+        }
+        state.remaining.remove(&result.server);
+        state.done += 1;
+        let failed = state.failed.len();
+        match &self.bar {
+            Some(bar) => {
+                bar.set_message(format!("{} failed", failed));
+                bar.inc(1);
+            }
+            None if state.done == state.hosts || state.logged.elapsed() >= LOG_INTERVAL => {
+                state.logged = Instant::now();
+                let eta = eta(state.started.elapsed(), state.done, state.hosts);
+                eprintln!("{}", line(state.done, state.hosts, failed, eta));
+            }
+            None => {}
+        }
+    }
+
+    /// Runs `print` with the bar out of the way, so output lines don't
+    /// mix with it.
+    pub fn suspend<R>(&self, print: impl FnOnce() -> R) -> R {
+        match &self.bar {
+            Some(bar) => bar.suspend(print),
+            None => print(),
+        }
+    }
+
+    /// Takes the bar off the screen once the run is over, before the
+    /// summary.
+    pub fn close(&self) {
+        if let Some(bar) = &self.bar {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Host;
+    use crate::ssh::Cut;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_progress() {
+        assert_eq!(format_duration(Duration::from_secs(45)), "45s");
+        assert_eq!(format_duration(Duration::from_secs(130)), "2m10s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h02m");
+        assert_eq!(
+            eta(Duration::from_secs(60), 120, 480),
+            Some(Duration::from_secs(180))
+        );
+        assert_eq!(eta(Duration::from_secs(60), 0, 480), None);
+        assert_eq!(
+            line(120, 480, 3, Some(Duration::from_secs(180))),
+            "progress: 120/480 host(s) done, 3 failed, about 3m00s left"
+        );
+        assert_eq!(
+            line(480, 480, 3, Some(Duration::ZERO)),
+            "progress: 480/480 host(s) done, 3 failed"
+        );
+
+        let job = |host: &str, command: &str| (Arc::new(Host::new(host)), Arc::from(command));
+        let jobs = vec![job("web01", "a"), job("web01", "b"), job("web02", "a")];
+        let progress = Progress::new(&jobs, false);
+        // Cancelled at --deadline counts as failed, a job never started not.
+        progress.finish(&ServerResult::skipped("web01", Cut::Cancelled));
+        {
+            let state = progress.state.lock().unwrap();
+            assert_eq!((state.hosts, state.done, state.failed.len()), (2, 0, 1));
+        }
+        progress.finish(&ServerResult::skipped("web01", Cut::FailFast));
+        assert_eq!(progress.state.lock().unwrap().done, 1);
+    }
+}
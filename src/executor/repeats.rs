@@ -0,0 +1,86 @@
+//! Collapses runs of identical consecutive lines from one host, such as a
+//! health check polling in a loop. The first line of a run is shown as it
+//! arrives; when the run ends, by another line from that host or the end of
+//! the output, the line is shown once more with the run's length:
+//! `ready (x47)`. Only the live display is collapsed; results keep every
+//! line.
+
+use std::collections::HashMap;
+
+#[derive(Default)]
+pub struct Repeats {
+    /// Each host's last line and how many times in a row it came.
+    last: HashMap<String, (String, usize)>,
+}
+
+impl Repeats {
+    /// What to show for `line` from `server`: nothing for a repeat, else the
+    /// end of the host's previous run (if it repeated) and the line.
+    pub fn line(&mut self, server: &str, line: &str) -> Vec<String> {
+        if let Some((last, count)) = self.last.get_mut(server) {
+            if last == line {
+                *count += 1;
+                return Vec::new();
+            }
+        }
+        let mut shown: Vec<String> = self
+            .last
+            .insert(server.to_string(), (line.to_string(), 1))
+            .and_then(|(last, count)| collapsed(&last, count))
+            .into_iter()
+            .collect();
+        shown.push(line.to_string());
+        shown
+    }
+
+    /// The ends of every host's run still repeating, once output is over.
+    pub fn finish(&mut self) -> Vec<(String, String)> {
+        let mut shown: Vec<(String, String)> = self
+            .last
+            .drain()
+            .filter_map(|(server, (last, count))| Some((server, collapsed(&last, count)?)))
+            .collect();
+        shown.sort();
+        shown
+    }
+}
+
+fn collapsed(line: &str, count: usize) -> Option<String> {
+    (count > 1).then(|| format!("{} (x{})", line, count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats() {
+        let mut repeats = Repeats::default();
+        let mut shown = Vec::new();
+        for (server, line) in [
+            ("web01", "waiting"),
+            ("web01", "waiting"),
+            ("web02", "waiting"),
+            ("web01", "waiting"),
+            ("web01", "ready"),
+            ("web02", "waiting"),
+        ] {
+            for line in repeats.line(server, line) {
+                shown.push(format!("{} {}", server, line));
+            }
+        }
+        for (server, line) in repeats.finish() {
+            shown.push(format!("{} {}", server, line));
+        }
+        assert_eq!(
+            shown,
+            [
+                "web01 waiting",
+                "web02 waiting",
+                "web01 waiting (x3)",
+                "web01 ready",
+                "web02 waiting (x2)",
+            ]
+        );
+    }
+}
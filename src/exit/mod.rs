@@ -0,0 +1,121 @@
+//! The process exit code, so CI jobs can gate on a run:
+//!
+//! | code | meaning |
+//! |------|---------|
+//! | 0 | every command that ran succeeded |
+//! | 1 | russh itself failed (arguments, config, lock, ...) |
+//! | 2 | some hosts failed |
+//! | 3 | every host failed |
+//! | 4 | with `--all-must-succeed`: nothing failed, but some selected hosts or jobs never ran |
+//! | 130 | the run was interrupted with Ctrl-C |
+//!
+//! A host counts as failed when any of its commands failed. Results are
+//! recorded as they are reported, so every command that prints a summary
+//! feeds the exit code without threading it back up to `main`.
+
+use crate::interrupt;
+use crate::ssh::ServerResult;
+use crate::summary::Status;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+
+pub const SUCCESS: i32 = 0;
+pub const ERROR: i32 = 1;
+pub const SOME_FAILED: i32 = 2;
+pub const ALL_FAILED: i32 = 3;
+pub const INCOMPLETE: i32 = 4;
+
+#[derive(Default)]
+struct Outcome {
+    /// The worst status of each host's commands.
+    hosts: BTreeMap<String, Status>,
+    /// Selected hosts dropped before running anything (`--require`).
+    left_out: usize,
+}
+
+static OUTCOME: Mutex<Outcome> = Mutex::new(Outcome {
+    hosts: BTreeMap::new(),
+    left_out: 0,
+});
+
+fn outcome() -> std::sync::MutexGuard<'static, Outcome> {
+    OUTCOME
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Records reported results.
+pub fn record(results: &[(String, ServerResult)]) {
+    let mut outcome = outcome();
+    for (_, result) in results {
+        let status = Status::of(result);
+        let worst = outcome.hosts.entry(result.server.clone()).or_insert(status);
+        *worst = (*worst).max(status);
+    }
+}
+
+/// Records selected hosts that were dropped without running anything.
+pub fn record_left_out(count: usize) {
+    outcome().left_out += count;
+}
+
+/// The exit code for everything recorded so far.
+pub fn code(all_must_succeed: bool) -> i32 {
+    if interrupt::interrupted() {
+        return interrupt::EXIT_CODE;
+    }
+    let outcome = outcome();
+    code_for(&outcome.hosts, outcome.left_out, all_must_succeed)
+}
+
+fn code_for(hosts: &BTreeMap<String, Status>, left_out: usize, all_must_succeed: bool) -> i32 {
+    let failed = hosts.values().filter(|&&s| s == Status::Failed).count();
+    let skipped = hosts.values().filter(|&&s| s == Status::Skipped).count();
+    if failed > 0 && failed == hosts.len() {
+        ALL_FAILED
+    } else if failed > 0 {
+        SOME_FAILED
+    } else if all_must_succeed && (skipped > 0 || left_out > 0) {
+        INCOMPLETE
+    } else {
+        SUCCESS
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_for() {
+        let hosts = |statuses: &[Status]| -> BTreeMap<String, Status> {
+            statuses
+                .iter()
+                .enumerate()
+                .map(|(i, &s)| (format!("web{:02}", i), s))
+                .collect()
+        };
+        assert_eq!(code_for(&BTreeMap::new(), 0, true), SUCCESS);
+        assert_eq!(
+            code_for(&hosts(&[Status::Ok, Status::Changed]), 0, true),
+            SUCCESS
+        );
+        assert_eq!(
+            code_for(&hosts(&[Status::Ok, Status::Failed]), 0, false),
+            SOME_FAILED
+        );
+        assert_eq!(
+            code_for(&hosts(&[Status::Failed, Status::Failed]), 0, false),
+            ALL_FAILED
+        );
+        // Hosts that never ran only matter when all must succeed.
+        let skipped = hosts(&[Status::Ok, Status::Skipped]);
+        assert_eq!(code_for(&skipped, 0, false), SUCCESS);
+        assert_eq!(code_for(&skipped, 0, true), INCOMPLETE);
+        assert_eq!(code_for(&hosts(&[Status::Ok]), 1, true), INCOMPLETE);
+        assert_eq!(
+            code_for(&hosts(&[Status::Failed, Status::Skipped]), 0, true),
+            SOME_FAILED
+        );
+    }
+}
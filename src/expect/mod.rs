@@ -0,0 +1,271 @@
+//! Expect scripts for hosts that only offer an interactive CLI, such as
+//! switches and other vendor appliances. A script is a list of steps, each
+//! waiting for a prompt (`expect`, a regex) and then typing a line
+//! (`send`). It runs over `ssh -tt` on every host; a step that doesn't see
+//! its prompt within its timeout ends the session and fails the host.
+
+use crate::errors::{ConfigError, Result};
+use regex::Regex;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Seconds a step waits for its prompt when neither it nor the script says.
+pub const DEFAULT_STEP_TIMEOUT: u64 = 30;
+
+/// Output kept for matching the current step's prompt.
+const WINDOW: usize = 4096;
+
+#[derive(Deserialize)]
+struct ScriptFile {
+    timeout: Option<u64>,
+    #[serde(default)]
+    steps: Vec<StepRule>,
+}
+
+#[derive(Deserialize)]
+struct StepRule {
+    expect: Option<String>,
+    send: Option<String>,
+    timeout: Option<u64>,
+    #[serde(default)]
+    secret: bool,
+}
+
+/// A parsed expect script.
+#[derive(Debug, PartialEq)]
+pub struct Script {
+    steps: Vec<Step>,
+}
+
+#[derive(Debug)]
+struct Step {
+    expect: Option<Regex>,
+    send: Option<String>,
+    timeout: Duration,
+    secret: bool,
+}
+
+impl PartialEq for Step {
+    fn eq(&self, other: &Step) -> bool {
+        self.expect.as_ref().map(Regex::as_str) == other.expect.as_ref().map(Regex::as_str)
+            && self.send == other.send
+            && self.timeout == other.timeout
+            && self.secret == other.secret
+    }
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            file: path.to_path_buf(),
+            source,
+        })?;
+        Script::parse(&text).map_err(|message| {
+            ConfigError::Parse {
+                file: path.to_path_buf(),
+                line: None,
+                message,
+            }
+            .into()
+        })
+    }
+
+    fn parse(text: &str) -> std::result::Result<Script, String> {
+        let file: ScriptFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        if file.steps.is_empty() {
+            return Err("the script has no [[steps]]".to_string());
+        }
+        let default_timeout = file.timeout.unwrap_or(DEFAULT_STEP_TIMEOUT);
+        let steps = file
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(i, rule)| {
+                if rule.expect.is_none() && rule.send.is_none() {
+                    return Err(format!("step {} has neither expect nor send", i + 1));
+                }
+                let expect = rule
+                    .expect
+                    .as_deref()
+                    .map(Regex::new)
+                    .transpose()
+                    .map_err(|e| format!("step {}: invalid expect regex: {}", i + 1, e))?;
+                Ok(Step {
+                    expect,
+                    send: rule.send,
+                    timeout: Duration::from_secs(rule.timeout.unwrap_or(default_timeout)),
+                    secret: rule.secret,
+                })
+            })
+            .collect::<std::result::Result<_, String>>()?;
+        Ok(Script { steps })
+    }
+}
+
+/// A line typed into the session.
+pub struct Sent {
+    /// What goes to the terminal, newline included.
+    pub text: String,
+    /// The output line recording it.
+    pub log: String,
+}
+
+/// One host's run through a script. Clones share the progress, so the
+/// thread reading the output and the one waiting for the process both see
+/// it.
+#[derive(Clone)]
+pub struct Driver(Arc<Mutex<State>>);
+
+struct State {
+    script: Arc<Script>,
+    step: usize,
+    since: Instant,
+    window: String,
+}
+
+impl Driver {
+    pub fn new(script: Arc<Script>) -> Driver {
+        Driver(Arc::new(Mutex::new(State {
+            script,
+            step: 0,
+            since: Instant::now(),
+            window: String::new(),
+        })))
+    }
+
+    /// The lines to type before any output, from leading steps with
+    /// nothing to wait for.
+    pub fn start(&self) -> Vec<Sent> {
+        self.feed("")
+    }
+
+    /// Takes the next piece of output and returns the lines it made due.
+    pub fn feed(&self, output: &str) -> Vec<Sent> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let state = &mut *state;
+        state.window.push_str(output);
+        if state.window.len() > WINDOW {
+            let mut cut = state.window.len() - WINDOW;
+            while !state.window.is_char_boundary(cut) {
+                cut += 1;
+            }
+            state.window.drain(..cut);
+        }
+        let total = state.script.steps.len();
+        let mut sent = Vec::new();
+        while let Some(step) = state.script.steps.get(state.step) {
+            if let Some(expect) = &step.expect {
+                // Later steps only look at what came after this prompt.
+                let Some(found) = expect.find(&state.window) else {
+                    break;
+                };
+                state.window.drain(..found.end());
+            }
+            state.step += 1;
+            state.since = Instant::now();
+            let Some(send) = &step.send else {
+                continue;
+            };
+            let shown = if step.secret { "***" } else { send.as_str() };
+            sent.push(Sent {
+                text: format!("{}\n", send),
+                log: format!(
+                    "[russh] expect step {}/{}: sent '{}'",
+                    state.step, total, shown
+                ),
+            });
+        }
+        sent
+    }
+
+    /// Whether every step has run.
+    pub fn finished(&self) -> bool {
+        let state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.step >= state.script.steps.len()
+    }
+
+    /// Whether the current step has waited longer than its timeout.
+    pub fn overdue(&self) -> bool {
+        let state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state
+            .script
+            .steps
+            .get(state.step)
+            .is_some_and(|step| state.since.elapsed() >= step.timeout)
+    }
+
+    /// Why the script didn't finish, if it didn't.
+    pub fn unfinished(&self) -> Option<String> {
+        let state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let step = state.script.steps.get(state.step)?;
+        let waiting = step.expect.as_ref().map_or(String::new(), |re| {
+            format!(" waiting for /{}/", re.as_str())
+        });
+        let reason = if state.since.elapsed() >= step.timeout {
+            format!("timed out after {}s", step.timeout.as_secs())
+        } else {
+            "the session ended".to_string()
+        };
+        Some(format!(
+            "expect script stopped at step {}/{}{}: {}",
+            state.step + 1,
+            state.script.steps.len(),
+            waiting,
+            reason
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_driver() {
+        let script = Script::parse(
+            r##"
+            timeout = 5
+
+            [[steps]]
+            send = ""
+
+            [[steps]]
+            expect = "[Uu]sername:"
+            send = "admin"
+
+            [[steps]]
+            expect = "Password:"
+            send = "hunter2"
+            secret = true
+
+            [[steps]]
+            expect = "#\\s*$"
+            send = "show version"
+            timeout = 60
+            "##,
+        )
+        .unwrap();
+        let driver = Driver::new(Arc::new(script));
+        assert_eq!(driver.start().len(), 1);
+        assert!(driver.feed("Welcome\r\nUser").is_empty());
+        let sent = driver.feed("name: Password: ");
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0].text, "admin\n");
+        assert_eq!(sent[1].log, "[russh] expect step 3/4: sent '***'");
+        assert!(!driver.overdue());
+        assert_eq!(
+            driver.unfinished().as_deref(),
+            Some("expect script stopped at step 4/4 waiting for /#\\s*$/: the session ended")
+        );
+        assert_eq!(driver.feed("\r\nswitch01# ")[0].text, "show version\n");
+        assert!(driver.finished());
+        assert_eq!(driver.unfinished(), None);
+
+        assert!(Script::parse("").is_err());
+        assert!(Script::parse("[[steps]]\ntimeout = 3").is_err());
+        assert!(Script::parse("[[steps]]\nexpect = \"(\"").is_err());
+    }
+}
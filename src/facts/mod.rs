@@ -0,0 +1,287 @@
+//! Host facts: what a host is (OS, kernel, architecture, CPUs, memory),
+//! gathered with a single ssh command and cached per host under
+//! `<state dir>/facts/`. A cached entry is used until it is older than
+//! `facts_ttl` seconds, the host is reached as a different user or port, or
+//! `--refresh-facts` asks for fresh ones.
+
+use crate::audit::now;
+use crate::cli::{inventory, require_client, Cli, FactsArgs};
+use crate::config::Host;
+use crate::errors::{AppError, Result};
+use crate::monitor;
+use crate::paths;
+use crate::prereq;
+use crate::runtime;
+use crate::ssh::{run_ssh_command, Event, ExecOptions};
+use crate::warnings::{warn, WarningKind};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::mpsc;
+
+/// Seconds cached facts stay valid without `facts_ttl` in the config.
+pub const DEFAULT_TTL: u64 = 3600;
+
+/// The cache's directory in the state directory.
+pub const CACHE_DIR: &str = "facts";
+
+/// Prefix of the probe's output lines, so login banners and the like are
+/// never taken for facts.
+const PREFIX: &str = "russh-fact.";
+
+/// One shell command printing every fact as `russh-fact.NAME=VALUE`. The
+/// os-release variables are read in a subshell so they can't leak. Every
+/// fact has a plain POSIX way of getting it, for hosts without `hostname`,
+/// `getconf` or awk; sizes are in KiB.
+fn probe() -> String {
+    format!(
+        "echo \"russh-fact.hostname=$(hostname 2>/dev/null || uname -n)\"; \
+(. /etc/os-release 2>/dev/null; \
+echo \"russh-fact.os=${{ID:-$(uname -s | tr '[:upper:]' '[:lower:]')}}\"; \
+echo \"russh-fact.os_version=${{VERSION_ID:-$(uname -r)}}\"); \
+echo \"russh-fact.kernel=$(uname -r)\"; \
+echo \"russh-fact.arch=$(uname -m)\"; \
+echo \"russh-fact.cpus=$(getconf _NPROCESSORS_ONLN 2>/dev/null || nproc 2>/dev/null \
+|| grep -c '^processor' /proc/cpuinfo 2>/dev/null)\"; \
+echo \"russh-fact.mem_total_kb=$({})\"",
+        prereq::meminfo_kb("MemTotal")
+    )
+}
+
+/// The facts of one host, as cached.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Facts {
+    /// How the host was reached, `user@host:port`; facts gathered through
+    /// another target don't count for this one.
+    pub target: String,
+    pub gathered_at: u64,
+    pub values: BTreeMap<String, String>,
+}
+
+impl Facts {
+    pub fn age_secs(&self) -> u64 {
+        now().saturating_sub(self.gathered_at)
+    }
+
+    /// Whether these facts can stand in for gathering them on `host`.
+    pub fn is_fresh(&self, host: &Host, ttl: u64) -> bool {
+        self.target == target(host) && self.age_secs() < ttl
+    }
+}
+
+/// A host's facts and where they came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Gathered {
+    Cached(Facts),
+    Fresh(Facts),
+    Failed(String),
+}
+
+fn target(host: &Host) -> String {
+    format!(
+        "{}@{}:{}",
+        host.user.as_deref().unwrap_or(""),
+        host.host,
+        host.port.unwrap_or(22)
+    )
+}
+
+fn cache_path(host: &str) -> Result<PathBuf> {
+    Ok(paths::state_path(CACHE_DIR)?.join(format!("{}.json", paths::file_name(host))))
+}
+
+/// The cached facts for `host`, if there are any; a corrupt entry counts as
+/// none and is gathered again.
+pub fn load_cached(host: &str) -> Option<Facts> {
+    let bytes = fs::read(cache_path(host).ok()?).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+pub fn save(host: &str, facts: &Facts) -> Result<()> {
+    let json = serde_json::to_vec_pretty(facts).map_err(|e| AppError::Generic(e.to_string()))?;
+    paths::write_atomic(&cache_path(host)?, &json)
+}
+
+/// Drops the cached facts of `host`, if there are any.
+pub fn forget(host: &str) -> Result<()> {
+    match fs::remove_file(cache_path(host)?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn parse(lines: &[String]) -> BTreeMap<String, String> {
+    lines
+        .iter()
+        .filter_map(|line| line.trim_end().strip_prefix(PREFIX)?.split_once('='))
+        .filter(|(_, value)| !value.is_empty())
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+async fn gather(host: &Host, options: &ExecOptions) -> std::result::Result<Facts, String> {
+    if host.shell().is_windows() {
+        return Err("facts are only gathered on POSIX hosts".to_string());
+    }
+    let (tx, rx) = mpsc::channel();
+    let result = run_ssh_command(host, &probe(), options, tx).await;
+    if !result.success {
+        return Err(prereq::unsupported(&result).unwrap_or_else(|| {
+            format!(
+                "gathering facts failed (exit code {})",
+                result.exit_code.map_or("?".to_string(), |c| c.to_string())
+            )
+        }));
+    }
+    let lines: Vec<String> = rx.into_iter().filter_map(Event::into_stdout).collect();
+    Ok(Facts {
+        target: target(host),
+        gathered_at: now(),
+        values: parse(&lines),
+    })
+}
+
+/// The facts of every host, in order: cached ones while they are fresh,
+/// the rest gathered in parallel and cached.
+pub fn for_hosts(
+    hosts: &[Host],
+    ttl: u64,
+    refresh: bool,
+    options: &ExecOptions,
+) -> Vec<(String, Gathered)> {
+    // Only the backend matters here; the probe must not run escalated or
+    // under nice/ionice.
+    let options = ExecOptions {
+        backend: options.backend,
+        ..Default::default()
+    };
+    let lookups = runtime::block_on(async {
+        let lookups: Vec<_> = hosts
+            .iter()
+            .cloned()
+            .map(|host| {
+                let options = options.clone();
+                tokio::spawn(async move {
+                    let cached = load_cached(&host.host).filter(|facts| facts.is_fresh(&host, ttl));
+                    let gathered = match cached {
+                        Some(facts) if !refresh => Gathered::Cached(facts),
+                        _ => match gather(&host, &options).await {
+                            Ok(facts) => match save(&host.host, &facts) {
+                                Ok(()) => Gathered::Fresh(facts),
+                                Err(e) => Gathered::Failed(format!("could not cache facts: {}", e)),
+                            },
+                            Err(e) => Gathered::Failed(e),
+                        },
+                    };
+                    (host.host, gathered)
+                })
+            })
+            .collect();
+        let mut done = Vec::with_capacity(lookups.len());
+        for lookup in lookups {
+            done.push(lookup.await);
+        }
+        done
+    });
+    lookups
+        .into_iter()
+        .zip(hosts)
+        .map(|(lookup, host)| {
+            lookup.unwrap_or_else(|_| (host.host.clone(), Gathered::Failed("panicked".to_string())))
+        })
+        .collect()
+}
+
+/// `russh facts`: prints the facts of every selected host.
+pub fn command(cli: &Cli, args: &FactsArgs) -> Result<()> {
+    let (config, hosts) = inventory(cli)?;
+    require_client(cli.backend)?;
+    let options = ExecOptions {
+        backend: cli.backend,
+        ..Default::default()
+    };
+    let ttl = config.facts_ttl.unwrap_or(DEFAULT_TTL);
+    let gathered = for_hosts(&hosts, ttl, cli.refresh_facts, &options);
+    for (host, gathered) in &gathered {
+        if let Gathered::Failed(e) = gathered {
+            warn(WarningKind::HostSkipped, format!("{}: {}", host, e));
+        }
+    }
+    if args.json {
+        let document: serde_json::Map<String, serde_json::Value> = gathered
+            .iter()
+            .map(|(host, gathered)| {
+                let entry = match gathered {
+                    Gathered::Cached(facts) | Gathered::Fresh(facts) => serde_json::json!({
+                        "cached": matches!(gathered, Gathered::Cached(_)),
+                        "gathered_at": facts.gathered_at,
+                        "facts": facts.values,
+                    }),
+                    Gathered::Failed(e) => serde_json::json!({ "error": e }),
+                };
+                (host.clone(), entry)
+            })
+            .collect();
+        let json = serde_json::to_string_pretty(&document)
+            .map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    for (host, gathered) in &gathered {
+        let (facts, source) = match gathered {
+            Gathered::Cached(facts) => (
+                facts,
+                format!("cached {} ago", monitor::format_age(facts.age_secs())),
+            ),
+            Gathered::Fresh(facts) => (facts, "gathered now".to_string()),
+            Gathered::Failed(_) => continue,
+        };
+        println!("{} ({})", host, source);
+        let width = facts
+            .values
+            .keys()
+            .map(String::len)
+            .max()
+            .unwrap_or_default();
+        for (name, value) in &facts.values {
+            println!("  {:<width$}  {}", name, value, width = width);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_freshness() {
+        let lines: Vec<String> = [
+            "Welcome to web01",
+            "russh-fact.os=debian",
+            "russh-fact.os_version=12",
+            "russh-fact.cpus=4\r",
+            "russh-fact.mem_total_kb=",
+        ]
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+        let values = parse(&lines);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values["cpus"], "4");
+
+        let host: Host = "web01 user=deploy".parse().unwrap();
+        let facts = Facts {
+            target: target(&host),
+            gathered_at: now() - 120,
+            values,
+        };
+        assert_eq!(facts.values["os"], "debian");
+        assert!(facts.is_fresh(&host, DEFAULT_TTL));
+        assert!(!facts.is_fresh(&host, 60));
+        // Reached as someone else, the host may look different.
+        let other: Host = "web01 user=root".parse().unwrap();
+        assert!(!facts.is_fresh(&other, DEFAULT_TTL));
+    }
+}
@@ -0,0 +1,134 @@
+//! `--hosts` and `--exclude`: narrowing the selected hosts down by name
+//! without editing the config. Patterns are shell-style globs (`web-*`,
+//! `db-0[1-3]`, `cache?`) matched against the whole host name, or regexes
+//! when written between slashes (`/^web-\d+$/`), which may match anywhere.
+//! `--tags` selects by tag expression instead, see [`TagExpr`].
+
+mod tags;
+
+pub use self::tags::TagExpr;
+
+use crate::config::Host;
+use crate::errors::{AppError, ExecutionError, Result};
+use regex::Regex;
+use std::str::FromStr;
+
+#[derive(Clone, Debug)]
+pub struct HostPattern {
+    text: String,
+    regex: Regex,
+}
+
+impl PartialEq for HostPattern {
+    fn eq(&self, other: &HostPattern) -> bool {
+        self.text == other.text
+    }
+}
+
+impl HostPattern {
+    pub fn matches(&self, name: &str) -> bool {
+        self.regex.is_match(name)
+    }
+}
+
+impl FromStr for HostPattern {
+    type Err = AppError;
+
+    fn from_str(text: &str) -> Result<HostPattern> {
+        let source = match text.strip_prefix('/').and_then(|t| t.strip_suffix('/')) {
+            Some(regex) if !regex.is_empty() => regex.to_string(),
+            _ => glob_to_regex(text),
+        };
+        let regex = Regex::new(&source).map_err(|e| ExecutionError::InvalidOption {
+            option: "--hosts/--exclude",
+            reason: format!("invalid pattern '{}': {}", text, e),
+        })?;
+        Ok(HostPattern {
+            text: text.to_string(),
+            regex,
+        })
+    }
+}
+
+/// An anchored regex for a glob: `*` is any run of characters, `?` any one,
+/// and `[...]` (or `[!...]`) a character class.
+fn glob_to_regex(glob: &str) -> String {
+    let mut regex = String::from("^");
+    let mut chars = glob.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            '[' => {
+                regex.push('[');
+                if chars.next_if(|&c| c == '!' || c == '^').is_some() {
+                    regex.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        regex.push('\\');
+                    }
+                    regex.push(c);
+                }
+                regex.push(']');
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
+/// Keeps the hosts matching any of `only` (all of them when it's empty)
+/// and none of `exclude`.
+pub fn apply(hosts: Vec<Host>, only: &[HostPattern], exclude: &[HostPattern]) -> Vec<Host> {
+    hosts
+        .into_iter()
+        .filter(|host| only.is_empty() || only.iter().any(|p| p.matches(&host.host)))
+        .filter(|host| !exclude.iter().any(|p| p.matches(&host.host)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(hosts: &[Host]) -> Vec<&str> {
+        hosts.iter().map(|h| h.host.as_str()).collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let hosts: Vec<Host> = ["web-01", "web-02", "db-01", "db-04", "web.example.com"]
+            .into_iter()
+            .map(Host::new)
+            .collect();
+        let pattern = |p: &str| p.parse::<HostPattern>().unwrap();
+
+        let web = [pattern("web-*")];
+        assert_eq!(
+            names(&apply(hosts.clone(), &web, &[])),
+            ["web-01", "web-02"]
+        );
+        let old_dbs = [pattern("db-0[1-3]")];
+        assert_eq!(
+            names(&apply(hosts.clone(), &[], &old_dbs)),
+            ["web-01", "web-02", "db-04", "web.example.com"]
+        );
+        let regex = [pattern(r"/\d$/")];
+        let not_two = [pattern("*-0[!1]")];
+        assert_eq!(
+            names(&apply(hosts.clone(), &regex, &not_two)),
+            ["web-01", "db-01"]
+        );
+        // Dots in a glob are literal.
+        assert_eq!(
+            names(&apply(hosts, &[pattern("web.*")], &[])),
+            ["web.example.com"]
+        );
+        assert!("/(/".parse::<HostPattern>().is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! `--tags`: boolean expressions over host tags, e.g. `prod & gpu`,
+//! `(web | api) & !canary`. `!` binds tightest, then `&`, then `|`;
+//! parentheses group. A tag is any run of characters other than
+//! whitespace, the operators and parentheses.
+
+use crate::errors::{AppError, ExecutionError, Result};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expr {
+    Tag(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn holds(&self, tags: &[String]) -> bool {
+        match self {
+            Expr::Tag(tag) => tags.iter().any(|t| t == tag),
+            Expr::Not(expr) => !expr.holds(tags),
+            Expr::And(left, right) => left.holds(tags) && right.holds(tags),
+            Expr::Or(left, right) => left.holds(tags) || right.holds(tags),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Tag(String),
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Tag(tag) => f.write_str(tag),
+            Token::Not => f.write_str("!"),
+            Token::And => f.write_str("&"),
+            Token::Or => f.write_str("|"),
+            Token::Open => f.write_str("("),
+            Token::Close => f.write_str(")"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut tag = String::new();
+    for c in text.chars() {
+        let token = match c {
+            '!' => Some(Token::Not),
+            '&' => Some(Token::And),
+            '|' => Some(Token::Or),
+            '(' => Some(Token::Open),
+            ')' => Some(Token::Close),
+            c if c.is_whitespace() => None,
+            c => {
+                tag.push(c);
+                continue;
+            }
+        };
+        if !tag.is_empty() {
+            tokens.push(Token::Tag(std::mem::take(&mut tag)));
+        }
+        tokens.extend(token);
+    }
+    if !tag.is_empty() {
+        tokens.push(Token::Tag(tag));
+    }
+    tokens
+}
+
+/// A recursive-descent parser over the tokens, one function per precedence
+/// level.
+struct Parser {
+    tokens: Vec<Token>,
+    at: usize,
+}
+
+impl Parser {
+    fn next_if(&mut self, token: &Token) -> bool {
+        let matches = self.tokens.get(self.at) == Some(token);
+        if matches {
+            self.at += 1;
+        }
+        matches
+    }
+
+    fn or(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.next_if(&Token::Or) {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> std::result::Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.next_if(&Token::And) {
+            expr = Expr::And(Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> std::result::Result<Expr, String> {
+        if self.next_if(&Token::Not) {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        match self.tokens.get(self.at).cloned() {
+            Some(Token::Tag(tag)) => {
+                self.at += 1;
+                Ok(Expr::Tag(tag))
+            }
+            Some(Token::Open) => {
+                self.at += 1;
+                let expr = self.or()?;
+                match self.next_if(&Token::Close) {
+                    true => Ok(expr),
+                    false => Err("missing ')'".to_string()),
+                }
+            }
+            Some(token) => Err(format!("expected a tag, found '{}'", token)),
+            None => Err("expected a tag at the end".to_string()),
+        }
+    }
+}
+
+/// A parsed `--tags` expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TagExpr {
+    text: String,
+    expr: Expr,
+}
+
+impl TagExpr {
+    /// Whether a host with `tags` is selected.
+    pub fn matches(&self, tags: &[String]) -> bool {
+        self.expr.holds(tags)
+    }
+}
+
+impl fmt::Display for TagExpr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
+
+impl FromStr for TagExpr {
+    type Err = AppError;
+
+    fn from_str(text: &str) -> Result<TagExpr> {
+        let mut parser = Parser {
+            tokens: tokenize(text),
+            at: 0,
+        };
+        let parsed = parser
+            .or()
+            .and_then(|expr| match parser.tokens.get(parser.at) {
+                None => Ok(expr),
+                Some(token) => Err(format!("unexpected '{}'", token)),
+            });
+        let expr = parsed.map_err(|reason| ExecutionError::InvalidOption {
+            option: "--tags",
+            reason: format!("invalid expression '{}': {}", text, reason),
+        })?;
+        Ok(TagExpr {
+            text: text.to_string(),
+            expr,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tag_expr() {
+        let tags = |list: &[&str]| list.iter().map(|t| t.to_string()).collect::<Vec<_>>();
+        let gpu_box = tags(&["ubuntu", "gpu", "prod"]);
+        let canary = tags(&["web", "prod", "canary"]);
+        let expr = |text: &str| text.parse::<TagExpr>().unwrap();
+
+        assert!(expr("prod & gpu").matches(&gpu_box));
+        assert!(!expr("prod & gpu").matches(&canary));
+        assert!(expr("gpu | web").matches(&canary));
+        assert!(!expr("(web | api) & !canary").matches(&canary));
+        assert!(expr("!canary&prod").matches(&gpu_box));
+        // `&` binds tighter than `|`.
+        assert!(expr("web | gpu & canary").matches(&canary));
+        assert!(!expr("(web | gpu) & ubuntu").matches(&canary));
+        assert!(expr("!!prod").matches(&canary));
+        assert!(expr("eu-west-1").matches(&tags(&["eu-west-1"])));
+
+        for bad in ["", "prod &", "(prod", "prod)", "prod gpu", "& prod"] {
+            assert!(bad.parse::<TagExpr>().is_err(), "{}", bad);
+        }
+    }
+}
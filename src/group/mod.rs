@@ -0,0 +1,125 @@
+//! `--group-output`: instead of streaming lines, every host's output is
+//! kept until the end and each distinct output is printed once under the
+//! hosts that produced it, like dsh or clush. Outputs are compared by
+//! their SHA-256, per command.
+
+use crate::ssh::ServerResult;
+use ansi_term::Style;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Hosts whose command printed the same thing.
+#[derive(Debug, PartialEq)]
+pub struct Group<'a> {
+    pub command: &'a str,
+    pub hosts: Vec<&'a str>,
+    /// stdout, then stderr.
+    pub output: String,
+}
+
+fn combined(result: &ServerResult) -> String {
+    let mut output = result.output.clone();
+    if let Some(error) = &result.error {
+        output.push_str(error);
+    }
+    output
+}
+
+/// The distinct outputs of each command, commands in the order they first
+/// ran and the most common output first.
+pub fn groups(results: &[(String, ServerResult)]) -> Vec<Group<'_>> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut seen: HashMap<[u8; 32], usize> = HashMap::new();
+    for (command, result) in results {
+        let output = combined(result);
+        let mut hasher = Sha256::new();
+        hasher.update(command.as_bytes());
+        hasher.update([0]);
+        hasher.update(output.as_bytes());
+        let key: [u8; 32] = hasher.finalize().into();
+        match seen.get(&key) {
+            Some(&i) => groups[i].hosts.push(&result.server),
+            None => {
+                seen.insert(key, groups.len());
+                groups.push(Group {
+                    command,
+                    hosts: vec![&result.server],
+                    output,
+                });
+            }
+        }
+    }
+    let order: Vec<&str> = groups.iter().map(|g| g.command).collect();
+    let first = |command: &str| order.iter().position(|&c| c == command);
+    groups.sort_by_key(|g| (first(g.command), std::cmp::Reverse(g.hosts.len())));
+    groups
+}
+
+pub fn format_groups(groups: &[Group], color: bool) -> String {
+    let several_commands = groups.iter().any(|g| g.command != groups[0].command);
+    let mut text = String::new();
+    for group in groups {
+        let mut header = format!("{} ({})", group.hosts.join(", "), group.hosts.len());
+        if several_commands {
+            header.push_str(&format!(" - {}", group.command));
+        }
+        let rule = "-".repeat(header.chars().count().min(78));
+        let header = match color {
+            true => Style::new().bold().paint(header).to_string(),
+            false => header,
+        };
+        text.push_str(&format!("{}\n{}\n{}\n", rule, header, rule));
+        match group.output.trim_end() {
+            "" => text.push_str("(no output)\n"),
+            output => {
+                text.push_str(output);
+                text.push('\n');
+            }
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(server: &str, output: &str) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            output: output.to_string(),
+            duration: 0.1,
+            success: true,
+            exit_code: Some(0),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_groups() {
+        let results: Vec<(String, ServerResult)> = [
+            ("cat /etc/issue", "web01", "Debian 12\n"),
+            ("cat /etc/issue", "web02", "Ubuntu 22.04\n"),
+            ("cat /etc/issue", "web03", "Debian 12\n"),
+            ("cat /etc/issue", "web04", "Debian 12\n"),
+        ]
+        .iter()
+        .map(|(command, server, output)| (command.to_string(), result(server, output)))
+        .collect();
+        let groups = groups(&results);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].hosts, ["web01", "web03", "web04"]);
+        assert_eq!(groups[1].hosts, ["web02"]);
+        assert_eq!(
+            format_groups(&groups, false),
+            "-----------------------\n\
+             web01, web03, web04 (3)\n\
+             -----------------------\n\
+             Debian 12\n\
+             ---------\n\
+             web02 (1)\n\
+             ---------\n\
+             Ubuntu 22.04\n"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Run history: every run is recorded in the audit log as a `run` event
+//! with its `--label`s (`ticket=OPS-1234`, `reason=kernel patch`), the
+//! commands and hosts and how they went; `russh history` lists the runs,
+//! optionally only those carrying given labels, so a change ticket leads
+//! back to what was done for it.
+
+use crate::audit::{self, audit_log_path};
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::logs::timestamp;
+use crate::ssh::ServerResult;
+use crate::summary::{self, Status};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::str::FromStr;
+
+/// A run's labels by key.
+pub type Labels = BTreeMap<String, String>;
+
+/// One `--label KEY=VALUE`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Label {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Label {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        match spec.split_once('=') {
+            Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+                Ok(Label {
+                    key: key.to_string(),
+                    value: value.to_string(),
+                })
+            }
+            _ => Err(ExecutionError::InvalidOption {
+                option: "--label",
+                reason: format!("expected KEY=VALUE, got '{}'", spec),
+            }
+            .into()),
+        }
+    }
+}
+
+impl fmt::Display for Label {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.key, self.value)
+    }
+}
+
+/// `labels` by key; a key given twice keeps its last value.
+pub fn labels(labels: &[Label]) -> Labels {
+    labels
+        .iter()
+        .map(|label| (label.key.clone(), label.value.clone()))
+        .collect()
+}
+
+/// A run as the audit log keeps it.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Run {
+    pub time: u64,
+    pub user: String,
+    pub labels: Labels,
+    /// The commands, each once, in the order they first ran.
+    pub commands: Vec<String>,
+    pub hosts: Vec<String>,
+    pub ok: usize,
+    pub changed: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl Run {
+    pub(crate) fn of(labels: &Labels, results: &[(String, ServerResult)]) -> Run {
+        let mut commands: Vec<String> = Vec::new();
+        for (command, _) in results {
+            if !commands.contains(command) {
+                commands.push(command.clone());
+            }
+        }
+        let (ok, changed, failed) = summary::tally(results);
+        Run {
+            labels: labels.clone(),
+            commands,
+            hosts: summary::by_host(results)
+                .iter()
+                .map(|host| host.server.to_string())
+                .collect(),
+            ok,
+            changed,
+            failed,
+            skipped: results
+                .iter()
+                .filter(|(_, r)| Status::of(r) == Status::Skipped)
+                .count(),
+            ..Run::default()
+        }
+    }
+
+    /// Whether the run carries every one of `labels`.
+    pub fn matches(&self, labels: &[Label]) -> bool {
+        labels
+            .iter()
+            .all(|label| self.labels.get(&label.key) == Some(&label.value))
+    }
+}
+
+/// Records the run of `results` in the audit log.
+pub fn record(labels: &Labels, results: &[(String, ServerResult)]) -> Result<()> {
+    let run = Run::of(labels, results);
+    audit::record(
+        "run",
+        serde_json::json!({
+            "labels": run.labels,
+            "commands": run.commands,
+            "hosts": run.hosts,
+            "ok": run.ok,
+            "changed": run.changed,
+            "failed": run.failed,
+            "skipped": run.skipped,
+        }),
+    )
+}
+
+/// The `run` events of an audit log, oldest first; other events and lines
+/// that don't parse are passed over.
+fn runs(log: &str) -> Vec<Run> {
+    log.lines()
+        .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+        .filter(|entry| entry["event"] == "run")
+        .filter_map(|entry| serde_json::from_value(entry).ok())
+        .collect()
+}
+
+/// The recorded runs carrying all of `labels`, the newest `limit`.
+pub fn load(labels: &[Label], limit: usize) -> Result<Vec<Run>> {
+    let log = match fs::read_to_string(audit_log_path()?) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let mut runs: Vec<Run> = runs(&log)
+        .into_iter()
+        .filter(|run| run.matches(labels))
+        .collect();
+    runs.drain(..runs.len().saturating_sub(limit));
+    Ok(runs)
+}
+
+pub fn print(runs: &[Run], json: bool) -> Result<()> {
+    if json {
+        let json =
+            serde_json::to_string_pretty(runs).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    if runs.is_empty() {
+        println!("No runs recorded.");
+        return Ok(());
+    }
+    for run in runs {
+        let labels: Vec<String> = run
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect();
+        let line = format!(
+            "{}  {}  {} host(s): {} ok, {} changed, {} failed, {} skipped  {}",
+            timestamp(run.time),
+            run.user,
+            run.hosts.len(),
+            run.ok,
+            run.changed,
+            run.failed,
+            run.skipped,
+            labels.join(" ")
+        );
+        println!("{}", line.trim_end());
+        for command in &run.commands {
+            println!("    $ {}", command);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_runs() {
+        let label = |spec: &str| spec.parse::<Label>().unwrap();
+        assert_eq!(
+            label("reason=kernel patch"),
+            Label {
+                key: "reason".to_string(),
+                value: "kernel patch".to_string(),
+            }
+        );
+        assert_eq!(label("note=a=b").value, "a=b");
+        for bad in ["ticket", "=OPS-1", "my key=x"] {
+            assert!(bad.parse::<Label>().is_err(), "{}", bad);
+        }
+
+        let log = [
+            r#"{"time":1,"user":"ana","event":"run","labels":{"ticket":"OPS-1234","reason":"kernel patch"},"commands":["apt-get -y upgrade"],"hosts":["web01","web02"],"ok":2,"changed":0,"failed":0,"skipped":0}"#,
+            r#"{"time":2,"user":"ana","event":"decommissioned","host":"web03"}"#,
+            r#"{"time":3,"user":"bo","event":"run","labels":{},"commands":["uptime"],"hosts":["web01"],"ok":1,"changed":0,"failed":0,"skipped":0}"#,
+            "not json",
+        ]
+        .join("\n");
+        let runs = runs(&log);
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0].user, "ana");
+        assert_eq!(runs[0].hosts, ["web01", "web02"]);
+        assert!(runs[0].matches(&[label("ticket=OPS-1234")]));
+        assert!(!runs[0].matches(&[label("ticket=OPS-1234"), label("reason=reboot")]));
+        assert!(!runs[1].matches(&[label("ticket=OPS-1234")]));
+        assert!(runs[1].matches(&[]));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::capability::{self, Capability};
+use crate::errors::{AppError, Result, TransportError};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Like the ssh transport, HTTP goes through the system `curl` binary instead
+// of pulling a TLS stack into the build.
+const USER_AGENT: &str = concat!("russh/", env!("CARGO_PKG_VERSION"));
+
+fn curl() -> Command {
+    let mut command = Command::new("curl");
+    command.args(["--silent", "--show-error", "--fail", "--location"]);
+    command.args(["--user-agent", USER_AGENT]);
+    command
+}
+
+fn spawn_failed(source: std::io::Error) -> AppError {
+    TransportError::Spawn {
+        program: "curl",
+        source,
+    }
+    .into()
+}
+
+fn request_failed(url: &str, stderr: &[u8]) -> AppError {
+    TransportError::Http {
+        url: url.to_string(),
+        message: String::from_utf8_lossy(stderr).trim().to_string(),
+    }
+    .into()
+}
+
+fn run(mut command: Command, url: &str) -> Result<Vec<u8>> {
+    capability::check(Capability::Http)?;
+    let output = command
+        .stdin(Stdio::null())
+        .output()
+        .map_err(spawn_failed)?;
+    if !output.status.success() {
+        return Err(request_failed(url, &output.stderr));
+    }
+    Ok(output.stdout)
+}
+
+/// Fetches `url` and returns the response body.
+pub fn get(url: &str) -> Result<Vec<u8>> {
+    let mut command = curl();
+    command.arg(url);
+    run(command, url)
+}
+
+/// Downloads `url` straight into `destination`.
+pub fn download(url: &str, destination: &Path) -> Result<()> {
+    let mut command = curl();
+    command.arg("--output").arg(destination).arg(url);
+    run(command, url).map(|_| ())
+}
+
+/// POSTs a JSON document to `url`, giving up after `timeout_secs`.
+pub fn post_json(url: &str, body: &[u8], timeout_secs: u64) -> Result<()> {
+    capability::check(Capability::Http)?;
+    let mut command = curl();
+    command
+        .args([
+            "--request",
+            "POST",
+            "--header",
+            "Content-Type: application/json",
+        ])
+        .args(["--max-time", &timeout_secs.to_string()])
+        .args(["--data-binary", "@-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(spawn_failed)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(body)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(request_failed(url, &output.stderr));
+    }
+    Ok(())
+}
+
+/// Sends a `method` request to `url` with extra curl `args`. `secrets` are
+/// curl config lines, such as `user = "..."`, given on stdin so they never
+/// show up in the process list. Returns the response body.
+pub fn request(method: &str, url: &str, args: &[&str], secrets: &str) -> Result<Vec<u8>> {
+    capability::check(Capability::Http)?;
+    let mut command = curl();
+    command
+        .args(["--request", method])
+        .args(args)
+        .args(["--config", "-", url])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+    let mut child = command.spawn().map_err(spawn_failed)?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(secrets.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(request_failed(url, &output.stderr));
+    }
+    Ok(output.stdout)
+}
@@ -0,0 +1,406 @@
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// Languages the message catalog has translations for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Locale {
+    En,
+    De,
+    Es,
+}
+
+impl Locale {
+    /// Parses a locale tag such as `de`, `de_DE.UTF-8` or `es-ES`.
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let language = tag
+            .split(['_', '-', '.', '@'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        match language.as_str() {
+            "en" | "c" | "posix" => Some(Locale::En),
+            "de" => Some(Locale::De),
+            "es" => Some(Locale::Es),
+            _ => None,
+        }
+    }
+
+    /// Picks the locale from `RUSSH_LANG`, then the usual POSIX variables.
+    pub fn detect() -> Locale {
+        ["RUSSH_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|var| env::var(var).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::from_tag(&value))
+            .unwrap_or(Locale::En)
+    }
+}
+
+/// Every user-facing message: the run's progress, the summary, prompts and
+/// the fixed text of errors. Details filled into them (host names, reasons
+/// from ssh or a config parser, warnings) stay as they come. Add the English
+/// text first, translations fall back to it when missing.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Msg {
+    ProcessingCommands,
+    ExecutionCompleted,
+    WarningsHeader,
+    CreateConfigPrompt,
+    ConfigCreationError,
+    ConfigNotFound,
+    ConfigReadFailed,
+    InvalidConfigPath,
+    ApplicationError,
+    TerminalRequired,
+    ThreadJoinFailed,
+    // The summary.
+    SummaryHeader,
+    SummaryTotals,
+    SummarySkipped,
+    SummaryHosts,
+    SummaryAttempts,
+    StatusOk,
+    StatusChanged,
+    StatusFailed,
+    StatusSkipped,
+    // Prompts and their answers.
+    ConfirmPrompt,
+    EditAgainPrompt,
+    ApprovalRequest,
+    ApprovePrompt,
+    NotApproved,
+    Approved,
+    DestructivePrompt,
+    GatePrompt,
+    GateTypeOrRun,
+    GateRunToContinue,
+    GateOpened,
+    GateAborted,
+    DecommissionPrompt,
+    NotDecommissioned,
+    // Errors.
+    FileError,
+    TomlError,
+    InvalidHostSpec,
+    UnknownGroup,
+    UnknownSet,
+    MembersCommand,
+    NoneSelected,
+    SpawnFailed,
+    SshMissing,
+    SshMissingNative,
+    RequestFailed,
+    Offline,
+    UnknownTask,
+    // Approvals refusing a run.
+    ApprovalNeeded,
+    ApprovalNotIssued,
+    ApprovalTokenMismatch,
+    ApprovalExpired,
+    ApprovalWrongOwner,
+    ApprovalSelfApproved,
+    ApprovalOtherRequester,
+    ApprovalUsed,
+    // The offline queue.
+    QueueFlushRunning,
+    QueueEmpty,
+    QueueStaleEntry,
+    QueueRemaining,
+    QueuedUnreachable,
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Sets the locale for the rest of the run. Only the first call has an effect.
+pub fn set_locale(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+pub fn locale() -> Locale {
+    *LOCALE.get_or_init(Locale::detect)
+}
+
+/// Looks up a message in the active locale.
+pub fn tr(msg: Msg) -> &'static str {
+    lookup(locale(), msg)
+}
+
+/// Looks up a message and fills its `{}` placeholders in order.
+pub fn trf(msg: Msg, args: &[&dyn Display]) -> String {
+    format_message(tr(msg), args)
+}
+
+/// Whether a prompt answer means "yes" in the active locale.
+pub fn is_yes(answer: &str) -> bool {
+    let answer = answer.trim().to_lowercase();
+    let yes = match locale() {
+        Locale::En => "y",
+        Locale::De => "j",
+        Locale::Es => "s",
+    };
+    answer.starts_with(yes) || answer.starts_with('y')
+}
+
+fn lookup(locale: Locale, msg: Msg) -> &'static str {
+    let translated = match locale {
+        Locale::En => None,
+        Locale::De => de(msg),
+        Locale::Es => es(msg),
+    };
+    translated.unwrap_or_else(|| en(msg))
+}
+
+fn format_message(template: &str, args: &[&dyn Display]) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut args = args.iter();
+    let mut pieces = template.split("{}").peekable();
+    while let Some(piece) = pieces.next() {
+        out.push_str(piece);
+        if pieces.peek().is_some() {
+            if let Some(arg) = args.next() {
+                out.push_str(&arg.to_string());
+            }
+        }
+    }
+    out
+}
+
+fn en(msg: Msg) -> &'static str {
+    match msg {
+        Msg::ProcessingCommands => "Processing commands...",
+        Msg::ExecutionCompleted => "Execution completed.",
+        Msg::WarningsHeader => "Warnings ({})",
+        Msg::CreateConfigPrompt => {
+            "Configuration file not found. Do you want to create a default user file at {}? [Y/n]"
+        }
+        Msg::ConfigCreationError => "Error during configuration creation: {}",
+        Msg::ConfigNotFound => "Configuration file not found",
+        Msg::ConfigReadFailed => "Failed to read configuration file",
+        Msg::InvalidConfigPath => "Invalid configuration file path",
+        Msg::ApplicationError => "Application error: {}",
+        Msg::TerminalRequired => "This application must be run in a terminal.",
+        Msg::ThreadJoinFailed => "Failed to join thread: {}",
+        Msg::SummaryHeader => "Summary",
+        Msg::SummaryTotals => "{} ok, {} changed, {} failed",
+        Msg::SummarySkipped => ", {} skipped",
+        Msg::SummaryHosts => "{} host(s): {} ok, {} changed, {} failed",
+        Msg::SummaryAttempts => "{} attempts",
+        Msg::StatusOk => "ok",
+        Msg::StatusChanged => "changed",
+        Msg::StatusFailed => "failed",
+        Msg::StatusSkipped => "skipped",
+        Msg::ConfirmPrompt => "[y/N]",
+        Msg::EditAgainPrompt => "Edit again? [Y/n]",
+        Msg::ApprovalRequest => "Request {} from {}",
+        Msg::ApprovePrompt => "Approve this run? [y/N]",
+        Msg::NotApproved => "Not approved.",
+        Msg::Approved => "Approved. Token (valid for {} minutes): {}",
+        Msg::DestructivePrompt => {
+            "Queued {}h ago by {}: '{}' on {} looks destructive. Run it now? [y/N]"
+        }
+        Msg::GatePrompt => "Step '{}' is gated: continue on {} host(s)? [y/N]{}",
+        Msg::GateTypeOrRun => " (or russh gate {})",
+        Msg::GateRunToContinue => " Run russh gate {} to continue.",
+        Msg::GateOpened => "Gate '{}' opened by {}.",
+        Msg::GateAborted => "Gate '{}' aborted by {}; skipping it.",
+        Msg::DecommissionPrompt => "Decommission {}?",
+        Msg::NotDecommissioned => "Not decommissioned.",
+        Msg::FileError => "file error: {}",
+        Msg::TomlError => "toml error: {}",
+        Msg::InvalidHostSpec => "invalid host spec '{}': {}",
+        Msg::UnknownGroup => "unknown group '{}' (configured groups: {})",
+        Msg::UnknownSet => "unknown host set '{}' (saved sets: {})",
+        Msg::MembersCommand => "members_command for group '{}' {}",
+        Msg::NoneSelected => "no hosts left after {}",
+        Msg::SpawnFailed => "failed to run {}: {}",
+        Msg::SshMissing => {
+            "ssh was not found on PATH; install the OpenSSH client (`apt install openssh-client`, \
+             `dnf install openssh-clients`, `brew install openssh`, or OpenSSH Client in Windows' \
+             optional features)"
+        }
+        Msg::SshMissingNative => " or use --backend native",
+        Msg::RequestFailed => "request to {} failed: {}",
+        Msg::Offline => "{} is disabled by --offline",
+        Msg::UnknownTask => "unknown task '{}' (configured tasks: {})",
+        Msg::ApprovalNeeded => {
+            "{} protected and need a second operator's approval; \
+             ask them to run `russh approve {}`, then rerun with --approval <token>"
+        }
+        Msg::ApprovalNotIssued => "no approval has been issued for request {}",
+        Msg::ApprovalTokenMismatch => "approval token does not match",
+        Msg::ApprovalExpired => "approval for request {} has expired",
+        Msg::ApprovalWrongOwner => "approval for request {} names {} but was written by {}",
+        Msg::ApprovalSelfApproved => "a run can't be approved by the operator starting it",
+        Msg::ApprovalOtherRequester => "approval for request {} was issued to {}",
+        Msg::ApprovalUsed => "approval for request {} has already been used",
+        Msg::QueueFlushRunning => "another russh flush-queue is already running",
+        Msg::QueueEmpty => "The offline queue is empty.",
+        Msg::QueueStaleEntry => "Skipping stale entry for {} ({}h old): {}",
+        Msg::QueueRemaining => "{} entr(ies) remain queued.",
+        Msg::QueuedUnreachable => {
+            "Queued {} command(s) for unreachable hosts, retry with `russh flush-queue`."
+        }
+    }
+}
+
+fn de(msg: Msg) -> Option<&'static str> {
+    Some(match msg {
+        Msg::ProcessingCommands => "Befehle werden ausgeführt...",
+        Msg::ExecutionCompleted => "Ausführung abgeschlossen.",
+        Msg::WarningsHeader => "Warnungen ({})",
+        Msg::CreateConfigPrompt => "Keine Konfigurationsdatei gefunden. Soll unter {} eine Standarddatei angelegt werden? [J/n]",
+        Msg::ConfigCreationError => "Fehler beim Anlegen der Konfiguration: {}",
+        Msg::ConfigNotFound => "Konfigurationsdatei nicht gefunden",
+        Msg::ConfigReadFailed => "Konfigurationsdatei konnte nicht gelesen werden",
+        Msg::InvalidConfigPath => "Ungültiger Pfad zur Konfigurationsdatei",
+        Msg::ApplicationError => "Anwendungsfehler: {}",
+        Msg::TerminalRequired => "Diese Anwendung muss in einem Terminal ausgeführt werden.",
+        Msg::ThreadJoinFailed => return None,
+        Msg::SummaryHeader => "Zusammenfassung",
+        Msg::SummaryTotals => "{} ok, {} geändert, {} fehlgeschlagen",
+        Msg::SummarySkipped => ", {} übersprungen",
+        Msg::SummaryHosts => "{} Host(s): {} ok, {} geändert, {} fehlgeschlagen",
+        Msg::SummaryAttempts => "{} Versuche",
+        Msg::StatusOk => "ok",
+        Msg::StatusChanged => "geändert",
+        Msg::StatusFailed => "Fehler",
+        Msg::StatusSkipped => "übersprungen",
+        Msg::ConfirmPrompt => "[j/N]",
+        Msg::EditAgainPrompt => "Erneut bearbeiten? [J/n]",
+        Msg::ApprovalRequest => "Anfrage {} von {}",
+        Msg::ApprovePrompt => "Diesen Lauf freigeben? [j/N]",
+        Msg::NotApproved => "Nicht freigegeben.",
+        Msg::Approved => "Freigegeben. Token ({} Minuten gültig): {}",
+        Msg::DestructivePrompt => "Vor {}h von {} eingereiht: '{}' auf {} sieht destruktiv aus. Jetzt ausführen? [j/N]",
+        Msg::GatePrompt => "Schritt '{}' wartet auf Freigabe: auf {} Host(s) fortfahren? [j/N]{}",
+        Msg::GateTypeOrRun => " (oder russh gate {})",
+        Msg::GateRunToContinue => " Zum Fortfahren russh gate {} ausführen.",
+        Msg::GateOpened => "Schritt '{}' von {} freigegeben.",
+        Msg::GateAborted => "Schritt '{}' von {} abgebrochen; er wird übersprungen.",
+        Msg::DecommissionPrompt => "{} außer Betrieb nehmen?",
+        Msg::NotDecommissioned => "Nicht außer Betrieb genommen.",
+        Msg::FileError => "Dateifehler: {}",
+        Msg::TomlError => "TOML-Fehler: {}",
+        Msg::InvalidHostSpec => "Ungültige Host-Angabe '{}': {}",
+        Msg::UnknownGroup => "Unbekannte Gruppe '{}' (konfigurierte Gruppen: {})",
+        Msg::UnknownSet => "Unbekannte Host-Menge '{}' (gespeicherte Mengen: {})",
+        Msg::MembersCommand => "members_command der Gruppe '{}' {}",
+        Msg::NoneSelected => "Nach {} sind keine Hosts übrig",
+        Msg::SpawnFailed => "{} konnte nicht gestartet werden: {}",
+        Msg::SshMissing => "ssh wurde im PATH nicht gefunden; bitte den OpenSSH-Client installieren (`apt install openssh-client`, `dnf install openssh-clients`, `brew install openssh` oder OpenSSH-Client in den optionalen Features von Windows)",
+        Msg::SshMissingNative => " oder --backend native verwenden",
+        Msg::RequestFailed => "Anfrage an {} fehlgeschlagen: {}",
+        Msg::Offline => "{} ist durch --offline deaktiviert",
+        Msg::UnknownTask => "Unbekannte Aufgabe '{}' (konfigurierte Aufgaben: {})",
+        Msg::ApprovalNeeded => "{} geschützt und brauchen die Freigabe eines zweiten Operators; dieser muss `russh approve {}` ausführen, danach erneut mit --approval <token> starten",
+        Msg::ApprovalNotIssued => "Für Anfrage {} wurde keine Freigabe erteilt",
+        Msg::ApprovalTokenMismatch => "Das Freigabe-Token passt nicht",
+        Msg::ApprovalExpired => "Die Freigabe für Anfrage {} ist abgelaufen",
+        Msg::ApprovalWrongOwner => "Die Freigabe für Anfrage {} nennt {}, wurde aber von {} geschrieben",
+        Msg::ApprovalSelfApproved => "Ein Lauf kann nicht von dem Operator freigegeben werden, der ihn startet",
+        Msg::ApprovalOtherRequester => "Die Freigabe für Anfrage {} wurde für {} erteilt",
+        Msg::ApprovalUsed => "Die Freigabe für Anfrage {} wurde bereits verwendet",
+        Msg::QueueFlushRunning => "Ein anderes russh flush-queue läuft bereits",
+        Msg::QueueEmpty => "Die Offline-Warteschlange ist leer.",
+        Msg::QueueStaleEntry => "Veralteter Eintrag für {} ({}h alt) wird übersprungen: {}",
+        Msg::QueueRemaining => "{} Eintrag/Einträge bleiben in der Warteschlange.",
+        Msg::QueuedUnreachable => "{} Befehl(e) für nicht erreichbare Hosts eingereiht, erneut versuchen mit `russh flush-queue`.",
+    })
+}
+
+fn es(msg: Msg) -> Option<&'static str> {
+    Some(match msg {
+        Msg::ProcessingCommands => "Procesando comandos...",
+        Msg::ExecutionCompleted => "Ejecución completada.",
+        Msg::WarningsHeader => "Advertencias ({})",
+        Msg::CreateConfigPrompt => "No se encontró el archivo de configuración. ¿Desea crear un archivo predeterminado en {}? [S/n]",
+        Msg::ConfigCreationError => "Error al crear la configuración: {}",
+        Msg::ConfigNotFound => "No se encontró el archivo de configuración",
+        Msg::ConfigReadFailed => "No se pudo leer el archivo de configuración",
+        Msg::InvalidConfigPath => "Ruta del archivo de configuración no válida",
+        Msg::ApplicationError => "Error de la aplicación: {}",
+        Msg::TerminalRequired => "Esta aplicación debe ejecutarse en una terminal.",
+        Msg::ThreadJoinFailed => return None,
+        Msg::SummaryHeader => "Resumen",
+        Msg::SummaryTotals => "{} ok, {} cambiados, {} fallidos",
+        Msg::SummarySkipped => ", {} omitidos",
+        Msg::SummaryHosts => "{} host(s): {} ok, {} cambiados, {} fallidos",
+        Msg::SummaryAttempts => "{} intentos",
+        Msg::StatusOk => "ok",
+        Msg::StatusChanged => "cambiado",
+        Msg::StatusFailed => "fallido",
+        Msg::StatusSkipped => "omitido",
+        Msg::ConfirmPrompt => "[s/N]",
+        Msg::EditAgainPrompt => "¿Editar de nuevo? [S/n]",
+        Msg::ApprovalRequest => "Solicitud {} de {}",
+        Msg::ApprovePrompt => "¿Aprobar esta ejecución? [s/N]",
+        Msg::NotApproved => "No aprobada.",
+        Msg::Approved => "Aprobada. Token (válido durante {} minutos): {}",
+        Msg::DestructivePrompt => "En cola hace {}h por {}: '{}' en {} parece destructivo. ¿Ejecutarlo ahora? [s/N]",
+        Msg::GatePrompt => "El paso '{}' requiere confirmación: ¿continuar en {} host(s)? [s/N]{}",
+        Msg::GateTypeOrRun => " (o russh gate {})",
+        Msg::GateRunToContinue => " Ejecute russh gate {} para continuar.",
+        Msg::GateOpened => "Paso '{}' abierto por {}.",
+        Msg::GateAborted => "Paso '{}' cancelado por {}; se omite.",
+        Msg::DecommissionPrompt => "¿Dar de baja {}?",
+        Msg::NotDecommissioned => "No se dio de baja.",
+        Msg::FileError => "error de archivo: {}",
+        Msg::TomlError => "error de TOML: {}",
+        Msg::InvalidHostSpec => "especificación de host no válida '{}': {}",
+        Msg::UnknownGroup => "grupo desconocido '{}' (grupos configurados: {})",
+        Msg::UnknownSet => "conjunto de hosts desconocido '{}' (conjuntos guardados: {})",
+        Msg::MembersCommand => "members_command del grupo '{}' {}",
+        Msg::NoneSelected => "no quedan hosts después de {}",
+        Msg::SpawnFailed => "no se pudo ejecutar {}: {}",
+        Msg::SshMissing => "no se encontró ssh en el PATH; instale el cliente OpenSSH (`apt install openssh-client`, `dnf install openssh-clients`, `brew install openssh` o el Cliente OpenSSH en las características opcionales de Windows)",
+        Msg::SshMissingNative => " o use --backend native",
+        Msg::RequestFailed => "la solicitud a {} falló: {}",
+        Msg::Offline => "{} está desactivado por --offline",
+        Msg::UnknownTask => "tarea desconocida '{}' (tareas configuradas: {})",
+        Msg::ApprovalNeeded => "{} protegidos y necesitan la aprobación de un segundo operador; pídale que ejecute `russh approve {}` y vuelva a ejecutar con --approval <token>",
+        Msg::ApprovalNotIssued => "no se ha emitido ninguna aprobación para la solicitud {}",
+        Msg::ApprovalTokenMismatch => "el token de aprobación no coincide",
+        Msg::ApprovalExpired => "la aprobación de la solicitud {} ha caducado",
+        Msg::ApprovalWrongOwner => "la aprobación de la solicitud {} nombra a {} pero la escribió {}",
+        Msg::ApprovalSelfApproved => "una ejecución no puede ser aprobada por el operador que la inicia",
+        Msg::ApprovalOtherRequester => "la aprobación de la solicitud {} se emitió para {}",
+        Msg::ApprovalUsed => "la aprobación de la solicitud {} ya se ha usado",
+        Msg::QueueFlushRunning => "ya hay otro russh flush-queue en ejecución",
+        Msg::QueueEmpty => "La cola sin conexión está vacía.",
+        Msg::QueueStaleEntry => "Omitiendo entrada antigua para {} ({}h): {}",
+        Msg::QueueRemaining => "{} entrada(s) siguen en la cola.",
+        Msg::QueuedUnreachable => "{} comando(s) en cola para hosts inalcanzables, reintente con `russh flush-queue`.",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_locale_from_tag() {
+        assert_eq!(Locale::from_tag("de_DE.UTF-8"), Some(Locale::De));
+        assert_eq!(Locale::from_tag("es-ES"), Some(Locale::Es));
+        assert_eq!(Locale::from_tag("C"), Some(Locale::En));
+        assert_eq!(Locale::from_tag("ja_JP"), None);
+    }
+
+    #[test]
+    fn test_lookup_falls_back_to_english() {
+        assert_eq!(
+            lookup(Locale::De, Msg::ThreadJoinFailed),
+            en(Msg::ThreadJoinFailed)
+        );
+        assert_eq!(
+            lookup(Locale::Es, Msg::ConfigNotFound),
+            es(Msg::ConfigNotFound).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_format_message() {
+        assert_eq!(format_message("Warnings ({})", &[&3]), "Warnings (3)");
+        assert_eq!(format_message("{} and {}", &[&"a"]), "a and ");
+    }
+}
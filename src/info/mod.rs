@@ -0,0 +1,101 @@
+use crate::config::{find_config_in_cwd, find_config_in_user_dir};
+use crate::errors::{AppError, Result};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Build and environment details, the first thing needed in a bug report.
+#[derive(Serialize)]
+pub struct Info {
+    pub version: &'static str,
+    pub git_commit: &'static str,
+    pub target_os: &'static str,
+    pub target_arch: &'static str,
+    pub config_path: Option<PathBuf>,
+    pub cwd_config: Option<PathBuf>,
+    pub user_config: Option<PathBuf>,
+    pub ssh_version: Option<String>,
+    pub features: Vec<&'static str>,
+}
+
+pub fn version() -> String {
+    format!("russh {}", env!("CARGO_PKG_VERSION"))
+}
+
+/// `ssh -V` prints its version to stderr, e.g. `OpenSSH_9.6p1, OpenSSL 3.0.13`.
+pub fn ssh_version() -> Option<String> {
+    let output = Command::new("ssh").arg("-V").output().ok()?;
+    let text = if output.stderr.is_empty() {
+        output.stdout
+    } else {
+        output.stderr
+    };
+    let version = String::from_utf8_lossy(&text).trim().to_string();
+    (!version.is_empty()).then_some(version)
+}
+
+/// Cargo features compiled into this binary.
+fn features() -> Vec<&'static str> {
+    let mut features = Vec::new();
+    if cfg!(feature = "native") {
+        features.push("native");
+    }
+    if cfg!(feature = "tui") {
+        features.push("tui");
+    }
+    features
+}
+
+pub fn gather(config_file: &Option<String>) -> Info {
+    let cwd_config = find_config_in_cwd();
+    let user_config = find_config_in_user_dir();
+    let config_path = config_file
+        .as_ref()
+        .map(PathBuf::from)
+        .or_else(|| cwd_config.clone())
+        .or_else(|| user_config.clone());
+
+    Info {
+        version: env!("CARGO_PKG_VERSION"),
+        git_commit: env!("RUSSH_GIT_COMMIT"),
+        target_os: std::env::consts::OS,
+        target_arch: std::env::consts::ARCH,
+        config_path,
+        cwd_config,
+        user_config,
+        ssh_version: ssh_version(),
+        features: features(),
+    }
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+pub fn print_info(info: &Info, json: bool) -> Result<()> {
+    if json {
+        let json =
+            serde_json::to_string_pretty(info).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+
+    println!("{} ({})", version(), info.git_commit);
+    println!("platform:     {}-{}", info.target_os, info.target_arch);
+    println!("config:       {}", display_path(&info.config_path));
+    println!("  cwd:        {}", display_path(&info.cwd_config));
+    println!("  user dir:   {}", display_path(&info.user_config));
+    println!(
+        "ssh client:   {}",
+        info.ssh_version.as_deref().unwrap_or("not found")
+    );
+    let features = if info.features.is_empty() {
+        "(none)".to_string()
+    } else {
+        info.features.join(", ")
+    };
+    println!("features:     {}", features);
+    Ok(())
+}
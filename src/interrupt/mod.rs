@@ -0,0 +1,83 @@
+//! Ctrl-C during a run: jobs still waiting are not started, running ones
+//! are cancelled (their ssh processes killed) and the run ends with the
+//! usual summary, unfinished jobs marked as cancelled. A second Ctrl-C, or
+//! one while nothing runs, quits at once.
+
+use crate::runtime;
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+use std::task::Poll;
+use tokio::sync::watch;
+
+/// The exit code of a run ended by Ctrl-C, as shells report SIGINT.
+pub const EXIT_CODE: i32 = 130;
+
+static INTERRUPTED: OnceLock<watch::Sender<bool>> = OnceLock::new();
+
+/// Runs in progress; Ctrl-C outside of one quits right away.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+fn interrupted_tx() -> &'static watch::Sender<bool> {
+    INTERRUPTED.get_or_init(|| watch::Sender::new(false))
+}
+
+/// Handles Ctrl-C from now on, instead of being killed by it.
+pub fn install() {
+    runtime::runtime().spawn(async {
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if interrupted() || RUNNING.load(Ordering::SeqCst) == 0 {
+                process::exit(EXIT_CODE);
+            }
+            eprintln!("Interrupted: cancelling the running commands (Ctrl-C again to quit now)");
+            interrupt();
+        }
+    });
+}
+
+/// Cancels the run, as Ctrl-C does.
+pub fn interrupt() {
+    interrupted_tx().send_replace(true);
+}
+
+pub fn interrupted() -> bool {
+    *interrupted_tx().borrow()
+}
+
+/// Marks a run in progress while alive.
+pub struct Running(());
+
+impl Running {
+    pub fn start() -> Running {
+        RUNNING.fetch_add(1, Ordering::SeqCst);
+        Running(())
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        RUNNING.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Runs `future` to completion, or returns `None` as soon as the run is
+/// interrupted.
+pub async fn unless_interrupted<F: Future>(future: F) -> Option<F::Output> {
+    let mut rx = interrupted_tx().subscribe();
+    let mut future = pin!(future);
+    let mut interrupted = pin!(async move {
+        let _ = rx.wait_for(|interrupted| *interrupted).await;
+    });
+    poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Some(output));
+        }
+        match interrupted.as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(None),
+            Poll::Pending => Poll::Pending,
+        }
+    })
+    .await
+}
@@ -0,0 +1,404 @@
+//! Host key verification. `host_key_policy` says what happens when a
+//! host's key isn't known yet: `strict` refuses to connect, `accept-new`
+//! trusts and remembers it, `ask` asks on the terminal (ssh's default) and
+//! `insecure` checks nothing. Asking stalls a parallel run on its first
+//! contact with many hosts, so `russh known-hosts` scans the fleet's keys
+//! with `ssh-keyscan` beforehand and pins them in a russh-managed
+//! `known_hosts` file in the state directory, which every connection
+//! checks besides `~/.ssh/known_hosts`.
+
+use crate::cli::{inventory, Cli, KnownHostsArgs};
+use crate::config::Host;
+use crate::decommission::known_host_names;
+use crate::errors::{AppError, InventoryError, Result};
+use crate::paths;
+use crate::summary;
+use crate::warnings::{warn, WarningKind};
+use ansi_term::Color::{Blue, Green, Red, Yellow};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+/// The managed file's name in the state directory.
+const FILE: &str = "known_hosts";
+
+/// Seconds `ssh-keyscan` waits for a host.
+const SCAN_TIMEOUT: u64 = 5;
+
+/// What to do about a host key that isn't known yet.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyPolicy {
+    /// Refuse to connect.
+    Strict,
+    /// Trust and remember it; a changed key is still refused.
+    AcceptNew,
+    /// Ask on the terminal.
+    Ask,
+    /// Check no host keys at all.
+    Insecure,
+}
+
+impl HostKeyPolicy {
+    /// ssh's `StrictHostKeyChecking` for the policy.
+    fn strict_host_key_checking(self) -> &'static str {
+        match self {
+            HostKeyPolicy::Strict => "yes",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::Ask => "ask",
+            HostKeyPolicy::Insecure => "no",
+        }
+    }
+}
+
+impl fmt::Display for HostKeyPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            HostKeyPolicy::Strict => "strict",
+            HostKeyPolicy::AcceptNew => "accept-new",
+            HostKeyPolicy::Ask => "ask",
+            HostKeyPolicy::Insecure => "insecure",
+        })
+    }
+}
+
+impl FromStr for HostKeyPolicy {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "strict" => Ok(HostKeyPolicy::Strict),
+            "accept-new" => Ok(HostKeyPolicy::AcceptNew),
+            "ask" => Ok(HostKeyPolicy::Ask),
+            "insecure" => Ok(HostKeyPolicy::Insecure),
+            _ => Err(InventoryError::HostSpec {
+                spec: format!("host_key_policy={}", name),
+                reason: "host_key_policy must be strict, accept-new, ask or insecure".to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+static PINNED: OnceLock<Option<PathBuf>> = OnceLock::new();
+
+/// The managed `known_hosts` file.
+pub fn path() -> Result<PathBuf> {
+    paths::state_path(FILE)
+}
+
+/// Makes connections check the managed file from now on, if there is one.
+pub fn use_pinned() {
+    let _ = PINNED.get_or_init(|| path().ok().filter(|path| path.exists()));
+}
+
+/// The managed file, when connections check it.
+pub fn pinned() -> Option<&'static Path> {
+    PINNED.get().and_then(Option::as_deref)
+}
+
+/// `path` as an ssh config value, quoted if it has spaces.
+pub fn config_path(path: &Path) -> String {
+    let path = path.display().to_string();
+    match path.contains(char::is_whitespace) {
+        true => format!("\"{}\"", path),
+        false => path,
+    }
+}
+
+/// The `-o` options for `host`'s key checking, understood by ssh and scp
+/// alike. Keys accepted with `accept-new` go into the managed file.
+pub fn ssh_options(host: &Host) -> Vec<String> {
+    let mut options = Vec::new();
+    let mut option = |value: String| {
+        options.push("-o".to_string());
+        options.push(value);
+    };
+    if let Some(policy) = host.host_key_policy {
+        option(format!(
+            "StrictHostKeyChecking={}",
+            policy.strict_host_key_checking()
+        ));
+        if policy == HostKeyPolicy::Insecure {
+            option("UserKnownHostsFile=/dev/null".to_string());
+            option("LogLevel=ERROR".to_string());
+            return options;
+        }
+    }
+    if let Some(pinned) = pinned() {
+        option(format!(
+            "UserKnownHostsFile={} ~/.ssh/known_hosts ~/.ssh/known_hosts2",
+            config_path(pinned)
+        ));
+    }
+    options
+}
+
+/// One key of a `known_hosts` line: its type and base64 blob.
+type Key = (String, String);
+
+/// The keys of a `known_hosts` text by host name (`web01`, `[db01]:2222`),
+/// and the lines it can't take apart (comments, hashed names), kept as
+/// they are.
+fn parse(text: &str) -> (BTreeMap<String, Vec<Key>>, Vec<String>) {
+    let mut keys: BTreeMap<String, Vec<Key>> = BTreeMap::new();
+    let mut other = Vec::new();
+    for line in text.lines().filter(|line| !line.trim().is_empty()) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            [names, key_type, blob, ..] if !names.starts_with(['#', '|', '@']) => {
+                for name in names.split(',') {
+                    let key = (key_type.to_string(), blob.to_string());
+                    let entry = keys.entry(name.to_string()).or_default();
+                    if !entry.contains(&key) {
+                        entry.push(key);
+                    }
+                }
+            }
+            _ => other.push(line.to_string()),
+        }
+    }
+    (keys, other)
+}
+
+/// What scanning did for a host.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Pinned {
+    /// Its keys are pinned now, `usize` of them new.
+    Added(usize),
+    /// Everything it offered was pinned already.
+    Unchanged,
+    /// It offered a different key than the pinned one; kept the old one
+    /// unless replacing.
+    Changed { replaced: bool },
+    /// `ssh-keyscan` got no key from it.
+    Unreachable,
+    /// Reached through a jump host, which `ssh-keyscan` can't do.
+    BehindJump,
+}
+
+/// Merges `scanned` keys into the pinned `keys` for the host known as
+/// `name`. A key type pinned with another blob means the key changed,
+/// which only `replace` lets through.
+fn merge(
+    keys: &mut BTreeMap<String, Vec<Key>>,
+    name: &str,
+    scanned: &[Key],
+    replace: bool,
+) -> Pinned {
+    if scanned.is_empty() {
+        return Pinned::Unreachable;
+    }
+    let pinned = keys.entry(name.to_string()).or_default();
+    let changed = scanned
+        .iter()
+        .any(|(key_type, blob)| pinned.iter().any(|(t, b)| t == key_type && b != blob));
+    if changed {
+        if replace {
+            *pinned = scanned.to_vec();
+        }
+        return Pinned::Changed { replaced: replace };
+    }
+    let new: Vec<Key> = scanned
+        .iter()
+        .filter(|key| !pinned.contains(key))
+        .cloned()
+        .collect();
+    pinned.extend(new.iter().cloned());
+    match new.len() {
+        0 => Pinned::Unchanged,
+        added => Pinned::Added(added),
+    }
+}
+
+/// Renders pinned `keys` and the `other` lines kept from the file.
+fn render(keys: &BTreeMap<String, Vec<Key>>, other: &[String]) -> String {
+    let mut text = String::new();
+    for line in other {
+        text.push_str(line);
+        text.push('\n');
+    }
+    for (name, keys) in keys {
+        for (key_type, blob) in keys {
+            text.push_str(&format!("{} {} {}\n", name, key_type, blob));
+        }
+    }
+    text
+}
+
+/// The name `host`'s key is pinned under: the bare name on port 22,
+/// `[name]:port` otherwise, as ssh looks it up.
+fn pinned_name(host: &Host) -> String {
+    known_host_names(host).pop().unwrap_or_default()
+}
+
+/// Runs `ssh-keyscan` on `hosts`, which share `port`.
+fn keyscan(port: u16, hosts: &[&Host]) -> Result<BTreeMap<String, Vec<Key>>> {
+    let output = Command::new("ssh-keyscan")
+        .args(["-T", &SCAN_TIMEOUT.to_string(), "-p", &port.to_string()])
+        .args(hosts.iter().map(|host| host.host.as_str()))
+        .output()
+        .map_err(|e| AppError::Generic(format!("could not run ssh-keyscan: {}", e)))?;
+    Ok(parse(&String::from_utf8_lossy(&output.stdout)).0)
+}
+
+/// `russh known-hosts`: scans `hosts`' keys and pins them in the managed
+/// file. A changed key is only replaced with `replace`.
+pub fn scan(hosts: &[Host], replace: bool) -> Result<Vec<(String, Pinned)>> {
+    let path = path()?;
+    let _lock = paths::lock(&path)?;
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let (mut keys, other) = parse(&text);
+
+    let mut by_port: BTreeMap<u16, Vec<&Host>> = BTreeMap::new();
+    for host in hosts.iter().filter(|host| host.jump().is_none()) {
+        by_port
+            .entry(host.port.unwrap_or(22))
+            .or_default()
+            .push(host);
+    }
+    let mut scanned = BTreeMap::new();
+    for (port, hosts) in &by_port {
+        scanned.extend(keyscan(*port, hosts)?);
+    }
+
+    let results = hosts
+        .iter()
+        .map(|host| {
+            let name = pinned_name(host);
+            let pinned = match host.jump() {
+                Some(_) => Pinned::BehindJump,
+                None => merge(
+                    &mut keys,
+                    &name,
+                    scanned.get(&name).map(Vec::as_slice).unwrap_or_default(),
+                    replace,
+                ),
+            };
+            (host.host.clone(), pinned)
+        })
+        .collect();
+    keys.retain(|_, keys| !keys.is_empty());
+    paths::write_atomic(&path, render(&keys, &other).as_bytes())?;
+    Ok(results)
+}
+
+/// `russh known-hosts`: pins the selected hosts' keys.
+pub fn command(cli: &Cli, args: &KnownHostsArgs) -> Result<()> {
+    let (_, hosts) = inventory(cli)?;
+    let results = scan(&hosts, args.replace)?;
+    let width = results
+        .iter()
+        .map(|(host, _)| host.len())
+        .max()
+        .unwrap_or(0);
+    let mut changed = 0;
+    for (host, pinned) in &results {
+        let (color, status) = match pinned {
+            Pinned::Added(keys) => (Green, format!("pinned {} key(s)", keys)),
+            Pinned::Unchanged => (Blue, "unchanged".to_string()),
+            Pinned::Changed { replaced: true } => (Yellow, "key changed, replaced".to_string()),
+            Pinned::Changed { replaced: false } => {
+                changed += 1;
+                (Red, "key changed, kept the pinned one".to_string())
+            }
+            Pinned::Unreachable => (Red, "no key, unreachable".to_string()),
+            Pinned::BehindJump => (Yellow, "skipped, behind a jump host".to_string()),
+        };
+        let status = match summary::color_enabled() {
+            true => color.paint(status).to_string(),
+            false => status,
+        };
+        println!("{:width$}  {}", host, status, width = width);
+    }
+    println!("Pinned in {}", path()?.display());
+    if changed > 0 {
+        warn(
+            WarningKind::HostSkipped,
+            format!(
+                "{} host(s) offered a different key; check them, then run russh known-hosts --replace",
+                changed
+            ),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(key_type: &str, blob: &str) -> Key {
+        (key_type.to_string(), blob.to_string())
+    }
+
+    #[test]
+    fn test_merge() {
+        let (mut keys, other) = parse(
+            "# pinned by russh\n\
+             web01,10.0.0.1 ssh-ed25519 AAAAold\n\
+             |1|hashed= ssh-rsa AAAAhashed\n",
+        );
+        assert_eq!(
+            other,
+            ["# pinned by russh", "|1|hashed= ssh-rsa AAAAhashed"]
+        );
+        assert_eq!(keys["10.0.0.1"], [key("ssh-ed25519", "AAAAold")]);
+
+        let scanned = [key("ssh-ed25519", "AAAAnew"), key("ssh-rsa", "AAAArsa")];
+        assert_eq!(
+            merge(&mut keys, "web01", &scanned, false),
+            Pinned::Changed { replaced: false }
+        );
+        assert_eq!(keys["web01"], [key("ssh-ed25519", "AAAAold")]);
+        assert_eq!(
+            merge(&mut keys, "web01", &scanned, true),
+            Pinned::Changed { replaced: true }
+        );
+        assert_eq!(
+            merge(&mut keys, "web01", &scanned[..1], false),
+            Pinned::Unchanged
+        );
+        assert_eq!(
+            merge(&mut keys, "[db01]:2222", &scanned, false),
+            Pinned::Added(2)
+        );
+        assert_eq!(merge(&mut keys, "web02", &[], false), Pinned::Unreachable);
+        keys.retain(|_, keys| !keys.is_empty());
+
+        let text = render(&keys, &other);
+        assert!(text.contains("[db01]:2222 ssh-rsa AAAArsa\n"));
+        assert!(text.contains("web01 ssh-ed25519 AAAAnew\n"));
+        assert!(!text.contains("web02"));
+        assert_eq!(parse(&text).0, keys);
+
+        let mut host = Host::new("web01");
+        host.host_key_policy = Some(HostKeyPolicy::Insecure);
+        assert_eq!(
+            ssh_options(&host),
+            [
+                "-o",
+                "StrictHostKeyChecking=no",
+                "-o",
+                "UserKnownHostsFile=/dev/null",
+                "-o",
+                "LogLevel=ERROR"
+            ]
+        );
+        assert_eq!(
+            "accept-new".parse::<HostKeyPolicy>().unwrap(),
+            HostKeyPolicy::AcceptNew
+        );
+        assert!("lenient".parse::<HostKeyPolicy>().is_err());
+    }
+}
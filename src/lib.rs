@@ -0,0 +1,145 @@
+//! russh as a library: load a russh config, pick hosts and run commands on
+//! all of them at once, getting back a [`ServerResult`] per host and
+//! command. The `russh` binary is a command-line front end to this crate.
+//!
+//! ```no_run
+//! use russh::{read_config, Event, Executor};
+//!
+//! let config = read_config("russh.toml")?;
+//! let results = Executor::from_config(&config)
+//!     .command("systemctl is-active nginx")
+//!     .timeout(10)
+//!     .run_with(|event| match event {
+//!         Event::StdoutLine { server, line } => println!("{} | {}", server, line),
+//!         Event::StderrLine { server, line } => eprintln!("{} | {}", server, line),
+//!         Event::Completed { .. } => {}
+//!     });
+//! let failed = results.iter().filter(|(_, result)| !result.success).count();
+//! println!("{} failed", failed);
+//! # Ok::<(), russh::AppError>(())
+//! ```
+//!
+//! The modules not documented here back the command-line interface and may
+//! change between any two releases.
+
+pub mod auth;
+pub mod config;
+pub mod errors;
+pub mod executor;
+pub mod ssh;
+
+#[doc(hidden)]
+pub mod answers;
+#[doc(hidden)]
+pub mod approval;
+#[doc(hidden)]
+pub mod archive;
+#[doc(hidden)]
+pub mod askpass;
+#[doc(hidden)]
+pub mod audit;
+#[doc(hidden)]
+pub mod bot;
+#[doc(hidden)]
+pub mod capability;
+#[doc(hidden)]
+pub mod chain;
+#[doc(hidden)]
+pub mod cli;
+#[doc(hidden)]
+pub mod clipboard;
+#[doc(hidden)]
+pub mod crypto;
+#[doc(hidden)]
+pub mod decommission;
+#[doc(hidden)]
+pub mod deploy;
+#[doc(hidden)]
+pub mod du;
+#[doc(hidden)]
+pub mod escalation;
+#[doc(hidden)]
+pub mod estimate;
+#[doc(hidden)]
+pub mod exit;
+#[doc(hidden)]
+pub mod expect;
+#[doc(hidden)]
+pub mod facts;
+#[doc(hidden)]
+pub mod filter;
+#[doc(hidden)]
+pub mod group;
+#[doc(hidden)]
+pub mod history;
+#[doc(hidden)]
+pub mod http;
+#[doc(hidden)]
+pub mod i18n;
+#[doc(hidden)]
+pub mod info;
+#[doc(hidden)]
+pub mod interrupt;
+#[doc(hidden)]
+pub mod known_hosts;
+#[doc(hidden)]
+pub mod list;
+#[doc(hidden)]
+pub mod lock;
+#[doc(hidden)]
+pub mod logs;
+#[doc(hidden)]
+pub mod monitor;
+#[doc(hidden)]
+pub mod multiplex;
+#[doc(hidden)]
+pub mod output;
+#[doc(hidden)]
+pub mod paths;
+#[doc(hidden)]
+pub mod pick;
+#[doc(hidden)]
+pub mod ping;
+#[doc(hidden)]
+pub mod preflight;
+#[doc(hidden)]
+pub mod prereq;
+#[doc(hidden)]
+pub mod prompt;
+#[doc(hidden)]
+pub mod queue;
+#[doc(hidden)]
+pub mod report;
+#[doc(hidden)]
+pub mod runbook;
+#[doc(hidden)]
+pub mod runtime;
+#[doc(hidden)]
+pub mod script;
+#[doc(hidden)]
+pub mod serve;
+#[doc(hidden)]
+pub mod sets;
+#[doc(hidden)]
+pub mod sink;
+#[doc(hidden)]
+pub mod summary;
+#[doc(hidden)]
+pub mod template;
+#[doc(hidden)]
+pub mod transfer;
+#[cfg(feature = "tui")]
+#[doc(hidden)]
+pub mod tui;
+#[doc(hidden)]
+pub mod update;
+#[doc(hidden)]
+pub mod warnings;
+#[doc(hidden)]
+pub mod webhook;
+
+pub use auth::AuthHandler;
+pub use config::{read_config, Config, Host};
+pub use errors::{AppError, Result};
+pub use executor::{Executor, Running};
+pub use ssh::{Event, ExecOptions, ServerResult};
@@ -0,0 +1,133 @@
+//! `russh list`: the selected hosts as a run would see them, after the
+//! config's `[users]`, `[ssh_options]`, `[tags]`, `[bastion]` and defaults
+//! are applied, to find out why a host is or isn't targeted.
+
+use crate::cli::{inventory, Cli};
+use crate::config::{Config, Host};
+use crate::errors::{AppError, Result};
+use crate::output::OutputFormat;
+use crate::ssh::connection_args;
+use serde::Serialize;
+
+/// One host of the inventory.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Entry {
+    pub host: String,
+    pub user: Option<String>,
+    pub port: u16,
+    /// The config groups it is a member of.
+    pub groups: Vec<String>,
+    pub tags: Vec<String>,
+    pub jump_host: Option<String>,
+    /// Everything ssh is given before the destination.
+    pub ssh_args: Vec<String>,
+}
+
+pub fn entries(config: &Config, hosts: &[Host]) -> Vec<Entry> {
+    hosts
+        .iter()
+        .map(|host| {
+            let mut groups: Vec<String> = config
+                .groups
+                .iter()
+                .filter(|(_, group)| group.members.contains(&host.host))
+                .map(|(name, _)| name.clone())
+                .collect();
+            groups.sort();
+            Entry {
+                host: host.host.clone(),
+                user: host.user.clone().filter(|user| !user.is_empty()),
+                port: host.port.unwrap_or(22),
+                groups,
+                tags: host.tags.clone(),
+                jump_host: host.jump().map(str::to_string),
+                ssh_args: connection_args(host),
+            }
+        })
+        .collect()
+}
+
+fn or_dash(values: &[String]) -> String {
+    match values.is_empty() {
+        true => "-".to_string(),
+        false => values.join(","),
+    }
+}
+
+pub fn print(entries: &[Entry], json: bool) -> Result<()> {
+    if json {
+        let json =
+            serde_json::to_string_pretty(entries).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let rows: Vec<[String; 6]> = entries
+        .iter()
+        .map(|entry| {
+            [
+                entry.host.clone(),
+                entry.user.clone().unwrap_or_else(|| "-".to_string()),
+                entry.port.to_string(),
+                or_dash(&entry.groups),
+                or_dash(&entry.tags),
+                entry.jump_host.clone().unwrap_or_else(|| "-".to_string()),
+            ]
+        })
+        .collect();
+    let header = ["HOST", "USER", "PORT", "GROUPS", "TAGS", "JUMP"].map(str::to_string);
+    let mut widths = header.clone().map(|h| h.len());
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let line = |row: &[String; 6]| {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        cells.join("  ").trim_end().to_string()
+    };
+    println!("{}", line(&header));
+    for (row, entry) in rows.iter().zip(entries) {
+        println!("{}", line(row));
+        if !entry.ssh_args.is_empty() {
+            println!("    ssh {}", shell_words::join(&entry.ssh_args));
+        }
+    }
+    println!("{} host(s)", entries.len());
+    Ok(())
+}
+
+/// `russh list`: prints the selected hosts.
+pub fn command(cli: &Cli) -> Result<()> {
+    let (config, hosts) = inventory(cli)?;
+    print(&entries(&config, &hosts), cli.output != OutputFormat::Text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Group;
+
+    #[test]
+    fn test_entries() {
+        let mut config = Config::default();
+        config.groups.insert(
+            "web".to_string(),
+            Group {
+                members: vec!["web01".to_string()],
+                ..Group::default()
+            },
+        );
+        let web01: Host = "web01 user=deploy port=2222 tag=canary".parse().unwrap();
+        let entries = entries(&config, &[web01, Host::new("db01")]);
+        assert_eq!(entries[0].groups, ["web"]);
+        assert_eq!(entries[0].tags, ["canary"]);
+        assert_eq!(entries[0].ssh_args[..2], ["-p", "2222"]);
+        assert_eq!(entries[1].port, 22);
+        assert_eq!(entries[1].user, None);
+        assert!(entries[1].groups.is_empty());
+    }
+}
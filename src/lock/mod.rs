@@ -0,0 +1,166 @@
+use crate::audit::{current_user, now};
+use crate::config::{Config, Host};
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::warnings::{warn, WarningKind};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+/// Who holds a run lock, stored as JSON in the lock file.
+#[derive(Debug, Serialize, Deserialize)]
+struct LockInfo {
+    pid: u32,
+    user: String,
+    started: u64,
+    target: String,
+}
+
+/// Advisory lock preventing two russh runs against the same inventory.
+/// The lock file is removed when the guard is dropped.
+pub struct RunLock {
+    path: PathBuf,
+}
+
+/// Locks live in the system temp dir so that different operators on the
+/// same bastion see each other's runs.
+pub fn lock_dir() -> PathBuf {
+    env::temp_dir().join("russh-locks")
+}
+
+/// Creates (if needed) and returns a directory under the system temp dir that
+/// every local user can write to.
+pub fn shared_dir(name: &str) -> Result<PathBuf> {
+    let dir = env::temp_dir().join(name);
+    create_shared_dir(&dir)?;
+    Ok(dir)
+}
+
+/// What the run lock is keyed on: the config file, or the host list for
+/// ad-hoc runs without one.
+pub fn target(config: &Config, hosts: &[Host]) -> String {
+    match &config.path {
+        Some(path) => path.display().to_string(),
+        None => {
+            let mut names: Vec<&str> = hosts.iter().map(|h| h.host.as_str()).collect();
+            names.sort();
+            format!("hosts:{}", names.join(","))
+        }
+    }
+}
+
+fn lock_path(target: &str) -> PathBuf {
+    let digest = Sha256::digest(target.as_bytes());
+    let name: String = digest
+        .iter()
+        .take(8)
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    lock_dir().join(format!("{}.lock", name))
+}
+
+fn create_shared_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    fs::create_dir_all(dir)?;
+    #[cfg(unix)]
+    {
+        // World-writable with the sticky bit, like /tmp itself.
+        use std::os::unix::fs::PermissionsExt;
+        let _ = fs::set_permissions(dir, fs::Permissions::from_mode(0o1777));
+    }
+    Ok(())
+}
+
+/// Whether the process that wrote the lock is known to be gone.
+fn holder_is_dead(info: &LockInfo) -> bool {
+    if cfg!(target_os = "linux") {
+        !PathBuf::from(format!("/proc/{}", info.pid)).exists()
+    } else {
+        false
+    }
+}
+
+impl RunLock {
+    /// Takes the lock for `target` (the config path or host list being run).
+    /// `break_lock` removes an existing lock regardless of who holds it.
+    pub fn acquire(target: &str, break_lock: bool) -> Result<RunLock> {
+        create_shared_dir(&lock_dir())?;
+        let path = lock_path(target);
+        let info = LockInfo {
+            pid: std::process::id(),
+            user: current_user(),
+            started: now(),
+            target: target.to_string(),
+        };
+
+        for _ in 0..2 {
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    let json =
+                        serde_json::to_vec(&info).map_err(|e| AppError::Generic(e.to_string()))?;
+                    file.write_all(&json)?;
+                    return Ok(RunLock { path });
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let holder: Option<LockInfo> = fs::read(&path)
+                        .ok()
+                        .and_then(|bytes| serde_json::from_slice(&bytes).ok());
+                    let stale = holder.as_ref().is_none_or(holder_is_dead);
+                    if let Some(holder) = holder.as_ref().filter(|_| !(break_lock || stale)) {
+                        return Err(ExecutionError::Refused(format!(
+                            "another russh run holds the lock for {} (pid {} by {}, started {}s ago); \
+                             use --break-lock if it is stale or --no-lock to run anyway",
+                            target,
+                            holder.pid,
+                            holder.user,
+                            now().saturating_sub(holder.started)
+                        ))
+                        .into());
+                    }
+                    if let Some(holder) = holder {
+                        warn(
+                            WarningKind::Lock,
+                            format!(
+                                "removed run lock for {} held by pid {} ({})",
+                                target, holder.pid, holder.user
+                            ),
+                        );
+                    }
+                    fs::remove_file(&path)?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Err(AppError::Generic(format!(
+            "could not take the run lock for {}",
+            target
+        )))
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lock_is_exclusive_until_dropped() {
+        let target = format!("test-target-{}", std::process::id());
+        let lock = RunLock::acquire(&target, false).expect("Failed to take lock");
+        assert!(RunLock::acquire(&target, false).is_err());
+        drop(lock);
+        let lock = RunLock::acquire(&target, false).expect("Failed to retake lock");
+        let forced = RunLock::acquire(&target, true).expect("Failed to break lock");
+        drop(lock);
+        drop(forced);
+    }
+}
@@ -0,0 +1,176 @@
+//! Per-host run logs: with `log_dir` (or `--log-dir`), every run writes
+//! `<log_dir>/<timestamp>/<host>.log`, each holding the commands run on
+//! that host with their stdout, stderr, exit code and duration, under the
+//! run's `--label`s, and `report.json` with every result, so fleet runs can
+//! be archived as they are.
+
+use crate::audit::now;
+use crate::errors::Result;
+use crate::history::Labels;
+use crate::output;
+use crate::paths;
+use crate::ssh::ServerResult;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// `secs` since the epoch as a UTC `YYYYMMDDTHHMMSSZ` timestamp, which
+/// sorts by time and is safe in a file name.
+pub fn timestamp(secs: u64) -> String {
+    let (days, rest) = (secs / 86_400, secs % 86_400);
+    // Days to a civil date, from Howard Hinnant's `civil_from_days`.
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// A new directory for this run's logs under `log_dir`; a run starting
+/// in the same second as another gets a `-2`, `-3`... suffix.
+fn run_dir(log_dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(log_dir)?;
+    let stamp = timestamp(now());
+    for n in 1.. {
+        let dir = match n {
+            1 => log_dir.join(&stamp),
+            _ => log_dir.join(format!("{}-{}", stamp, n)),
+        };
+        match fs::create_dir(&dir) {
+            Ok(()) => return Ok(dir),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    unreachable!()
+}
+
+/// One command's entry in its host's log.
+fn entry(command: &str, result: &ServerResult) -> String {
+    let mut text = format!("$ {}\n", command);
+    match result.exit_code {
+        Some(code) => text.push_str(&format!("exit code: {}\n", code)),
+        None => text.push_str("exit code: none\n"),
+    }
+    text.push_str(&format!("duration: {:.2}s\n", result.duration));
+    if let Some(cut) = result.cut {
+        text.push_str(&format!("skipped: {}\n", cut.describe()));
+    }
+    for (name, stream) in [
+        ("stdout", result.output.as_str()),
+        ("stderr", result.error.as_deref().unwrap_or("")),
+    ] {
+        text.push_str(&format!("--- {} ---\n", name));
+        if !stream.is_empty() {
+            text.push_str(stream);
+            if !stream.ends_with('\n') {
+                text.push('\n');
+            }
+        }
+    }
+    text
+}
+
+/// The run's JSON report next to the host logs.
+pub const REPORT: &str = "report.json";
+
+/// Writes the logs of a run into a new directory under `log_dir`, one
+/// file per host with the labels and its commands in the order they ran,
+/// and its report. Returns the directory.
+pub fn write(
+    log_dir: &Path,
+    results: &[(String, ServerResult)],
+    labels: &Labels,
+) -> Result<PathBuf> {
+    let dir = run_dir(log_dir)?;
+    let mut header: String = labels
+        .iter()
+        .map(|(key, value)| format!("label: {}={}\n", key, value))
+        .collect();
+    if !header.is_empty() {
+        header.push('\n');
+    }
+    let mut logs: Vec<(&str, String)> = Vec::new();
+    for (command, result) in results {
+        let text = entry(command, result);
+        match logs.iter_mut().find(|(host, _)| *host == result.server) {
+            Some((_, log)) => {
+                log.push('\n');
+                log.push_str(&text);
+            }
+            None => logs.push((&result.server, format!("{}{}", header, text))),
+        }
+    }
+    for (host, log) in logs {
+        let path = dir.join(format!("{}.log", paths::file_name(host)));
+        fs::write(path, log)?;
+    }
+    fs::write(dir.join(REPORT), output::to_json(results)?)?;
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_logs() {
+        assert_eq!(timestamp(0), "19700101T000000Z");
+        assert_eq!(timestamp(1_709_251_199), "20240229T235959Z");
+
+        let result = |server: &str, output: &str, error: Option<&str>, code| ServerResult {
+            server: server.to_string(),
+            output: output.to_string(),
+            error: error.map(str::to_string),
+            duration: 1.5,
+            success: code == 0,
+            exit_code: Some(code),
+            ..Default::default()
+        };
+        let results = vec![
+            (
+                "uptime".to_string(),
+                result("web01", "up 3 days\n", None, 0),
+            ),
+            (
+                "uptime".to_string(),
+                result("db/01", "", Some("no route"), 255),
+            ),
+            ("df -h".to_string(), result("web01", "/ 40%\n", None, 0)),
+        ];
+        let log_dir = std::env::temp_dir().join(format!("russh-logs-{}", std::process::id()));
+        let labels = Labels::from([("ticket".to_string(), "OPS-1234".to_string())]);
+        let first = write(&log_dir, &results, &Labels::new()).unwrap();
+        let second = write(&log_dir, &results, &labels).unwrap();
+        let web01 = fs::read_to_string(first.join("web01.log")).unwrap();
+        let db01 = fs::read_to_string(first.join("db_01.log")).unwrap();
+        let labelled = fs::read_to_string(second.join("db_01.log")).unwrap();
+        let report = fs::read_to_string(first.join(REPORT)).unwrap();
+        let _ = fs::remove_dir_all(&log_dir);
+        assert_ne!(first, second);
+        assert!(labelled.starts_with("label: ticket=OPS-1234\n\n$ uptime\n"));
+        assert!(report.contains("\"no route\""));
+        assert_eq!(
+            web01,
+            "$ uptime\nexit code: 0\nduration: 1.50s\n--- stdout ---\nup 3 days\n--- stderr ---\n\n\
+             $ df -h\nexit code: 0\nduration: 1.50s\n--- stdout ---\n/ 40%\n--- stderr ---\n"
+        );
+        assert_eq!(
+            db01,
+            "$ uptime\nexit code: 255\nduration: 1.50s\n--- stdout ---\n--- stderr ---\nno route\n"
+        );
+    }
+}
@@ -1,165 +1,97 @@
-mod config;
-mod ssh;
-use crate::config::Config;
-use crate::config::{
-    find_config_in_cwd, find_config_in_user_dir, prompt_create_default_config, read_config,
+use russh::cli::{self, Cli, Subcommand};
+use russh::errors::Result;
+use russh::i18n::{trf, Locale, Msg};
+use russh::runbook::gate;
+use russh::warnings::{print_warnings, warn, WarningKind};
+use russh::{
+    approval, askpass, bot, capability, decommission, deploy, du, exit, facts, history, i18n, info,
+    interrupt, known_hosts, list, monitor, multiplex, paths, ping, queue, runbook, script, serve,
+    sets, transfer, update, warnings,
 };
-use crate::ssh::run_ssh_command;
 
-use ansi_term::Color::{Blue, Green, Red};
-use argh::FromArgs;
-
-use crate::ssh::ServerResult;
-
-use std::io::{self, IsTerminal, Write}; // Use std::io::Write and others
-use std::path::PathBuf;
-use std::sync::mpsc::{self, Receiver};
-
-use std::thread;
-use thiserror::Error;
-#[derive(Error, Debug)]
-enum AppError {
-    #[error("file error: {0}")]
-    File(#[from] std::io::Error),
-    #[error("generic error: {0}")]
-    Generic(String),
-    #[error("toml error: {0}")]
-    TomlDeserializationError(toml::de::Error),
-    #[error("toml error: {0}")]
-    TomlSerializationError(toml::ser::Error),
-    // Add other error types as needed
-}
-
-/// executes SSH commands on multiple servers.
-/// This is the main configuration for the command line interface.
-#[derive(FromArgs, PartialEq, Debug)]
-struct Cli {
-    /// specify the commands that should be executed on the remote servers.
-    /// These are the actual SSH commands that will be run on each server.
-    #[argh(positional)]
-    commands: Vec<String>,
-
-    /// optional: specify the relative path to the russh.json file.
-    /// If not provided, a default path or other logic will be used.
-    #[argh(option, short = 'c')]
-    config_file: Option<String>,
-}
-
-type Result<T> = std::result::Result<T, AppError>;
-
-fn parse_cli_args() -> Cli {
-    argh::from_env()
-}
-
-// Assuming `prompt_create_default_config` returns a Result<Option<PathBuf>, Error>
-
-fn load_config(config_file: &Option<String>) -> Result<Config> {
-    let config_path = match config_file {
-        Some(path) => PathBuf::from(path),
-        None => find_config_in_cwd()
-            .or_else(find_config_in_user_dir)
-            .or_else(|| match prompt_create_default_config() {
-                Ok(Some(path)) => Some(path),
-                Ok(None) => None, // User chose not to create a config
-                Err(e) => {
-                    eprintln!("Error during configuration creation: {}", e);
-                    None
-                }
-            })
-            .ok_or_else(|| AppError::Generic("Configuration file not found".to_string()))?,
-    };
-
-    let config_path_str = config_path
-        .to_str()
-        .ok_or_else(|| AppError::Generic("Invalid configuration file path".to_string()))?;
-
-    read_config(config_path_str).map_err(|e| {
-        eprintln!("Failed to read configuration file: {}", e);
-        AppError::Generic("Failed to read configuration file".to_string())
-    })
-}
-
-fn run_application(cli: Cli) -> Result<()> {
-    let (tx, rx): (mpsc::Sender<ServerResult>, Receiver<ServerResult>) = mpsc::channel();
-    let mut handles = Vec::new();
-
-    // Start a thread for displaying outputs
-    thread::spawn(move || {
-        display_outputs(rx);
-    });
-
-    // Load configuration
-    let config = load_config(&cli.config_file)?;
-
-    println!("Processing commands...");
-    for server in &config.servers {
-        for command in &cli.commands {
-            // Clone the values inside the loop before passing them to the thread
-            let server_clone = server.clone();
-            let user_clone = config.users.get(server).unwrap_or(&String::new()).clone();
-            let ssh_options_clone = config
-                .ssh_options
-                .get(server)
-                .unwrap_or(&String::new())
-                .clone();
-            let command_clone = command.clone();
-            let tx_clone = tx.clone();
-
-            let handle = thread::spawn(move || {
-                run_ssh_command(
-                    &server_clone,
-                    &user_clone,
-                    &command_clone,
-                    &ssh_options_clone,
-                    tx_clone,
-                );
-            });
-            handles.push(handle);
+fn main() {
+    if let Some(code) = askpass::answer() {
+        std::process::exit(code);
+    }
+    let cli: Cli = argh::from_env();
+    if let Some(tag) = &cli.lang {
+        match Locale::from_tag(tag) {
+            Some(locale) => i18n::set_locale(locale),
+            None => eprintln!("Unsupported language '{}', using the default", tag),
         }
     }
-
-    // Wait for all threads to complete
-    for handle in handles {
-        if let Err(e) = handle.join() {
-            eprintln!("Failed to join thread: {:?}", e);
+    capability::set_offline(cli.offline);
+    if let Some(dir) = &cli.state_dir {
+        paths::set_state_dir(dir.clone());
+    }
+    known_hosts::use_pinned();
+    if !cli.no_multiplex {
+        if let Err(e) = multiplex::enable() {
+            warn(
+                WarningKind::ConfigFallback,
+                format!("connections are not shared: {}", e),
+            );
         }
     }
 
-    // Final summary or any other post-processing can be done here
-    println!("Execution completed.");
-
-    Ok(())
-}
-
-fn display_outputs(rx: Receiver<ServerResult>) {
-    for result in rx {
-        println!("{} - Output: {}", result.server, result.output);
-        std::io::stdout().flush().unwrap();
-
-        // Handle keyboard inputs for scrolling here
-        // ...
+    if cli.version {
+        if cli.verbose {
+            let _ = info::print_info(&info::gather(&cli.config_file), false);
+        } else {
+            println!("{}", info::version());
+        }
+        return;
+    }
 
-        thread::sleep(std::time::Duration::from_millis(100));
+    let all_must_succeed = cli.all_must_succeed;
+    interrupt::install();
+    let result = run_application(cli);
+    print_warnings(&warnings::take());
+    if let Err(e) = result {
+        eprintln!("{}", trf(Msg::ApplicationError, &[&e]));
+        std::process::exit(exit::ERROR);
+    }
+    let code = exit::code(all_must_succeed);
+    if code != exit::SUCCESS {
+        std::process::exit(code);
     }
 }
 
-fn main() {
-    if !io::stdout().is_terminal() {
-        eprint!("This application must be run in a terminal.");
-        std::process::exit(1);
+fn run_application(mut cli: Cli) -> Result<()> {
+    if let Some(path) = cli.hosts_file.take() {
+        cli.hosts.extend(cli::read_hosts_file(&path)?);
     }
-
-    println!("{}", Blue.paint("russh - Multi-Host SSH Client"));
-    println!("-----------------------------");
-    println!("{}", Green.paint("Author: Eric Tossell"));
-    println!(
-        "{}",
-        Red.paint("GitHub: https://github.com/erictossell/russh")
-    );
-
-    let cli = parse_cli_args();
-    if let Err(e) = run_application(cli) {
-        eprintln!("Application error: {}", e);
-        std::process::exit(1); // Use an appropriate exit code
+    let Some(subcommand) = cli.subcommand.take() else {
+        return cli::run::commands(&cli);
+    };
+    match subcommand {
+        Subcommand::SelfUpdate(args) => update::self_update(args.check),
+        Subcommand::Info(args) => info::print_info(&info::gather(&cli.config_file), args.json),
+        Subcommand::Approve(args) => approval::approve(&args.request_id, args.yes),
+        Subcommand::FlushQueue(args) => queue::flush(&cli, &args),
+        Subcommand::Push(args) => transfer::push_command(&cli, &args),
+        Subcommand::Pull(args) => transfer::pull_command(&cli, &args),
+        Subcommand::RerunFailed(_) => cli::run::rerun_failed(&cli),
+        Subcommand::Monitor(args) => monitor::command(&cli, &args),
+        Subcommand::Import(args) => cli::config::import(&cli, &args),
+        Subcommand::Deploy(args) => deploy::command(&cli, &args),
+        Subcommand::Task(args) => cli::task::command(&cli, &args),
+        Subcommand::Facts(args) => facts::command(&cli, &args),
+        Subcommand::Paths(args) => paths::print_paths(&paths::gather(&cli.config_file), args.json),
+        Subcommand::Decommission(args) => decommission::command(&cli, &args),
+        Subcommand::Script(args) => script::command(&cli, &args),
+        Subcommand::Runbook(args) => runbook::command(&cli, &args),
+        Subcommand::Gate(args) => gate::command(&args),
+        Subcommand::KnownHosts(args) => known_hosts::command(&cli, &args),
+        Subcommand::Serve(args) => serve::command(&cli, &args),
+        Subcommand::List(_) => list::command(&cli),
+        Subcommand::Bot(_) => bot::command(&cli),
+        Subcommand::Set(args) => sets::command(&cli, &args),
+        Subcommand::History(args) => {
+            history::print(&history::load(&args.label, args.limit)?, args.json)
+        }
+        Subcommand::Config(args) => cli::config::manage(&cli, &args),
+        Subcommand::Ping(args) => ping::command(&cli, &args),
+        Subcommand::Du(args) => du::command(&cli, &args),
     }
 }
@@ -0,0 +1,296 @@
+//! `russh monitor`: a lightweight availability watch that probes every host
+//! over SSH at an interval and reports when hosts go down or come back.
+
+use crate::capability::{self, Capability};
+use crate::cli::{inventory, Cli, MonitorArgs};
+use crate::config::Host;
+use crate::errors::{AppError, ExecutionError, Result, TransportError};
+use crate::executor::{execute_blocking_jobs, parallelism, Display, Schedule};
+use crate::http;
+use crate::ssh::{self, build_ssh_args, run_process_blocking, Input, ServerResult};
+use crate::warnings::{self, print_warnings, warn, WarningKind};
+use crate::webhook::WebhookConfig;
+use crate::{audit, summary};
+use ansi_term::Color::{Green, Red};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Connects to `host` and runs `true`, without prompting for anything.
+pub fn probe(host: &Host, connect_timeout: u64, tx: Sender<ssh::Event>) -> ServerResult {
+    let mut args = vec![
+        "-o".to_string(),
+        "BatchMode=yes".to_string(),
+        "-o".to_string(),
+        format!("ConnectTimeout={}", connect_timeout),
+    ];
+    args.extend(build_ssh_args(host, "true"));
+    run_process_blocking(host, "ssh", &args, Input::default(), None, tx)
+}
+
+/// A host changing between reachable and unreachable.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Event {
+    pub host: String,
+    pub up: bool,
+    pub at: u64,
+    /// Why the probe failed, for hosts going down.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+}
+
+impl Event {
+    pub fn message(&self) -> String {
+        match (self.up, &self.reason) {
+            (true, _) => format!("{} is reachable again", self.host),
+            (false, Some(reason)) => format!("{} is unreachable: {}", self.host, reason),
+            (false, None) => format!("{} is unreachable", self.host),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct HostStatus {
+    up: bool,
+    since: u64,
+}
+
+/// The last known state of every host, keyed by host name.
+#[derive(Default)]
+pub struct StatusTable {
+    hosts: BTreeMap<String, HostStatus>,
+}
+
+impl StatusTable {
+    /// Records one round of probe results and returns the hosts whose state
+    /// changed. A host that is down on its first check counts as a change;
+    /// one that is up on its first check doesn't.
+    pub fn update(&mut self, results: &[(String, ServerResult)], at: u64) -> Vec<Event> {
+        let mut events = Vec::new();
+        for (_, result) in results {
+            let up = result.success;
+            let changed = match self.hosts.get(&result.server) {
+                Some(status) if status.up == up => continue,
+                Some(_) => true,
+                None => !up,
+            };
+            self.hosts
+                .insert(result.server.clone(), HostStatus { up, since: at });
+            if changed {
+                events.push(Event {
+                    host: result.server.clone(),
+                    up,
+                    at,
+                    reason: if up { None } else { reason(result) },
+                });
+            }
+        }
+        events
+    }
+
+    pub fn format(&self, now: u64, color: bool) -> String {
+        let width = self.hosts.keys().map(String::len).max().unwrap_or_default();
+        let mut out = String::new();
+        for (host, status) in &self.hosts {
+            let state = format!("{:<4}", if status.up { "up" } else { "down" });
+            let state = match (color, status.up) {
+                (false, _) => state,
+                (true, true) => Green.paint(state).to_string(),
+                (true, false) => Red.paint(state).to_string(),
+            };
+            out.push_str(&format!(
+                "{:<width$}  {}  for {}\n",
+                host,
+                state,
+                format_age(now.saturating_sub(status.since)),
+                width = width
+            ));
+        }
+        let down = self.hosts.values().filter(|s| !s.up).count();
+        out.push_str(&format!("{} up, {} down\n", self.hosts.len() - down, down));
+        out
+    }
+}
+
+/// The last non-empty stderr line, which is where ssh says what went wrong.
+fn reason(result: &ServerResult) -> Option<String> {
+    result
+        .error
+        .as_deref()
+        .and_then(|e| e.lines().rev().find(|l| !l.trim().is_empty()))
+        .map(|l| l.trim().to_string())
+}
+
+pub fn format_age(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+        _ => format!("{}d{}h", secs / 86400, secs % 86400 / 3600),
+    }
+}
+
+/// Delivers state changes to the configured webhook and, with `--desktop`,
+/// as desktop notifications. Delivery problems are only warnings so the
+/// watch keeps running.
+pub struct Notifier {
+    pub webhook: Option<WebhookConfig>,
+    pub desktop: bool,
+}
+
+impl Notifier {
+    pub fn notify(&self, event: &Event) {
+        if let Some(webhook) = &self.webhook {
+            let delivered = serde_json::to_vec(event)
+                .map_err(|e| AppError::Generic(e.to_string()))
+                .and_then(|body| http::post_json(&webhook.url, &body, webhook.timeout));
+            if let Err(e) = delivered {
+                warn(
+                    WarningKind::Delivery,
+                    format!("monitor webhook for {} not delivered: {}", event.host, e),
+                );
+            }
+        }
+        if self.desktop {
+            if let Err(e) = desktop_notification("russh monitor", &event.message()) {
+                warn(
+                    WarningKind::Delivery,
+                    format!("desktop notification failed: {}", e),
+                );
+            }
+        }
+    }
+}
+
+fn desktop_notification(title: &str, body: &str) -> Result<()> {
+    let (program, mut command) = if cfg!(target_os = "macos") {
+        let script = format!("display notification {:?} with title {:?}", body, title);
+        let mut command = Command::new("osascript");
+        command.args(["-e", &script]);
+        ("osascript", command)
+    } else {
+        let mut command = Command::new("notify-send");
+        command.args([title, body]);
+        ("notify-send", command)
+    };
+    let status = command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|source| TransportError::Spawn { program, source })?;
+    if !status.success() {
+        return Err(AppError::Generic(format!(
+            "{} exited with {}",
+            program, status
+        )));
+    }
+    Ok(())
+}
+
+/// `russh monitor`: probes every selected host each interval, prints the
+/// status table and notifies about hosts going down or coming back.
+pub fn command(cli: &Cli, args: &MonitorArgs) -> Result<()> {
+    if args.interval == 0 {
+        return Err(ExecutionError::InvalidOption {
+            option: "--interval",
+            reason: "must be at least 1".to_string(),
+        }
+        .into());
+    }
+    let (config, hosts) = inventory(cli)?;
+    if config.webhook.is_some() {
+        capability::check(Capability::Webhooks)?;
+    }
+    if config.archive.is_some() {
+        capability::check(Capability::Archive)?;
+    }
+    let parallel = parallelism(cli.parallel, Some(&config))?;
+    let notifier = Notifier {
+        webhook: config.webhook.clone(),
+        desktop: args.desktop,
+    };
+    let mut table = StatusTable::default();
+    let color = summary::color_enabled();
+    let connect_timeout = args.connect_timeout;
+    let label: Arc<str> = "ssh".into();
+    let hosts: Vec<Arc<Host>> = hosts.into_iter().map(Arc::new).collect();
+    loop {
+        let jobs = hosts
+            .iter()
+            .map(|host| (Arc::clone(host), Arc::clone(&label)))
+            .collect();
+        let results = execute_blocking_jobs(
+            jobs,
+            move |host, _, tx| probe(host, connect_timeout, tx),
+            None,
+            Display::Quiet,
+            Schedule::parallel(parallel),
+        );
+        let now = audit::now();
+        for event in table.update(&results, now) {
+            eprintln!("{}", event.message());
+            notifier.notify(&event);
+        }
+        print!("{}", table.format(now, color));
+        print_warnings(&warnings::take());
+        if args.once {
+            return Ok(());
+        }
+        println!();
+        thread::sleep(Duration::from_secs(args.interval));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn probed(server: &str, up: bool) -> (String, ServerResult) {
+        (
+            "ssh".to_string(),
+            ServerResult {
+                server: server.to_string(),
+                error: (!up).then(|| {
+                    format!(
+                        "ssh: connect to host {} port 22: Connection refused\n",
+                        server
+                    )
+                }),
+                duration: 0.1,
+                success: up,
+                exit_code: Some(if up { 0 } else { 255 }),
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_status_transitions() {
+        let mut table = StatusTable::default();
+
+        let events = table.update(&[probed("web01", true), probed("web02", false)], 100);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].host, "web02");
+        assert!(!events[0].up);
+        assert_eq!(
+            events[0].reason.as_deref(),
+            Some("ssh: connect to host web02 port 22: Connection refused")
+        );
+
+        assert!(table
+            .update(&[probed("web01", true), probed("web02", false)], 160)
+            .is_empty());
+
+        let events = table.update(&[probed("web01", false), probed("web02", true)], 220);
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].message(), "web02 is reachable again");
+
+        let text = table.format(400, false);
+        assert!(text.contains("web01  down  for 3m"));
+        assert!(text.ends_with("1 up, 1 down\n"));
+    }
+}
@@ -0,0 +1,85 @@
+//! Connection reuse: several commands for the same host share one SSH
+//! connection instead of each paying for a TCP and authentication
+//! handshake. ssh and scp do it with `ControlMaster`, through sockets in a
+//! private directory; the master stays up for `PERSIST` after the last
+//! command so the next run's first commands can use it too. The native
+//! backend keeps finished sessions for the next command to the host.
+//! `--no-multiplex` turns it off.
+
+use crate::audit::current_user;
+use crate::errors::{AppError, Result};
+use crate::known_hosts::config_path;
+use crate::ssh::client;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+/// How long an idle master connection stays up.
+const PERSIST: &str = "60s";
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Where ssh's control sockets go.
+static SOCKETS: OnceLock<PathBuf> = OnceLock::new();
+
+/// A directory only the current user can use, short enough for socket
+/// paths: under `$XDG_RUNTIME_DIR` if there is one, else the temp dir.
+#[cfg(unix)]
+fn socket_dir() -> Result<PathBuf> {
+    use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+    let dir = match env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(runtime) => PathBuf::from(runtime).join("russh-mux"),
+        None => env::temp_dir().join(format!("russh-mux-{}", current_user())),
+    };
+    if let Err(e) = fs::DirBuilder::new().mode(0o700).create(&dir) {
+        if e.kind() != std::io::ErrorKind::AlreadyExists {
+            return Err(e.into());
+        }
+    }
+    let metadata = fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.permissions().mode() & 0o077 != 0 {
+        return Err(AppError::Generic(format!(
+            "{} is not a private directory",
+            dir.display()
+        )));
+    }
+    Ok(dir)
+}
+
+/// Turns connection reuse on for this run. ssh only multiplexes on Unix;
+/// elsewhere only the native backend reuses its sessions.
+pub fn enable() -> Result<()> {
+    ENABLED.store(true, Ordering::SeqCst);
+    #[cfg(unix)]
+    {
+        let dir = socket_dir()?;
+        let _ = SOCKETS.set(dir);
+    }
+    Ok(())
+}
+
+/// Whether connections are reused.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// The `-o` options sharing connections, understood by ssh and scp alike;
+/// none when multiplexing is off or the client is too old for it.
+pub fn ssh_options() -> Vec<String> {
+    let shared = enabled() && client::client().supports_persist();
+    let Some(dir) = SOCKETS.get().filter(|_| shared) else {
+        return Vec::new();
+    };
+    // %C: a hash of host, port, user and jump host, so sockets stay short.
+    [
+        "ControlMaster=auto".to_string(),
+        format!("ControlPath={}", config_path(&dir.join("%C"))),
+        format!("ControlPersist={}", PERSIST),
+    ]
+    .into_iter()
+    .flat_map(|option| ["-o".to_string(), option])
+    .collect()
+}
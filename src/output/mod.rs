@@ -0,0 +1,129 @@
+//! How results are reported once a run is over: the human-readable summary
+//! or, with `--output json` or `json-hosts`, one JSON document for scripts
+//! and CI.
+
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::exit;
+use crate::ssh::ServerResult;
+use crate::summary::{self, Status};
+use serde::Serialize;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+    /// JSON grouped by host: each host with its commands' results.
+    JsonHosts,
+}
+
+impl FromStr for OutputFormat {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "json-hosts" => Ok(OutputFormat::JsonHosts),
+            _ => Err(ExecutionError::InvalidOption {
+                option: "--output",
+                reason: format!(
+                    "unknown format '{}', expected text, json or json-hosts",
+                    name
+                ),
+            }
+            .into()),
+        }
+    }
+}
+
+/// One host's result together with the command that produced it.
+#[derive(Serialize)]
+pub struct Record<'a> {
+    pub command: &'a str,
+    #[serde(flatten)]
+    pub result: &'a ServerResult,
+}
+
+pub fn to_json(results: &[(String, ServerResult)]) -> Result<String> {
+    let records: Vec<Record> = results
+        .iter()
+        .map(|(command, result)| Record { command, result })
+        .collect();
+    serde_json::to_string_pretty(&records).map_err(|e| AppError::Generic(e.to_string()))
+}
+
+/// One host with the results of its commands, in the order they ran.
+#[derive(Serialize)]
+pub struct HostRecord<'a> {
+    pub server: &'a str,
+    /// The worst status of the host's commands.
+    pub status: Status,
+    pub commands: Vec<Record<'a>>,
+}
+
+pub fn to_json_hosts(results: &[(String, ServerResult)]) -> Result<String> {
+    let hosts: Vec<HostRecord> = summary::by_host(results)
+        .into_iter()
+        .map(|host| HostRecord {
+            server: host.server,
+            status: host.status,
+            commands: host
+                .commands
+                .into_iter()
+                .map(|(command, result)| Record { command, result })
+                .collect(),
+        })
+        .collect();
+    serde_json::to_string_pretty(&hosts).map_err(|e| AppError::Generic(e.to_string()))
+}
+
+pub fn report(results: &[(String, ServerResult)], format: OutputFormat) -> Result<()> {
+    exit::record(results);
+    match format {
+        OutputFormat::Text => summary::print_summary(results),
+        OutputFormat::Json => println!("{}", to_json(results)?),
+        OutputFormat::JsonHosts => println!("{}", to_json_hosts(results)?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_records() {
+        let results = vec![(
+            "uptime".to_string(),
+            ServerResult {
+                server: "web01".to_string(),
+                output: "up 3 days\n".to_string(),
+                error: Some("warning: low disk\n".to_string()),
+                duration: 0.4,
+                success: true,
+                exit_code: Some(0),
+                ..Default::default()
+            },
+        )];
+        let json: serde_json::Value = serde_json::from_str(&to_json(&results).unwrap()).unwrap();
+        assert_eq!(json[0]["command"], "uptime");
+        assert_eq!(json[0]["server"], "web01");
+        assert_eq!(json[0]["output"], "up 3 days\n");
+        assert_eq!(json[0]["exit_code"], 0);
+        assert!("yaml".parse::<OutputFormat>().is_err());
+
+        let mut results = results;
+        let mut failed = results[0].1.clone();
+        failed.success = false;
+        results.push(("df -h".to_string(), failed));
+        let json: serde_json::Value =
+            serde_json::from_str(&to_json_hosts(&results).unwrap()).unwrap();
+        assert_eq!(json.as_array().unwrap().len(), 1);
+        assert_eq!(json[0]["server"], "web01");
+        assert_eq!(json[0]["status"], "failed");
+        assert_eq!(json[0]["commands"][1]["command"], "df -h");
+        assert_eq!(json[0]["commands"][1]["success"], false);
+    }
+}
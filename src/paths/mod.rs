@@ -0,0 +1,331 @@
+//! Where russh keeps things. The config is only ever read; everything russh
+//! writes (run reports, the offline queue, the audit log, caches) goes to
+//! the state directory: `--state-dir`, else `$RUSSH_STATE_DIR`, else
+//! `<XDG data dir>/russh`. Locks and approvals stay in the system temp dir,
+//! where every operator on a shared bastion sees them.
+//!
+//! Scheduled and interactive runs overlap, so state files are written
+//! atomically (a temp file renamed into place, readers never see half a
+//! file) and read-modify-write cycles hold a lock file next to them.
+
+use crate::config::{find_config_in_cwd, find_config_in_user_dir};
+use crate::errors::{AppError, Result};
+use serde::Serialize;
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::OnceLock;
+
+pub const STATE_DIR_ENV: &str = "RUSSH_STATE_DIR";
+
+static STATE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Keeps the temp files of concurrent writers in this process apart.
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Overrides the state directory for this run (`--state-dir`).
+pub fn set_state_dir(dir: PathBuf) {
+    let _ = STATE_DIR.set(dir);
+}
+
+/// Where the state directory came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Flag,
+    Env,
+    Default,
+}
+
+fn resolve(flag: Option<&PathBuf>, env: Option<String>) -> Option<(PathBuf, Source)> {
+    if let Some(dir) = flag {
+        return Some((dir.clone(), Source::Flag));
+    }
+    if let Some(dir) = env.filter(|dir| !dir.is_empty()) {
+        return Some((PathBuf::from(dir), Source::Env));
+    }
+    dirs::data_dir().map(|dir| (dir.join("russh"), Source::Default))
+}
+
+fn state_dir_with_source() -> Option<(PathBuf, Source)> {
+    resolve(STATE_DIR.get(), env::var(STATE_DIR_ENV).ok())
+}
+
+pub fn state_dir() -> Result<PathBuf> {
+    state_dir_with_source().map(|(dir, _)| dir).ok_or_else(|| {
+        AppError::Generic(format!(
+            "no data directory for russh state; set --state-dir or {}",
+            STATE_DIR_ENV
+        ))
+    })
+}
+
+/// A file or directory under the state directory.
+pub fn state_path(name: &str) -> Result<PathBuf> {
+    Ok(state_dir()?.join(name))
+}
+
+/// An exclusive lock on a state file, released when dropped.
+pub struct StateLock(#[allow(dead_code)] File);
+
+fn open_lock(path: &Path) -> Result<File> {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".lock");
+    let lock_path = path.with_file_name(name);
+    if let Some(dir) = lock_path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(lock_path)?)
+}
+
+/// Locks `path` (through `<path>.lock`) against other russh processes,
+/// waiting for the current holder.
+pub fn lock(path: &Path) -> Result<StateLock> {
+    let file = open_lock(path)?;
+    file.lock()?;
+    Ok(StateLock(file))
+}
+
+/// Locks `path` unless another process holds it.
+pub fn try_lock(path: &Path) -> Result<Option<StateLock>> {
+    let file = open_lock(path)?;
+    match file.try_lock() {
+        Ok(()) => Ok(Some(StateLock(file))),
+        Err(TryLockError::WouldBlock) => Ok(None),
+        Err(TryLockError::Error(e)) => Err(e.into()),
+    }
+}
+
+/// Replaces `path` with `bytes` in one step: readers see the old content
+/// or the new one, never a mix.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> Result<()> {
+    replace(path, bytes, None)
+}
+
+/// [`write_atomic`] for secrets: the file is only ever readable by the
+/// current user, from the moment it is created.
+pub fn write_private(path: &Path, bytes: &[u8]) -> Result<()> {
+    replace(path, bytes, Some(0o600))
+}
+
+/// [`write_atomic`] for a file that belongs to the user, like their config:
+/// it keeps its permissions, and when `path` is a symlink (as dotfile
+/// managers leave behind) the file it points at is replaced instead.
+pub fn rewrite(path: &Path, bytes: &[u8]) -> Result<()> {
+    let target = fs::canonicalize(path)?;
+    #[cfg(unix)]
+    let mode = {
+        use std::os::unix::fs::PermissionsExt;
+        Some(fs::metadata(&target)?.permissions().mode() & 0o7777)
+    };
+    #[cfg(not(unix))]
+    let mode = None;
+    replace(&target, bytes, mode)
+}
+
+/// Writes `bytes` to a temp file next to `path` and renames it into place.
+/// With a `mode`, the temp file is created private and given that mode
+/// before anything is written to it.
+fn replace(path: &Path, bytes: &[u8], mode: Option<u32>) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(dir)?;
+    let mut name = std::ffi::OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    let n = TEMP_FILES.fetch_add(1, Ordering::Relaxed);
+    name.push(format!(".{}-{}.tmp", std::process::id(), n));
+    let temp = dir.join(name);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if mode.is_some() {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let written = options
+        .open(&temp)
+        .and_then(|mut file| {
+            #[cfg(unix)]
+            if let Some(mode) = mode {
+                use std::os::unix::fs::PermissionsExt;
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+            }
+            file.write_all(bytes).and_then(|()| file.sync_all())
+        })
+        .and_then(|()| fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    Ok(written?)
+}
+
+/// `name` (a host name, usually) made safe to use as a file name.
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' | '_' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Every location russh reads or writes, as `russh paths` shows them.
+#[derive(Serialize)]
+pub struct Paths {
+    pub config: Option<PathBuf>,
+    pub config_dir: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+    pub state_dir_source: Option<Source>,
+    pub last_run: Option<PathBuf>,
+    pub queue: Option<PathBuf>,
+    pub audit_log: Option<PathBuf>,
+    pub facts: Option<PathBuf>,
+    pub locks: PathBuf,
+    pub approvals: PathBuf,
+}
+
+pub fn gather(config_file: &Option<String>) -> Paths {
+    let state = state_dir_with_source();
+    let under = |name: &str| state.as_ref().map(|(dir, _)| dir.join(name));
+    Paths {
+        config: config_file
+            .as_ref()
+            .map(PathBuf::from)
+            .or_else(find_config_in_cwd)
+            .or_else(find_config_in_user_dir),
+        config_dir: dirs::config_dir().map(|dir| dir.join("russh")),
+        last_run: under(crate::report::LAST_RUN),
+        queue: under(crate::queue::QUEUE),
+        audit_log: under(crate::audit::AUDIT_LOG),
+        facts: under(crate::facts::CACHE_DIR),
+        state_dir_source: state.as_ref().map(|(_, source)| *source),
+        state_dir: state.map(|(dir, _)| dir),
+        locks: crate::lock::lock_dir(),
+        approvals: env::temp_dir().join(crate::approval::STORE),
+    }
+}
+
+fn display_path(path: &Option<PathBuf>) -> String {
+    path.as_ref()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|| "(none)".to_string())
+}
+
+pub fn print_paths(paths: &Paths, json: bool) -> Result<()> {
+    if json {
+        let json =
+            serde_json::to_string_pretty(paths).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let source = match paths.state_dir_source {
+        Some(Source::Flag) => " (--state-dir)",
+        Some(Source::Env) => " ($RUSSH_STATE_DIR)",
+        _ => "",
+    };
+    println!("config:       {}", display_path(&paths.config));
+    println!("config dir:   {}", display_path(&paths.config_dir));
+    println!("state dir:    {}{}", display_path(&paths.state_dir), source);
+    println!("  last run:   {}", display_path(&paths.last_run));
+    println!("  queue:      {}", display_path(&paths.queue));
+    println!("  audit log:  {}", display_path(&paths.audit_log));
+    println!("  facts:      {}", display_path(&paths.facts));
+    println!("locks:        {}", paths.locks.display());
+    println!("approvals:    {}", paths.approvals.display());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_locked_updates() {
+        let dir = env::temp_dir().join(format!("russh-state-test-{}", std::process::id()));
+        let counter = dir.join("counter");
+        write_atomic(&counter, b"0").unwrap();
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let counter = counter.clone();
+                thread::spawn(move || {
+                    for _ in 0..25 {
+                        let _lock = lock(&counter).unwrap();
+                        let n: u32 = fs::read_to_string(&counter).unwrap().parse().unwrap();
+                        write_atomic(&counter, (n + 1).to_string().as_bytes()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert_eq!(fs::read_to_string(&counter).unwrap(), "100");
+
+        let held = try_lock(&counter).unwrap();
+        assert!(held.is_some());
+        assert!(try_lock(&counter).unwrap().is_none());
+        drop(held);
+        assert!(try_lock(&counter).unwrap().is_some());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_resolve() {
+        let flag = PathBuf::from("/srv/russh");
+        assert_eq!(
+            resolve(Some(&flag), Some("/var/lib/russh".to_string())),
+            Some((flag, Source::Flag))
+        );
+        assert_eq!(
+            resolve(None, Some("/var/lib/russh".to_string())),
+            Some((PathBuf::from("/var/lib/russh"), Source::Env))
+        );
+        // An empty variable counts as unset.
+        assert_ne!(
+            resolve(None, Some(String::new())).map(|(_, source)| source),
+            Some(Source::Env)
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = env::temp_dir().join(format!("russh-private-test-{}", std::process::id()));
+        let secret = dir.join("secret");
+        write_private(&secret, b"s3cret").unwrap();
+        let mode = fs::metadata(&secret).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+        assert_eq!(fs::read(&secret).unwrap(), b"s3cret");
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_rewrite_keeps_mode_and_symlink() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = env::temp_dir().join(format!("russh-rewrite-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("russh.toml");
+        let link = dir.join("link.toml");
+        fs::write(&file, "old").unwrap();
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+
+        rewrite(&link, b"new").unwrap();
+        assert!(fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&file).unwrap(), b"new");
+        let mode = fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
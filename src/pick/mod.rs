@@ -0,0 +1,111 @@
+//! `--pick`: a checklist of the selected hosts to tick the ones to run on
+//! before anything runs, for runs where a filter expression alone is too
+//! much trust. Typing narrows the list with fuzzy matching on the host's
+//! name, user, port and tags.
+
+use crate::config::Host;
+use crate::errors::{ExecutionError, InventoryError, Result};
+use inquire::{InquireError, MultiSelect};
+use std::fmt;
+use std::io::{self, IsTerminal};
+
+/// Hosts shown at once; the list scrolls.
+const PAGE_SIZE: usize = 15;
+
+/// A host as the checklist shows it.
+struct Choice {
+    index: usize,
+    label: String,
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.label)
+    }
+}
+
+/// One line per host, the columns lined up: name, user, port and tags.
+fn labels(hosts: &[Host]) -> Vec<String> {
+    let width = hosts.iter().map(|h| h.host.len()).max().unwrap_or(0);
+    hosts
+        .iter()
+        .map(|host| {
+            let label = format!(
+                "{:<width$}  {}:{}  {}",
+                host.host,
+                host.user
+                    .as_deref()
+                    .filter(|u| !u.is_empty())
+                    .unwrap_or("-"),
+                host.port.unwrap_or(22),
+                host.tags.join(","),
+            );
+            label.trim_end().to_string()
+        })
+        .collect()
+}
+
+/// Asks which of `hosts` to run on; none are ticked to begin with.
+pub fn pick(hosts: Vec<Host>) -> Result<Vec<Host>> {
+    if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+        return Err(ExecutionError::InvalidOption {
+            option: "--pick",
+            reason: "needs a terminal to show the checklist".to_string(),
+        }
+        .into());
+    }
+    let choices: Vec<Choice> = labels(&hosts)
+        .into_iter()
+        .enumerate()
+        .map(|(index, label)| Choice { index, label })
+        .collect();
+    let picked = MultiSelect::new(
+        &format!("Hosts to run on ({} selected by the flags):", hosts.len()),
+        choices,
+    )
+    .with_page_size(PAGE_SIZE)
+    .with_help_message(
+        "type to filter, space to tick, → all shown, ← none, enter to run, esc to cancel",
+    )
+    .prompt();
+    let picked = match picked {
+        Ok(picked) => picked,
+        Err(InquireError::OperationCanceled | InquireError::OperationInterrupted) => {
+            return Err(ExecutionError::Refused("no hosts picked, nothing run".to_string()).into())
+        }
+        Err(e) => {
+            return Err(ExecutionError::InvalidOption {
+                option: "--pick",
+                reason: e.to_string(),
+            }
+            .into())
+        }
+    };
+    if picked.is_empty() {
+        return Err(InventoryError::NoneSelected("--pick".to_string()).into());
+    }
+    let mut hosts: Vec<Option<Host>> = hosts.into_iter().map(Some).collect();
+    Ok(picked
+        .into_iter()
+        .filter_map(|choice| hosts[choice.index].take())
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels() {
+        let hosts = [
+            "web01 user=deploy port=2222 tag=prod tag=canary"
+                .parse::<Host>()
+                .unwrap(),
+            Host::new("db-primary"),
+        ];
+        assert_eq!(
+            labels(&hosts),
+            ["web01       deploy:2222  prod,canary", "db-primary  -:22",]
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! `russh ping`: connects to every selected host at once and runs `exit`,
+//! to see which hosts are reachable, and how fast, before a real run. The
+//! connections skip shared ssh connections and never prompt, so a host that
+//! wants a password shows up as failed instead of stalling the check.
+
+use crate::cli::{inventory, require_client, Cli, PingArgs};
+use crate::config::Host;
+use crate::errors::{AppError, Result};
+use crate::exit;
+use crate::output::OutputFormat;
+use crate::runtime;
+use crate::ssh::{run_ssh_command, ExecOptions, ServerResult};
+use serde::Serialize;
+use std::sync::mpsc;
+
+/// Seconds a host gets to answer.
+pub const DEFAULT_TIMEOUT: u64 = 10;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reach {
+    Ok,
+    /// ssh could not connect or log in.
+    Unreachable,
+    /// It did not answer within the timeout.
+    Timeout,
+}
+
+/// How one host answered.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Probe {
+    pub host: String,
+    pub status: Reach,
+    /// Seconds from starting ssh to the session closing.
+    pub latency: f64,
+    /// The first line ssh complained with, for hosts that failed.
+    pub error: Option<String>,
+}
+
+impl Probe {
+    fn of(result: &ServerResult, timeout: u64) -> Probe {
+        let status = match result.success {
+            true => Reach::Ok,
+            false if result.duration >= timeout as f64 => Reach::Timeout,
+            false => Reach::Unreachable,
+        };
+        let error = result
+            .error
+            .as_deref()
+            .and_then(|error| error.lines().map(str::trim).find(|l| !l.is_empty()))
+            .filter(|_| status != Reach::Ok)
+            .map(str::to_string);
+        Probe {
+            host: result.server.clone(),
+            status,
+            latency: result.duration,
+            error,
+        }
+    }
+}
+
+/// Connects to every host in parallel, `timeout` seconds at most each, and
+/// records the outcome for the exit code.
+pub fn probe(hosts: &[Host], options: &ExecOptions, timeout: u64) -> Vec<Probe> {
+    let options = ExecOptions {
+        backend: options.backend,
+        timeout: Some(timeout),
+        ..Default::default()
+    };
+    let results = runtime::block_on(async {
+        let probes: Vec<_> = hosts
+            .iter()
+            .cloned()
+            .map(|mut host| {
+                // Before the config's options, so these win.
+                host.extra_options.splice(
+                    0..0,
+                    [
+                        "BatchMode=yes".to_string(),
+                        format!("ConnectTimeout={}", timeout),
+                        "ControlPath=none".to_string(),
+                    ]
+                    .into_iter()
+                    .flat_map(|option| ["-o".to_string(), option]),
+                );
+                let options = options.clone();
+                tokio::spawn(async move {
+                    let (tx, _rx) = mpsc::channel();
+                    run_ssh_command(&host, "exit", &options, tx).await
+                })
+            })
+            .collect();
+        let mut done = Vec::with_capacity(probes.len());
+        for probe in probes {
+            done.push(probe.await);
+        }
+        done
+    });
+    let results: Vec<(String, ServerResult)> = results
+        .into_iter()
+        .filter_map(|result| result.ok())
+        .map(|result| ("exit".to_string(), result))
+        .collect();
+    exit::record(&results);
+    results
+        .iter()
+        .map(|(_, result)| Probe::of(result, timeout))
+        .collect()
+}
+
+pub fn print(probes: &[Probe], json: bool) -> Result<()> {
+    if json {
+        let json =
+            serde_json::to_string_pretty(probes).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    let width = probes
+        .iter()
+        .map(|p| p.host.len())
+        .max()
+        .unwrap_or(0)
+        .max(4);
+    println!("{:<width$}  {:<11}  {:>7}", "HOST", "STATUS", "LATENCY");
+    for probe in probes {
+        let status = format!("{:?}", probe.status).to_lowercase();
+        let line = format!(
+            "{:<width$}  {:<11}  {:>6.0}ms  {}",
+            probe.host,
+            status,
+            probe.latency * 1000.0,
+            probe.error.as_deref().unwrap_or_default(),
+        );
+        println!("{}", line.trim_end());
+    }
+    let reachable = probes.iter().filter(|p| p.status == Reach::Ok).count();
+    println!("{}/{} host(s) reachable", reachable, probes.len());
+    Ok(())
+}
+
+/// `russh ping`: probes every selected host and prints how each answered.
+pub fn command(cli: &Cli, args: &PingArgs) -> Result<()> {
+    let (_, hosts) = inventory(cli)?;
+    let options = ExecOptions {
+        backend: cli.backend,
+        ..Default::default()
+    };
+    require_client(cli.backend)?;
+    let timeout = args.timeout.unwrap_or(DEFAULT_TIMEOUT);
+    print(
+        &probe(&hosts, &options, timeout),
+        cli.output != OutputFormat::Text,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_of() {
+        let mut result = ServerResult {
+            server: "web01".to_string(),
+            error: Some("Warning: Permanently added 'web01'\n".to_string()),
+            duration: 0.12,
+            success: true,
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        assert_eq!(
+            Probe::of(&result, 10),
+            Probe {
+                host: "web01".to_string(),
+                status: Reach::Ok,
+                latency: 0.12,
+                error: None,
+            }
+        );
+
+        result.success = false;
+        result.error =
+            Some("\nssh: connect to host web01 port 22: Connection refused\n".to_string());
+        let probe = Probe::of(&result, 10);
+        assert_eq!(probe.status, Reach::Unreachable);
+        assert_eq!(
+            probe.error.as_deref(),
+            Some("ssh: connect to host web01 port 22: Connection refused")
+        );
+        result.duration = 10.0;
+        assert_eq!(Probe::of(&result, 10).status, Reach::Timeout);
+    }
+}
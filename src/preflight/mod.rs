@@ -0,0 +1,335 @@
+//! `--require` preflight assertions, checked on every host before the main
+//! command runs so that e.g. an upgrade never starts on a host with a full
+//! disk. Expressions look like `disk_free("/var") > 2GB`, `mem_free() >= 512MB`,
+//! `load() < 4` or `has_command("docker")`.
+
+use crate::config::Host;
+use crate::errors::{AppError, Result};
+use crate::prereq;
+use crate::runtime;
+use crate::ssh::{run_ssh_command, shell_quote, Event, ExecOptions};
+use crate::warnings::{warn, WarningKind};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::mpsc;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Check {
+    DiskFree(String),
+    MemFree,
+    Load,
+    HasCommand(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Op {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+}
+
+impl Op {
+    fn holds(self, left: f64, right: f64) -> bool {
+        match self {
+            Op::Gt => left > right,
+            Op::Ge => left >= right,
+            Op::Lt => left < right,
+            Op::Le => left <= right,
+            Op::Eq => left == right,
+        }
+    }
+}
+
+/// One parsed `--require` expression.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Requirement {
+    check: Check,
+    comparison: Option<(Op, f64)>,
+    source: String,
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Parses `2GB`, `512M`, `100` etc. into a plain number (binary units).
+fn parse_quantity(text: &str) -> Option<f64> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let factor = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1.0,
+        "K" | "KB" => 1024.0,
+        "M" | "MB" => 1024.0 * 1024.0,
+        "G" | "GB" => 1024.0 * 1024.0 * 1024.0,
+        "T" | "TB" => 1024.0 * 1024.0 * 1024.0 * 1024.0,
+        _ => return None,
+    };
+    Some(number * factor)
+}
+
+pub(crate) fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1}{}", value, UNITS[unit])
+}
+
+impl FromStr for Requirement {
+    type Err = AppError;
+
+    fn from_str(source: &str) -> Result<Self> {
+        let invalid = |reason: &str| {
+            AppError::Generic(format!("invalid requirement '{}': {}", source, reason))
+        };
+        let open = source.find('(').ok_or_else(|| invalid("expected a call"))?;
+        let close = source.rfind(')').ok_or_else(|| invalid("missing ')'"))?;
+        if close < open {
+            return Err(invalid("missing ')'"));
+        }
+        let name = source[..open].trim();
+        let arg = source[open + 1..close].trim().trim_matches(['"', '\'']);
+        let rest = source[close + 1..].trim();
+
+        let check = match name {
+            "disk_free" if !arg.is_empty() => Check::DiskFree(arg.to_string()),
+            "mem_free" => Check::MemFree,
+            "load" => Check::Load,
+            "has_command" if !arg.is_empty() => Check::HasCommand(arg.to_string()),
+            "disk_free" | "has_command" => return Err(invalid("missing argument")),
+            _ => return Err(invalid(&format!("unknown check '{}'", name))),
+        };
+
+        let comparison = if rest.is_empty() {
+            None
+        } else {
+            let (op, value) = [
+                (">=", Op::Ge),
+                ("<=", Op::Le),
+                ("==", Op::Eq),
+                (">", Op::Gt),
+                ("<", Op::Lt),
+            ]
+            .iter()
+            .find_map(|(token, op)| rest.strip_prefix(token).map(|value| (*op, value)))
+            .ok_or_else(|| invalid("expected a comparison"))?;
+            let value = parse_quantity(value).ok_or_else(|| invalid("bad value"))?;
+            Some((op, value))
+        };
+
+        match (&check, comparison) {
+            (Check::HasCommand(_), Some(_)) => Err(invalid("has_command takes no comparison")),
+            (Check::HasCommand(_), None) => Ok(()),
+            (_, None) => Err(invalid("missing comparison")),
+            _ => Ok(()),
+        }?;
+
+        Ok(Requirement {
+            check,
+            comparison,
+            source: source.trim().to_string(),
+        })
+    }
+}
+
+impl Requirement {
+    /// Shell snippet printing the measured value, in KiB for sizes. Only
+    /// the shell's own `read` picks the fields apart, so hosts without awk
+    /// or cut can be checked too.
+    fn probe(&self) -> String {
+        match &self.check {
+            Check::DiskFree(path) => format!(
+                "df -Pk {} 2>/dev/null | {{ read -r _; read -r _ _ _ free _; echo \"$free\"; }}",
+                shell_quote(path)
+            ),
+            Check::MemFree => prereq::meminfo_kb("MemAvailable"),
+            Check::Load => "read -r load _ </proc/loadavg; echo \"$load\"".to_string(),
+            Check::HasCommand(name) => format!(
+                "command -v {} >/dev/null 2>&1 && echo 1 || echo 0",
+                shell_quote(name)
+            ),
+        }
+    }
+
+    fn evaluate(&self, raw: &str) -> std::result::Result<(), String> {
+        let measured: f64 = raw
+            .trim()
+            .parse()
+            .map_err(|_| format!("{}: could not be measured", self.source))?;
+        match (&self.check, self.comparison) {
+            (Check::HasCommand(name), _) if measured == 0.0 => {
+                Err(format!("{} is not installed", name))
+            }
+            (Check::HasCommand(_), _) => Ok(()),
+            (check, Some((op, expected))) => {
+                let measured = match check {
+                    Check::DiskFree(_) | Check::MemFree => measured * 1024.0,
+                    _ => measured,
+                };
+                if op.holds(measured, expected) {
+                    Ok(())
+                } else {
+                    let shown = match check {
+                        Check::Load => format!("{}", measured),
+                        _ => format_bytes(measured),
+                    };
+                    Err(format!("{} failed (measured {})", self.source, shown))
+                }
+            }
+            (_, None) => Ok(()),
+        }
+    }
+}
+
+/// One remote script for all requirements; each value is tagged with its
+/// index so missing lines can't shift the others.
+fn script(requirements: &[Requirement]) -> String {
+    requirements
+        .iter()
+        .enumerate()
+        .map(|(i, requirement)| format!("echo \"req{}=$({})\"", i, requirement.probe()))
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+fn evaluate(requirements: &[Requirement], lines: &[String]) -> Vec<String> {
+    requirements
+        .iter()
+        .enumerate()
+        .filter_map(|(i, requirement)| {
+            let prefix = format!("req{}=", i);
+            let raw = lines
+                .iter()
+                .find_map(|line| line.strip_prefix(&prefix))
+                .unwrap_or("");
+            requirement.evaluate(raw).err()
+        })
+        .collect()
+}
+
+async fn check_host(
+    host: &Host,
+    requirements: &[Requirement],
+    options: &ExecOptions,
+) -> Vec<String> {
+    let (tx, rx) = mpsc::channel();
+    let result = run_ssh_command(host, &script(requirements), options, tx).await;
+    if !result.success {
+        return vec![prereq::unsupported(&result).unwrap_or_else(|| {
+            format!(
+                "preflight could not run (exit code {})",
+                result.exit_code.map_or("?".to_string(), |c| c.to_string())
+            )
+        })];
+    }
+    let lines: Vec<String> = rx.into_iter().filter_map(Event::into_stdout).collect();
+    evaluate(requirements, &lines)
+}
+
+/// Checks `requirements` on every host in parallel and returns the hosts that
+/// passed. Hosts that fail are reported as warnings and left out of the run.
+pub fn filter_hosts(
+    hosts: Vec<Host>,
+    requirements: &[Requirement],
+    options: &ExecOptions,
+) -> Vec<Host> {
+    if requirements.is_empty() {
+        return hosts;
+    }
+    // Only the backend matters here; the probes must not run under nice/ionice.
+    let options = ExecOptions {
+        backend: options.backend,
+        ..Default::default()
+    };
+    let checks = runtime::block_on(async {
+        let checks: Vec<_> = hosts
+            .into_iter()
+            .map(|host| {
+                let requirements = requirements.to_vec();
+                let options = options.clone();
+                tokio::spawn(async move {
+                    let failures = check_host(&host, &requirements, &options).await;
+                    (host, failures)
+                })
+            })
+            .collect();
+        let mut done = Vec::with_capacity(checks.len());
+        for check in checks {
+            done.push(check.await);
+        }
+        done
+    });
+
+    checks
+        .into_iter()
+        .filter_map(|check| match check {
+            Ok((host, failures)) if failures.is_empty() => Some(host),
+            Ok((host, failures)) => {
+                warn(
+                    WarningKind::HostSkipped,
+                    format!("{} skipped: {}", host.host, failures.join("; ")),
+                );
+                None
+            }
+            Err(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requirements() {
+        let disk: Requirement = r#"disk_free("/var") > 2GB"#.parse().unwrap();
+        assert_eq!(disk.check, Check::DiskFree("/var".to_string()));
+        assert_eq!(
+            disk.comparison,
+            Some((Op::Gt, 2.0 * 1024.0 * 1024.0 * 1024.0))
+        );
+
+        let load: Requirement = "load() <= 4".parse().unwrap();
+        assert_eq!(load.comparison, Some((Op::Le, 4.0)));
+
+        assert!("has_command('docker')".parse::<Requirement>().is_ok());
+        assert!("disk_free(\"/\")".parse::<Requirement>().is_err());
+        assert!("uptime() > 1".parse::<Requirement>().is_err());
+        assert!("mem_free() > lots".parse::<Requirement>().is_err());
+    }
+
+    #[test]
+    fn test_evaluate_probe_output() {
+        let requirements: Vec<Requirement> = [
+            r#"disk_free("/var") > 2GB"#,
+            "mem_free() > 512MB",
+            "has_command(\"docker\")",
+        ]
+        .iter()
+        .map(|r| r.parse().unwrap())
+        .collect();
+        // 1GB free on /var, 1GB memory, docker present.
+        let lines = vec![
+            "req0=1048576".to_string(),
+            "req1=1048576".to_string(),
+            "req2=1".to_string(),
+        ];
+        let failures = evaluate(&requirements, &lines);
+        assert_eq!(failures.len(), 1);
+        assert!(failures[0].contains("disk_free"));
+
+        let failures = evaluate(&requirements, &["req0=".to_string()]);
+        assert_eq!(failures.len(), 3);
+    }
+}
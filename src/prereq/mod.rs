@@ -0,0 +1,115 @@
+//! What russh's own remote helpers need on a host. Facts and `--require`
+//! probes stick to what any POSIX `sh` has, so minimal hosts (alpine,
+//! busybox, distroless images with a shell) answer them without awk, bash
+//! or python. Where a host really lacks something, e.g. the interpreter of
+//! a `russh script`, the helper fails with exit code 127 and is reported as
+//! an unsupported host instead of with the shell's own complaint.
+
+use crate::ssh::{shell_quote, ServerResult};
+
+/// What a guard prints on stderr before giving up.
+const MARKER: &str = "russh: unsupported host: ";
+
+/// The exit code of a command that could not be found.
+const NOT_FOUND: i32 = 127;
+
+/// A shell snippet failing with exit code 127 when `program` is not on the
+/// host, to run before whatever needs it.
+pub fn guard(program: &str) -> String {
+    format!(
+        "command -v {} >/dev/null 2>&1 || {{ echo {} >&2; exit {}; }}",
+        shell_quote(program),
+        shell_quote(&format!("{}{} is not installed", MARKER, program)),
+        NOT_FOUND
+    )
+}
+
+/// A shell snippet printing `field` of `/proc/meminfo` in KiB, read with
+/// the shell's own `read` rather than awk.
+pub fn meminfo_kb(field: &str) -> String {
+    format!(
+        "while read -r key value _; do [ \"$key\" = {}: ] && echo \"$value\"; done 2>/dev/null </proc/meminfo",
+        field
+    )
+}
+
+/// The name of the command a shell says it could not find, from lines like
+/// `sh: 1: awk: not found`, `bash: line 1: awk: command not found` or
+/// `zsh:1: command not found: awk`.
+fn missing_command(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if let Some((_, name)) = line.split_once("command not found: ") {
+        return Some(name.trim());
+    }
+    let rest = line
+        .strip_suffix(": command not found")
+        .or_else(|| line.strip_suffix(": not found"))?;
+    rest.rsplit(": ").next().map(str::trim)
+}
+
+/// Why a helper could not run on a host, when its `result` says something
+/// it needs is missing there.
+pub fn unsupported(result: &ServerResult) -> Option<String> {
+    if result.exit_code != Some(NOT_FOUND) {
+        return None;
+    }
+    let stderr = result.error.as_deref().unwrap_or_default();
+    let reason = stderr
+        .lines()
+        .find_map(|line| {
+            let reason = line.split_once(MARKER).map(|(_, reason)| reason.trim());
+            reason.map(str::to_string)
+        })
+        .or_else(|| {
+            let name = stderr.lines().find_map(missing_command)?;
+            Some(format!("{} is not installed", name))
+        })
+        .unwrap_or_else(|| "a command russh needs is missing (exit code 127)".to_string());
+    Some(format!("unsupported host: {}", reason))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unsupported() {
+        let result = |exit_code: i32, stderr: &str| ServerResult {
+            server: "box01".to_string(),
+            error: Some(stderr.to_string()),
+            duration: 0.1,
+            exit_code: Some(exit_code),
+            ..Default::default()
+        };
+        let reason = |stderr: &str| unsupported(&result(127, stderr));
+
+        assert_eq!(
+            reason("russh: unsupported host: python3 is not installed\n").as_deref(),
+            Some("unsupported host: python3 is not installed")
+        );
+        for stderr in [
+            "sh: 1: awk: not found",
+            "sh: awk: not found",
+            "bash: line 1: awk: command not found",
+            "zsh:1: command not found: awk",
+        ] {
+            assert_eq!(
+                reason(stderr).as_deref(),
+                Some("unsupported host: awk is not installed"),
+                "{}",
+                stderr
+            );
+        }
+        assert_eq!(
+            reason("").as_deref(),
+            Some("unsupported host: a command russh needs is missing (exit code 127)")
+        );
+        assert_eq!(unsupported(&result(1, "sh: awk: not found")), None);
+
+        assert_eq!(
+            guard("python3"),
+            "command -v python3 >/dev/null 2>&1 || \
+             { echo 'russh: unsupported host: python3 is not installed' >&2; exit 127; }"
+        );
+    }
+}
@@ -0,0 +1,24 @@
+//! Yes/no questions for the operator. Without a terminal on stdin, as under
+//! cron or in CI, nothing is asked: an answer could never come, or would be
+//! read from whatever was piped in. Each caller picks the safe outcome
+//! instead, usually refusing with a hint at the flag that confirms up front.
+
+use crate::errors::Result;
+use crate::i18n::is_yes;
+use std::io::{self, IsTerminal};
+
+/// Whether there is someone to ask.
+pub fn interactive() -> bool {
+    io::stdin().is_terminal()
+}
+
+/// Prints `question` and reads the answer; `None` when nobody can answer.
+pub fn confirm(question: &str) -> Result<Option<bool>> {
+    if !interactive() {
+        return Ok(None);
+    }
+    println!("{}", question);
+    let mut response = String::new();
+    io::stdin().read_line(&mut response)?;
+    Ok(Some(is_yes(&response)))
+}
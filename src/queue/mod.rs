@@ -0,0 +1,506 @@
+use crate::audit::{current_user, now};
+use crate::cli::{admit, load_config, Cli, FlushQueueArgs};
+use crate::config::Host;
+use crate::errors::{AppError, Result};
+use crate::escalation::{self, Password};
+use crate::executor::{execute_jobs, parallelism, Display, Schedule};
+use crate::i18n::{tr, trf, Msg};
+use crate::paths;
+use crate::ssh::{run_ssh_command, EnvVar, ExecOptions, IoPriority};
+use crate::warnings::{warn, WarningKind};
+use crate::{approval, auth, prompt, summary};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+/// Programs that are too dangerous to replay without the operator
+/// confirming them again, whatever their arguments.
+const DESTRUCTIVE_PROGRAMS: &[&str] = &[
+    "reboot", "shutdown", "poweroff", "halt", "mkfs", "dd", "wipefs", "userdel", "truncate",
+];
+
+/// `systemctl` verbs that take the machine down.
+const SYSTEMCTL_VERBS: &[&str] = &["reboot", "poweroff", "halt", "kexec"];
+
+/// Programs that run the rest of their arguments as a command.
+const WRAPPERS: &[&str] = &[
+    "sudo", "doas", "env", "nohup", "nice", "ionice", "timeout", "exec", "command", "time", "xargs",
+];
+
+/// Wrapper options whose value is the next argument rather than the command.
+const WRAPPER_OPTIONS: &[&str] = &[
+    "-u", "-g", "-p", "-C", "-D", "-h", "-r", "-t", "-U", "-n", "-c", "-s", "-k", "-I", "-L", "-P",
+    "-d",
+];
+
+/// Shell keywords that can come before a command.
+const KEYWORDS: &[&str] = &[
+    "!", "{", "if", "then", "else", "elif", "do", "while", "until",
+];
+
+/// Shells whose `-c` script is a command line of its own.
+const SHELLS: &[&str] = &["sh", "bash", "dash", "zsh", "ksh", "ash"];
+
+/// Splits a command line into its simple commands (split at `;`, `&`, `|`,
+/// parentheses, backticks and newlines) and those into words, honouring
+/// quotes and backslashes.
+fn simple_commands(command: &str) -> Vec<Vec<String>> {
+    let mut commands = vec![Vec::new()];
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') => {
+                if let Some(next) = chars.next() {
+                    word.get_or_insert_with(String::new).push(next);
+                }
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, '\\') => {
+                if let Some(next) = chars.next() {
+                    word.get_or_insert_with(String::new).push(next);
+                }
+            }
+            (None, ';' | '&' | '|' | '(' | ')' | '`' | '\n') => {
+                commands.last_mut().unwrap().extend(word.take());
+                commands.push(Vec::new());
+            }
+            (None, c) if c.is_whitespace() => commands.last_mut().unwrap().extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    commands.last_mut().unwrap().extend(word);
+    commands.retain(|words| !words.is_empty());
+    commands
+}
+
+/// The program a simple command runs and its arguments, looking through
+/// `sudo`, `env`, variable assignments and the like.
+fn program(words: &[String]) -> Option<(String, &[String])> {
+    let mut i = 0;
+    while let Some(word) = words.get(i) {
+        let name = word.rsplit('/').next().unwrap_or(word).to_lowercase();
+        if KEYWORDS.contains(&name.as_str()) || is_assignment(word) {
+            i += 1;
+        } else if WRAPPERS.contains(&name.as_str()) {
+            i = skip_options(words, i + 1);
+            // `timeout` takes its duration before the command.
+            if name == "timeout" {
+                i += 1;
+            }
+        } else {
+            return Some((name, &words[i + 1..]));
+        }
+    }
+    None
+}
+
+/// The index of the first word from `i` on that isn't a wrapper's option.
+fn skip_options(words: &[String], mut i: usize) -> usize {
+    while let Some(word) = words.get(i).filter(|word| word.starts_with('-')) {
+        i += match WRAPPER_OPTIONS.contains(&word.as_str()) {
+            true => 2,
+            false => 1,
+        };
+    }
+    i
+}
+
+/// A `NAME=value` variable assignment before the command.
+fn is_assignment(word: &str) -> bool {
+    word.split_once('=').is_some_and(|(name, _)| {
+        !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+    })
+}
+
+/// Whether `rm`'s arguments ask for both recursion and force.
+fn recursive_force(args: &[String]) -> bool {
+    let (mut recursive, mut force) = (false, false);
+    for arg in args {
+        match arg.as_str() {
+            "--" => break,
+            "--recursive" => recursive = true,
+            "--force" => force = true,
+            arg if arg.starts_with('-') && !arg.starts_with("--") => {
+                recursive |= arg.contains(['r', 'R']);
+                force |= arg.contains('f');
+            }
+            _ => {}
+        }
+    }
+    recursive && force
+}
+
+/// Work recorded for a host that was unreachable during a run.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QueueEntry {
+    pub host: Host,
+    pub command: String,
+    pub queued_at: u64,
+    pub queued_by: String,
+    /// Entries queued before replays kept their settings have none.
+    #[serde(default)]
+    pub replay: Replay,
+}
+
+/// The run settings a queued command is replayed with, so it runs as it
+/// would have: escalated, with its environment, time limit and stdin (a
+/// script's text, say). A sudo password is never stored; `flush-queue`
+/// asks for it again.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Replay {
+    pub escalate: bool,
+    pub become_password: bool,
+    pub env: Vec<(String, String)>,
+    pub timeout: Option<u64>,
+    pub nice: Option<i32>,
+    pub ionice: Option<(u8, Option<u8>)>,
+    pub stdin: Option<String>,
+    pub requires: Option<String>,
+}
+
+impl Replay {
+    pub fn of(options: &ExecOptions) -> Replay {
+        Replay {
+            escalate: options.escalate,
+            become_password: options.become_password.is_some(),
+            env: options
+                .env
+                .iter()
+                .map(|var| (var.name.clone(), var.value.clone()))
+                .collect(),
+            timeout: options.timeout,
+            nice: options.nice,
+            ionice: options.ionice.map(|ionice| (ionice.class, ionice.level)),
+            stdin: options.stdin.as_deref().map(str::to_string),
+            requires: options.requires.clone(),
+        }
+    }
+
+    /// The options to replay with, given the sudo password asked for again.
+    pub fn options(&self, become_password: Option<Password>) -> Result<ExecOptions> {
+        Ok(ExecOptions {
+            escalate: self.escalate,
+            become_password: become_password.filter(|_| self.become_password),
+            env: self
+                .env
+                .iter()
+                .map(|(name, value)| EnvVar::new(name, value))
+                .collect::<Result<_>>()?,
+            timeout: self.timeout,
+            nice: self.nice,
+            ionice: self
+                .ionice
+                .map(|(class, level)| IoPriority { class, level }),
+            stdin: self.stdin.as_deref().map(Arc::from),
+            requires: self.requires.clone(),
+            ..Default::default()
+        })
+    }
+}
+
+impl QueueEntry {
+    pub fn new(host: &Host, command: &str, options: &ExecOptions) -> QueueEntry {
+        QueueEntry {
+            host: host.clone(),
+            command: command.to_string(),
+            queued_at: now(),
+            queued_by: current_user(),
+            replay: Replay::of(options),
+        }
+    }
+
+    pub fn age_secs(&self) -> u64 {
+        now().saturating_sub(self.queued_at)
+    }
+
+    pub fn is_destructive(&self) -> bool {
+        is_destructive(&self.command)
+    }
+}
+
+/// Whether replaying `command` could lose data or take a host down. Each
+/// simple command in it is judged by the program it runs and that
+/// program's flags, so `asphalt` isn't `halt` but `rm -r -f` is `rm -rf`.
+pub fn is_destructive(command: &str) -> bool {
+    static SQL: OnceLock<Regex> = OnceLock::new();
+    let sql = SQL
+        .get_or_init(|| Regex::new(r"(?i)\b(drop\s+(database|table)|truncate\s+table)\b").unwrap());
+    if sql.is_match(command) {
+        return true;
+    }
+    simple_commands(command).iter().any(|words| {
+        let Some((program, args)) = program(words) else {
+            return false;
+        };
+        match program.as_str() {
+            "rm" => recursive_force(args),
+            "systemctl" => args
+                .iter()
+                .any(|arg| SYSTEMCTL_VERBS.contains(&arg.to_lowercase().as_str())),
+            shell if SHELLS.contains(&shell) => args
+                .iter()
+                .position(|arg| arg == "-c")
+                .and_then(|i| args.get(i + 1))
+                .is_some_and(|script| is_destructive(script)),
+            program => DESTRUCTIVE_PROGRAMS.contains(&program) || program.starts_with("mkfs."),
+        }
+    })
+}
+
+/// The queue's file name in the state directory.
+pub const QUEUE: &str = "queue.json";
+
+pub fn queue_path() -> Result<PathBuf> {
+    paths::state_path(QUEUE)
+}
+
+pub fn load() -> Result<Vec<QueueEntry>> {
+    let path = queue_path()?;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| AppError::Generic(format!("corrupt queue file {}: {}", path.display(), e)))
+}
+
+fn save(entries: &[QueueEntry]) -> Result<()> {
+    let json = serde_json::to_vec_pretty(entries).map_err(|e| AppError::Generic(e.to_string()))?;
+    paths::write_atomic(&queue_path()?, &json)
+}
+
+/// Applies `change` to the queue with it locked, so concurrent runs
+/// queueing or flushing entries don't undo each other's changes.
+fn update(change: impl FnOnce(&mut Vec<QueueEntry>)) -> Result<()> {
+    let _lock = paths::lock(&queue_path()?)?;
+    let mut entries = load()?;
+    change(&mut entries);
+    save(&entries)
+}
+
+/// Removes entries that have been handled, leaving any queued since they
+/// were loaded.
+pub fn remove(handled: &[QueueEntry]) -> Result<()> {
+    if handled.is_empty() {
+        return Ok(());
+    }
+    update(|entries| entries.retain(|entry| !handled.contains(entry)))
+}
+
+/// Locks out other `russh flush-queue` runs for as long as the returned
+/// lock lives; `None` when one is already running.
+pub fn lock_flush() -> Result<Option<paths::StateLock>> {
+    let mut path = queue_path()?;
+    path.set_extension("flush");
+    paths::try_lock(&path)
+}
+
+/// Adds entries to the queue, replacing an older entry for the same host and command.
+pub fn enqueue(new_entries: Vec<QueueEntry>) -> Result<()> {
+    if new_entries.is_empty() {
+        return Ok(());
+    }
+    update(|entries| {
+        for entry in new_entries {
+            entries.retain(|e| !(e.host.host == entry.host.host && e.command == entry.command));
+            entries.push(entry);
+        }
+    })
+}
+
+/// `russh flush-queue`: runs the queued commands again, keeping the ones
+/// whose host is still unreachable.
+pub fn flush(cli: &Cli, args: &FlushQueueArgs) -> Result<()> {
+    let parallel = parallelism(cli.parallel, None)?;
+    let Some(_flush) = lock_flush()? else {
+        return Err(AppError::Generic(tr(Msg::QueueFlushRunning).to_string()));
+    };
+    let entries = load()?;
+    if entries.is_empty() {
+        println!("{}", tr(Msg::QueueEmpty));
+        return Ok(());
+    }
+
+    let max_age = args.max_age.saturating_mul(3600);
+    let mut keep = Vec::new();
+    let mut runnable = Vec::new();
+    for entry in entries {
+        if entry.age_secs() > max_age && !args.include_stale {
+            println!(
+                "{}",
+                trf(
+                    Msg::QueueStaleEntry,
+                    &[&entry.host.host, &(entry.age_secs() / 3600), &entry.command],
+                )
+            );
+            keep.push(entry);
+        } else if entry.is_destructive() && !args.yes && !confirm_destructive(&entry)? {
+            keep.push(entry);
+        } else {
+            runnable.push(entry);
+        }
+    }
+
+    // Replays go through the same lock and approval as the runs that
+    // queued them.
+    let mut hosts: Vec<Host> = runnable.iter().map(|entry| entry.host.clone()).collect();
+    hosts.sort_by(|a, b| a.host.cmp(&b.host));
+    hosts.dedup_by(|a, b| a.host == b.host);
+    let mut commands: Vec<String> = runnable
+        .iter()
+        .flat_map(|entry| {
+            let input = entry.replay.stdin.as_deref().map(approval::input);
+            [entry.command.clone()].into_iter().chain(input)
+        })
+        .collect();
+    commands.sort();
+    commands.dedup();
+    let config = load_config(&cli.config_file, true)?;
+    let _lock = match runnable.is_empty() {
+        true => None,
+        false => admit(cli, &config, &hosts, &commands)?,
+    };
+
+    // Entries are replayed with the settings they were queued with, a batch
+    // per set of settings; a sudo password is asked for once.
+    let become_password = match runnable.iter().any(|entry| entry.replay.become_password) {
+        true => escalation::ask_password(&*auth::Handler::default()),
+        false => None,
+    };
+    let mut batches: Vec<(Replay, Vec<QueueEntry>)> = Vec::new();
+    for entry in runnable {
+        match batches
+            .iter_mut()
+            .find(|(replay, _)| *replay == entry.replay)
+        {
+            Some((_, entries)) => entries.push(entry),
+            None => batches.push((entry.replay.clone(), vec![entry])),
+        }
+    }
+    let mut results = Vec::new();
+    let mut handled = Vec::new();
+    for (replay, entries) in batches {
+        let jobs = entries
+            .iter()
+            .map(|entry| (Arc::new(entry.host.clone()), entry.command.as_str().into()))
+            .collect();
+        let options = Arc::new(replay.options(become_password.clone())?);
+        let batch = execute_jobs(
+            jobs,
+            move |host, command, tx| {
+                let options = Arc::clone(&options);
+                async move { run_ssh_command(&host, &command, &options, tx).await }
+            },
+            None,
+            Display::Lines {
+                collapse: true,
+                progress: false,
+            },
+            Schedule::parallel(parallel),
+        );
+
+        // Hosts that are still down stay queued, everything else has been
+        // handled. Entries queued by other runs meanwhile are left alone.
+        for (entry, (_, result)) in entries.into_iter().zip(&batch) {
+            if result.unreachable() {
+                keep.push(entry);
+            } else {
+                handled.push(entry);
+            }
+        }
+        results.extend(batch);
+    }
+    remove(&handled)?;
+
+    summary::print_summary(&results);
+    if !keep.is_empty() {
+        println!("{}", trf(Msg::QueueRemaining, &[&keep.len()]));
+    }
+    Ok(())
+}
+
+fn confirm_destructive(entry: &QueueEntry) -> Result<bool> {
+    let question = trf(
+        Msg::DestructivePrompt,
+        &[
+            &(entry.age_secs() / 3600),
+            &entry.queued_by,
+            &entry.command,
+            &entry.host.host,
+        ],
+    );
+    let confirmed = prompt::confirm(&question)?;
+    if confirmed.is_none() {
+        warn(
+            WarningKind::HostSkipped,
+            format!(
+                "'{}' on {} looks destructive and there is no terminal to confirm it on, \
+                 leaving it queued; pass --yes to run it",
+                entry.command, entry.host.host
+            ),
+        );
+    }
+    Ok(confirmed == Some(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_destructive() {
+        assert!(is_destructive("sudo REBOOT"));
+        assert!(is_destructive("rm -rf /var/cache/app"));
+        assert!(!is_destructive("uptime"));
+        assert!(is_destructive("rm -r -f /srv/old"));
+        assert!(is_destructive("rm  --recursive --force /srv/old"));
+        assert!(is_destructive("uptime; /sbin/reboot"));
+        assert!(is_destructive("sudo -u root env LANG=C shutdown -h now"));
+        assert!(is_destructive("systemctl reboot"));
+        assert!(is_destructive("sh -c 'mkfs.ext4 /dev/sdb1'"));
+        assert!(is_destructive("psql -c 'DROP TABLE users'"));
+        assert!(!is_destructive("echo asphalt"));
+        assert!(!is_destructive("./reboot-notify.sh"));
+        assert!(!is_destructive("rm -r build"));
+        assert!(!is_destructive("grep -rf patterns.txt ."));
+    }
+
+    #[test]
+    fn test_entry_round_trip() {
+        let host: Host = "laptop01 user=me port=2200".parse().unwrap();
+        let options = ExecOptions {
+            escalate: true,
+            env: vec!["RELEASE=v2".parse().unwrap()],
+            timeout: Some(30),
+            ionice: Some("idle".parse().unwrap()),
+            stdin: Some("apt-get upgrade -y\n".into()),
+            ..Default::default()
+        };
+        let entry = QueueEntry::new(&host, "sh -s", &options);
+        let json = serde_json::to_string(&entry).unwrap();
+        let back: QueueEntry = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, entry);
+        assert!(entry.age_secs() < 5);
+        // Replayed the way it was queued.
+        let replayed = back.replay.options(None).unwrap();
+        assert!(replayed.escalate);
+        assert_eq!(replayed.env, options.env);
+        assert_eq!(replayed.stdin, options.stdin);
+        assert_eq!(Replay::of(&replayed), entry.replay);
+
+        // Entries queued before settings were kept replay plainly.
+        let old = r#"{"host": {"host": "laptop01"}, "command": "uptime",
+            "queued_at": 0, "queued_by": "me"}"#;
+        let old: QueueEntry = serde_json::from_str(old).unwrap();
+        assert_eq!(old.replay, Replay::default());
+    }
+}
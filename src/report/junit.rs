@@ -0,0 +1,172 @@
+//! `--report junit:PATH`: one `<testsuite>` per host and one `<testcase>`
+//! per command, the shape CI servers (GitLab, Jenkins, GitHub test
+//! reporters) turn into pass/fail dashboards.
+
+use super::excerpt;
+use crate::history::Labels;
+use crate::ssh::ServerResult;
+use crate::summary::{self, Status};
+
+/// `text` escaped for XML, without the control characters XML 1.0 can't
+/// hold at all, like the escape of a color code.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            '\t' | '\n' | '\r' => escaped.push(c),
+            c if c.is_control() => {}
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn count(commands: &[(&str, &ServerResult)], status: Status) -> usize {
+    commands
+        .iter()
+        .filter(|(_, result)| Status::of(result) == status)
+        .count()
+}
+
+fn testcase(xml: &mut String, command: &str, result: &ServerResult) {
+    xml.push_str(&format!(
+        "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\">\n",
+        escape(&result.server),
+        escape(command),
+        result.duration
+    ));
+    match (Status::of(result), result.cut) {
+        (Status::Skipped, cut) => xml.push_str(&format!(
+            "      <skipped message=\"{}\"/>\n",
+            cut.map_or("skipped", |cut| cut.describe())
+        )),
+        (Status::Failed, _) => {
+            let message = match (result.cut, result.exit_code) {
+                (Some(cut), _) => cut.describe().to_string(),
+                (None, Some(code)) => format!("exit code {}", code),
+                (None, None) => "no exit code, the connection failed".to_string(),
+            };
+            xml.push_str(&format!(
+                "      <failure message=\"{}\" type=\"failed\"/>\n",
+                escape(&message)
+            ));
+        }
+        (Status::Ok | Status::Changed, _) => {}
+    }
+    let output = excerpt(&result.output);
+    if !output.is_empty() {
+        xml.push_str(&format!(
+            "      <system-out>{}</system-out>\n",
+            escape(&output)
+        ));
+    }
+    let error = excerpt(result.error.as_deref().unwrap_or_default());
+    if !error.is_empty() {
+        xml.push_str(&format!(
+            "      <system-err>{}</system-err>\n",
+            escape(&error)
+        ));
+    }
+    xml.push_str("    </testcase>\n");
+}
+
+/// The run's labels as `<properties>`, which every suite repeats since
+/// JUnit has no place for them on `<testsuites>`.
+fn properties(labels: &Labels) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let mut xml = String::from("    <properties>\n");
+    for (key, value) in labels {
+        xml.push_str(&format!(
+            "      <property name=\"{}\" value=\"{}\"/>\n",
+            escape(key),
+            escape(value)
+        ));
+    }
+    xml.push_str("    </properties>\n");
+    xml
+}
+
+pub fn render(results: &[(String, ServerResult)], labels: &Labels) -> String {
+    let hosts = summary::by_host(results);
+    let all: Vec<(&str, &ServerResult)> = results.iter().map(|(c, r)| (c.as_str(), r)).collect();
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str(&format!(
+        "<testsuites name=\"russh\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+        all.len(),
+        count(&all, Status::Failed),
+        count(&all, Status::Skipped),
+        // Hosts run in parallel, so the longest job stands in for the run.
+        results.iter().map(|(_, r)| r.duration).fold(0.0, f64::max)
+    ));
+    for host in &hosts {
+        xml.push_str(&format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            escape(host.server),
+            host.commands.len(),
+            count(&host.commands, Status::Failed),
+            count(&host.commands, Status::Skipped),
+            host.commands.iter().map(|(_, r)| r.duration).sum::<f64>()
+        ));
+        xml.push_str(&properties(labels));
+        for (command, result) in &host.commands {
+            testcase(&mut xml, command, result);
+        }
+        xml.push_str("  </testsuite>\n");
+    }
+    xml.push_str("</testsuites>\n");
+    xml
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::Cut;
+
+    fn result(server: &str, success: bool, output: &str) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            output: output.to_string(),
+            duration: 0.25,
+            success,
+            exit_code: Some(if success { 0 } else { 2 }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let mut skipped = result("web02", false, "");
+        skipped.cut = Some(Cut::AfterFailure);
+        skipped.exit_code = None;
+        let results = vec![
+            ("uptime".to_string(), result("web01", true, "up 3 days\n")),
+            (
+                "test -f /etc/<app>".to_string(),
+                result("web02", false, "\x1b[31mmissing\x1b[0m & gone\n"),
+            ),
+            ("systemctl restart app".to_string(), skipped),
+        ];
+        let labels = Labels::from([("reason".to_string(), "kernel \"6.8\"".to_string())]);
+        let xml = render(&results, &labels);
+        assert!(xml.contains("<property name=\"reason\" value=\"kernel &quot;6.8&quot;\"/>"));
+        assert!(xml.contains(
+            "<testsuites name=\"russh\" tests=\"3\" failures=\"1\" skipped=\"1\" time=\"0.250\">"
+        ));
+        assert!(xml.contains(
+            "<testsuite name=\"web02\" tests=\"2\" failures=\"1\" skipped=\"1\" time=\"0.500\">"
+        ));
+        assert!(xml.contains("name=\"test -f /etc/&lt;app&gt;\""));
+        assert!(xml.contains("<failure message=\"exit code 2\" type=\"failed\"/>"));
+        assert!(xml.contains("<system-out>[31mmissing[0m &amp; gone</system-out>"));
+        assert!(xml.contains("<skipped message=\"not run, an earlier command failed\"/>"));
+        assert!(xml.contains("<system-out>up 3 days</system-out>"));
+        assert_eq!(xml.matches("<testsuite ").count(), 2);
+    }
+}
@@ -0,0 +1,123 @@
+//! `--report md:PATH`: a table of every host and command with its status
+//! and duration, then the output of what failed, for PR comments and CI job
+//! summaries.
+
+use super::excerpt;
+use crate::history::Labels;
+use crate::ssh::ServerResult;
+use crate::summary::{self, Status};
+
+/// `text` safe inside a table cell.
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// A code fence longer than any run of backticks in `text`.
+fn fence(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    "`".repeat(longest.max(2) + 1)
+}
+
+pub fn render(results: &[(String, ServerResult)], labels: &Labels) -> String {
+    let hosts = summary::by_host(results);
+    let (ok, changed, failed) = summary::tally(results);
+    let skipped = results.len() - ok - changed - failed;
+    let mut md = String::from("## russh run\n\n");
+    md.push_str(&format!(
+        "{} host(s): {} ok, {} changed, {} failed, {} skipped\n\n",
+        hosts.len(),
+        ok,
+        changed,
+        failed,
+        skipped
+    ));
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("`{}={}`", key, value).replace('\n', " "))
+            .collect();
+        md.push_str(&format!("Labels: {}\n\n", labels.join(", ")));
+    }
+    md.push_str("| Host | Command | Status | Exit | Duration |\n");
+    md.push_str("| --- | --- | --- | --- | --- |\n");
+    for (command, result) in results {
+        let status = match Status::of(result) {
+            Status::Failed => "**failed**",
+            status => status.as_str(),
+        };
+        md.push_str(&format!(
+            "| {} | `{}` | {} | {} | {:.2}s |\n",
+            cell(&result.server),
+            cell(command).replace('`', "'"),
+            status,
+            result
+                .exit_code
+                .map_or("-".to_string(), |code| code.to_string()),
+            result.duration
+        ));
+    }
+    for host in hosts.iter().filter(|host| host.status == Status::Failed) {
+        for (command, result) in &host.commands {
+            if Status::of(result) != Status::Failed {
+                continue;
+            }
+            let output = excerpt(&format!(
+                "{}{}",
+                result.output,
+                result.error.as_deref().unwrap_or_default()
+            ));
+            md.push_str(&format!(
+                "\n<details><summary>{}: <code>{}</code></summary>\n\n",
+                host.server,
+                command.replace('&', "&amp;").replace('<', "&lt;")
+            ));
+            match result.cut {
+                Some(cut) => md.push_str(&format!("{}\n\n", cut.describe())),
+                None if output.is_empty() => md.push_str("(no output)\n\n"),
+                None => {}
+            }
+            if !output.is_empty() {
+                let fence = fence(&output);
+                md.push_str(&format!("{}\n{}\n{}\n\n", fence, output, fence));
+            }
+            md.push_str("</details>\n");
+        }
+    }
+    md
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(server: &str, success: bool, output: &str) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            output: output.to_string(),
+            duration: 1.5,
+            success,
+            exit_code: Some(if success { 0 } else { 1 }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let results = vec![
+            ("uptime".to_string(), result("web01", true, "up 3 days\n")),
+            (
+                "grep x | wc -l".to_string(),
+                result("web02", false, "see ```this```\n"),
+            ),
+        ];
+        let labels = Labels::from([("ticket".to_string(), "OPS-1234".to_string())]);
+        let md = render(&results, &labels);
+        assert!(md.contains("Labels: `ticket=OPS-1234`\n"));
+        assert!(md.contains("2 host(s): 1 ok, 0 changed, 1 failed, 0 skipped"));
+        assert!(md.contains("| web01 | `uptime` | ok | 0 | 1.50s |"));
+        assert!(md.contains("| web02 | `grep x \\| wc -l` | **failed** | 1 | 1.50s |"));
+        assert!(md.contains("<summary>web02: <code>grep x | wc -l</code></summary>"));
+        assert!(md.contains("````\nsee ```this```\n````"));
+        assert!(!md.contains("web01: <code>"));
+    }
+}
@@ -0,0 +1,299 @@
+//! The report of the most recent run, kept so follow-ups like
+//! `russh rerun-failed` know which hosts and commands failed, and the
+//! `--report` files written for CI: JUnit XML and Markdown.
+
+use crate::audit::{current_user, now};
+use crate::config::Host;
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::history::Labels;
+use crate::paths;
+use crate::ssh::ServerResult;
+use crate::summary::Status;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+mod junit;
+mod markdown;
+
+/// One host of the run with its commands, in the order they ran.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HostReport {
+    /// The fully resolved host, so ad-hoc `-H` hosts can be rerun as well.
+    pub host: Host,
+    pub commands: Vec<CommandReport>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CommandReport {
+    pub command: String,
+    pub result: ServerResult,
+}
+
+/// A flat host/command/result entry, as reports were written before they
+/// were grouped by host; still read so an older report can be rerun.
+#[derive(Clone, Debug, Deserialize)]
+struct LegacyEntry {
+    host: Host,
+    command: String,
+    result: ServerResult,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunReport {
+    pub finished: u64,
+    pub user: String,
+    /// The run's `--label`s.
+    #[serde(default, skip_serializing_if = "Labels::is_empty")]
+    pub labels: Labels,
+    #[serde(default)]
+    pub hosts: Vec<HostReport>,
+    #[serde(default, skip_serializing)]
+    entries: Vec<LegacyEntry>,
+}
+
+impl RunReport {
+    pub fn new(hosts: &[Host], results: &[(String, ServerResult)]) -> RunReport {
+        let mut report = RunReport {
+            finished: now(),
+            user: current_user(),
+            labels: Labels::new(),
+            hosts: Vec::new(),
+            entries: Vec::new(),
+        };
+        for (command, result) in results {
+            if let Some(host) = hosts.iter().find(|h| h.host == result.server) {
+                report.add(host, command, result);
+            }
+        }
+        report
+    }
+
+    pub fn with_labels(mut self, labels: Labels) -> RunReport {
+        self.labels = labels;
+        self
+    }
+
+    fn add(&mut self, host: &Host, command: &str, result: &ServerResult) {
+        let command = CommandReport {
+            command: command.to_string(),
+            result: result.clone(),
+        };
+        match self.hosts.iter_mut().find(|h| h.host.host == host.host) {
+            Some(report) => report.commands.push(command),
+            None => self.hosts.push(HostReport {
+                host: host.clone(),
+                commands: vec![command],
+            }),
+        }
+    }
+
+    /// The host and command of every failed or skipped entry, host by host.
+    pub fn failed_jobs(&self) -> Vec<(Host, String)> {
+        self.hosts
+            .iter()
+            .flat_map(|report| {
+                report
+                    .commands
+                    .iter()
+                    .filter(|c| matches!(Status::of(&c.result), Status::Failed | Status::Skipped))
+                    .map(|c| (report.host.clone(), c.command.clone()))
+            })
+            .collect()
+    }
+}
+
+/// The report's file name in the state directory.
+pub const LAST_RUN: &str = "last-run.json";
+
+pub fn last_run_path() -> Result<PathBuf> {
+    paths::state_path(LAST_RUN)
+}
+
+pub fn save(report: &RunReport) -> Result<()> {
+    let json = serde_json::to_vec_pretty(report).map_err(|e| AppError::Generic(e.to_string()))?;
+    paths::write_atomic(&last_run_path()?, &json)
+}
+
+pub fn load_last() -> Result<RunReport> {
+    let path = last_run_path()?;
+    if !path.exists() {
+        return Err(AppError::Generic(
+            "no previous run has been recorded yet".to_string(),
+        ));
+    }
+    parse(&fs::read(&path)?)
+        .map_err(|e| AppError::Generic(format!("corrupt run report {}: {}", path.display(), e)))
+}
+
+/// Takes `host` out of the last run's report, so follow-ups no longer
+/// touch it; returns its part of the report.
+pub fn forget(host: &str) -> Result<Option<HostReport>> {
+    if !last_run_path()?.exists() {
+        return Ok(None);
+    }
+    let mut report = load_last()?;
+    let Some(i) = report.hosts.iter().position(|h| h.host.host == host) else {
+        return Ok(None);
+    };
+    let removed = report.hosts.remove(i);
+    save(&report)?;
+    Ok(Some(removed))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReportFormat {
+    Junit,
+    Markdown,
+}
+
+/// A `--report FORMAT:PATH` file to write once the run is over.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReportSpec {
+    pub format: ReportFormat,
+    pub path: PathBuf,
+}
+
+impl FromStr for ReportSpec {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let invalid = |reason: String| ExecutionError::InvalidOption {
+            option: "--report",
+            reason,
+        };
+        let (format, path) = spec
+            .split_once(':')
+            .filter(|(_, path)| !path.is_empty())
+            .ok_or_else(|| invalid(format!("expected FORMAT:PATH, got '{}'", spec)))?;
+        let format = match format {
+            "junit" => ReportFormat::Junit,
+            "md" | "markdown" => ReportFormat::Markdown,
+            _ => {
+                return Err(
+                    invalid(format!("unknown format '{}', expected junit or md", format)).into(),
+                )
+            }
+        };
+        Ok(ReportSpec {
+            format,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+/// Output kept per command in a `--report` file; longer output keeps its
+/// end, where the error usually is.
+const MAX_EXCERPT: usize = 4096;
+
+/// The last `MAX_EXCERPT` bytes of `text`, from the start of a line, with
+/// a note of how much was left out.
+fn excerpt(text: &str) -> String {
+    let text = text.trim_end();
+    if text.len() <= MAX_EXCERPT {
+        return text.to_string();
+    }
+    let mut start = text.len() - MAX_EXCERPT;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    if let Some(newline) = text[start..].find('\n') {
+        start += newline + 1;
+    }
+    format!("... ({} bytes cut)\n{}", start, &text[start..])
+}
+
+/// Renders `results` in `spec`'s format, under the run's `labels`, and
+/// writes them to its path.
+pub fn write(spec: &ReportSpec, results: &[(String, ServerResult)], labels: &Labels) -> Result<()> {
+    let text = match spec.format {
+        ReportFormat::Junit => junit::render(results, labels),
+        ReportFormat::Markdown => markdown::render(results, labels),
+    };
+    if let Some(parent) = spec.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&spec.path, text)?;
+    Ok(())
+}
+
+fn parse(json: &[u8]) -> serde_json::Result<RunReport> {
+    let mut report: RunReport = serde_json::from_slice(json)?;
+    for entry in std::mem::take(&mut report.entries) {
+        report.add(&entry.host, &entry.command, &entry.result);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(server: &str, success: bool) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            duration: 0.2,
+            success,
+            exit_code: Some(if success { 0 } else { 1 }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_failed_jobs() {
+        let hosts = vec![
+            "web01 port=2222".parse::<Host>().unwrap(),
+            Host::new("web02"),
+        ];
+        let results = vec![
+            ("uptime".to_string(), result("web01", false)),
+            ("uptime".to_string(), result("web02", true)),
+            ("df -h".to_string(), result("web02", false)),
+        ];
+        let report = RunReport::new(&hosts, &results);
+        let json = serde_json::to_string(&report).unwrap();
+        let report = parse(json.as_bytes()).unwrap();
+
+        let failed = report.failed_jobs();
+        assert_eq!(failed.len(), 2);
+        assert_eq!(failed[0].0.port, Some(2222));
+        assert_eq!(failed[0].1, "uptime");
+        assert_eq!(failed[1].0.host, "web02");
+        assert_eq!(failed[1].1, "df -h");
+        assert_eq!(report.hosts.len(), 2);
+        assert_eq!(report.hosts[1].commands.len(), 2);
+
+        // A report from before results were grouped by host.
+        let legacy = serde_json::json!({
+            "finished": 0,
+            "user": "ops",
+            "entries": [
+                {"host": {"host": "web01"}, "command": "uptime", "result": result("web01", false)},
+                {"host": {"host": "web01"}, "command": "df -h", "result": result("web01", false)},
+            ],
+        });
+        let report = parse(legacy.to_string().as_bytes()).unwrap();
+        assert_eq!(report.hosts.len(), 1);
+        assert_eq!(report.failed_jobs().len(), 2);
+    }
+
+    #[test]
+    fn test_report_spec() {
+        let spec: ReportSpec = "junit:out/report.xml".parse().unwrap();
+        assert_eq!(spec.format, ReportFormat::Junit);
+        assert_eq!(spec.path, PathBuf::from("out/report.xml"));
+        let spec: ReportSpec = "md:C:/ci/report.md".parse().unwrap();
+        assert_eq!(spec.format, ReportFormat::Markdown);
+        assert_eq!(spec.path, PathBuf::from("C:/ci/report.md"));
+        for bad in ["report.xml", "html:report.html", "junit:"] {
+            assert!(bad.parse::<ReportSpec>().is_err(), "{}", bad);
+        }
+
+        let long = format!("{}\nlast line\n", "x".repeat(MAX_EXCERPT));
+        let cut = excerpt(&long);
+        assert!(cut.starts_with(&format!("... ({} bytes cut)\n", MAX_EXCERPT + 1)));
+        assert!(cut.ends_with("\nlast line"));
+        assert_eq!(excerpt("up 3 days\n"), "up 3 days");
+    }
+}
@@ -0,0 +1,271 @@
+//! Manual gates: a step marked `gate = "manual"` starts on no host until an
+//! operator lets it through, which they are asked once the steps it needs
+//! have finished on every host ("check the dashboards, then continue").
+//! The run asks in its terminal; `russh gate ID` (or `--abort`) answers
+//! from anywhere else on the machine, through files in a shared directory.
+
+use crate::audit::{self, current_user, now};
+use crate::cli::GateArgs;
+use crate::errors::{AppError, Result};
+use crate::i18n::{is_yes, trf, Msg};
+use crate::lock::shared_dir;
+use crate::paths;
+use crate::warnings::{warn, WarningKind};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::fs;
+use std::future;
+use std::io::{self, BufRead, IsTerminal};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+use tokio::sync::{mpsc, Mutex};
+
+/// Where waiting gates and their decisions are kept.
+const STORE: &str = "russh-gates";
+
+/// How often a waiting gate looks for a decision by `russh gate`.
+const POLL: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Gate {
+    /// Wait for an operator.
+    Manual,
+}
+
+/// A run waiting at a gate, as `russh gate` lists it.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Waiting {
+    pub id: String,
+    pub step: String,
+    pub command: String,
+    pub hosts: Vec<String>,
+    pub requester: String,
+    pub created: u64,
+}
+
+/// An operator's answer to a gate.
+#[derive(Debug, Serialize, Deserialize)]
+struct Decision {
+    open: bool,
+    by: String,
+}
+
+/// Lines typed into the run's terminal, read by a single thread for every
+/// gate of the run. The lock also keeps two gates from asking at once.
+pub type Terminal = Mutex<Option<mpsc::UnboundedReceiver<String>>>;
+
+/// The id of this run's gate at `step`.
+fn gate_id(step: &str) -> String {
+    paths::file_name(&format!("{}-{}", std::process::id(), step))
+}
+
+fn waiting_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.waiting", id))
+}
+
+fn decision_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.decision", id))
+}
+
+fn read_json<T: for<'de> Deserialize<'de>>(path: &Path) -> Option<T> {
+    fs::read(path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+}
+
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let json = serde_json::to_vec_pretty(value).map_err(|e| AppError::Generic(e.to_string()))?;
+    paths::write_atomic(path, &json)?;
+    Ok(())
+}
+
+fn read_lines() -> mpsc::UnboundedReceiver<String> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines() {
+            let Ok(line) = line else { break };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Waits until an operator opens or aborts the gate at `step` for `hosts`,
+/// in the run's terminal or with `russh gate`. Returns whether it opened.
+pub async fn wait(step: &str, command: &str, hosts: &[String], terminal: &Terminal) -> bool {
+    let mut terminal = terminal.lock().await;
+    let id = gate_id(step);
+    let dir = match shared_dir(STORE) {
+        Ok(dir) => Some(dir),
+        Err(e) => {
+            warn(
+                WarningKind::Delivery,
+                format!("gate {}: russh gate can't answer it: {}", id, e),
+            );
+            None
+        }
+    };
+    if let Some(dir) = &dir {
+        let waiting = Waiting {
+            id: id.clone(),
+            step: step.to_string(),
+            command: command.to_string(),
+            hosts: hosts.to_vec(),
+            requester: current_user(),
+            created: now(),
+        };
+        if let Err(e) = write_json(&waiting_path(dir, &id), &waiting) {
+            warn(
+                WarningKind::Delivery,
+                format!("gate {}: russh gate can't answer it: {}", id, e),
+            );
+        }
+    }
+
+    let mut typing = io::stdin().is_terminal();
+    if typing && terminal.is_none() {
+        *terminal = Some(read_lines());
+    }
+    let how = match typing {
+        true => trf(Msg::GateTypeOrRun, &[&id]),
+        false => trf(Msg::GateRunToContinue, &[&id]),
+    };
+    eprintln!("{}", trf(Msg::GatePrompt, &[&step, &hosts.len(), &how]));
+    let decision = loop {
+        if let Some(decision) = dir
+            .as_deref()
+            .and_then(|dir| read_json::<Decision>(&decision_path(dir, &id)))
+        {
+            break decision;
+        }
+        let line = async {
+            match terminal.as_mut().filter(|_| typing) {
+                Some(lines) => lines.recv().await,
+                None => future::pending().await,
+            }
+        };
+        match tokio::time::timeout(POLL, line).await {
+            Ok(Some(line)) => {
+                break Decision {
+                    open: is_yes(&line),
+                    by: current_user(),
+                }
+            }
+            // stdin closed: only `russh gate` can answer now.
+            Ok(None) => typing = false,
+            Err(_) => {}
+        }
+    };
+    if let Some(dir) = &dir {
+        let _ = fs::remove_file(waiting_path(dir, &id));
+        let _ = fs::remove_file(decision_path(dir, &id));
+    }
+
+    let event = match decision.open {
+        true => "gate_opened",
+        false => "gate_aborted",
+    };
+    if let Err(e) = audit::record(
+        event,
+        json!({ "id": id, "step": step, "hosts": hosts, "by": decision.by }),
+    ) {
+        warn(
+            WarningKind::Delivery,
+            format!("could not record the gate in the audit log: {}", e),
+        );
+    }
+    match decision.open {
+        true => eprintln!("{}", trf(Msg::GateOpened, &[&step, &decision.by])),
+        false => eprintln!("{}", trf(Msg::GateAborted, &[&step, &decision.by])),
+    }
+    decision.open
+}
+
+/// The gates runs are waiting at, oldest first.
+pub fn waiting() -> Result<Vec<Waiting>> {
+    let dir = shared_dir(STORE)?;
+    let mut waiting: Vec<Waiting> = fs::read_dir(&dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "waiting"))
+        .filter_map(|path| read_json(&path))
+        .collect();
+    waiting.sort_by_key(|w| w.created);
+    Ok(waiting)
+}
+
+/// `russh gate ID`: opens (or, with `open` false, aborts) a waiting gate.
+pub fn decide(id: &str, open: bool) -> Result<()> {
+    let dir = shared_dir(STORE)?;
+    if read_json::<Waiting>(&waiting_path(&dir, id)).is_none() {
+        return Err(AppError::Generic(format!(
+            "no run is waiting at gate {}",
+            id
+        )));
+    }
+    let decision = Decision {
+        open,
+        by: current_user(),
+    };
+    write_json(&decision_path(&dir, id), &decision)
+}
+
+/// `russh gate`: answers a gate, or lists the ones runs are waiting at.
+pub fn command(args: &GateArgs) -> Result<()> {
+    if let Some(id) = &args.id {
+        decide(id, !args.abort)?;
+        match args.abort {
+            true => println!("Gate {} aborted.", id),
+            false => println!("Gate {} opened.", id),
+        }
+        return Ok(());
+    }
+    let waiting = waiting()?;
+    if waiting.is_empty() {
+        println!("No run is waiting at a gate.");
+    }
+    for gate in waiting {
+        println!(
+            "{}  step {} ({}) on {} host(s), started by {}",
+            gate.id,
+            gate.step,
+            gate.command,
+            gate.hosts.len(),
+            gate.requester
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decide() {
+        let id = gate_id("verify-dashboards");
+        assert!(decide(&id, true).is_err());
+
+        let dir = shared_dir(STORE).unwrap();
+        let waiting = Waiting {
+            id: id.clone(),
+            step: "verify-dashboards".to_string(),
+            command: "true".to_string(),
+            hosts: vec!["web01".to_string()],
+            requester: "alice".to_string(),
+            created: now(),
+        };
+        write_json(&waiting_path(&dir, &id), &waiting).unwrap();
+        let listed = super::waiting().unwrap().into_iter().any(|w| w.id == id);
+        decide(&id, false).unwrap();
+        let decision: Option<Decision> = read_json(&decision_path(&dir, &id));
+        let _ = fs::remove_file(waiting_path(&dir, &id));
+        let _ = fs::remove_file(decision_path(&dir, &id));
+        assert!(listed);
+        assert!(!decision.unwrap().open);
+    }
+}
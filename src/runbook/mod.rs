@@ -0,0 +1,389 @@
+//! Runbooks: named steps with dependencies, read from a TOML file and run
+//! on every selected host by `russh runbook FILE`. A step starts on a host
+//! once every step it `needs` has succeeded there, and is skipped there if
+//! one of them didn't; steps that don't depend on each other run at the
+//! same time, and hosts fan out as for any other run.
+//!
+//! ```toml
+//! [[steps]]
+//! name = "drain"
+//! command = "curl -fsX POST localhost:8080/drain"
+//!
+//! [[steps]]
+//! name = "upgrade"
+//! command = "apt-get install -y app"
+//! needs = ["drain"]
+//!
+//! [[steps]]
+//! name = "restart"
+//! command = "systemctl restart app"
+//! needs = ["upgrade"]
+//! gate = "manual"
+//! ```
+//!
+//! A step with `gate = "manual"` waits for an operator before it starts
+//! anywhere (see [`gate`]).
+
+use crate::cli::run::run;
+use crate::cli::{exec_options, inventory, schedule, Cli, RunbookArgs};
+use crate::config::Host;
+use crate::errors::{AppError, ConfigError, Result};
+use crate::executor::Job;
+use crate::report;
+use crate::ssh::{Cut, ServerResult};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{watch, OnceCell};
+
+pub mod gate;
+pub mod plan;
+
+pub use gate::Gate;
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Step {
+    pub name: String,
+    pub command: String,
+    /// Steps that must have succeeded on a host before this one runs there.
+    #[serde(default)]
+    pub needs: Vec<String>,
+    /// Holds the step back on every host until an operator lets it run.
+    #[serde(default)]
+    pub gate: Option<Gate>,
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+pub struct Runbook {
+    /// In an order where every step comes after the steps it needs.
+    pub steps: Vec<Step>,
+}
+
+impl Runbook {
+    pub fn load(path: &Path) -> Result<Runbook> {
+        let text = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            file: path.to_path_buf(),
+            source,
+        })?;
+        let runbook: Runbook = toml::from_str(&text).map_err(|e| ConfigError::parse(path, e))?;
+        runbook.sorted().map_err(|message| {
+            ConfigError::Parse {
+                file: PathBuf::from(path),
+                line: None,
+                message,
+            }
+            .into()
+        })
+    }
+
+    /// The runbook with its steps in dependency order, file order where
+    /// that leaves a choice; an error for unknown, duplicate or circular
+    /// steps.
+    fn sorted(self) -> std::result::Result<Runbook, String> {
+        if self.steps.is_empty() {
+            return Err("the runbook has no steps".to_string());
+        }
+        for (i, step) in self.steps.iter().enumerate() {
+            if self.steps[..i].iter().any(|s| s.name == step.name) {
+                return Err(format!("step '{}' is defined twice", step.name));
+            }
+            if let Some(unknown) = step
+                .needs
+                .iter()
+                .find(|need| !self.steps.iter().any(|s| &s.name == *need))
+            {
+                return Err(format!(
+                    "step '{}' needs '{}', which is not a step",
+                    step.name, unknown
+                ));
+            }
+        }
+        let mut left = self.steps;
+        let mut steps: Vec<Step> = Vec::with_capacity(left.len());
+        while !left.is_empty() {
+            let Some(ready) = left.iter().position(|step| {
+                step.needs
+                    .iter()
+                    .all(|need| steps.iter().any(|s| &s.name == need))
+            }) else {
+                let names: Vec<&str> = left.iter().map(|s| s.name.as_str()).collect();
+                return Err(format!(
+                    "steps {} need each other in a cycle",
+                    names.join(", ")
+                ));
+            };
+            steps.push(left.remove(ready));
+        }
+        Ok(Runbook { steps })
+    }
+}
+
+/// Which steps have finished on which hosts, and whether they succeeded.
+type Finished = HashMap<(String, usize), bool>;
+
+/// Runs a runbook's jobs, holding each one back until the steps it needs
+/// have finished on its host.
+pub struct Tracker {
+    steps: Vec<Step>,
+    commands: Vec<Arc<str>>,
+    finished: watch::Sender<Finished>,
+    /// The hosts the runbook runs on.
+    hosts: Mutex<Vec<String>>,
+    /// Whether each gated step's gate was opened, once it was asked.
+    gates: Vec<OnceCell<bool>>,
+    terminal: gate::Terminal,
+}
+
+/// Records a step as failed on its host if its job never finishes, e.g.
+/// because it panicked, so the steps after it aren't left waiting.
+struct Pending<'a> {
+    tracker: &'a Tracker,
+    key: Option<(String, usize)>,
+}
+
+impl Pending<'_> {
+    fn finish(mut self, success: bool) {
+        if let Some(key) = self.key.take() {
+            self.tracker.finished.send_modify(|finished| {
+                finished.insert(key, success);
+            });
+        }
+    }
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if let Some(key) = self.key.take() {
+            self.tracker.finished.send_modify(|finished| {
+                finished.insert(key, false);
+            });
+        }
+    }
+}
+
+impl Tracker {
+    pub fn new(runbook: Runbook) -> Tracker {
+        Tracker {
+            commands: runbook
+                .steps
+                .iter()
+                .map(|step| step.command.as_str().into())
+                .collect(),
+            gates: runbook.steps.iter().map(|_| OnceCell::new()).collect(),
+            steps: runbook.steps,
+            finished: watch::Sender::new(HashMap::new()),
+            hosts: Mutex::new(Vec::new()),
+            terminal: gate::Terminal::default(),
+        }
+    }
+
+    pub fn has_gates(&self) -> bool {
+        self.steps.iter().any(|step| step.gate.is_some())
+    }
+
+    /// Every step on every host, step by step. A job only ever waits for
+    /// jobs before it, which the executor has started already, so the
+    /// runbook can't stall whatever `--parallel` is.
+    pub fn jobs(&self, hosts: Vec<Host>) -> Vec<Job> {
+        *self.hosts.lock().unwrap() = hosts.iter().map(|h| h.host.clone()).collect();
+        let hosts: Vec<Arc<Host>> = hosts.into_iter().map(Arc::new).collect();
+        self.commands
+            .iter()
+            .flat_map(|command| {
+                hosts
+                    .iter()
+                    .map(move |host| (Arc::clone(host), Arc::clone(command)))
+            })
+            .collect()
+    }
+
+    /// Keeps only `remaining` of the hosts, e.g. after preflight checks
+    /// left some out, so gates don't wait for hosts that never run.
+    pub fn retain_hosts(&self, remaining: &HashSet<&str>) {
+        self.hosts
+            .lock()
+            .unwrap()
+            .retain(|host| remaining.contains(host.as_str()));
+    }
+
+    /// Whether the operator opened the gate of `step`; asked once the steps
+    /// it needs have finished on every host, and only for the hosts where
+    /// they succeeded.
+    async fn gate_opened(&self, step: usize) -> bool {
+        *self.gates[step]
+            .get_or_init(|| async {
+                let hosts = self.hosts.lock().unwrap().clone();
+                let needs: Vec<usize> = self.steps[step]
+                    .needs
+                    .iter()
+                    .filter_map(|need| self.steps.iter().position(|s| &s.name == need))
+                    .collect();
+                let mut finished = self.finished.subscribe();
+                let ready: Vec<String> = match finished
+                    .wait_for(|finished| {
+                        hosts.iter().all(|host| {
+                            needs
+                                .iter()
+                                .all(|&need| finished.contains_key(&(host.clone(), need)))
+                        })
+                    })
+                    .await
+                {
+                    Ok(finished) => hosts
+                        .iter()
+                        .filter(|host| {
+                            needs
+                                .iter()
+                                .all(|&need| finished[&(host.to_string(), need)])
+                        })
+                        .cloned()
+                        .collect(),
+                    Err(_) => Vec::new(),
+                };
+                if ready.is_empty() {
+                    return false;
+                }
+                let step = &self.steps[step];
+                gate::wait(&step.name, &step.command, &ready, &self.terminal).await
+            })
+            .await
+    }
+
+    /// Runs `job`, the command of one of this runbook's jobs on `host`,
+    /// once the steps it needs have succeeded there. Steps are told apart
+    /// by their command's `Arc`, as two may run the same command.
+    pub async fn run(
+        &self,
+        host: &Host,
+        command: &Arc<str>,
+        job: impl Future<Output = ServerResult>,
+    ) -> ServerResult {
+        let Some(step) = self.commands.iter().position(|c| Arc::ptr_eq(c, command)) else {
+            return job.await;
+        };
+        let pending = Pending {
+            tracker: self,
+            key: Some((host.host.clone(), step)),
+        };
+        let needs: Vec<(String, usize)> = self.steps[step]
+            .needs
+            .iter()
+            .filter_map(|need| self.steps.iter().position(|s| &s.name == need))
+            .map(|need| (host.host.clone(), need))
+            .collect();
+        let mut finished = self.finished.subscribe();
+        let ready = finished
+            .wait_for(|finished| needs.iter().all(|need| finished.contains_key(need)))
+            .await
+            .map(|finished| needs.iter().all(|need| finished[need]))
+            .unwrap_or(false);
+        if !ready {
+            pending.finish(false);
+            return ServerResult::skipped(&host.host, Cut::NeedsFailed);
+        }
+        if self.steps[step].gate.is_some() && !self.gate_opened(step).await {
+            pending.finish(false);
+            return ServerResult::skipped(&host.host, Cut::GateClosed);
+        }
+        let result = job.await;
+        pending.finish(result.success);
+        result
+    }
+}
+
+/// `russh runbook`: runs a runbook's steps on every selected host.
+pub fn command(cli: &Cli, args: &RunbookArgs) -> Result<()> {
+    let runbook = Runbook::load(&args.path)?;
+    let (config, hosts) = inventory(cli)?;
+    if args.plan {
+        let estimates = match report::last_run_path()?.exists() {
+            true => plan::estimates(&report::load_last()?),
+            false => HashMap::new(),
+        };
+        print!("{}", plan::format_plan(&runbook, &hosts, &estimates));
+        return Ok(());
+    }
+    let tracker = Arc::new(Tracker::new(runbook));
+    let jobs = tracker.jobs(hosts);
+    let options = exec_options(cli, &config)?;
+    let schedule = schedule(cli, &config)?;
+    if tracker.has_gates()
+        && (schedule.serial.is_some() || schedule.fail_fast || schedule.deadline.is_some())
+    {
+        return Err(AppError::Generic(
+            "manual gates hold back the whole fleet; they can't be combined with \
+             --serial, --fail-fast or --deadline"
+                .to_string(),
+        ));
+    }
+    run(cli, &config, jobs, &options, schedule, Some(tracker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::runtime;
+
+    fn runbook(text: &str) -> std::result::Result<Runbook, String> {
+        toml::from_str::<Runbook>(text).unwrap().sorted()
+    }
+
+    #[test]
+    fn test_steps_run_after_their_needs() {
+        let sorted = runbook(
+            "[[steps]]\nname = \"restart\"\ncommand = \"true\"\nneeds = [\"upgrade\", \"config\"]\n\
+             [[steps]]\nname = \"upgrade\"\ncommand = \"false\"\n\
+             [[steps]]\nname = \"config\"\ncommand = \"true\"\n",
+        )
+        .unwrap();
+        let names: Vec<&str> = sorted.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["upgrade", "config", "restart"]);
+        assert!(
+            runbook("[[steps]]\nname = \"a\"\ncommand = \"x\"\nneeds = [\"b\"]\n")
+                .unwrap_err()
+                .contains("which is not a step")
+        );
+        assert!(runbook(
+            "[[steps]]\nname = \"a\"\ncommand = \"x\"\nneeds = [\"b\"]\n\
+             [[steps]]\nname = \"b\"\ncommand = \"x\"\nneeds = [\"a\"]\n"
+        )
+        .unwrap_err()
+        .contains("cycle"));
+
+        // upgrade fails on web01 only: restart is skipped there, config
+        // runs everywhere.
+        let tracker = Arc::new(Tracker::new(sorted));
+        let jobs = tracker.jobs(vec![Host::new("web01"), Host::new("web02")]);
+        assert_eq!(jobs.len(), 6);
+        let results = runtime::block_on(async {
+            let tasks: Vec<_> = jobs
+                .into_iter()
+                .rev()
+                .map(|(host, command)| {
+                    let tracker = Arc::clone(&tracker);
+                    tokio::spawn(async move {
+                        let success = !(host.host == "web01" && &*command == "false");
+                        let job = async {
+                            let mut result = ServerResult::skipped(&host.host, Cut::NotStarted);
+                            result.cut = None;
+                            result.success = success;
+                            result
+                        };
+                        tracker.run(&host, &command, job).await
+                    })
+                })
+                .collect();
+            let mut results = Vec::new();
+            for task in tasks {
+                results.push(task.await.unwrap());
+            }
+            results
+        });
+        let cuts: Vec<Option<Cut>> = results.iter().map(|r| r.cut).collect();
+        // Reversed: restart on web02, then web01, config..., upgrade...
+        assert_eq!(cuts, [None, Some(Cut::NeedsFailed), None, None, None, None]);
+    }
+}
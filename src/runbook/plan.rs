@@ -0,0 +1,187 @@
+//! `russh runbook FILE --plan`: what a runbook would do, without running
+//! it. Steps are shown by stage (steps in one stage can run at the same
+//! time), with the hosts they target and how long they took in the last
+//! run, and the longest chain of steps gives the run's estimate. Gated
+//! steps are marked; the estimate doesn't count time spent at gates.
+
+use super::Runbook;
+use crate::config::Host;
+use crate::report::RunReport;
+use std::collections::HashMap;
+
+/// The mean duration of every command that succeeded in `report`.
+pub fn estimates(report: &RunReport) -> HashMap<String, f64> {
+    let mut durations: HashMap<&str, Vec<f64>> = HashMap::new();
+    for command in report.hosts.iter().flat_map(|host| &host.commands) {
+        if command.result.success {
+            durations
+                .entry(&command.command)
+                .or_default()
+                .push(command.result.duration);
+        }
+    }
+    durations
+        .into_iter()
+        .map(|(command, durations)| {
+            let mean = durations.iter().sum::<f64>() / durations.len() as f64;
+            (command.to_string(), mean)
+        })
+        .collect()
+}
+
+/// Each step's stage: 1 for steps that need nothing, else one more than
+/// the latest stage among the steps it needs.
+fn stages(runbook: &Runbook) -> Vec<usize> {
+    let mut stages: Vec<usize> = Vec::with_capacity(runbook.steps.len());
+    for step in &runbook.steps {
+        let stage = step
+            .needs
+            .iter()
+            .filter_map(|need| runbook.steps.iter().position(|s| &s.name == need))
+            .map(|need| stages[need])
+            .max()
+            .unwrap_or(0);
+        stages.push(stage + 1);
+    }
+    stages
+}
+
+/// The slowest chain of steps by `estimates`, as step indexes, and how
+/// long it takes; steps without an estimate count as instant.
+fn longest_chain(runbook: &Runbook, estimates: &HashMap<String, f64>) -> (Vec<usize>, f64) {
+    let mut finish: Vec<f64> = Vec::with_capacity(runbook.steps.len());
+    let mut previous: Vec<Option<usize>> = Vec::with_capacity(runbook.steps.len());
+    for step in &runbook.steps {
+        let before = step
+            .needs
+            .iter()
+            .filter_map(|need| runbook.steps.iter().position(|s| &s.name == need))
+            .max_by(|&a, &b| finish[a].total_cmp(&finish[b]));
+        let own = estimates.get(&step.command).copied().unwrap_or_default();
+        finish.push(before.map_or(0.0, |i| finish[i]) + own);
+        previous.push(before);
+    }
+    let Some(last) = (0..finish.len()).max_by(|&a, &b| finish[a].total_cmp(&finish[b])) else {
+        return (Vec::new(), 0.0);
+    };
+    let (mut chain, mut step) = (vec![last], last);
+    while let Some(before) = previous[step] {
+        chain.push(before);
+        step = before;
+    }
+    chain.reverse();
+    (chain, finish[last])
+}
+
+pub fn format_plan(runbook: &Runbook, hosts: &[Host], estimates: &HashMap<String, f64>) -> String {
+    let names: Vec<&str> = hosts.iter().map(|h| h.host.as_str()).collect();
+    let mut text = format!(
+        "{} step(s) on {} host(s): {}\n\n",
+        runbook.steps.len(),
+        hosts.len(),
+        names.join(", ")
+    );
+
+    let stages = stages(runbook);
+    let mut order: Vec<usize> = (0..runbook.steps.len()).collect();
+    order.sort_by_key(|&i| stages[i]);
+    let rows: Vec<[String; 5]> = order
+        .iter()
+        .map(|&i| {
+            let step = &runbook.steps[i];
+            [
+                stages[i].to_string(),
+                match step.gate {
+                    Some(_) => format!("{} [gate]", step.name),
+                    None => step.name.clone(),
+                },
+                match step.needs.is_empty() {
+                    true => "-".to_string(),
+                    false => step.needs.join(", "),
+                },
+                estimates
+                    .get(&step.command)
+                    .map_or("?".to_string(), |secs| format!("~{:.1}s", secs)),
+                step.command.clone(),
+            ]
+        })
+        .collect();
+    let header = ["stage", "step", "needs", "estimate", "command"].map(String::from);
+    let mut widths = [0; 4];
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    for row in std::iter::once(&header).chain(&rows) {
+        for (width, cell) in widths.iter().zip(row) {
+            text.push_str(&format!("{:<width$}  ", cell, width = width));
+        }
+        text.push_str(&row[4]);
+        text.push('\n');
+    }
+
+    let (chain, total) = longest_chain(runbook, estimates);
+    let chain: Vec<&str> = chain
+        .iter()
+        .map(|&i| runbook.steps[i].name.as_str())
+        .collect();
+    text.push_str(&format!(
+        "\nEstimated ~{:.1}s per host, for {}.\n",
+        total,
+        chain.join(" -> ")
+    ));
+    let unknown: Vec<&str> = runbook
+        .steps
+        .iter()
+        .filter(|step| !estimates.contains_key(&step.command))
+        .map(|step| step.name.as_str())
+        .collect();
+    if !unknown.is_empty() {
+        text.push_str(&format!(
+            "No history for {}; counted as instant.\n",
+            unknown.join(", ")
+        ));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_plan() {
+        let runbook: Runbook = toml::from_str(
+            "[[steps]]\nname = \"drain\"\ncommand = \"drain.sh\"\n\
+             [[steps]]\nname = \"config\"\ncommand = \"cp app.conf /etc\"\n\
+             [[steps]]\nname = \"upgrade\"\ncommand = \"apt-get install app\"\nneeds = [\"drain\"]\n\
+             [[steps]]\nname = \"restart\"\ncommand = \"systemctl restart app\"\n\
+             needs = [\"upgrade\", \"config\"]\ngate = \"manual\"\n",
+        )
+        .unwrap();
+        let runbook = runbook.sorted().unwrap();
+        let estimates = HashMap::from([
+            ("drain.sh".to_string(), 0.3),
+            ("apt-get install app".to_string(), 12.0),
+            ("systemctl restart app".to_string(), 1.1),
+        ]);
+        assert_eq!(
+            format_plan(
+                &runbook,
+                &[Host::new("web01"), Host::new("web02")],
+                &estimates
+            ),
+            "4 step(s) on 2 host(s): web01, web02\n\
+             \n\
+             stage  step            needs            estimate  command\n\
+             1      drain           -                ~0.3s     drain.sh\n\
+             1      config          -                ?         cp app.conf /etc\n\
+             2      upgrade         drain            ~12.0s    apt-get install app\n\
+             3      restart [gate]  upgrade, config  ~1.1s     systemctl restart app\n\
+             \n\
+             Estimated ~13.4s per host, for drain -> upgrade -> restart.\n\
+             No history for config; counted as instant.\n"
+        );
+    }
+}
@@ -0,0 +1,26 @@
+//! The tokio runtime every run shares. Commands are tasks and their ssh
+//! processes are driven by async I/O, so a run with thousands of hosts
+//! needs a handful of worker threads instead of one (or three) per job.
+
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::{Builder, Runtime};
+
+static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+
+pub fn runtime() -> &'static Runtime {
+    RUNTIME.get_or_init(|| {
+        Builder::new_multi_thread()
+            .enable_all()
+            .thread_name("russh-worker")
+            .build()
+            .expect("could not start the tokio runtime")
+    })
+}
+
+/// Runs `future` to completion from synchronous code: the CLI, a
+/// library caller's thread or a blocking job (`tokio::task::spawn_blocking`).
+/// Must not be called from inside an async task.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    runtime().handle().block_on(future)
+}
@@ -0,0 +1,126 @@
+//! `russh script FILE [ARGS...]`: runs a local script on every host by
+//! piping it to the interpreter on the host's stdin (`sh -s`), so
+//! multi-line commands never need quoting and nothing is left behind on
+//! the hosts.
+
+use crate::cli::run::run;
+use crate::cli::{exec_options, inventory, schedule, Cli, ScriptArgs};
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::ssh::ExecOptions;
+use crate::warnings::{warn, WarningKind};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The interpreter used for scripts without a `#!` line.
+pub const DEFAULT_INTERPRETER: &str = "sh";
+
+/// A script read from a local file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Script {
+    pub text: String,
+    /// What runs it on the host: its `#!` line, or `sh`.
+    pub interpreter: String,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Script> {
+        let text = fs::read_to_string(path).map_err(|e| {
+            AppError::Generic(format!("could not read script {}: {}", path.display(), e))
+        })?;
+        Ok(Script::new(text))
+    }
+
+    pub fn new(text: String) -> Script {
+        let interpreter = text
+            .lines()
+            .next()
+            .and_then(|line| line.strip_prefix("#!"))
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .unwrap_or(DEFAULT_INTERPRETER)
+            .to_string();
+        Script { text, interpreter }
+    }
+
+    /// The program the `#!` line runs, looking past `env` and its options
+    /// in `/usr/bin/env -S python3 -u`, for hosts to be checked for; `None`
+    /// for `sh`, which every POSIX host has.
+    pub fn requires(&self) -> Option<&str> {
+        let mut words = self.interpreter.split_whitespace();
+        let first = words.next().unwrap_or(DEFAULT_INTERPRETER);
+        let program = match first.rsplit('/').next() {
+            Some("env") => words
+                .find(|word| !word.starts_with('-') && !word.contains('='))
+                .unwrap_or(first),
+            _ => first,
+        };
+        Some(program).filter(|program| program.rsplit('/').next() != Some(DEFAULT_INTERPRETER))
+    }
+
+    /// The remote command reading the script from stdin, with `args` as
+    /// its positional parameters.
+    pub fn command(&self, args: &[String]) -> String {
+        let mut command = format!("{} -s", self.interpreter);
+        if !args.is_empty() {
+            command.push_str(" -- ");
+            command.push_str(&shell_words::join(args));
+        }
+        command
+    }
+}
+
+/// `russh script`: runs a local script on every selected host.
+pub fn command(cli: &Cli, args: &ScriptArgs) -> Result<()> {
+    if cli.expect.is_some() {
+        return Err(ExecutionError::InvalidOption {
+            option: "--expect",
+            reason: "can't drive a script, its stdin is the script".to_string(),
+        }
+        .into());
+    }
+    let script = Script::load(&args.path)?;
+    let (config, hosts) = inventory(cli)?;
+    let command: Arc<str> = script.command(&args.args).into();
+    let jobs = hosts
+        .into_iter()
+        .filter(|host| {
+            let windows = host.shell().is_windows();
+            if windows {
+                warn(
+                    WarningKind::HostSkipped,
+                    format!("{}: scripts only run on POSIX hosts", host.host),
+                );
+            }
+            !windows
+        })
+        .map(|host| (Arc::new(host), Arc::clone(&command)))
+        .collect();
+    let options = ExecOptions {
+        requires: script.requires().map(str::to_string),
+        stdin: Some(script.text.into()),
+        ..exec_options(cli, &config)?
+    };
+    run(cli, &config, jobs, &options, schedule(cli, &config)?, None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command() {
+        let script = Script::new("#!/usr/bin/env bash\nset -e\napt-get update\n".to_string());
+        assert_eq!(script.interpreter, "/usr/bin/env bash");
+        assert_eq!(script.command(&[]), "/usr/bin/env bash -s");
+        assert_eq!(
+            script.command(&["--dry run".to_string(), "x".to_string()]),
+            "/usr/bin/env bash -s -- '--dry run' x"
+        );
+        assert_eq!(script.requires(), Some("bash"));
+        assert_eq!(Script::new("uptime\n".to_string()).command(&[]), "sh -s");
+        assert_eq!(Script::new("#!/bin/sh -e\n".to_string()).requires(), None);
+        let python = Script::new("#!/usr/bin/env -S python3 -u\n".to_string());
+        assert_eq!(python.requires(), Some("python3"));
+    }
+}
@@ -0,0 +1,772 @@
+//! `russh serve`: a small HTTP API on a local port, for dashboards and
+//! chat-ops bots. It lists the inventory, starts runs with the library
+//! [`Executor`], streams their output as server-sent events and returns
+//! the last run's report, and has a dashboard page following runs in a
+//! browser. Every API request needs the token from the `serve-token` file
+//! in the state directory as `Authorization: Bearer`.
+//!
+//! Like the rest of russh it avoids a web framework: requests are read
+//! with plain HTTP/1.1 parsing on one thread per connection, and every
+//! response closes the connection. Connections are limited in number,
+//! request and header lines in length, and a client that goes quiet for
+//! [`IO_TIMEOUT`] is dropped.
+//!
+//! | Method | Path | |
+//! |--------|------|-|
+//! | GET | `/` | the dashboard page, which asks for the token itself |
+//! | GET | `/inventory` | the served hosts |
+//! | POST | `/runs` | `{"commands": [...], "hosts": [...], "groups": [...], "approval": "..."}` starts a run |
+//! | GET | `/runs` | the runs started since the server came up |
+//! | GET | `/runs/<id>` | one run, with its results once it finished |
+//! | GET | `/runs/<id>/events` | `line`, `result` and `done` events as the run goes |
+//! | GET | `/history` | the last run's report, from any russh |
+
+use crate::approval;
+use crate::audit::{self, now};
+use crate::auth::{self, AuthHandler};
+use crate::cli::{inventory, require_client, Cli, ServeArgs};
+use crate::config::{Config, Host};
+use crate::errors::{AppError, ExecutionError, Result};
+use crate::executor::{parallelism, Executor};
+use crate::lock::{self, RunLock};
+use crate::paths;
+use crate::report::{self, RunReport};
+use crate::ssh::{self, Backend, ExecOptions, ServerResult};
+use crate::summary::Status;
+use crate::warnings::{warn, WarningKind};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+/// Where `russh serve` listens without `--listen`.
+pub const DEFAULT_LISTEN: &str = "127.0.0.1:7878";
+
+/// The token's file in the state directory.
+const TOKEN_FILE: &str = "serve-token";
+
+/// The dashboard, a single page using the API.
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+/// Largest request body taken.
+const MAX_BODY: usize = 1 << 20;
+
+/// Most header lines taken.
+const MAX_HEADERS: usize = 64;
+
+/// Longest request or header line taken, in bytes.
+const MAX_LINE: usize = 8 << 10;
+
+/// Most connections served at once; more are answered 503.
+const MAX_CONNECTIONS: usize = 64;
+
+/// How long a client may take to send its request, or to take a part of
+/// the response, before the connection is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often an idle event stream gets a comment, so a client that went
+/// away is noticed.
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+/// API runs can't stop to ask anyone anything.
+struct Decline;
+
+impl AuthHandler for Decline {}
+
+/// 256 bits from the OS's randomness, hex-encoded.
+fn new_token() -> Result<String> {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| AppError::Generic(format!("could not make a serve token: {}", e)))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// The API token, made on first use and only readable by the current
+/// user.
+pub fn token() -> Result<String> {
+    let path = paths::state_path(TOKEN_FILE)?;
+    match fs::read_to_string(&path) {
+        Ok(token) if !token.trim().is_empty() => {
+            check_private(&path)?;
+            return Ok(token.trim().to_string());
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    let token = new_token()?;
+    paths::write_private(&path, format!("{}\n", token).as_bytes())?;
+    Ok(token)
+}
+
+/// Refuses a token others could have read: it can't be trusted any more.
+#[cfg(unix)]
+fn check_private(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = fs::metadata(path)?.permissions().mode();
+    if mode & 0o077 != 0 {
+        return Err(AppError::Generic(format!(
+            "{} is readable by other users (mode {:o}); delete it to make a new token",
+            path.display(),
+            mode & 0o777
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// Compares without stopping at the first difference, so response times
+/// don't give the token away.
+fn same(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (x, y)| acc | (x ^ y))
+            == 0
+}
+
+#[derive(Debug, PartialEq)]
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn authorized(&self, token: &str) -> bool {
+        self.header("authorization")
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .is_some_and(|given| same(given.trim(), token))
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    body: Value,
+}
+
+fn respond(status: u16, body: Value) -> Response {
+    Response { status, body }
+}
+
+fn error(status: u16, message: impl Into<String>) -> Response {
+    respond(status, json!({ "error": message.into() }))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Reads one request; `Err` is the response refusing it.
+fn read_request(reader: &mut impl BufRead) -> std::result::Result<Request, Response> {
+    let bad = |message: &str| error(400, message);
+    let mut line = String::new();
+    let mut next_line = |line: &mut String| {
+        line.clear();
+        match reader.by_ref().take(MAX_LINE as u64).read_line(line) {
+            Ok(0) | Err(_) => Err(bad("incomplete request")),
+            Ok(_) if !line.ends_with('\n') => Err(error(
+                431,
+                format!("lines are limited to {} bytes", MAX_LINE),
+            )),
+            Ok(_) => Ok(line.trim_end_matches(['\r', '\n']).to_string()),
+        }
+    };
+    let request_line = next_line(&mut line)?;
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(path)) = (parts.next(), parts.next()) else {
+        return Err(bad("malformed request line"));
+    };
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut headers = Vec::new();
+    loop {
+        let header = next_line(&mut line)?;
+        if header.is_empty() {
+            break;
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(bad("too many headers"));
+        }
+        let Some((name, value)) = header.split_once(':') else {
+            return Err(bad("malformed header"));
+        };
+        headers.push((name.trim().to_string(), value.trim().to_string()));
+    }
+    let mut request = Request {
+        method,
+        path,
+        headers,
+        body: Vec::new(),
+    };
+    let length = match request.header("content-length") {
+        Some(length) => length
+            .parse::<usize>()
+            .map_err(|_| bad("invalid Content-Length"))?,
+        None => 0,
+    };
+    if length > MAX_BODY {
+        return Err(error(
+            413,
+            format!("bodies are limited to {} bytes", MAX_BODY),
+        ));
+    }
+    request.body = vec![0; length];
+    reader
+        .read_exact(&mut request.body)
+        .map_err(|_| bad("incomplete body"))?;
+    Ok(request)
+}
+
+fn write_response(stream: &mut TcpStream, response: &Response) -> io::Result<()> {
+    let body = serde_json::to_vec_pretty(&response.body).unwrap_or_default();
+    write!(
+        stream,
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        body.len()
+    )?;
+    stream.write_all(&body)?;
+    stream.flush()
+}
+
+/// Sends the dashboard. The page holds no data of its own, so it needs no
+/// token; it asks for one and sends it with its API requests.
+fn write_dashboard(stream: &mut TcpStream) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\
+         Content-Security-Policy: default-src 'self'; script-src 'unsafe-inline'; style-src 'unsafe-inline'\r\n\
+         X-Frame-Options: DENY\r\nConnection: close\r\n\r\n",
+        DASHBOARD.len()
+    )?;
+    stream.write_all(DASHBOARD.as_bytes())?;
+    stream.flush()
+}
+
+/// Something that happened in a run, as sent on its event stream.
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event {
+    /// An output line; `stream` is `stdout` or `stderr`.
+    Line {
+        host: String,
+        stream: &'static str,
+        line: String,
+    },
+    /// A command finished on a host.
+    Result {
+        command: String,
+        #[serde(flatten)]
+        result: ServerResult,
+    },
+    /// The run finished.
+    Done { ok: usize, failed: usize },
+}
+
+impl Event {
+    fn name(&self) -> &'static str {
+        match self {
+            Event::Line { .. } => "line",
+            Event::Result { .. } => "result",
+            Event::Done { .. } => "done",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Progress {
+    events: Vec<Event>,
+    results: Option<Vec<(String, ServerResult)>>,
+}
+
+/// A run started through the API.
+struct Run {
+    id: usize,
+    started: u64,
+    commands: Vec<String>,
+    hosts: Vec<String>,
+    progress: Mutex<Progress>,
+    updated: Condvar,
+}
+
+impl Run {
+    fn push(&self, events: impl IntoIterator<Item = Event>) {
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.events.extend(events);
+        self.updated.notify_all();
+    }
+
+    fn finish(&self, results: Vec<(String, ServerResult)>, events: Vec<Event>) {
+        let mut progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        progress.results = Some(results);
+        progress.events.extend(events);
+        self.updated.notify_all();
+    }
+
+    fn summary(&self, with_results: bool) -> Value {
+        let progress = self.progress.lock().unwrap_or_else(|e| e.into_inner());
+        let mut summary = json!({
+            "id": self.id,
+            "started": self.started,
+            "commands": self.commands,
+            "hosts": self.hosts,
+            "finished": progress.results.is_some(),
+        });
+        if let (true, Some(results)) = (with_results, &progress.results) {
+            summary["results"] = results
+                .iter()
+                .map(|(command, result)| {
+                    let mut entry = json!(result);
+                    entry["command"] = json!(command);
+                    entry
+                })
+                .collect();
+        }
+        summary
+    }
+}
+
+/// What `POST /runs` takes.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RunRequest {
+    commands: Vec<String>,
+    /// Served hosts to run on, by name; all of them when empty.
+    #[serde(default)]
+    hosts: Vec<String>,
+    /// Config groups to run on, within the served hosts.
+    #[serde(default)]
+    groups: Vec<String>,
+    /// Token for protected hosts, as `--approval`.
+    approval: Option<String>,
+}
+
+pub struct Server {
+    config: Config,
+    hosts: Vec<Host>,
+    parallel: usize,
+    token: String,
+    runs: Mutex<Vec<Arc<Run>>>,
+}
+
+impl Server {
+    /// Serves `hosts` of `config`, running `parallel` jobs at once.
+    pub fn new(config: Config, hosts: Vec<Host>, parallel: usize) -> Result<Server> {
+        Ok(Server {
+            config,
+            hosts,
+            parallel,
+            token: token()?,
+            runs: Mutex::new(Vec::new()),
+        })
+    }
+
+    /// Answers requests on `addr` until the process ends.
+    pub fn listen(self, addr: SocketAddr) -> Result<()> {
+        if !addr.ip().is_loopback() {
+            warn(
+                WarningKind::IgnoredOption,
+                format!(
+                    "{} is reachable from other machines; anyone there with the token can run commands",
+                    addr
+                ),
+            );
+        }
+        let listener = TcpListener::bind(addr)
+            .map_err(|e| AppError::Generic(format!("could not listen on {}: {}", addr, e)))?;
+        eprintln!(
+            "Serving {} host(s) on http://{}/, token in {}",
+            self.hosts.len(),
+            listener.local_addr()?,
+            paths::state_path(TOKEN_FILE)?.display()
+        );
+        let server = Arc::new(self);
+        let connections = Arc::new(AtomicUsize::new(0));
+        for mut stream in listener.incoming().flatten() {
+            if stream.set_read_timeout(Some(IO_TIMEOUT)).is_err()
+                || stream.set_write_timeout(Some(IO_TIMEOUT)).is_err()
+            {
+                continue;
+            }
+            let slot = Slot::take(&connections);
+            if slot.is_none() {
+                let _ = write_response(&mut stream, &error(503, "too many connections"));
+                continue;
+            }
+            let server = Arc::clone(&server);
+            thread::spawn(move || {
+                let _slot = slot;
+                server.handle(stream)
+            });
+        }
+        Ok(())
+    }
+
+    fn handle(&self, mut stream: TcpStream) {
+        let Ok(reader) = stream.try_clone() else {
+            return;
+        };
+        let request = match read_request(&mut BufReader::new(reader)) {
+            Ok(request) => request,
+            Err(response) => {
+                let _ = write_response(&mut stream, &response);
+                return;
+            }
+        };
+        if request.method == "GET" && matches!(request.path.as_str(), "/" | "/index.html") {
+            let _ = write_dashboard(&mut stream);
+            return;
+        }
+        if !request.authorized(&self.token) {
+            let _ = write_response(&mut stream, &error(401, "missing or wrong token"));
+            return;
+        }
+        let segments: Vec<&str> = request
+            .path
+            .split('?')
+            .next()
+            .unwrap_or_default()
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .collect();
+        let response = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["inventory"]) => respond(200, json!({ "hosts": self.hosts })),
+            ("GET", ["runs"]) => {
+                let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+                respond(200, runs.iter().map(|run| run.summary(false)).collect())
+            }
+            ("POST", ["runs"]) => self.start(&request.body),
+            ("GET", ["runs", id]) => match self.run(id) {
+                Some(run) => respond(200, run.summary(true)),
+                None => error(404, format!("no run {}", id)),
+            },
+            ("GET", ["runs", id, "events"]) => match self.run(id) {
+                Some(run) => {
+                    let _ = stream_events(&mut stream, &run);
+                    return;
+                }
+                None => error(404, format!("no run {}", id)),
+            },
+            ("GET", ["history"]) => match report::load_last() {
+                Ok(report) => respond(200, json!(report)),
+                Err(e) => error(404, e.to_string()),
+            },
+            (_, ["inventory" | "runs" | "history", ..]) => error(405, "method not allowed"),
+            _ => error(404, "not found"),
+        };
+        let _ = write_response(&mut stream, &response);
+    }
+
+    fn run(&self, id: &str) -> Option<Arc<Run>> {
+        let id: usize = id.parse().ok()?;
+        let runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        runs.iter().find(|run| run.id == id).cloned()
+    }
+
+    /// The served hosts `request` picks.
+    fn select(&self, request: &RunRequest) -> Result<Vec<Host>> {
+        let mut hosts = self.hosts.clone();
+        if !request.groups.is_empty() {
+            let members = self.config.group_hosts(&request.groups)?;
+            hosts.retain(|host| members.iter().any(|m| m.host == host.host));
+        }
+        if !request.hosts.is_empty() {
+            if let Some(unknown) = request
+                .hosts
+                .iter()
+                .find(|name| !self.hosts.iter().any(|h| &h.host == *name))
+            {
+                return Err(ExecutionError::InvalidOption {
+                    option: "hosts",
+                    reason: format!("{} is not a served host", unknown),
+                }
+                .into());
+            }
+            hosts.retain(|host| request.hosts.contains(&host.host));
+        }
+        Ok(hosts)
+    }
+
+    /// `POST /runs`: checks the request like the CLI checks a run, then
+    /// runs it in the background.
+    fn start(&self, body: &[u8]) -> Response {
+        let request: RunRequest = match serde_json::from_slice(body) {
+            Ok(request) => request,
+            Err(e) => return error(400, format!("invalid run request: {}", e)),
+        };
+        if request.commands.is_empty() {
+            return error(400, "no commands to run");
+        }
+        let hosts = match self.select(&request) {
+            Ok(hosts) if hosts.is_empty() => return error(400, "no hosts selected"),
+            Ok(hosts) => hosts,
+            Err(e) => return error(400, e.to_string()),
+        };
+        let admitted = admit(
+            &self.config,
+            &hosts,
+            &request.commands,
+            request.approval.as_deref(),
+            "api_run",
+        );
+        let lock = match admitted {
+            Ok(lock) => lock,
+            Err(AppError::Execution(ExecutionError::Refused(message))) => {
+                return error(403, message)
+            }
+            Err(e) => return error(400, e.to_string()),
+        };
+        let names: Vec<String> = hosts.iter().map(|h| h.host.clone()).collect();
+
+        let mut runs = self.runs.lock().unwrap_or_else(|e| e.into_inner());
+        let run = Arc::new(Run {
+            id: runs.len() + 1,
+            started: now(),
+            commands: request.commands.clone(),
+            hosts: names,
+            progress: Mutex::new(Progress::default()),
+            updated: Condvar::new(),
+        });
+        runs.push(Arc::clone(&run));
+        drop(runs);
+
+        let id = run.id;
+        let executor = batch_executor(&self.config, &hosts, request.commands, self.parallel);
+        thread::spawn(move || {
+            let _lock = lock;
+            let mut running = executor.start();
+            for event in running.by_ref() {
+                let (host, stream, line) = match event {
+                    ssh::Event::StdoutLine { server, line } => (server, "stdout", line),
+                    ssh::Event::StderrLine { server, line } => (server, "stderr", line),
+                    ssh::Event::Completed { .. } => continue,
+                };
+                run.push([Event::Line { host, stream, line }]);
+            }
+            let results = running.finish();
+            if let Err(e) = report::save(&RunReport::new(&hosts, &results)) {
+                warn(
+                    WarningKind::Delivery,
+                    format!("could not save the run report: {}", e),
+                );
+            }
+            let failed = results
+                .iter()
+                .filter(|(_, r)| Status::of(r) == Status::Failed)
+                .count();
+            let mut events: Vec<Event> = results
+                .iter()
+                .map(|(command, result)| Event::Result {
+                    command: command.clone(),
+                    result: result.clone(),
+                })
+                .collect();
+            events.push(Event::Done {
+                ok: results.len() - failed,
+                failed,
+            });
+            run.finish(results, events);
+        });
+        respond(202, json!({ "id": id }))
+    }
+}
+
+/// One of the [`MAX_CONNECTIONS`] connections served at once, given back
+/// when dropped.
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(connections: &Arc<AtomicUsize>) -> Option<Slot> {
+        connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| {
+                (open < MAX_CONNECTIONS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Slot(Arc::clone(connections)))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Checks a run started from outside the CLI the way the CLI checks its
+/// own: protected hosts need `approval`, and the run takes the run lock,
+/// which it holds until the returned guard is dropped. Admitted runs go to
+/// the audit log as `event`.
+pub(crate) fn admit(
+    config: &Config,
+    hosts: &[Host],
+    commands: &[String],
+    approval: Option<&str>,
+    event: &str,
+) -> Result<RunLock> {
+    approval::check(hosts, commands, approval)?;
+    let lock = RunLock::acquire(&lock::target(config, hosts), false)?;
+    let names: Vec<&str> = hosts.iter().map(|h| h.host.as_str()).collect();
+    audit::record(event, json!({ "hosts": names, "commands": commands }))?;
+    Ok(lock)
+}
+
+/// An executor for runs nobody watches at a terminal: ssh in batch mode
+/// and nothing asked of the auth handler, so nothing can stop to prompt.
+pub(crate) fn batch_executor(
+    config: &Config,
+    hosts: &[Host],
+    commands: Vec<String>,
+    parallel: usize,
+) -> Executor {
+    let mut options = ExecOptions {
+        retries: config.retries.unwrap_or_default(),
+        auth: auth::Handler::new(Decline),
+        ..ExecOptions::default()
+    };
+    if let Some(delay) = config.retry_delay {
+        options.retry_delay = Duration::from_secs_f64(delay);
+    }
+    let hosts = hosts.iter().cloned().map(|mut host| {
+        host.extra_options
+            .extend(["-o".to_string(), "BatchMode=yes".to_string()]);
+        host
+    });
+    Executor::new()
+        .hosts(hosts)
+        .commands(commands)
+        .parallel(parallel)
+        .options(options)
+}
+
+/// Sends `run`'s events as server-sent events, from its first one until
+/// it is done.
+fn stream_events(stream: &mut TcpStream, run: &Run) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    let mut sent = 0;
+    loop {
+        let progress = run.progress.lock().unwrap_or_else(|e| e.into_inner());
+        let (progress, _) = run
+            .updated
+            .wait_timeout_while(progress, KEEPALIVE, |p| p.events.len() == sent)
+            .unwrap_or_else(|e| e.into_inner());
+        let new: Vec<Event> = progress.events[sent..].to_vec();
+        drop(progress);
+        let done = new.iter().any(|e| matches!(e, Event::Done { .. }));
+        if new.is_empty() {
+            stream.write_all(b": keepalive\n\n")?;
+        }
+        for event in &new {
+            let data = serde_json::to_string(event).unwrap_or_default();
+            write!(stream, "event: {}\ndata: {}\n\n", event.name(), data)?;
+        }
+        stream.flush()?;
+        sent += new.len();
+        if done {
+            return Ok(());
+        }
+    }
+}
+
+/// `russh serve`: serves the selected hosts over HTTP until killed.
+pub fn command(cli: &Cli, args: &ServeArgs) -> Result<()> {
+    let (config, hosts) = inventory(cli)?;
+    let parallel = parallelism(cli.parallel, Some(&config))?;
+    let listen = match args.listen {
+        Some(listen) => listen,
+        None => DEFAULT_LISTEN
+            .parse()
+            .map_err(|e| AppError::Generic(format!("{}", e)))?,
+    };
+    require_client(Backend::OpenSsh)?;
+    Server::new(config, hosts, parallel)?.listen(listen)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_request() {
+        let raw = "POST /runs HTTP/1.1\r\nHost: localhost\r\nAuthorization: Bearer s3cret\r\n\
+                   Content-Length: 24\r\n\r\n{\"commands\": [\"uptime\"]}";
+        let request = read_request(&mut raw.as_bytes()).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.path, "/runs");
+        assert!(request.authorized("s3cret"));
+        assert!(!request.authorized("s3cre"));
+        assert!(!request.authorized("other!"));
+        let run: RunRequest = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(run.commands, ["uptime"]);
+
+        let unauthenticated = read_request(&mut "GET /inventory HTTP/1.1\r\n\r\n".as_bytes());
+        assert!(!unauthenticated.unwrap().authorized("s3cret"));
+        let huge = format!(
+            "POST /runs HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(
+            read_request(&mut huge.as_bytes()).err().unwrap().status,
+            413
+        );
+        assert_eq!(
+            read_request(&mut "GET /runs HTTP/1.1\r\n".as_bytes())
+                .err()
+                .unwrap()
+                .status,
+            400
+        );
+        let token = new_token().unwrap();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, new_token().unwrap());
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(
+            read_request(&mut long.as_bytes()).err().unwrap().status,
+            431
+        );
+        let connections = Arc::new(AtomicUsize::new(0));
+        let slots: Vec<Slot> = (0..MAX_CONNECTIONS)
+            .map_while(|_| Slot::take(&connections))
+            .collect();
+        assert_eq!(slots.len(), MAX_CONNECTIONS);
+        assert!(Slot::take(&connections).is_none());
+        drop(slots);
+        assert!(Slot::take(&connections).is_some());
+    }
+}
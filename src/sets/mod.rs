@@ -0,0 +1,226 @@
+//! Host sets: named selections saved in the state directory with
+//! `russh set save`, from the hosts that failed in the last run or the ones
+//! the selection flags pick, and targeted again with `--set NAME`. They sit
+//! between the config's groups and retyping the same flags every time.
+
+use crate::cli::{filter_hosts, inventory, load_config, Cli, SetAction, SetArgs};
+use crate::config::{Config, Host};
+use crate::errors::{AppError, ExecutionError, InventoryError, Result};
+use crate::{paths, report};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// The sets' file name in the state directory.
+pub const SETS: &str = "sets.json";
+
+/// Saved sets by name.
+pub type Sets = BTreeMap<String, Vec<Host>>;
+
+pub fn sets_path() -> Result<PathBuf> {
+    paths::state_path(SETS)
+}
+
+pub fn load() -> Result<Sets> {
+    let path = sets_path()?;
+    if !path.exists() {
+        return Ok(Sets::new());
+    }
+    serde_json::from_slice(&fs::read(&path)?)
+        .map_err(|e| AppError::Generic(format!("corrupt host sets {}: {}", path.display(), e)))
+}
+
+/// Applies `change` to the sets with them locked, so two operators saving
+/// sets at once keep both.
+fn update<T>(change: impl FnOnce(&mut Sets) -> T) -> Result<T> {
+    let path = sets_path()?;
+    let _lock = paths::lock(&path)?;
+    let mut sets = load()?;
+    let changed = change(&mut sets);
+    let json = serde_json::to_vec_pretty(&sets).map_err(|e| AppError::Generic(e.to_string()))?;
+    paths::write_atomic(&path, &json)?;
+    Ok(changed)
+}
+
+/// Hosts as they are saved: a configured host the config still resolves
+/// the same way is kept by name only, so later changes to the config
+/// apply to it; an ad-hoc one keeps all of its attributes.
+fn stored(config: &Config, hosts: Vec<Host>) -> Vec<Host> {
+    hosts
+        .into_iter()
+        .map(|host| match host == config.host(&host.host) {
+            true => Host::new(&host.host),
+            false => host,
+        })
+        .collect()
+}
+
+/// Saves `hosts` as `name`, replacing a set of that name; returns whether
+/// one was replaced.
+pub fn save(config: &Config, name: &str, hosts: Vec<Host>) -> Result<bool> {
+    let hosts = stored(config, hosts);
+    update(|sets| sets.insert(name.to_string(), hosts).is_some())
+}
+
+/// Deletes `name`; returns whether it existed.
+pub fn delete(name: &str) -> Result<bool> {
+    update(|sets| sets.remove(name).is_some())
+}
+
+/// The hosts of the sets `names`, with the config filling in what a saved
+/// host leaves out.
+pub fn hosts(config: &Config, names: &[String]) -> Result<Vec<Host>> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    let sets = load()?;
+    let mut hosts = Vec::new();
+    for name in names {
+        let set = sets.get(name).ok_or_else(|| InventoryError::UnknownSet {
+            name: name.clone(),
+            known: match sets.is_empty() {
+                true => "none".to_string(),
+                false => sets.keys().cloned().collect::<Vec<_>>().join(", "),
+            },
+        })?;
+        hosts.extend(
+            set.iter()
+                .map(|host| host.clone().with_defaults(&config.host(&host.host))),
+        );
+    }
+    Ok(hosts)
+}
+
+#[derive(Serialize)]
+struct Listed<'a> {
+    name: &'a str,
+    hosts: Vec<&'a str>,
+}
+
+pub fn print(sets: &Sets, json: bool) -> Result<()> {
+    let listed: Vec<Listed> = sets
+        .iter()
+        .map(|(name, hosts)| Listed {
+            name,
+            hosts: hosts.iter().map(|h| h.host.as_str()).collect(),
+        })
+        .collect();
+    if json {
+        let json =
+            serde_json::to_string_pretty(&listed).map_err(|e| AppError::Generic(e.to_string()))?;
+        println!("{}", json);
+        return Ok(());
+    }
+    if listed.is_empty() {
+        println!("No host sets saved.");
+    }
+    for set in listed {
+        println!(
+            "{} ({}): {}",
+            set.name,
+            set.hosts.len(),
+            set.hosts.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// `russh set`: saves the current selection or the last run's failed hosts
+/// under a name, lists the sets or deletes one.
+pub fn command(cli: &Cli, args: &SetArgs) -> Result<()> {
+    match &args.action {
+        SetAction::Save(args) => {
+            if args.name.is_empty() || args.name.contains(char::is_whitespace) {
+                return Err(ExecutionError::InvalidOption {
+                    option: "set save",
+                    reason: format!("'{}' is not a valid set name", args.name),
+                }
+                .into());
+            }
+            let config = load_config(&cli.config_file, true)?;
+            let hosts = if args.from_last_failed {
+                let mut hosts: Vec<Host> = Vec::new();
+                for (host, _) in report::load_last()?.failed_jobs() {
+                    if !hosts.iter().any(|h| h.host == host.host) {
+                        hosts.push(host);
+                    }
+                }
+                if hosts.is_empty() {
+                    return Err(ExecutionError::Refused(
+                        "nothing failed in the last run".to_string(),
+                    )
+                    .into());
+                }
+                filter_hosts(cli, hosts)?
+            } else {
+                let picked = [
+                    cli.hosts.is_empty(),
+                    cli.groups.is_empty(),
+                    cli.sets.is_empty(),
+                    cli.only.is_empty(),
+                    cli.exclude.is_empty(),
+                    cli.tags.is_empty(),
+                ]
+                .contains(&false);
+                if !picked {
+                    return Err(ExecutionError::Refused(
+                        "no hosts picked; pass --from-last-failed, or -H, --group, --set, \
+                         --hosts, --exclude or --tags before `set`"
+                            .to_string(),
+                    )
+                    .into());
+                }
+                inventory(cli)?.1
+            };
+            let count = hosts.len();
+            let verb = match save(&config, &args.name, hosts)? {
+                true => "Replaced",
+                false => "Saved",
+            };
+            println!("{} set '{}' with {} host(s)", verb, args.name, count);
+            Ok(())
+        }
+        SetAction::List(args) => print(&load()?, args.json),
+        SetAction::Delete(args) => {
+            if !delete(&args.name)? {
+                return Err(
+                    ExecutionError::Refused(format!("there is no set '{}'", args.name)).into(),
+                );
+            }
+            println!("Deleted set '{}'", args.name);
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stored() {
+        let config: Config = toml::from_str(
+            r#"
+            servers = [{ host = "web01", port = 2222 }]
+            [users]
+            web01 = "deploy"
+            "#,
+        )
+        .unwrap();
+        let adhoc = "db01 port=2200".parse::<Host>().unwrap();
+        let hosts = stored(&config, vec![config.host("web01"), adhoc.clone()]);
+        assert_eq!(hosts, [Host::new("web01"), adhoc]);
+
+        // A configured host picked with inline attributes keeps them.
+        let custom = "web01 port=22"
+            .parse::<Host>()
+            .unwrap()
+            .with_defaults(&config.host("web01"));
+        let hosts = stored(&config, vec![custom.clone()]);
+        assert_eq!(hosts, [custom]);
+        let back = hosts[0].clone().with_defaults(&config.host("web01"));
+        assert_eq!(back.port, Some(22));
+        assert_eq!(back.user.as_deref(), Some("deploy"));
+    }
+}
@@ -0,0 +1,128 @@
+//! A JSON Lines file growing while the run goes on: an object per output
+//! line (`stdout_line`, `stderr_line`) and finished job (`completed`) as
+//! they happen, and once the run is over one per job with its whole result
+//! (`result`, in job order) and one for the run (`run`, with its labels and
+//! counts). Each has the Unix `time` it was written at.
+
+use super::ResultSink;
+use crate::audit::{current_user, now};
+use crate::errors::{AppError, Result};
+use crate::history::{Labels, Run};
+use crate::output::Record;
+use crate::ssh::{Event, ServerResult};
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Item<'a> {
+    Result(Record<'a>),
+    /// Carries its own `time`.
+    Run(Run),
+    /// Tagged the same way already.
+    #[serde(untagged)]
+    Event(&'a Event),
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time: Option<u64>,
+    #[serde(flatten)]
+    item: Item<'a>,
+}
+
+pub struct Jsonl {
+    path: PathBuf,
+    /// Opened on the first line, so a run with nothing to say leaves no
+    /// file behind.
+    file: Option<File>,
+}
+
+impl Jsonl {
+    pub fn new(path: PathBuf) -> Jsonl {
+        Jsonl { path, file: None }
+    }
+
+    fn write(&mut self, item: Item) -> Result<()> {
+        let time = match item {
+            Item::Run(_) => None,
+            _ => Some(now()),
+        };
+        let mut line = serde_json::to_vec(&Line { time, item })
+            .map_err(|e| AppError::Generic(e.to_string()))?;
+        line.push(b'\n');
+        let file = match &mut self.file {
+            Some(file) => file,
+            None => {
+                if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+                    fs::create_dir_all(parent)?;
+                }
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?;
+                self.file.insert(file)
+            }
+        };
+        // One write per line, so lines from runs sharing the file don't mix.
+        file.write_all(&line)?;
+        Ok(())
+    }
+}
+
+impl ResultSink for Jsonl {
+    fn streams(&self) -> bool {
+        true
+    }
+
+    fn event(&mut self, event: &Event) -> Result<()> {
+        self.write(Item::Event(event))
+    }
+
+    fn finish(&mut self, results: &[(String, ServerResult)], labels: &Labels) -> Result<()> {
+        for (command, result) in results {
+            self.write(Item::Result(Record { command, result }))?;
+        }
+        let run = Run {
+            time: now(),
+            user: current_user(),
+            ..Run::of(labels, results)
+        };
+        self.write(Item::Run(run))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::Cut;
+
+    #[test]
+    fn test_jsonl_lines() {
+        let dir = std::env::temp_dir().join(format!("russh-jsonl-{}", std::process::id()));
+        let path = dir.join("runs.jsonl");
+        let mut sink = Jsonl::new(path.clone());
+        let result = ServerResult::skipped("web01", Cut::FailFast);
+        sink.event(&Event::stdout("web01", "up 3 days")).unwrap();
+        sink.event(&Event::completed(&result)).unwrap();
+        sink.finish(&[("uptime".to_string(), result)], &Labels::new())
+            .unwrap();
+
+        let text = fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        let events: Vec<&str> = lines.iter().map(|l| l["event"].as_str().unwrap()).collect();
+        assert_eq!(events, ["stdout_line", "completed", "result", "run"]);
+        assert_eq!(lines[0]["line"], "up 3 days");
+        assert_eq!(lines[2]["command"], "uptime");
+        assert_eq!(lines[2]["server"], "web01");
+        assert_eq!(lines[3]["skipped"], 1);
+        assert!(lines.iter().all(|line| line["time"].as_u64() > Some(0)));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
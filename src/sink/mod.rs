@@ -0,0 +1,368 @@
+//! Where a run's results go besides the live display: the console summary,
+//! `--report` files, per-host logs, JSONL and SQLite files, the webhook and
+//! the S3 archive. Each is a [`ResultSink`] fed from a thread and a queue of
+//! its own, so a slow one holds up the run only once its queue is full and
+//! never the other sinks; one that fails is warned about and gets nothing
+//! more for the rest of the run.
+
+mod jsonl;
+mod sqlite;
+
+pub use self::jsonl::Jsonl;
+pub use self::sqlite::Sqlite;
+
+use crate::archive::{self, ArchiveConfig};
+use crate::capability::{self, Capability};
+use crate::errors::Result;
+use crate::group;
+use crate::history::Labels;
+use crate::logs;
+use crate::output::{self, OutputFormat};
+use crate::report::{self, ReportSpec};
+use crate::ssh::{Event, ServerResult};
+use crate::summary;
+use crate::warnings::{warn, WarningKind};
+use crate::webhook::{Webhook, WebhookConfig};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+/// Messages waiting for a sink before the run's workers block.
+pub const DEFAULT_QUEUE_SIZE: usize = 64;
+
+/// A destination for what a run produces. Every method but `finish` is
+/// called while the run goes on, from the sink's own thread.
+pub trait ResultSink: Send {
+    /// Whether it wants every output line; the others only get results.
+    fn streams(&self) -> bool {
+        false
+    }
+
+    /// Messages waiting for it before the run's workers block.
+    fn queue_size(&self) -> usize {
+        DEFAULT_QUEUE_SIZE
+    }
+
+    /// A line of output or a finished job, for sinks that stream.
+    fn event(&mut self, _event: &Event) -> Result<()> {
+        Ok(())
+    }
+
+    /// A job is done.
+    fn result(&mut self, _command: &str, _result: &ServerResult) -> Result<()> {
+        Ok(())
+    }
+
+    /// The run is over; `results` are all of its jobs, in order.
+    fn finish(&mut self, _results: &[(String, ServerResult)], _labels: &Labels) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// `[sinks.NAME]` config section: one more place every run goes to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SinkConfig {
+    /// Per-host logs in a new directory under `dir` for every run.
+    LogDir { dir: PathBuf },
+    /// Every output line and result as a JSON object, appended to `path`.
+    Jsonl { path: PathBuf },
+    /// A row per job in the `results` table of the SQLite database at
+    /// `path`, written by the `sqlite3` program.
+    Sqlite { path: PathBuf },
+    /// Each job's result POSTed as it completes, as with `[webhook]`.
+    Webhook(WebhookConfig),
+    /// The run's logs uploaded to a bucket, as with `[archive]`.
+    S3(ArchiveConfig),
+}
+
+impl SinkConfig {
+    /// The sink, if `--offline` allows it.
+    pub fn open(&self) -> Result<Box<dyn ResultSink>> {
+        Ok(match self {
+            SinkConfig::LogDir { dir } => Box::new(LogDir(dir.clone())),
+            SinkConfig::Jsonl { path } => Box::new(Jsonl::new(path.clone())),
+            SinkConfig::Sqlite { path } => Box::new(Sqlite::new(path.clone())),
+            SinkConfig::Webhook(config) => {
+                capability::check(Capability::Webhooks)?;
+                Box::new(Webhook::new(config.clone()))
+            }
+            SinkConfig::S3(config) => {
+                capability::check(Capability::Archive)?;
+                Box::new(Archive {
+                    config: config.clone(),
+                    log_dir: None,
+                })
+            }
+        })
+    }
+}
+
+/// The summary, or the JSON document for `--output json`, printed once
+/// the run is over; `--group-output` puts the grouped output first. The
+/// output while the run goes on is the display's.
+pub struct Console {
+    pub format: OutputFormat,
+    pub group: bool,
+}
+
+impl ResultSink for Console {
+    fn finish(&mut self, results: &[(String, ServerResult)], _labels: &Labels) -> Result<()> {
+        if self.group {
+            print!(
+                "{}",
+                group::format_groups(&group::groups(results), summary::color_enabled())
+            );
+        }
+        output::report(results, self.format)
+    }
+}
+
+/// A `--report` file.
+pub struct ReportFile(pub ReportSpec);
+
+impl ResultSink for ReportFile {
+    fn finish(&mut self, results: &[(String, ServerResult)], labels: &Labels) -> Result<()> {
+        report::write(&self.0, results, labels)
+    }
+}
+
+/// Per-host logs under a directory, see [`crate::logs`].
+pub struct LogDir(pub PathBuf);
+
+impl ResultSink for LogDir {
+    fn finish(&mut self, results: &[(String, ServerResult)], labels: &Labels) -> Result<()> {
+        logs::write(&self.0, results, labels).map(drop)
+    }
+}
+
+/// Uploads to a bucket, see [`crate::archive`]; the logs are also kept in
+/// `log_dir` when there is one.
+pub struct Archive {
+    pub config: ArchiveConfig,
+    pub log_dir: Option<PathBuf>,
+}
+
+impl ResultSink for Archive {
+    fn finish(&mut self, results: &[(String, ServerResult)], labels: &Labels) -> Result<()> {
+        archive::store(&self.config, self.log_dir.as_deref(), results, labels).map(drop)
+    }
+}
+
+enum Message {
+    Event(Event),
+    Result(String, ServerResult),
+    Finish(Arc<[(String, ServerResult)]>, Arc<Labels>),
+}
+
+/// A sink's queue, and whether it takes output lines.
+#[derive(Clone)]
+struct Queue {
+    tx: SyncSender<Message>,
+    streams: bool,
+}
+
+/// The sinks of a run, each started on its own thread.
+pub struct Sinks {
+    queues: Vec<Queue>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl Sinks {
+    /// Starts every sink, named for its warnings.
+    pub fn start(sinks: Vec<(String, Box<dyn ResultSink>)>) -> Sinks {
+        let mut queues = Vec::with_capacity(sinks.len());
+        let mut handles = Vec::with_capacity(sinks.len());
+        for (name, sink) in sinks {
+            let (tx, rx) = mpsc::sync_channel(sink.queue_size().max(1));
+            queues.push(Queue {
+                tx,
+                streams: sink.streams(),
+            });
+            handles.push(thread::spawn(move || deliver(&name, sink, rx)));
+        }
+        Sinks { queues, handles }
+    }
+
+    pub fn sender(&self) -> SinkSender {
+        SinkSender {
+            queues: self.queues.clone(),
+        }
+    }
+
+    /// Whether any sink wants the output lines.
+    pub fn streams(&self) -> bool {
+        self.queues.iter().any(|queue| queue.streams)
+    }
+
+    /// Hands every sink the whole run and waits for all of them to be done
+    /// with it (or to have failed).
+    pub fn finish(self, results: &[(String, ServerResult)], labels: &Labels) {
+        let results: Arc<[(String, ServerResult)]> = results.into();
+        let labels = Arc::new(labels.clone());
+        for queue in self.queues {
+            let _ = queue
+                .tx
+                .send(Message::Finish(Arc::clone(&results), Arc::clone(&labels)));
+        }
+        for handle in self.handles {
+            if handle.join().is_err() {
+                warn(WarningKind::Delivery, "a result sink panicked");
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct SinkSender {
+    queues: Vec<Queue>,
+}
+
+impl SinkSender {
+    pub fn event(&self, event: &Event) {
+        for queue in self.queues.iter().filter(|queue| queue.streams) {
+            let _ = queue.tx.send(Message::Event(event.clone()));
+        }
+    }
+
+    pub fn result(&self, command: &str, result: &ServerResult) {
+        for queue in &self.queues {
+            let _ = queue
+                .tx
+                .send(Message::Result(command.to_string(), result.clone()));
+        }
+    }
+
+    /// Passes the events from `rx` on to the sinks that stream, and then
+    /// to the receiver returned.
+    pub fn tee(self, rx: Receiver<Event>) -> Receiver<Event> {
+        let (tx, out) = mpsc::channel();
+        thread::spawn(move || {
+            for event in rx {
+                self.event(&event);
+                if tx.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+        out
+    }
+}
+
+fn deliver(name: &str, mut sink: Box<dyn ResultSink>, rx: Receiver<Message>) {
+    let mut messages = rx.into_iter();
+    for message in messages.by_ref() {
+        let delivered = match &message {
+            Message::Event(event) => sink.event(event),
+            Message::Result(command, result) => sink.result(command, result),
+            Message::Finish(results, labels) => sink.finish(results, labels),
+        };
+        if let Err(e) = delivered {
+            warn(
+                WarningKind::Delivery,
+                format!("result sink '{}' failed: {}", name, e),
+            );
+            break;
+        }
+    }
+    // A failed sink still drains its queue, so nothing waits on it.
+    messages.for_each(drop);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::AppError;
+    use std::sync::Mutex;
+
+    struct Recorder(Arc<Mutex<Vec<String>>>);
+
+    impl ResultSink for Recorder {
+        fn streams(&self) -> bool {
+            true
+        }
+
+        fn event(&mut self, event: &Event) -> Result<()> {
+            self.0.lock().unwrap().push(format!("{:?}", event.line()));
+            Ok(())
+        }
+
+        fn result(&mut self, command: &str, result: &ServerResult) -> Result<()> {
+            let entry = format!("{} {}", result.server, command);
+            self.0.lock().unwrap().push(entry);
+            Ok(())
+        }
+
+        fn finish(&mut self, results: &[(String, ServerResult)], _labels: &Labels) -> Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("{} result(s)", results.len()));
+            Ok(())
+        }
+    }
+
+    struct Broken;
+
+    impl ResultSink for Broken {
+        fn result(&mut self, _command: &str, _result: &ServerResult) -> Result<()> {
+            Err(AppError::Generic("disk full".to_string()))
+        }
+
+        fn finish(&mut self, _results: &[(String, ServerResult)], _labels: &Labels) -> Result<()> {
+            panic!("a failed sink is not finished");
+        }
+    }
+
+    #[test]
+    fn test_sinks_fail_alone() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sinks = Sinks::start(vec![
+            ("broken".to_string(), Box::new(Broken)),
+            (
+                "recorder".to_string(),
+                Box::new(Recorder(Arc::clone(&seen))),
+            ),
+        ]);
+        assert!(sinks.streams());
+        let sender = sinks.sender();
+        let result = ServerResult::skipped("web01", crate::ssh::Cut::FailFast);
+        sender.event(&Event::stdout("web01", "up 3 days"));
+        for _ in 0..3 {
+            sender.result("uptime", &result);
+        }
+        // The run's senders are gone by the time it finishes.
+        drop(sender);
+        sinks.finish(&[("uptime".to_string(), result)], &Labels::new());
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                "Some(\"up 3 days\")",
+                "web01 uptime",
+                "web01 uptime",
+                "web01 uptime",
+                "1 result(s)"
+            ]
+        );
+
+        let config: SinkConfig = toml::from_str(
+            r#"
+            type = "webhook"
+            url = "http://localhost:9000/hook"
+            "#,
+        )
+        .expect("Failed to parse a webhook sink");
+        assert!(matches!(config, SinkConfig::Webhook(hook) if hook.retries == 3));
+        let config: SinkConfig = toml::from_str("type = \"jsonl\"\npath = \"runs.jsonl\"")
+            .expect("Failed to parse a jsonl sink");
+        assert_eq!(
+            config,
+            SinkConfig::Jsonl {
+                path: PathBuf::from("runs.jsonl")
+            }
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! A SQLite database of runs, written by the `sqlite3` program once the
+//! run is over so the build needs no SQLite of its own: a row in `runs`
+//! per run, and a row in `results` per job, pointing at its run.
+
+use super::ResultSink;
+use crate::audit::{current_user, now};
+use crate::errors::{AppError, Result, TransportError};
+use crate::history::Labels;
+use crate::ssh::ServerResult;
+use crate::summary::Status;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const SCHEMA: &str = "\
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    user TEXT NOT NULL,
+    labels TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS results (
+    run INTEGER NOT NULL REFERENCES runs (id),
+    host TEXT NOT NULL,
+    command TEXT NOT NULL,
+    status TEXT NOT NULL,
+    exit_code INTEGER,
+    duration REAL NOT NULL,
+    stdout TEXT NOT NULL,
+    stderr TEXT
+);
+";
+
+pub struct Sqlite {
+    path: PathBuf,
+}
+
+impl Sqlite {
+    pub fn new(path: PathBuf) -> Sqlite {
+        Sqlite { path }
+    }
+}
+
+/// `text` as a SQL string literal.
+fn quote(text: &str) -> String {
+    format!("'{}'", text.replace('\0', "").replace('\'', "''"))
+}
+
+/// The statements adding a run, in one transaction.
+fn script(time: u64, user: &str, results: &[(String, ServerResult)], labels: &Labels) -> String {
+    let labels = serde_json::to_string(labels).unwrap_or_default();
+    let mut script = format!(
+        "{}BEGIN;\nINSERT INTO runs (time, user, labels) VALUES ({}, {}, {});\n",
+        SCHEMA,
+        time,
+        quote(user),
+        quote(&labels)
+    );
+    for (command, result) in results {
+        script.push_str(&format!(
+            "INSERT INTO results VALUES ((SELECT max(id) FROM runs), {}, {}, '{}', {}, {}, {}, {});\n",
+            quote(&result.server),
+            quote(command),
+            Status::of(result).as_str(),
+            result.exit_code.map_or("NULL".to_string(), |code| code.to_string()),
+            result.duration,
+            quote(&result.output),
+            result.error.as_deref().map_or("NULL".to_string(), quote),
+        ));
+    }
+    script.push_str("COMMIT;\n");
+    script
+}
+
+impl ResultSink for Sqlite {
+    fn finish(&mut self, results: &[(String, ServerResult)], labels: &Labels) -> Result<()> {
+        let script = script(now(), &current_user(), results, labels);
+        let mut child = Command::new("sqlite3")
+            .args(["-batch", "-bail"])
+            .arg(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|source| TransportError::Spawn {
+                program: "sqlite3",
+                source,
+            })?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(script.as_bytes())?;
+        }
+        let output = child.wait_with_output()?;
+        match output.status.success() {
+            true => Ok(()),
+            false => Err(AppError::Generic(format!(
+                "sqlite3 {}: {}",
+                self.path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ssh::Cut;
+
+    #[test]
+    fn test_script() {
+        let mut result = ServerResult::skipped("web01", Cut::FailFast);
+        result.output = "it's up\n".to_string();
+        result.error = None;
+        let labels = Labels::from([("ticket".to_string(), "OPS-1".to_string())]);
+        let script = script(
+            1700000000,
+            "ana",
+            &[("uptime".to_string(), result)],
+            &labels,
+        );
+        assert!(script.contains(
+            "INSERT INTO runs (time, user, labels) VALUES (1700000000, 'ana', '{\"ticket\":\"OPS-1\"}');"
+        ));
+        assert!(script.contains(
+            "VALUES ((SELECT max(id) FROM runs), 'web01', 'uptime', 'skipped', NULL, 0, 'it''s up\n', NULL);"
+        ));
+        assert!(script.ends_with("COMMIT;\n"));
+    }
+}
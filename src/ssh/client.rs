@@ -0,0 +1,166 @@
+//! The local OpenSSH client. Its version is read once per run from
+//! `ssh -V`, and options an old client doesn't know are left out or
+//! swapped for ones it does: `-J` becomes a `ProxyCommand` before 7.3, and
+//! connections aren't shared before 5.6, which brought `ControlPersist`.
+
+use crate::errors::{Result, TransportError};
+use crate::warnings::{warn, WarningKind};
+use std::process::Command;
+use std::sync::OnceLock;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Client {
+    /// There is no `ssh` to run.
+    Missing,
+    /// OpenSSH, with its major and minor version.
+    OpenSsh(u32, u32),
+    /// Something else answering `ssh -V`, trusted to keep up.
+    Other(String),
+}
+
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+/// Reads what `ssh -V` prints, e.g. `OpenSSH_9.6p1, OpenSSL 3.0.13` or
+/// `OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2`.
+pub fn parse(version: &str) -> Client {
+    fn number(text: &str) -> Option<(u32, &str)> {
+        let end = text
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(text.len());
+        text[..end].parse().ok().map(|n| (n, &text[end..]))
+    }
+    let release = version.find("OpenSSH_").map(|at| {
+        let rest = &version[at + "OpenSSH_".len()..];
+        rest.strip_prefix("for_Windows_").unwrap_or(rest)
+    });
+    match release.and_then(number) {
+        Some((major, rest)) => {
+            let minor = rest
+                .strip_prefix('.')
+                .and_then(number)
+                .map_or(0, |(n, _)| n);
+            Client::OpenSsh(major, minor)
+        }
+        None => Client::Other(version.trim().to_string()),
+    }
+}
+
+/// The client `ssh` runs, looked up on first use.
+pub fn client() -> &'static Client {
+    CLIENT.get_or_init(|| match Command::new("ssh").arg("-V").output() {
+        Ok(output) => {
+            let text = match output.stderr.is_empty() {
+                true => output.stdout,
+                false => output.stderr,
+            };
+            parse(&String::from_utf8_lossy(&text))
+        }
+        Err(_) => Client::Missing,
+    })
+}
+
+impl Client {
+    fn at_least(&self, major: u32, minor: u32) -> bool {
+        match self {
+            Client::OpenSsh(a, b) => (*a, *b) >= (major, minor),
+            Client::Missing | Client::Other(_) => true,
+        }
+    }
+
+    /// Whether it takes `-J` for jump hosts.
+    pub fn supports_jump(&self) -> bool {
+        self.at_least(7, 3)
+    }
+
+    /// Whether it takes `IdentityAgent`, to turn the agent off.
+    pub fn supports_identity_agent(&self) -> bool {
+        self.at_least(7, 3)
+    }
+
+    /// Whether it can keep a shared connection up with `ControlPersist`.
+    pub fn supports_persist(&self) -> bool {
+        self.at_least(5, 6)
+    }
+}
+
+/// Fails with installation help when there is no `ssh`, and warns about
+/// what an old one can't do.
+pub fn require() -> Result<()> {
+    let client = client();
+    let Client::OpenSsh(major, minor) = client else {
+        return match client {
+            Client::Missing => Err(TransportError::SshMissing.into()),
+            _ => Ok(()),
+        };
+    };
+    if !client.supports_jump() {
+        warn(
+            WarningKind::ConfigFallback,
+            format!(
+                "OpenSSH {}.{} has no -J: jump hosts go through ProxyCommand, \
+                 and chains of them need OpenSSH 7.3",
+                major, minor
+            ),
+        );
+    }
+    if !client.supports_persist() {
+        warn(
+            WarningKind::ConfigFallback,
+            format!(
+                "OpenSSH {}.{} has no ControlPersist: connections are not shared",
+                major, minor
+            ),
+        );
+    }
+    Ok(())
+}
+
+/// The `ProxyCommand` option reaching a host through `jump`
+/// (`[user@]host[:port]`) with `ssh -W`, for clients without `-J`.
+pub fn proxy_command(jump: &str) -> String {
+    let (destination, port) = match jump.rsplit_once(':') {
+        Some((destination, port))
+            if port.parse::<u16>().is_ok()
+                && (!destination.contains(':') || destination.ends_with(']')) =>
+        {
+            (destination, Some(port))
+        }
+        _ => (jump, None),
+    };
+    let destination = destination.replace(['[', ']'], "");
+    match port {
+        Some(port) => format!("ProxyCommand=ssh -W %h:%p -p {} {}", port, destination),
+        None => format!("ProxyCommand=ssh -W %h:%p {}", destination),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let modern = parse("OpenSSH_9.6p1 Ubuntu-3ubuntu13, OpenSSL 3.0.13 30 Jan 2024\n");
+        assert_eq!(modern, Client::OpenSsh(9, 6));
+        assert!(modern.supports_jump() && modern.supports_persist());
+        let windows = parse("OpenSSH_for_Windows_8.1p1, LibreSSL 3.0.2");
+        assert_eq!(windows, Client::OpenSsh(8, 1));
+        let old = parse("OpenSSH_5.3p1, OpenSSL 1.0.1e-fips 11 Feb 2013");
+        assert!(!old.supports_jump() && !old.supports_persist());
+        assert!(!parse("OpenSSH_7.2p2").supports_jump());
+        assert!(parse("Dropbear v2022.83").supports_jump());
+
+        assert_eq!(
+            proxy_command("admin@bastion:2222"),
+            "ProxyCommand=ssh -W %h:%p -p 2222 admin@bastion"
+        );
+        assert_eq!(
+            proxy_command("[fd00::1]:22"),
+            "ProxyCommand=ssh -W %h:%p -p 22 fd00::1"
+        );
+        assert_eq!(
+            proxy_command("fd00::1"),
+            "ProxyCommand=ssh -W %h:%p fd00::1"
+        );
+    }
+}
@@ -0,0 +1,148 @@
+//! Turning a remote command's output into lines without trusting it to
+//! behave: a line is never buffered beyond `MAX_LINE_LEN` bytes and the
+//! output kept for the result never beyond `MAX_CAPTURE` bytes, so a
+//! command printing one huge line can't exhaust memory.
+
+/// Longest piece of a line passed on at once. Longer lines are split into
+/// segments of at most this many bytes.
+pub const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Ends every segment of an over-long line that continues in the next one.
+pub const CONTINUATION_MARKER: &str = " [continued]";
+
+/// Output kept per stream for the final result; the live display still
+/// sees everything.
+pub const MAX_CAPTURE: usize = 16 * 1024 * 1024;
+
+/// Splits a byte stream into lines, fed chunk by chunk.
+#[derive(Default)]
+pub struct LineSplitter {
+    buffer: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Calls `f` with every line (or segment of an over-long line) that is
+    /// complete after appending `bytes`.
+    pub fn push(&mut self, bytes: &[u8], f: &mut impl FnMut(&str)) {
+        for piece in bytes.split_inclusive(|&b| b == b'\n') {
+            let (mut content, ends) = match piece.strip_suffix(b"\n") {
+                Some(content) => (content, true),
+                None => (piece, false),
+            };
+            while self.buffer.len() + content.len() > MAX_LINE_LEN {
+                let take = MAX_LINE_LEN - self.buffer.len();
+                self.buffer.extend_from_slice(&content[..take]);
+                content = &content[take..];
+                self.emit_segment(f);
+            }
+            self.buffer.extend_from_slice(content);
+            if ends {
+                self.emit_line(f);
+            }
+        }
+    }
+
+    /// Passes on whatever is left once the stream has ended.
+    pub fn finish(&mut self, f: &mut impl FnMut(&str)) {
+        if !self.buffer.is_empty() {
+            self.emit_line(f);
+        }
+    }
+
+    fn emit_line(&mut self, f: &mut impl FnMut(&str)) {
+        let line = String::from_utf8_lossy(&self.buffer);
+        f(line.trim_end_matches('\r'));
+        self.buffer.clear();
+    }
+
+    /// Emits a full buffer as a segment. A character cut in half at the end
+    /// is carried over to the next segment instead of being mangled.
+    fn emit_segment(&mut self, f: &mut impl FnMut(&str)) {
+        let keep = match std::str::from_utf8(&self.buffer) {
+            Err(e) if e.error_len().is_none() => self.buffer.len() - e.valid_up_to(),
+            _ => 0,
+        };
+        let split = self.buffer.len() - keep;
+        let segment = String::from_utf8_lossy(&self.buffer[..split]);
+        f(&format!("{}{}", segment, CONTINUATION_MARKER));
+        self.buffer.drain(..split);
+    }
+}
+
+/// Output collected for a result, capped at `MAX_CAPTURE` bytes.
+#[derive(Default)]
+pub struct Capture {
+    text: String,
+    truncated: bool,
+}
+
+impl Capture {
+    pub fn push_line(&mut self, line: &str) {
+        if self.truncated {
+            return;
+        }
+        if self.text.len() + line.len() + 1 > MAX_CAPTURE {
+            self.truncated = true;
+            self.text.push_str(&format!(
+                "[output truncated after {} bytes]\n",
+                self.text.len()
+            ));
+            return;
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    pub fn into_string(self) -> String {
+        self.text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(chunks: &[&[u8]]) -> Vec<String> {
+        let mut splitter = LineSplitter::default();
+        let mut lines = Vec::new();
+        let mut collect = |line: &str| lines.push(line.to_string());
+        for chunk in chunks {
+            splitter.push(chunk, &mut collect);
+        }
+        splitter.finish(&mut collect);
+        lines
+    }
+
+    #[test]
+    fn test_long_lines_are_split() {
+        assert_eq!(split(&[b"one\r\ntw", b"o\npart"]), ["one", "two", "part"]);
+
+        let long = vec![b'x'; 2 * MAX_LINE_LEN + 10];
+        let lines = split(&[&long, b"\nnext\n"]);
+        assert_eq!(lines.len(), 4);
+        assert!(lines[0].ends_with(CONTINUATION_MARKER));
+        assert_eq!(lines[0].len(), MAX_LINE_LEN + CONTINUATION_MARKER.len());
+        assert_eq!(lines[2], "x".repeat(10));
+        assert_eq!(lines[3], "next");
+
+        // A two-byte character straddling the limit stays whole.
+        let mut straddling = vec![b'a'; MAX_LINE_LEN - 1];
+        straddling.extend_from_slice("é".as_bytes());
+        let lines = split(&[&straddling]);
+        assert_eq!(lines.len(), 2);
+        assert!(!lines[0].contains('\u{fffd}'));
+        assert_eq!(lines[1], "é");
+    }
+
+    #[test]
+    fn test_capture_is_bounded() {
+        let mut capture = Capture::default();
+        let line = "y".repeat(MAX_LINE_LEN);
+        for _ in 0..(MAX_CAPTURE / MAX_LINE_LEN + 10) {
+            capture.push_line(&line);
+        }
+        let text = capture.into_string();
+        assert!(text.len() <= MAX_CAPTURE + 64);
+        assert!(text.ends_with("bytes]\n"));
+    }
+}
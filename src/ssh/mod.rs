@@ -1,98 +1,1222 @@
+#[doc(hidden)]
+pub mod client;
+mod lines;
+#[cfg(feature = "native")]
+mod native;
+
+pub(crate) use self::lines::{Capture, LineSplitter};
+use crate::answers::{self, Answer, Responder};
+use crate::askpass;
+use crate::auth;
+use crate::chain;
+use crate::config::{Host, Shell};
+use crate::crypto;
+use crate::errors::{AppError, ExecutionError, Result, TransportError};
+use crate::escalation::{self, BecomeMethod};
+use crate::expect::{Driver, Script};
+use crate::known_hosts;
+use crate::multiplex;
+use crate::prereq;
+use crate::runtime;
+use crate::template;
+use crate::warnings::{warn, WarningKind};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
 use serde::{Deserialize, Serialize};
-use std::io::{BufRead, BufReader};
-use std::process::{Command, Stdio};
+use std::ffi::OsString;
+use std::process::{ExitStatus, Stdio};
+use std::str::FromStr;
 use std::sync::mpsc::Sender;
-use std::thread;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::{Child, Command};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedSender};
 
-#[derive(Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ServerResult {
     pub server: String,
     pub output: String,
     pub error: Option<String>,
     pub duration: f64,
     pub success: bool,
+    /// Whether the command reported that it changed something.
+    #[serde(default)]
+    pub changed: bool,
+    /// Exit code of the remote command, once it has finished.
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    /// Time spent by the command on the host, with `--remote-timing`.
+    #[serde(default)]
+    pub remote_time: Option<RemoteTime>,
+    /// How many times the command was run, counting `--retries`.
+    #[serde(default = "one")]
+    pub attempts: u32,
+    /// What was moved, for `push` and `pull`.
+    #[serde(default)]
+    pub transfer: Option<TransferStats>,
+    /// Set when the job was skipped or cancelled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cut: Option<Cut>,
+    /// Whether the escalation tool let the command run, for escalated
+    /// commands.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub escalation: Option<escalation::Escalation>,
+}
+
+/// A result with nothing in it yet: no output, not successful, one attempt.
+/// Results are built from it with struct update syntax, naming only the
+/// fields that matter.
+impl Default for ServerResult {
+    fn default() -> ServerResult {
+        ServerResult {
+            server: String::new(),
+            output: String::new(),
+            error: None,
+            duration: 0.0,
+            success: false,
+            changed: false,
+            exit_code: None,
+            remote_time: None,
+            attempts: 1,
+            transfer: None,
+            cut: None,
+            escalation: None,
+        }
+    }
+}
+
+impl ServerResult {
+    /// The result of a job that was never run.
+    pub fn skipped(server: &str, cut: Cut) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            error: Some(cut.describe().to_string()),
+            attempts: 0,
+            cut: Some(cut),
+            ..Default::default()
+        }
+    }
+}
+
+fn one() -> u32 {
+    1
+}
+
+/// What a job reports while it runs: each line of output as it arrives,
+/// from the stream it came on, and how it ended. russh's own notes about a
+/// command (retries, timeouts, errors starting it) are stderr lines.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    StdoutLine {
+        server: String,
+        line: String,
+    },
+    StderrLine {
+        server: String,
+        line: String,
+    },
+    /// The job is done, after any retries; sent by the executor once the
+    /// job's lines are all out. `exit_code` is `None` for commands that
+    /// were killed or never started.
+    Completed {
+        server: String,
+        exit_code: Option<i32>,
+        success: bool,
+    },
+}
+
+impl Event {
+    pub fn stdout(server: &str, line: impl Into<String>) -> Event {
+        Event::StdoutLine {
+            server: server.to_string(),
+            line: line.into(),
+        }
+    }
+
+    pub fn stderr(server: &str, line: impl Into<String>) -> Event {
+        Event::StderrLine {
+            server: server.to_string(),
+            line: line.into(),
+        }
+    }
+
+    pub fn completed(result: &ServerResult) -> Event {
+        Event::Completed {
+            server: result.server.clone(),
+            exit_code: result.exit_code,
+            success: result.success,
+        }
+    }
+
+    pub fn server(&self) -> &str {
+        match self {
+            Event::StdoutLine { server, .. }
+            | Event::StderrLine { server, .. }
+            | Event::Completed { server, .. } => server,
+        }
+    }
+
+    /// The line, if it came on stdout.
+    pub fn into_stdout(self) -> Option<String> {
+        match self {
+            Event::StdoutLine { line, .. } => Some(line),
+            _ => None,
+        }
+    }
+
+    /// The output line, for either stream.
+    pub fn line(&self) -> Option<&str> {
+        match self {
+            Event::StdoutLine { line, .. } | Event::StderrLine { line, .. } => Some(line),
+            Event::Completed { .. } => None,
+        }
+    }
+}
+
+/// Why a job was cut short.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Cut {
+    /// It was still waiting to run when `--deadline` passed.
+    NotStarted,
+    /// It was still running when the grace period after `--deadline` ended.
+    Cancelled,
+    /// An earlier command of the same task failed on its host.
+    AfterFailure,
+    /// `--fail-fast` stopped the run before it started.
+    FailFast,
+    /// A runbook step it needs failed or was skipped on its host.
+    NeedsFailed,
+    /// Its runbook step has a manual gate the operator didn't open.
+    GateClosed,
+    /// The run was interrupted (Ctrl-C) before it finished.
+    Interrupted,
+}
+
+impl Cut {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Cut::NotStarted => "not started, past --deadline",
+            Cut::Cancelled => "cancelled at --deadline",
+            Cut::AfterFailure => "not run, an earlier command failed",
+            Cut::FailFast => "not started, --fail-fast",
+            Cut::NeedsFailed => "not run, a step it needs did not succeed",
+            Cut::GateClosed => "not run, its gate was not opened",
+            Cut::Interrupted => "cancelled by Ctrl-C",
+        }
+    }
+}
+
+/// Remote `time` measurements in seconds. `real` against the local
+/// `duration` shows how much of a run was network and handshake overhead.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RemoteTime {
+    pub real: f64,
+    pub user: f64,
+    pub sys: f64,
+}
+
+/// Bytes moved by a file transfer. Throughput is `bytes` over the
+/// result's `duration`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct TransferStats {
+    /// Bytes sent or received by this run.
+    pub bytes: u64,
+    /// Bytes already in place from an interrupted earlier transfer.
+    pub resumed: u64,
+    /// Whether the copy was checked against the source's sha256.
+    pub verified: bool,
+}
+
+/// Prefix of the stderr line the `--remote-timing` wrapper prints.
+const TIMING_MARKER: &str = "__RUSSH_TIME__";
+
+/// Parses the wrapper's `__RUSSH_TIME__ <real> <user> <sys>` line.
+pub(crate) fn parse_timing(line: &str) -> Option<RemoteTime> {
+    let mut fields = line.trim().strip_prefix(TIMING_MARKER)?.split_whitespace();
+    let mut next = || fields.next()?.parse::<f64>().ok();
+    Some(RemoteTime {
+        real: next()?,
+        user: next()?,
+        sys: next()?,
+    })
+}
+
+/// `ssh` exits with 255 when it couldn't connect or authenticate, as opposed
+/// to the remote command failing.
+pub const SSH_CONNECTION_FAILED: i32 = 255;
+
+impl ServerResult {
+    /// Whether the host couldn't be reached at all.
+    pub fn unreachable(&self) -> bool {
+        self.exit_code == Some(SSH_CONNECTION_FAILED)
+    }
+}
+
+/// A remote command prints this on a line of its own to report that it
+/// changed something, e.g. `apt-get install -y nginx | grep -q Setting && echo __RUSSH_CHANGED__`.
+/// The marker line is not shown in the output.
+pub const CHANGED_MARKER: &str = "__RUSSH_CHANGED__";
+
+/// A variable exported on the host before the command runs, from `--env`
+/// or `[env]`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EnvVar {
+    pub name: String,
+    pub value: String,
+}
+
+impl EnvVar {
+    pub fn new(name: &str, value: &str) -> Result<EnvVar> {
+        let mut chars = name.chars();
+        let valid = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+        if !valid {
+            return Err(ExecutionError::InvalidOption {
+                option: "--env",
+                reason: format!("'{}' is not a valid variable name", name),
+            }
+            .into());
+        }
+        Ok(EnvVar {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
 }
 
-pub fn run_ssh_command(
-    server: &str,
-    user: &str,
+impl FromStr for EnvVar {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let Some((name, value)) = spec.split_once('=') else {
+            return Err(ExecutionError::InvalidOption {
+                option: "--env",
+                reason: format!("expected KEY=VALUE, got '{}'", spec),
+            }
+            .into());
+        };
+        EnvVar::new(name, value)
+    }
+}
+
+/// I/O scheduling class for `ionice`, e.g. `idle` or `best-effort:7`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IoPriority {
+    pub class: u8,
+    pub level: Option<u8>,
+}
+
+impl FromStr for IoPriority {
+    type Err = AppError;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (class, level) = match spec.split_once(':') {
+            Some((class, level)) => (class, Some(level)),
+            None => (spec, None),
+        };
+        let class = match class {
+            "1" | "realtime" => 1,
+            "2" | "best-effort" => 2,
+            "3" | "idle" => 3,
+            _ => {
+                return Err(ExecutionError::InvalidOption {
+                    option: "--ionice",
+                    reason: format!(
+                        "unknown class '{}', expected idle, best-effort or realtime",
+                        class
+                    ),
+                }
+                .into())
+            }
+        };
+        let level = match level {
+            Some(level) => match level.parse::<u8>() {
+                Ok(level) if level <= 7 && class != 3 => Some(level),
+                _ => {
+                    return Err(ExecutionError::InvalidOption {
+                        option: "--ionice",
+                        reason: format!(
+                            "invalid level '{}', expected 0-7 (not allowed for idle)",
+                            level
+                        ),
+                    }
+                    .into())
+                }
+            },
+            None => None,
+        };
+        Ok(IoPriority { class, level })
+    }
+}
+
+/// How russh talks SSH: by running the system `ssh` binary, or in-process
+/// through libssh2 (requires the `native` feature).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    OpenSsh,
+    Native,
+}
+
+impl FromStr for Backend {
+    type Err = AppError;
+
+    fn from_str(name: &str) -> Result<Self> {
+        match name {
+            "openssh" => Ok(Backend::OpenSsh),
+            "native" if cfg!(feature = "native") => Ok(Backend::Native),
+            "native" => Err(ExecutionError::InvalidOption {
+                option: "--backend",
+                reason: "this russh was built without the `native` feature".to_string(),
+            }
+            .into()),
+            _ => Err(ExecutionError::InvalidOption {
+                option: "--backend",
+                reason: format!("unknown backend '{}', expected openssh or native", name),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Per-run settings that change how a command is executed on every host.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ExecOptions {
+    pub backend: Backend,
+    /// Niceness for the remote command (-20..=19).
+    pub nice: Option<i32>,
+    pub ionice: Option<IoPriority>,
+    /// Measure the command's real/user/sys time on the host.
+    pub remote_timing: bool,
+    /// `--timeout` in seconds, for hosts without their own `timeout`.
+    pub timeout: Option<u64>,
+    /// Run commands as another user through each host's `become_method`,
+    /// on hosts without their own `become` setting.
+    pub escalate: bool,
+    /// Password for `sudo -S`, from `--sudo`.
+    pub become_password: Option<escalation::Password>,
+    /// Responses to interactive prompts, from `--answer` and `[[answers]]`.
+    pub answers: Vec<Answer>,
+    /// `--expect`: a scripted session run on every host instead of answers.
+    pub expect: Option<Arc<Script>>,
+    /// Extra attempts after a failure before it is reported.
+    pub retries: u32,
+    /// Pause before the first retry; it doubles with every further one.
+    pub retry_delay: Duration,
+    /// When commands still running are killed: `--deadline` plus its grace
+    /// period.
+    pub cutoff: Option<Instant>,
+    /// Answers the native backend's authentication questions.
+    pub auth: auth::Handler,
+    /// Written to every command's stdin after any sudo password, e.g. the
+    /// script `russh script` runs.
+    pub stdin: Option<Arc<str>>,
+    /// Exported before every command, inside any escalation.
+    pub env: Vec<EnvVar>,
+    /// A program every command needs on POSIX hosts, e.g. a script's
+    /// interpreter; hosts without it fail with exit code 127 and say so.
+    pub requires: Option<String>,
+}
+
+impl ExecOptions {
+    pub fn validate(&self) -> Result<()> {
+        if let Some(nice) = self.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err(ExecutionError::InvalidOption {
+                    option: "--nice",
+                    reason: format!("must be between -20 and 19, got {}", nice),
+                }
+                .into());
+            }
+        }
+        if self.timeout == Some(0) {
+            return Err(ExecutionError::InvalidOption {
+                option: "--timeout",
+                reason: "must be at least 1 second".to_string(),
+            }
+            .into());
+        }
+        Ok(())
+    }
+
+    /// The flags that only work on POSIX hosts, if any are set.
+    pub fn posix_only_flags(&self) -> Vec<&'static str> {
+        let mut flags = Vec::new();
+        if self.escalate {
+            flags.push("--become");
+        }
+        if self.nice.is_some() {
+            flags.push("--nice");
+        }
+        if self.ionice.is_some() {
+            flags.push("--ionice");
+        }
+        if self.remote_timing {
+            flags.push("--remote-timing");
+        }
+        flags
+    }
+
+    /// The arguments `ssh` is run with for `command` on `host`.
+    pub fn ssh_args(&self, host: &Host, command: &str) -> Vec<String> {
+        let mut args = if command.is_empty() {
+            // No command: the host's login shell or CLI, for an expect script.
+            let mut args = build_ssh_args(host, "");
+            args.pop();
+            args
+        } else {
+            build_ssh_args(host, &self.host_command(host, command))
+        };
+        if self.expect.is_some() || !answers::for_command(&self.answers, command).is_empty() {
+            // Prompts are usually only shown on a terminal.
+            args.insert(0, "-tt".to_string());
+        }
+        args
+    }
+
+    /// What is sent to `host` for `command`: pre-commands and the wrappers
+    /// for POSIX hosts, the plain command for `cmd` and an encoded script
+    /// for PowerShell, which sidesteps Windows quoting entirely.
+    pub fn host_command(&self, host: &Host, command: &str) -> String {
+        let command = template::render(&host.command(command), host);
+        let command = self.with_env(host, &command);
+        let command = match (&self.requires, host.shell()) {
+            (Some(program), Shell::Posix) => format!("{}; {}", prereq::guard(program), command),
+            _ => command,
+        };
+        match host.shell() {
+            // Escalation goes outermost so e.g. a negative --nice works.
+            Shell::Posix if self.escalates(host) => escalation::wrap(
+                host.become_method.unwrap_or_default(),
+                host.become_user.as_deref(),
+                &self.remote_command(&command),
+                self.become_password.is_some(),
+                self.stdin.is_some(),
+            ),
+            Shell::Posix => self.remote_command(&command),
+            Shell::Cmd => command,
+            Shell::Powershell => powershell_command(&command),
+        }
+    }
+
+    /// `command` with `env` exported first, in `host`'s shell.
+    fn with_env(&self, host: &Host, command: &str) -> String {
+        if self.env.is_empty() {
+            return command.to_string();
+        }
+        let env = self.env.iter();
+        match host.shell() {
+            Shell::Posix => {
+                let vars: Vec<String> = env
+                    .map(|var| format!("{}={}", var.name, shell_quote(&var.value)))
+                    .collect();
+                format!("export {}; {}", vars.join(" "), command)
+            }
+            Shell::Cmd => {
+                let vars: Vec<String> = env
+                    .map(|var| format!("(set {}={})", var.name, cmd_escape(&var.value)))
+                    .collect();
+                format!("{} && {}", vars.join(" && "), command)
+            }
+            Shell::Powershell => {
+                let vars: Vec<String> = env
+                    .map(|var| format!("$env:{} = '{}'", var.name, var.value.replace('\'', "''")))
+                    .collect();
+                format!("{}; {}", vars.join("; "), command)
+            }
+        }
+    }
+
+    /// Whether commands on `host` are escalated: its own `become` if set,
+    /// otherwise `--become`. Windows hosts never are.
+    pub fn escalates(&self, host: &Host) -> bool {
+        !host.shell().is_windows() && host.escalate.unwrap_or(self.escalate)
+    }
+
+    /// What to write to the remote command's stdin on `host`: the password,
+    /// when sudo is going to read it, then `stdin` behind the marker the
+    /// escalated command skips to.
+    fn stdin_for(&self, host: &Host) -> Option<String> {
+        let password = self.become_password.as_ref().filter(|_| {
+            self.escalates(host) && host.become_method.unwrap_or_default() == BecomeMethod::Sudo
+        });
+        match (password, &self.stdin) {
+            (None, None) => None,
+            (Some(password), None) => Some(password.stdin_line()),
+            (None, Some(stdin)) => Some(stdin.to_string()),
+            (Some(password), Some(stdin)) => Some(format!(
+                "{}{}\n{}",
+                password.stdin_line(),
+                escalation::STDIN_MARKER,
+                stdin
+            )),
+        }
+    }
+
+    /// How long a command may run on `host`: its own `timeout` if it has
+    /// one, otherwise `--timeout`, and never past the cutoff.
+    pub fn timeout_for(&self, host: &Host) -> Option<Duration> {
+        let timeout = host.timeout.or(self.timeout).map(Duration::from_secs);
+        let Some(cutoff) = self.cutoff else {
+            return timeout;
+        };
+        let left = cutoff.saturating_duration_since(Instant::now());
+        Some(timeout.map_or(left, |timeout| timeout.min(left)))
+    }
+
+    /// Whether the cutoff has passed.
+    pub fn cut_off(&self) -> bool {
+        self.cutoff.is_some_and(|cutoff| Instant::now() >= cutoff)
+    }
+
+    /// How long to wait after failed attempt number `attempt` (from 1).
+    pub fn retry_backoff(&self, attempt: u32) -> Duration {
+        self.retry_delay
+            .saturating_mul(1 << attempt.saturating_sub(1).min(6))
+    }
+
+    /// The command line actually sent to the host: the user's command wrapped
+    /// in `nice`/`ionice` and timing when requested.
+    pub fn remote_command(&self, command: &str) -> String {
+        let command = self.priority_command(command);
+        if !self.remote_timing {
+            return command;
+        }
+        // `time` with TIMEFORMAT is a bash feature; hosts without bash
+        // simply run the command untimed.
+        let timed = format!(
+            "TIMEFORMAT='{} %R %U %S'; time sh -c {}",
+            TIMING_MARKER,
+            shell_quote(&command)
+        );
+        format!(
+            "if command -v bash >/dev/null 2>&1; then exec bash -c {}; else exec sh -c {}; fi",
+            shell_quote(&timed),
+            shell_quote(&command)
+        )
+    }
+
+    fn priority_command(&self, command: &str) -> String {
+        if self.nice.is_none() && self.ionice.is_none() {
+            return command.to_string();
+        }
+        let mut wrapper = Vec::new();
+        if let Some(nice) = self.nice {
+            wrapper.push(format!("nice -n {}", nice));
+        }
+        if let Some(ionice) = self.ionice {
+            match ionice.level {
+                Some(level) => wrapper.push(format!("ionice -c {} -n {}", ionice.class, level)),
+                None => wrapper.push(format!("ionice -c {}", ionice.class)),
+            }
+        }
+        format!("{} sh -c {}", wrapper.join(" "), shell_quote(command))
+    }
+}
+
+/// Runs `script` through `powershell -EncodedCommand`, which takes it as
+/// base64-encoded UTF-16LE so no character needs escaping.
+pub fn powershell_command(script: &str) -> String {
+    let utf16: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    format!(
+        "powershell -NoProfile -NonInteractive -EncodedCommand {}",
+        BASE64.encode(utf16)
+    )
+}
+
+/// `value` as cmd.exe takes it literally outside quotes, each
+/// metacharacter behind a `^`. Inside quotes a `"` in the value would end
+/// them. Commands run through `cmd /c` rather than a batch file, so `%` is
+/// escaped the same way: `%%` only means `%` in a batch file.
+fn cmd_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '^' | '&' | '|' | '<' | '>' | '"' | '(' | ')' | '%') {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Quotes `arg` for a POSIX shell, leaving it untouched when that's not needed.
+pub fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "@%+=:,./_-".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Renders a program and its arguments as a copy-pasteable shell command.
+pub fn format_command(program: &str, args: &[String]) -> String {
+    let mut line = shell_quote(program);
+    for arg in args {
+        line.push(' ');
+        line.push_str(&shell_quote(arg));
+    }
+    line
+}
+
+/// Local variables every process keeps with `clean_env`: what ssh needs to
+/// find its config and keys, and to run a `ProxyCommand`.
+const ALWAYS_PASSED: &[&str] = &["HOME", "PATH", "USER", "LOGNAME"];
+
+/// Whether the local variable `name` is in `passthrough`, where a trailing
+/// `*` matches any suffix (`LC_*`).
+fn passed_through(name: &str, passthrough: &[String]) -> bool {
+    ALWAYS_PASSED.contains(&name)
+        || passthrough
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => name.starts_with(prefix),
+                None => name == pattern,
+            })
+}
+
+/// With `clean_env` on `host`, the only local environment variables the
+/// ssh, scp and rsync processes for it get; `None` when they inherit all of
+/// russh's.
+pub fn local_env(host: &Host) -> Option<Vec<(OsString, OsString)>> {
+    if !host.clean_env.unwrap_or(false) {
+        return None;
+    }
+    Some(
+        std::env::vars_os()
+            .filter(|(name, _)| {
+                name.to_str()
+                    .is_some_and(|name| passed_through(name, &host.env_passthrough))
+            })
+            .collect(),
+    )
+}
+
+/// Builds the argument list passed to `ssh` for running `command` on `host`.
+pub fn build_ssh_args(host: &Host, command: &str) -> Vec<String> {
+    let mut args = connection_args(host);
+    // Nothing from here on is an option, whatever the host is called.
+    args.push("--".to_string());
+    match host.user.as_ref().filter(|u| !u.is_empty()) {
+        Some(user) => args.push(format!("{}@{}", user, host.host)),
+        None => args.push(host.host.clone()),
+    }
+    args.push(command.to_string());
+    args
+}
+
+/// The ssh arguments for reaching `host`, before its destination.
+pub fn connection_args(host: &Host) -> Vec<String> {
+    let mut args = Vec::new();
+    if let Some(port) = host.port {
+        args.push("-p".to_string());
+        args.push(port.to_string());
+    }
+    if let Some(identity_file) = &host.identity_file {
+        args.push("-i".to_string());
+        args.push(identity_file.display().to_string());
+    }
+    if let Some(jump) = host.jump() {
+        if client::client().supports_jump() || jump.contains(',') {
+            args.push("-J".to_string());
+            args.push(jump.to_string());
+        } else {
+            args.push("-o".to_string());
+            args.push(client::proxy_command(jump));
+        }
+    }
+    args.extend(host.option_args());
+    // After the host's own options, which win since ssh takes the first.
+    args.extend(agent_options(host));
+    args.extend(known_hosts::ssh_options(host));
+    args.extend(crypto::ssh_options(host));
+    args.extend(multiplex::ssh_options());
+    args
+}
+
+/// The options keeping ssh away from the agent for hosts with
+/// `use_agent = false`. Clients before 7.3 have no `IdentityAgent`, and
+/// only stop offering agent keys that don't match an `identity_file`.
+pub fn agent_options(host: &Host) -> Vec<String> {
+    if host.use_agent != Some(false) {
+        return Vec::new();
+    }
+    let mut args = vec!["-o".to_string(), "IdentitiesOnly=yes".to_string()];
+    if client::client().supports_identity_agent() {
+        args.push("-o".to_string());
+        args.push("IdentityAgent=none".to_string());
+    }
+    args
+}
+
+/// Runs `command` on `host`, streaming output lines over `tx` as they
+/// arrive. The returned result carries the complete stdout and stderr.
+/// Failed attempts are retried with exponential backoff up to
+/// `options.retries` times; the result is the last attempt's.
+pub async fn run_ssh_command(
+    host: &Host,
     command: &str,
-    ssh_options: &str,
-    tx: Sender<ServerResult>,
-) {
-    let start = Instant::now();
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let mut attempt = 1;
+    loop {
+        let mut result = run_attempt(host, command, options, tx.clone()).await;
+        result.attempts = attempt;
+        if options.escalates(host) {
+            record_escalation(host, command, &mut result);
+        }
+        if !result.success && result.exit_code.is_none() && options.cut_off() {
+            let message = "cancelled, the --deadline grace period ran out";
+            let _ = tx.send(Event::stderr(&result.server, message));
+            let error = result.error.get_or_insert_with(String::new);
+            error.push_str(message);
+            error.push('\n');
+            result.cut = Some(Cut::Cancelled);
+            return result;
+        }
+        if result.success || attempt > options.retries || options.cut_off() {
+            return result;
+        }
+        let delay = options.retry_backoff(attempt);
+        let _ = tx.send(Event::stderr(
+            &result.server,
+            format!(
+                "attempt {} failed, retrying in {:.1}s",
+                attempt,
+                delay.as_secs_f64()
+            ),
+        ));
+        tokio::time::sleep(delay).await;
+        attempt += 1;
+    }
+}
 
-    // Convert to owned String types
+/// Notes in `result` and the audit log whether `host`'s escalation tool
+/// let `command` run.
+fn record_escalation(host: &Host, command: &str, result: &mut ServerResult) {
+    let method = host.become_method.unwrap_or_default();
+    let user = host.become_user.as_deref();
+    let stderr = result.error.as_deref().unwrap_or("");
+    let Some(outcome) = escalation::outcome(method, user, result.exit_code, stderr) else {
+        return;
+    };
+    if let Err(e) = outcome.audit(&host.host, command) {
+        warn(
+            WarningKind::Delivery,
+            format!("could not record the escalation on {}: {}", host.host, e),
+        );
+    }
+    result.escalation = Some(outcome);
+}
+
+/// `run_ssh_command` for synchronous callers such as file transfers and
+/// deploy steps; must not be called from inside an async task.
+pub fn run_ssh_command_blocking(
+    host: &Host,
+    command: &str,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    runtime::block_on(run_ssh_command(host, command, options, tx))
+}
+
+async fn run_attempt(
+    host: &Host,
+    command: &str,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let text = options.stdin_for(host);
+    let answers = answers::for_command(&options.answers, command);
+    #[cfg(feature = "native")]
+    if options.backend == Backend::Native {
+        if options.expect.is_some() {
+            warn(
+                WarningKind::IgnoredOption,
+                format!(
+                    "--expect needs the openssh backend, ignored for {}",
+                    host.host
+                ),
+            );
+        }
+        if !answers.is_empty() {
+            warn(
+                WarningKind::IgnoredOption,
+                format!(
+                    "answers need the openssh backend, not given to '{}' on {}",
+                    command, host.host
+                ),
+            );
+        }
+        // libssh2 blocks, so the session gets a thread of its own.
+        let (host, command) = (host.clone(), options.host_command(host, command));
+        let (timeout, auth) = (options.timeout_for(&host), options.auth.clone());
+        return tokio::task::spawn_blocking(move || {
+            native::run(&host, &command, text.as_deref(), timeout, &*auth, tx)
+        })
+        .await
+        .unwrap_or_else(|e| std::panic::resume_unwind(e.into_panic()));
+    }
+
+    let args = options.ssh_args(host, command);
+    let input = Input {
+        text: text.as_deref(),
+        answers: &answers,
+        expect: options.expect.as_ref(),
+        command,
+    };
+    let mut result = run_process(host, "ssh", &args, input, options.timeout_for(host), tx).await;
+    if options.escalates(host)
+        && !result.success
+        && result
+            .error
+            .as_deref()
+            .is_some_and(escalation::password_required)
+    {
+        let method = host.become_method.unwrap_or_default();
+        let hint = match (&options.become_password, method) {
+            (Some(_), BecomeMethod::Sudo) => {
+                format!("sudo on {} did not accept the password\n", host.host)
+            }
+            (Some(_), _) => format!(
+                "{} on {} can't be given a password; allow passwordless escalation for this user\n",
+                method, host.host
+            ),
+            (None, _) => format!(
+                "{} on {} wants a password; use --sudo to enter one or allow passwordless escalation\n",
+                method, host.host
+            ),
+        };
+        result.error.get_or_insert_with(String::new).push_str(&hint);
+    }
+    result
+}
+
+/// What a process gets on stdin. With neither text nor answers it
+/// inherits russh's own.
+#[derive(Clone, Copy, Default)]
+pub struct Input<'a> {
+    /// Written as soon as the process starts, e.g. a sudo password.
+    pub text: Option<&'a str>,
+    /// Typed in when their prompt shows up in the output.
+    pub answers: &'a [Answer],
+    /// A script to drive the session with; answers are left unused.
+    pub expect: Option<&'a Arc<Script>>,
+    /// The command being answered, for the audit log.
+    pub command: &'a str,
+}
+
+/// The error reported for a command that ran past its timeout.
+pub(crate) fn timed_out(timeout: Duration) -> String {
+    format!("timed out after {}s and was killed", timeout.as_secs())
+}
+
+/// How often a running expect script is checked for a step that waits too
+/// long.
+const STEP_CHECK: Duration = Duration::from_millis(100);
+
+/// Runs a local `program` on behalf of `server`, streaming its output lines
+/// over `tx` and recognising the changed and timing markers. Shared by ssh
+/// and the scp-based file transfers. With a `timeout` the process is killed
+/// once it has run that long.
+pub async fn run_process(
+    host: &Host,
+    program: &'static str,
+    args: &[String],
+    input: Input<'_>,
+    timeout: Option<Duration>,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let start = Instant::now();
+    let server = host.host.as_str();
     let server_owned = server.to_string();
-    let user_owned = user.to_string();
-    let command_owned = command.to_string();
-    let ssh_options_owned = ssh_options.to_string();
-
-    let mut child = Command::new("ssh")
-        .args([
-            &ssh_options_owned,
-            &format!("{}@{}", user_owned, server_owned),
-            &command_owned,
-        ])
+    let failed = |error: AppError| {
+        let result = ServerResult {
+            server: server.to_string(),
+            error: Some(error.to_string()),
+            duration: start.elapsed().as_secs_f64(),
+            ..Default::default()
+        };
+        // Show the error live like any other output line. The receiver
+        // only goes away when nobody is displaying output.
+        let _ = tx.send(Event::stderr(server, error.to_string()));
+        result
+    };
+
+    let mut command = Command::new(program);
+    command
+        .args(args)
         .stdout(Stdio::piped())
         .stderr(Stdio::piped())
-        .spawn()
-        .expect("Failed to start ssh command");
-
-    let stdout = BufReader::new(child.stdout.take().expect("Failed to get stdout"));
-    let stderr = BufReader::new(child.stderr.take().expect("Failed to get stderr"));
+        .kill_on_drop(true);
+    if let Some(vars) = local_env(host) {
+        command.env_clear().envs(vars);
+    }
+    askpass::apply(command.as_std_mut());
+    if input.text.is_some() || !input.answers.is_empty() || input.expect.is_some() {
+        command.stdin(Stdio::piped());
+    }
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(source) if program == "ssh" && source.kind() == std::io::ErrorKind::NotFound => {
+            return failed(TransportError::SshMissing.into())
+        }
+        Err(source) => return failed(TransportError::Spawn { program, source }.into()),
+    };
+    // Everything typed into the process goes through one writer task, in
+    // order; dropping the sender closes stdin.
+    let mut typing = child.stdin.take().map(|mut stdin| {
+        let (typed, mut to_type) = unbounded_channel::<String>();
+        tokio::spawn(async move {
+            while let Some(text) = to_type.recv().await {
+                if stdin.write_all(text.as_bytes()).await.is_err() || stdin.flush().await.is_err() {
+                    break;
+                }
+            }
+        });
+        typed
+    });
+    let type_text = |typing: &Option<UnboundedSender<String>>, text: String| {
+        if let Some(typing) = typing {
+            let _ = typing.send(text);
+        }
+    };
+    if let Some(text) = input.text {
+        type_text(&typing, text.to_string());
+    }
+    let driver = input.expect.map(|script| Driver::new(Arc::clone(script)));
+    let mut opening = Vec::new();
+    if let Some(driver) = &driver {
+        for sent in driver.start() {
+            type_text(&typing, sent.text);
+            opening.push(sent.log);
+        }
+    }
+    let mut responder = match input.answers {
+        answers if answers.is_empty() || driver.is_some() => None,
+        answers => Some(Responder::new(answers.to_vec())),
+    };
+    // Without anything left to type, stdin is closed right away.
+    if responder.is_none() && driver.as_ref().is_none_or(Driver::finished) {
+        typing = None;
+    }
+    let stdout_driver = driver.clone();
+    let audit_command = input.command.to_string();
+    let (Some(stdout), Some(stderr)) = (child.stdout.take(), child.stderr.take()) else {
+        let _ = child.start_kill();
+        return failed(
+            TransportError::Connection {
+                host: server.to_string(),
+                message: format!("{} output streams were not captured", program),
+            }
+            .into(),
+        );
+    };
 
-    let server_clone_for_stdout = server_owned.clone(); // Clone for stdout thread
+    let server_clone_for_stdout = server_owned.clone(); // Clone for stdout task
     let tx_stdout = tx.clone();
-    let stdout_thread = thread::spawn(move || {
-        for line in stdout.lines() {
-            let line = line.expect("Failed to read line from stdout");
-            tx_stdout
-                .send(ServerResult {
-                    server: server_clone_for_stdout.clone(),
-                    output: line,
-                    error: None,
-                    duration: start.elapsed().as_secs_f64(),
-                    success: true,
-                })
-                .expect("Failed to send output");
+    let stdout_task = tokio::spawn(async move {
+        let mut text = Capture::default();
+        let mut changed = false;
+        let mut answered = Vec::new();
+        let on_chunk = |chunk: &[u8]| {
+            let output = String::from_utf8_lossy(chunk);
+            let mut typed = Vec::new();
+            if let Some(driver) = &stdout_driver {
+                typed.extend(driver.feed(&output).into_iter().map(|s| (s.text, s.log)));
+            }
+            if let Some(answer) = responder.as_mut().and_then(|r| r.feed(&output)) {
+                answered.push(answer.clone());
+                typed.push((answer.typed(), answer.log_line()));
+            }
+            let mut logs = Vec::new();
+            for (text, log) in typed {
+                type_text(&typing, text);
+                logs.push(log);
+            }
+            logs
+        };
+        let mut on_line = |line: &str| {
+            if line.trim() == CHANGED_MARKER {
+                changed = true;
+                return;
+            }
+            text.push_line(line);
+            let Some(line) = chain::shown(line) else {
+                return;
+            };
+            let _ = tx_stdout.send(Event::stdout(&server_clone_for_stdout, line));
+        };
+        for line in &opening {
+            on_line(line);
         }
+        for_each_line_with(stdout, on_chunk, &mut on_line).await;
+        for answer in answered {
+            if let Err(e) = answer.audit(&server_clone_for_stdout, &audit_command) {
+                warn(
+                    WarningKind::Delivery,
+                    format!("could not record an answered prompt: {}", e),
+                );
+            }
+        }
+        (text.into_string(), changed)
     });
 
-    let server_clone_for_stderr = server_owned.clone(); // Clone for stderr thread
+    let server_clone_for_stderr = server_owned.clone(); // Clone for stderr task
     let tx_stderr = tx.clone();
-    let stderr_thread = thread::spawn(move || {
-        for line in stderr.lines() {
-            let line = line.expect("Failed to read line from stdout");
-            tx_stderr
-                .send(ServerResult {
-                    server: server_clone_for_stderr.clone(),
-                    output: line,
-                    error: None,
-                    duration: start.elapsed().as_secs_f64(),
-                    success: true,
-                })
-                .expect("Failed to send output");
-        }
+    let stderr_task = tokio::spawn(async move {
+        let mut remote_time = None;
+        let mut text = Capture::default();
+        for_each_line(stderr, |line| {
+            if let Some(timing) = parse_timing(line) {
+                remote_time = Some(timing);
+                return;
+            }
+            text.push_line(line);
+            let Some(line) = chain::shown(line) else {
+                return;
+            };
+            let _ = tx_stderr.send(Event::stderr(&server_clone_for_stderr, line));
+        })
+        .await;
+        (text.into_string(), remote_time)
     });
 
-    // Wait for both threads to complete
-    stdout_thread.join().expect("Failed to join stdout thread");
-    stderr_thread.join().expect("Failed to join stderr thread");
+    // Wait for the process first, so a hung one can be killed; killing it
+    // closes its pipes and lets the reader tasks finish.
+    let stalled = || driver.as_ref().is_some_and(Driver::overdue);
+    let status = match wait(&mut child, timeout, driver.as_ref()).await {
+        Ok(Some(status)) => Some(status),
+        Ok(None) => {
+            let _ = child.kill().await;
+            None
+        }
+        Err(e) => {
+            return failed(
+                ExecutionError::Host {
+                    host: server.to_string(),
+                    message: format!("failed to wait for {}: {}", program, e),
+                }
+                .into(),
+            )
+        }
+    };
+
+    // Wait for both tasks to complete
+    let (stdout_text, changed) = stdout_task.await.unwrap_or_default();
+    let (mut stderr_text, remote_time) = stderr_task.await.unwrap_or_default();
 
-    // Check command completion status
-    let success = child.wait().expect("Failed to wait on child").success();
+    let unfinished = driver.as_ref().and_then(Driver::unfinished);
+    let Some(status) = status else {
+        let message = match unfinished {
+            Some(message) if stalled() => message,
+            _ => timed_out(timeout.unwrap_or_default()),
+        };
+        let _ = tx.send(Event::stderr(&server_owned, message.clone()));
+        stderr_text.push_str(&message);
+        stderr_text.push('\n');
+        return ServerResult {
+            server: server_owned,
+            output: stdout_text,
+            error: Some(stderr_text),
+            duration: start.elapsed().as_secs_f64(),
+            changed,
+            remote_time,
+            ..Default::default()
+        };
+    };
 
-    // Send final result indicating completion
-    tx.send(ServerResult {
-        server: server.to_string(),
-        output: String::new(), // No additional output at this point
-        error: None,
+    if let Some(message) = &unfinished {
+        let _ = tx.send(Event::stderr(&server_owned, message.clone()));
+        stderr_text.push_str(message);
+        stderr_text.push('\n');
+    }
+
+    ServerResult {
+        server: server_owned,
+        output: stdout_text,
+        error: Some(stderr_text).filter(|text| !text.is_empty()),
         duration: start.elapsed().as_secs_f64(),
-        success,
-    })
-    .expect("Failed to send final result");
+        success: status.success() && unfinished.is_none(),
+        changed,
+        exit_code: status.code(),
+        remote_time,
+        ..Default::default()
+    }
+}
+
+/// `run_process` for synchronous callers; must not be called from inside
+/// an async task.
+pub fn run_process_blocking(
+    host: &Host,
+    program: &'static str,
+    args: &[String],
+    input: Input<'_>,
+    timeout: Option<Duration>,
+    tx: Sender<Event>,
+) -> ServerResult {
+    runtime::block_on(run_process(host, program, args, input, timeout, tx))
+}
+
+/// Calls `f` with every line `reader` produces, splitting over-long lines
+/// into segments. Invalid UTF-8 is replaced rather than ending the stream,
+/// which would leave the process blocked on a full pipe.
+async fn for_each_line<R: AsyncRead + Unpin>(reader: R, f: impl FnMut(&str)) {
+    for_each_line_with(reader, |_| Vec::new(), f).await
+}
+
+/// `for_each_line`, also showing every chunk read to `on_chunk`; the lines
+/// it returns are passed to `f` after the chunk's own lines.
+async fn for_each_line_with<R: AsyncRead + Unpin>(
+    mut reader: R,
+    mut on_chunk: impl FnMut(&[u8]) -> Vec<String>,
+    mut f: impl FnMut(&str),
+) {
+    let mut splitter = LineSplitter::default();
+    let mut buffer = vec![0; 8 * 1024];
+    loop {
+        let chunk = match reader.read(&mut buffer).await {
+            Ok(0) => break,
+            Ok(read) => &buffer[..read],
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+        splitter.push(chunk, &mut f);
+        for line in on_chunk(chunk) {
+            f(&line);
+        }
+    }
+    splitter.finish(&mut f);
+}
+
+/// Waits for `child` to exit, giving up after `timeout` or once `driver`'s
+/// current step is overdue. `Ok(None)` means it is still running.
+async fn wait(
+    child: &mut Child,
+    timeout: Option<Duration>,
+    driver: Option<&Driver>,
+) -> std::io::Result<Option<ExitStatus>> {
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    loop {
+        let check = driver.map(|_| Instant::now() + STEP_CHECK);
+        let Some(wake) = deadline.into_iter().chain(check).min() else {
+            return child.wait().await.map(Some);
+        };
+        if let Ok(status) = tokio::time::timeout_at(wake.into(), child.wait()).await {
+            return status.map(Some);
+        }
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || driver.is_some_and(Driver::overdue)
+        {
+            return Ok(None);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -105,7 +1229,7 @@ mod tests {
     fn test_run_ssh_command_success() {
         let output = if cfg!(target_os = "windows") {
             Command::new("cmd")
-                .args(&["/C", "echo Success output"])
+                .args(["/C", "echo Success output"])
                 .output()
                 .expect("Failed to execute command")
         } else {
@@ -133,7 +1257,7 @@ mod tests {
     fn test_run_ssh_command_failure() {
         let output = if cfg!(target_os = "windows") {
             Command::new("cmd")
-                .args(&["/C", "echo Error output >&2 && exit 1"])
+                .args(["/C", "echo Error output >&2 && exit 1"])
                 .output()
                 .expect("Failed to execute command")
         } else {
@@ -157,6 +1281,359 @@ mod tests {
         assert!(result.output.is_empty());
         assert_eq!(result.error.unwrap().trim(), "Error output");
     }
+    #[test]
+    fn test_build_ssh_args() {
+        let host: Host = "web01 user=deploy port=2222".parse().unwrap();
+        assert_eq!(
+            build_ssh_args(&host, "uptime"),
+            vec!["-p", "2222", "--", "deploy@web01", "uptime"]
+        );
+        assert_eq!(
+            build_ssh_args(&Host::new("web02"), "uptime"),
+            vec!["--", "web02", "uptime"]
+        );
+        let host: Host = "web03 identity_file=~/.ssh/deploy".parse().unwrap();
+        assert_eq!(
+            build_ssh_args(&host, "uptime"),
+            vec!["-i", "~/.ssh/deploy", "--", "web03", "uptime"]
+        );
+        let host: Host = "web03 use_agent=false".parse().unwrap();
+        assert_eq!(
+            build_ssh_args(&host, "uptime")[..2],
+            ["-o", "IdentitiesOnly=yes"]
+        );
+        let host: Host = "web04 jump_host=ops@jump:2222".parse().unwrap();
+        assert_eq!(
+            build_ssh_args(&host, "uptime"),
+            vec!["-J", "ops@jump:2222", "--", "web04", "uptime"]
+        );
+        // Each option is its own argument.
+        let host = Host {
+            options: Some("-p 22 -o 'ProxyCommand=nc -X 5 %h %p'".to_string()),
+            extra_options: vec!["-o".to_string(), "LogLevel=ERROR".to_string()],
+            ..Host::new("web05")
+        };
+        assert_eq!(
+            build_ssh_args(&host, "uptime"),
+            vec![
+                "-p",
+                "22",
+                "-o",
+                "ProxyCommand=nc -X 5 %h %p",
+                "-o",
+                "LogLevel=ERROR",
+                "--",
+                "web05",
+                "uptime"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_command() {
+        let host: Host = "web01 user=deploy".parse().unwrap();
+        assert_eq!(
+            format_command("ssh", &build_ssh_args(&host, "echo 'hi'")),
+            r"ssh -- deploy@web01 'echo '\''hi'\'''"
+        );
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("-p"), "-p");
+    }
+
+    #[test]
+    fn test_priority_wrapper() {
+        let options = ExecOptions {
+            nice: Some(10),
+            ionice: Some("best-effort:7".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            options.remote_command("tar czf /backup/db.tgz /var/lib/db && echo 'done'"),
+            r"nice -n 10 ionice -c 2 -n 7 sh -c 'tar czf /backup/db.tgz /var/lib/db && echo '\''done'\'''"
+        );
+        assert_eq!(ExecOptions::default().remote_command("uptime"), "uptime");
+
+        let idle = ExecOptions {
+            ionice: Some("idle".parse().unwrap()),
+            ..Default::default()
+        };
+        assert_eq!(
+            idle.remote_command("du -sh /"),
+            "ionice -c 3 sh -c 'du -sh /'"
+        );
+
+        assert!("idle:3".parse::<IoPriority>().is_err());
+        assert!("best-effort:9".parse::<IoPriority>().is_err());
+        assert!("fast".parse::<IoPriority>().is_err());
+        assert!(ExecOptions {
+            nice: Some(25),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+    }
+
+    #[test]
+    fn test_timeout_kills_process() {
+        let mut host = Host::new("web01");
+        let options = ExecOptions {
+            timeout: Some(30),
+            ..Default::default()
+        };
+        assert_eq!(options.timeout_for(&host), Some(Duration::from_secs(30)));
+        host.timeout = Some(1);
+        assert_eq!(options.timeout_for(&host), Some(Duration::from_secs(1)));
+
+        let (tx, _rx) = std::sync::mpsc::channel();
+        let result = run_process_blocking(
+            &Host::new("web01"),
+            "sleep",
+            &["5".to_string()],
+            Input::default(),
+            Some(Duration::from_millis(200)),
+            tx,
+        );
+        assert!(!result.success);
+        assert_eq!(result.exit_code, None);
+        assert!(result.error.unwrap().contains("timed out"));
+        assert!(result.duration < 2.0);
+    }
+
+    #[test]
+    fn test_events_keep_streams_apart() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let result = run_process_blocking(
+            &Host::new("web01"),
+            "sh",
+            &[
+                "-c".to_string(),
+                "echo out; echo err >&2; exit 3".to_string(),
+            ],
+            Input::default(),
+            None,
+            tx,
+        );
+        assert_eq!(result.exit_code, Some(3));
+        let mut events: Vec<Event> = rx.try_iter().collect();
+        events.sort_by_key(|event| event.line().map(str::to_string));
+        assert_eq!(
+            events,
+            [Event::stderr("web01", "err"), Event::stdout("web01", "out")]
+        );
+        assert_eq!(
+            serde_json::to_value(Event::completed(&result)).unwrap(),
+            serde_json::json!({
+                "event": "completed",
+                "server": "web01",
+                "exit_code": 3,
+                "success": false,
+            })
+        );
+    }
+
+    #[test]
+    fn test_for_each_line_survives_invalid_utf8() {
+        let input: &[u8] = b"one\r\nt\xffo\nthree";
+        let mut lines = Vec::new();
+        runtime::block_on(for_each_line(input, |line| lines.push(line.to_string())));
+        assert_eq!(lines, ["one", "t\u{fffd}o", "three"]);
+    }
+
+    #[test]
+    fn test_windows_shells() {
+        let options = ExecOptions {
+            nice: Some(10),
+            ..Default::default()
+        };
+        let mut host: Host = "win01 shell=cmd".parse().unwrap();
+        host.pre_commands = vec!["cd C:\\app".to_string()];
+        assert_eq!(options.host_command(&host, "dir"), "cd C:\\app && dir");
+
+        host.shell = Some(Shell::Powershell);
+        let wrapped = options.host_command(&host, "Get-Service \"W32Time\"");
+        let encoded = wrapped
+            .strip_prefix("powershell -NoProfile -NonInteractive -EncodedCommand ")
+            .unwrap();
+        let bytes = BASE64.decode(encoded).unwrap();
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+            .collect();
+        assert_eq!(
+            String::from_utf16(&units).unwrap(),
+            "cd C:\\app; if (-not $?) { exit 1 }; Get-Service \"W32Time\""
+        );
+        assert_eq!(options.posix_only_flags(), ["--nice"]);
+    }
+
+    #[test]
+    fn test_escalated_script() {
+        struct Sudo;
+        impl auth::AuthHandler for Sudo {
+            fn password(&self, _prompt: &str) -> Option<String> {
+                Some("hunter2".to_string())
+            }
+        }
+        let script = crate::script::Script::new("echo ran\n".to_string());
+        let options = ExecOptions {
+            escalate: true,
+            become_password: escalation::ask_password(&Sudo),
+            stdin: Some(script.text.as_str().into()),
+            ..Default::default()
+        };
+        let host = Host::new("web01");
+        let command = options.host_command(&host, &script.command(&[]));
+        assert_eq!(
+            command,
+            escalation::wrap(BecomeMethod::Sudo, None, "sh -s", true, true)
+        );
+        assert!(!command.contains("/dev/null"));
+        let stdin = options.stdin_for(&host).unwrap();
+        assert_eq!(stdin, "hunter2\n__RUSSH_STDIN__\necho ran\n");
+
+        // Whether or not sudo read the password, the script is what the
+        // escalated shell runs, and the password never is.
+        #[cfg(unix)]
+        for stdin in [stdin.as_str(), "__RUSSH_STDIN__\necho ran\n"] {
+            use std::io::Write;
+            let inner = command
+                .strip_prefix("sudo -S -k -p '' -u root -- ")
+                .unwrap();
+            let mut child = Command::new("sh")
+                .args(["-c", inner])
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .spawn()
+                .unwrap();
+            child
+                .stdin
+                .take()
+                .unwrap()
+                .write_all(stdin.as_bytes())
+                .unwrap();
+            let output = child.wait_with_output().unwrap();
+            assert_eq!(String::from_utf8_lossy(&output.stdout), "ran\n");
+        }
+    }
+
+    #[test]
+    fn test_env() {
+        let options = ExecOptions {
+            env: vec![
+                "RELEASE=v1.2".parse().unwrap(),
+                "GREETING=it's me".parse().unwrap(),
+            ],
+            escalate: true,
+            ..Default::default()
+        };
+        let mut host = Host::new("web01");
+        host.pre_commands = vec!["cd /srv/app".to_string()];
+        assert_eq!(
+            options.host_command(&host, "make"),
+            escalation::wrap(
+                BecomeMethod::Sudo,
+                None,
+                "export RELEASE=v1.2 GREETING='it'\\''s me'; cd /srv/app && make",
+                false,
+                false
+            )
+        );
+        host.shell = Some(Shell::Cmd);
+        assert_eq!(
+            options.host_command(&host, "dir"),
+            "(set RELEASE=v1.2) && (set GREETING=it's me) && cd /srv/app && dir"
+        );
+        let options = ExecOptions {
+            env: vec![r#"NOTE=50% off & "free" <now> | ^(maybe)"#.parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(
+            options.host_command(&host, "dir"),
+            r#"(set NOTE=50^% off ^& ^"free^" ^<now^> ^| ^^^(maybe^)) && cd /srv/app && dir"#
+        );
+        assert!("1X=y".parse::<EnvVar>().is_err());
+        assert!("NOVALUE".parse::<EnvVar>().is_err());
+        assert_eq!("EMPTY=".parse::<EnvVar>().unwrap().value, "");
+    }
+
+    #[test]
+    fn test_local_env() {
+        let mut host: Host = "web01 clean_env=true env_passthrough=SSH_AUTH_SOCK,LC_*"
+            .parse()
+            .unwrap();
+        assert!(passed_through("HOME", &host.env_passthrough));
+        assert!(passed_through("SSH_AUTH_SOCK", &host.env_passthrough));
+        assert!(passed_through("LC_ALL", &host.env_passthrough));
+        assert!(!passed_through("LANG", &host.env_passthrough));
+        assert!(!passed_through("SSH_AUTH_SOCKET", &host.env_passthrough));
+        let kept = local_env(&host).unwrap();
+        assert!(kept.iter().any(|(name, _)| name == "PATH"));
+        assert!(kept
+            .iter()
+            .all(|(name, _)| passed_through(name.to_str().unwrap(), &host.env_passthrough)));
+        host.clean_env = Some(false);
+        assert_eq!(local_env(&host), None);
+    }
+
+    #[test]
+    fn test_ssh_args() {
+        let options = ExecOptions {
+            env: vec!["RELEASE=v1.2".parse().unwrap()],
+            answers: vec![r"Are you sure\?=y".parse().unwrap()],
+            ..Default::default()
+        };
+        let host: Host = "web01 user=deploy port=2222".parse().unwrap();
+        assert_eq!(
+            options.ssh_args(&host, "echo {host}"),
+            [
+                "-tt",
+                "-p",
+                "2222",
+                "--",
+                "deploy@web01",
+                "export RELEASE=v1.2; echo web01"
+            ]
+        );
+        assert_eq!(
+            ExecOptions::default().ssh_args(&Host::new("web02"), "uptime"),
+            ["--", "web02", "uptime"]
+        );
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let options = ExecOptions {
+            retries: 3,
+            retry_delay: Duration::from_millis(500),
+            ..Default::default()
+        };
+        assert_eq!(options.retry_backoff(1), Duration::from_millis(500));
+        assert_eq!(options.retry_backoff(2), Duration::from_secs(1));
+        assert_eq!(options.retry_backoff(3), Duration::from_secs(2));
+        assert_eq!(options.retry_backoff(20), Duration::from_secs(32));
+    }
+
+    #[test]
+    fn test_remote_timing() {
+        let options = ExecOptions {
+            remote_timing: true,
+            ..Default::default()
+        };
+        let wrapped = options.remote_command("sleep 1");
+        assert!(wrapped.starts_with("if command -v bash"));
+        assert!(wrapped.contains(TIMING_MARKER));
+
+        assert_eq!(
+            parse_timing("__RUSSH_TIME__ 1.02 0.01 0.00"),
+            Some(RemoteTime {
+                real: 1.02,
+                user: 0.01,
+                sys: 0.0
+            })
+        );
+        assert_eq!(parse_timing("real 1.02"), None);
+    }
+
     // The modified version of run_ssh_command that takes Output and Duration as arguments
     fn run_ssh_command_with_output(
         server: &str,
@@ -172,16 +1649,17 @@ mod tests {
             true => ServerResult {
                 server: server.to_string(),
                 output: String::from_utf8_lossy(&output.stdout).to_string(),
-                error: None,
                 duration: duration_secs,
                 success: output.status.success(),
+                exit_code: output.status.code(),
+                ..Default::default()
             },
             false => ServerResult {
                 server: server.to_string(),
-                output: String::new(),
                 error: Some(String::from_utf8_lossy(&output.stderr).to_string()),
                 duration: duration_secs,
-                success: false,
+                exit_code: output.status.code(),
+                ..Default::default()
             },
         }
     }
@@ -0,0 +1,600 @@
+//! In-process SSH transport built on libssh2, used with `--backend native`.
+//! Unlike the OpenSSH backend it doesn't need an `ssh` binary on the machine
+//! running russh. Authentication tries the SSH agent first, then the
+//! default key files, keyboard-interactive and a password; host keys are
+//! verified against `~/.ssh/known_hosts`. Passphrases, challenges,
+//! passwords and unknown host keys go to the run's [`AuthHandler`].
+
+use super::{
+    parse_timing, timed_out, Capture, Event, LineSplitter, RemoteTime, ServerResult,
+    CHANGED_MARKER, SSH_CONNECTION_FAILED,
+};
+use crate::askpass;
+use crate::audit::current_user;
+use crate::auth::{key_is_encrypted, AuthHandler, Challenge, UnknownHostKey};
+use crate::chain;
+use crate::config::Host;
+use crate::crypto::CryptoPolicy;
+use crate::known_hosts::{self, HostKeyPolicy};
+use crate::multiplex;
+use crate::warnings::{warn, WarningKind};
+use base64::engine::general_purpose::STANDARD_NO_PAD as BASE64;
+use base64::Engine;
+use ssh2::{
+    Channel, CheckResult, HashType, HostKeyType, KeyboardInteractivePrompt, KnownHostFileKind,
+    KnownHostKeyFormat, MethodType, Prompt, Session,
+};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const DEFAULT_PORT: u16 = 22;
+const DEFAULT_KEYS: &[&str] = &["id_ed25519", "id_ecdsa", "id_rsa"];
+
+fn ssh_dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".ssh"))
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Opens the TCP connection, giving up after `timeout` (the host's
+/// `--timeout`, cut short by `--deadline`) instead of whenever the OS does.
+fn tcp_connect(host: &str, port: u16, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    let Some(timeout) = timeout else {
+        return TcpStream::connect((host, port));
+    };
+    let start = Instant::now();
+    let mut failed = None;
+    for addr in (host, port).to_socket_addrs()? {
+        let left = timeout.saturating_sub(start.elapsed());
+        if left.is_zero() {
+            break;
+        }
+        match TcpStream::connect_timeout(&addr, left) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => failed = Some(e),
+        }
+    }
+    Err(failed.unwrap_or_else(|| io::Error::new(ErrorKind::TimedOut, "timed out")))
+}
+
+fn connect(
+    host: &Host,
+    timeout: Option<Duration>,
+    auth: &dyn AuthHandler,
+) -> Result<Session, String> {
+    let port = host.port.unwrap_or(DEFAULT_PORT);
+    let tcp = tcp_connect(&host.host, port, timeout)
+        .map_err(|e| format!("connection to {}:{} failed: {}", host.host, port, e))?;
+    let mut session = Session::new().map_err(|e| e.to_string())?;
+    session.set_tcp_stream(tcp);
+    prefer_algorithms(&session, host)?;
+    // A server that accepts the connection and then says nothing mustn't
+    // hold the host past its timeout either.
+    let millis = timeout.map_or(0, |t| t.as_millis().clamp(1, u32::MAX as u128) as u32);
+    session.set_timeout(millis);
+    session
+        .handshake()
+        .map_err(|e| format!("SSH handshake failed: {}", e))?;
+    session.set_timeout(0);
+
+    verify_host_key(&session, host, auth)?;
+    authenticate(&session, host, auth)?;
+    Ok(session)
+}
+
+/// Limits the session to `host`'s `crypto_policy`. libssh2 offers the
+/// legacy algorithms `compat` adds anyway, and skips names it doesn't know.
+fn prefer_algorithms(session: &Session, host: &Host) -> Result<(), String> {
+    let Some(policy) = host.crypto_policy.filter(|&p| p != CryptoPolicy::Compat) else {
+        return Ok(());
+    };
+    let algorithms = policy.algorithms();
+    for (method, prefs) in [
+        (MethodType::Kex, algorithms.kex),
+        (MethodType::HostKey, algorithms.host_keys),
+        (MethodType::CryptCs, algorithms.ciphers),
+        (MethodType::CryptSc, algorithms.ciphers),
+        (MethodType::MacCs, algorithms.macs),
+        (MethodType::MacSc, algorithms.macs),
+    ] {
+        session.method_pref(method, prefs).map_err(|e| {
+            format!(
+                "crypto_policy {}: libssh2 supports none of {}: {}",
+                policy, prefs, e
+            )
+        })?;
+    }
+    Ok(())
+}
+
+fn verify_host_key(session: &Session, host: &Host, auth: &dyn AuthHandler) -> Result<(), String> {
+    let (hostname, port) = (host.host.as_str(), host.port.unwrap_or(DEFAULT_PORT));
+    let policy = host.host_key_policy;
+    if policy == Some(HostKeyPolicy::Insecure) {
+        return Ok(());
+    }
+    let (key, key_type) = session
+        .host_key()
+        .ok_or_else(|| "server sent no host key".to_string())?;
+    let read = |file: &Path| {
+        let mut known_hosts = session.known_hosts().map_err(|e| e.to_string())?;
+        if file.exists() {
+            known_hosts
+                .read_file(file, KnownHostFileKind::OpenSSH)
+                .map_err(|e| format!("failed to read {}: {}", file.display(), e))?;
+        }
+        Ok::<_, String>(known_hosts)
+    };
+    let user_file = ssh_dir().map(|dir| dir.join("known_hosts"));
+    let files: Vec<&Path> = known_hosts::pinned()
+        .into_iter()
+        .chain(user_file.as_deref())
+        .collect();
+    let mut failed = false;
+    for file in &files {
+        match read(file)?.check_port(hostname, port, key) {
+            CheckResult::Match => return Ok(()),
+            CheckResult::Mismatch => {
+                return Err(format!(
+                    "host key for {} does not match {}, refusing to connect",
+                    hostname,
+                    file.display()
+                ))
+            }
+            CheckResult::NotFound => {}
+            CheckResult::Failure => failed = true,
+        }
+    }
+    if failed {
+        return Err(format!("could not verify the host key of {}", hostname));
+    }
+
+    let unknown = UnknownHostKey {
+        host: hostname.to_string(),
+        port,
+        key_type: key_type_name(key_type).to_string(),
+        fingerprint: session
+            .host_key_hash(HashType::Sha256)
+            .map(|hash| format!("SHA256:{}", BASE64.encode(hash)))
+            .unwrap_or_default(),
+    };
+    let trusted = trusts_unknown_key(policy, &unknown, auth);
+    // Keys accepted are remembered where ssh would put them.
+    let (Some(file), true) = (files.first(), trusted) else {
+        return Err(format!(
+            "{} is not in known_hosts; connect once with ssh to verify its key, or pin it with russh known-hosts",
+            hostname
+        ));
+    };
+    let entry = match port {
+        DEFAULT_PORT => hostname.to_string(),
+        port => format!("[{}]:{}", hostname, port),
+    };
+    let mut known_hosts = read(file)?;
+    known_hosts
+        .add(
+            &entry,
+            key,
+            "added by russh",
+            KnownHostKeyFormat::from(key_type),
+        )
+        .and_then(|()| known_hosts.write_file(file, KnownHostFileKind::OpenSSH))
+        .map_err(|e| format!("could not add {} to {}: {}", entry, file.display(), e))
+}
+
+/// Whether a host key found in no known_hosts file is trusted: never
+/// under `strict`, always under `accept-new`, else if `auth` says so.
+fn trusts_unknown_key(
+    policy: Option<HostKeyPolicy>,
+    unknown: &UnknownHostKey,
+    auth: &dyn AuthHandler,
+) -> bool {
+    match policy {
+        Some(HostKeyPolicy::Strict) => false,
+        Some(HostKeyPolicy::AcceptNew) => true,
+        _ => auth.confirm_host_key(unknown),
+    }
+}
+
+fn key_type_name(key_type: HostKeyType) -> &'static str {
+    match key_type {
+        HostKeyType::Rsa => "ssh-rsa",
+        HostKeyType::Dss => "ssh-dss",
+        HostKeyType::Ecdsa256 => "ecdsa-sha2-nistp256",
+        HostKeyType::Ecdsa384 => "ecdsa-sha2-nistp384",
+        HostKeyType::Ecdsa521 => "ecdsa-sha2-nistp521",
+        HostKeyType::Ed25519 => "ssh-ed25519",
+        HostKeyType::Unknown => "unknown",
+    }
+}
+
+/// Passes a keyboard-interactive challenge on to the `AuthHandler`.
+struct Challenged<'a> {
+    host: &'a str,
+    auth: &'a dyn AuthHandler,
+}
+
+impl KeyboardInteractivePrompt for Challenged<'_> {
+    fn prompt<'b>(
+        &mut self,
+        _user: &str,
+        instructions: &str,
+        prompts: &[Prompt<'b>],
+    ) -> Vec<String> {
+        let challenges: Vec<Challenge> = prompts
+            .iter()
+            .map(|prompt| Challenge {
+                text: prompt.text.to_string(),
+                echo: prompt.echo,
+            })
+            .collect();
+        // A lone password challenge is how many appliances ask for it.
+        if let (Some(password), [challenge]) = (askpass::password(), challenges.as_slice()) {
+            if !challenge.echo && askpass::asks_password(&challenge.text) {
+                return vec![password.to_string()];
+            }
+        }
+        self.auth
+            .keyboard_interactive(self.host, instructions, &challenges)
+            .unwrap_or_default()
+    }
+}
+
+fn authenticate(session: &Session, host: &Host, auth: &dyn AuthHandler) -> Result<(), String> {
+    let user = host.user.clone().unwrap_or_else(current_user);
+    if host.use_agent != Some(false)
+        && session.userauth_agent(&user).is_ok()
+        && session.authenticated()
+    {
+        return Ok(());
+    }
+    let mut keys: Vec<PathBuf> = host.identity_file.iter().map(|p| expand_home(p)).collect();
+    if let Some(dir) = ssh_dir() {
+        keys.extend(DEFAULT_KEYS.iter().map(|key| dir.join(key)));
+    }
+    for private_key in keys {
+        let Ok(text) = std::fs::read_to_string(&private_key) else {
+            continue;
+        };
+        let passphrase = match key_is_encrypted(&text) {
+            true => match auth.passphrase(&private_key) {
+                Some(passphrase) => Some(passphrase),
+                None => continue,
+            },
+            false => None,
+        };
+        if session
+            .userauth_pubkey_file(&user, None, &private_key, passphrase.as_deref())
+            .is_ok()
+            && session.authenticated()
+        {
+            return Ok(());
+        }
+    }
+    let methods = session.auth_methods(&user).unwrap_or_default().to_string();
+    if methods.contains("keyboard-interactive") {
+        let mut challenged = Challenged {
+            host: &host.host,
+            auth,
+        };
+        if session
+            .userauth_keyboard_interactive(&user, &mut challenged)
+            .is_ok()
+            && session.authenticated()
+        {
+            return Ok(());
+        }
+    }
+    if methods.contains("password") {
+        let prompt = format!("{}@{}'s password: ", user, host.host);
+        let password = askpass::password()
+            .map(str::to_string)
+            .or_else(|| auth.password(&prompt));
+        if let Some(password) = password {
+            if session.userauth_password(&user, &password).is_ok() && session.authenticated() {
+                return Ok(());
+            }
+        }
+    }
+    Err(format!(
+        "authentication failed for {}@{} (tried the SSH agent, identity_file, default keys, keyboard-interactive and password)",
+        user, host.host
+    ))
+}
+
+/// Sessions whose command finished, by [`pool_key`], for the next
+/// command to the same host when connections are reused.
+static IDLE: Mutex<Vec<(String, Session)>> = Mutex::new(Vec::new());
+
+fn pool_key(host: &Host) -> String {
+    format!(
+        "{}@{}:{}",
+        host.user.clone().unwrap_or_else(current_user),
+        host.host,
+        host.port.unwrap_or(DEFAULT_PORT)
+    )
+}
+
+fn give_back(host: &Host, session: Session) {
+    let mut idle = IDLE.lock().unwrap_or_else(|e| e.into_inner());
+    idle.push((pool_key(host), session));
+}
+
+/// A channel to `host`: on an idle session if there is one still alive,
+/// else on a new connection.
+fn open(
+    host: &Host,
+    timeout: Option<Duration>,
+    auth: &dyn AuthHandler,
+) -> Result<(Session, Channel), String> {
+    let key = pool_key(host);
+    loop {
+        let reused = {
+            let mut idle = IDLE.lock().unwrap_or_else(|e| e.into_inner());
+            idle.iter()
+                .position(|(k, _)| *k == key)
+                .map(|i| idle.swap_remove(i).1)
+        };
+        let Some(session) = reused else { break };
+        // The server may have closed it while it sat idle.
+        if let Ok(channel) = session.channel_session() {
+            return Ok((session, channel));
+        }
+    }
+    let session = connect(host, timeout, auth)?;
+    let channel = session.channel_session().map_err(|e| e.to_string())?;
+    Ok((session, channel))
+}
+
+pub fn run(
+    host: &Host,
+    command: &str,
+    input: Option<&str>,
+    timeout: Option<Duration>,
+    auth: &dyn AuthHandler,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let start = Instant::now();
+    let stderr_line = |line: String| Event::stderr(&host.host, line);
+    let failure = |error: String, exit_code: Option<i32>| ServerResult {
+        server: host.host.clone(),
+        error: Some(error),
+        duration: start.elapsed().as_secs_f64(),
+        exit_code,
+        ..Default::default()
+    };
+
+    if !host.option_args().is_empty() {
+        warn(
+            WarningKind::IgnoredOption,
+            format!(
+                "ssh options for {} are ignored by the native backend",
+                host.host
+            ),
+        );
+    }
+
+    if let Some(jump) = host.jump() {
+        let e = format!(
+            "{} is reached through {}; jump hosts need the openssh backend",
+            host.host, jump
+        );
+        let _ = tx.send(stderr_line(e.clone()));
+        return failure(e, Some(SSH_CONNECTION_FAILED));
+    }
+
+    let (session, mut channel) = match open(host, timeout, auth) {
+        Ok(opened) => opened,
+        Err(e) => {
+            let _ = tx.send(stderr_line(e.clone()));
+            return failure(e, Some(SSH_CONNECTION_FAILED));
+        }
+    };
+
+    let mut exec = || -> Result<Finished, String> {
+        channel.exec(command).map_err(|e| e.to_string())?;
+        if let Some(input) = input {
+            channel
+                .write_all(input.as_bytes())
+                .and_then(|()| channel.send_eof().map_err(Into::into))
+                .map_err(|e| e.to_string())?;
+        }
+
+        // Poll both streams so neither can fill up and stall the other.
+        session.set_blocking(false);
+        let mut stderr = channel.stderr();
+        let (mut out_split, mut err_split) = (LineSplitter::default(), LineSplitter::default());
+        let (mut out_lines, mut err_lines) = (Vec::new(), Vec::new());
+        let (mut stdout, mut stderr_text) = (Capture::default(), Capture::default());
+        let mut chunk = [0u8; 8192];
+        let mut finished = Finished::default();
+        // The channel's EOF can arrive while stderr still has data buffered,
+        // so the run only ends once both streams have read empty after it.
+        let (mut out_done, mut err_done) = (false, false);
+        let mut ended = false;
+        while !ended {
+            let mut progressed = false;
+            match channel.read(&mut chunk) {
+                Ok(0) => out_done = channel.eof(),
+                Ok(n) => {
+                    out_split.push(&chunk[..n], &mut |line| out_lines.push(line.to_string()));
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.to_string()),
+            }
+            match stderr.read(&mut chunk) {
+                Ok(0) => err_done = channel.eof(),
+                Ok(n) => {
+                    err_split.push(&chunk[..n], &mut |line| err_lines.push(line.to_string()));
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(e.to_string()),
+            }
+            if out_done && err_done && !progressed {
+                ended = true;
+                out_split.finish(&mut |line| out_lines.push(line.to_string()));
+                err_split.finish(&mut |line| err_lines.push(line.to_string()));
+            }
+            for line in out_lines.drain(..) {
+                if line.trim() == CHANGED_MARKER {
+                    finished.changed = true;
+                    continue;
+                }
+                stdout.push_line(&line);
+                if let Some(line) = chain::shown(&line) {
+                    let _ = tx.send(Event::stdout(&host.host, line));
+                }
+            }
+            for line in err_lines.drain(..) {
+                if let Some(timing) = parse_timing(&line) {
+                    finished.remote_time = Some(timing);
+                    continue;
+                }
+                stderr_text.push_line(&line);
+                if let Some(line) = chain::shown(&line) {
+                    let _ = tx.send(stderr_line(line.to_string()));
+                }
+            }
+            if ended {
+                break;
+            }
+            if let Some(timeout) = timeout.filter(|t| start.elapsed() >= *t) {
+                let _ = channel.close();
+                return Err(timed_out(timeout));
+            }
+            if !progressed {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+        finished.stdout = stdout.into_string();
+        finished.stderr = stderr_text.into_string();
+
+        session.set_blocking(true);
+        channel.wait_close().map_err(|e| e.to_string())?;
+        finished.exit_code = channel.exit_status().map_err(|e| e.to_string())?;
+        Ok(finished)
+    };
+
+    let finished = exec();
+    if finished.is_ok() && multiplex::enabled() {
+        give_back(host, session);
+    }
+    match finished {
+        Ok(finished) => ServerResult {
+            server: host.host.clone(),
+            output: finished.stdout,
+            error: Some(finished.stderr).filter(|text| !text.is_empty()),
+            duration: start.elapsed().as_secs_f64(),
+            success: finished.exit_code == 0,
+            changed: finished.changed,
+            exit_code: Some(finished.exit_code),
+            remote_time: finished.remote_time,
+            ..Default::default()
+        },
+        Err(e) => {
+            let _ = tx.send(stderr_line(e.clone()));
+            failure(e, None)
+        }
+    }
+}
+
+/// What a completed remote command produced.
+#[derive(Default)]
+struct Finished {
+    exit_code: i32,
+    changed: bool,
+    remote_time: Option<RemoteTime>,
+    stdout: String,
+    stderr: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::warnings;
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Answers every host key question with its `0`.
+    struct Confirm(bool);
+
+    impl AuthHandler for Confirm {
+        fn confirm_host_key(&self, _key: &UnknownHostKey) -> bool {
+            self.0
+        }
+    }
+
+    #[test]
+    fn test_tcp_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        assert!(tcp_connect("127.0.0.1", port, Some(Duration::from_secs(5))).is_ok());
+        assert!(tcp_connect("127.0.0.1", port, None).is_ok());
+        // A timeout used up before the first attempt never connects.
+        let error = tcp_connect("127.0.0.1", port, Some(Duration::ZERO)).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        // An address that never answers gives up at the timeout.
+        let start = Instant::now();
+        assert!(tcp_connect("192.0.2.1", 22, Some(Duration::from_millis(200))).is_err());
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_trusts_unknown_key() {
+        let unknown = UnknownHostKey {
+            host: "web01".to_string(),
+            port: 22,
+            key_type: "ssh-ed25519".to_string(),
+            fingerprint: "SHA256:abc".to_string(),
+        };
+        let (yes, no) = (Confirm(true), Confirm(false));
+        assert!(!trusts_unknown_key(
+            Some(HostKeyPolicy::Strict),
+            &unknown,
+            &yes
+        ));
+        assert!(trusts_unknown_key(
+            Some(HostKeyPolicy::AcceptNew),
+            &unknown,
+            &no
+        ));
+        assert!(trusts_unknown_key(Some(HostKeyPolicy::Ask), &unknown, &yes));
+        assert!(!trusts_unknown_key(Some(HostKeyPolicy::Ask), &unknown, &no));
+        assert!(trusts_unknown_key(None, &unknown, &yes));
+        assert!(!trusts_unknown_key(None, &unknown, &no));
+    }
+
+    #[test]
+    fn test_jump_hosts_and_options_refused() {
+        let host = Host {
+            jump_host: Some("ops@bastion".to_string()),
+            options: Some("-o ConnectTimeout=5".to_string()),
+            ..Host::new("native-web01")
+        };
+        let (tx, rx) = mpsc::channel();
+        let result = run(&host, "uptime", None, None, &Confirm(false), tx);
+        assert!(!result.success);
+        assert_eq!(result.exit_code, Some(SSH_CONNECTION_FAILED));
+        let error = result.error.unwrap();
+        assert!(error.contains("jump hosts need the openssh backend"));
+        assert!(rx.try_iter().any(|event| matches!(
+            event,
+            Event::StderrLine { line, .. } if line == error
+        )));
+        assert!(warnings::take()
+            .iter()
+            .any(|w| w.kind == WarningKind::IgnoredOption && w.message.contains("native-web01")));
+    }
+}
@@ -0,0 +1,315 @@
+use crate::i18n::{tr, trf, Msg};
+use crate::preflight::format_bytes;
+use crate::ssh::{Cut, ServerResult};
+use ansi_term::Color::{Green, Purple, Red, Yellow};
+use serde::Serialize;
+use std::env;
+use std::io::{self, IsTerminal};
+use std::sync::OnceLock;
+
+/// How a single command ended on a host, from best to worst.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Changed,
+    /// Never run: past `--deadline`, after `--fail-fast` stopped the run,
+    /// after a failed task command, or cancelled by Ctrl-C.
+    Skipped,
+    Failed,
+}
+
+impl Status {
+    pub fn of(result: &ServerResult) -> Status {
+        if result.cut.is_some_and(|cut| cut != Cut::Cancelled) {
+            Status::Skipped
+        } else if !result.success {
+            Status::Failed
+        } else if result.changed {
+            Status::Changed
+        } else {
+            Status::Ok
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Status::Ok => "ok",
+            Status::Changed => "changed",
+            Status::Failed => "failed",
+            Status::Skipped => "skipped",
+        }
+    }
+
+    /// The status as shown to the user, in their language. `as_str` is the
+    /// name in reports and JSON.
+    pub fn name(self) -> &'static str {
+        tr(match self {
+            Status::Ok => Msg::StatusOk,
+            Status::Changed => Msg::StatusChanged,
+            Status::Failed => Msg::StatusFailed,
+            Status::Skipped => Msg::StatusSkipped,
+        })
+    }
+
+    /// The status padded to the longest one's width and, optionally,
+    /// colored. Padding happens before coloring so escape codes don't break
+    /// the alignment.
+    fn label(self, color: bool) -> String {
+        let width = [Status::Ok, Status::Changed, Status::Failed, Status::Skipped]
+            .iter()
+            .map(|status| status.name().chars().count())
+            .max()
+            .unwrap_or_default();
+        self.paint(format!("{:<width$}", self.name(), width = width), color)
+    }
+
+    fn paint(self, text: String, color: bool) -> String {
+        if !color {
+            return text;
+        }
+        match self {
+            Status::Ok => Green.paint(text).to_string(),
+            Status::Changed => Yellow.paint(text).to_string(),
+            Status::Failed => Red.paint(text).to_string(),
+            Status::Skipped => Purple.paint(text).to_string(),
+        }
+    }
+}
+
+/// One host's results, in the order its commands ran.
+pub struct HostResults<'a> {
+    pub server: &'a str,
+    /// The worst status of the host's commands.
+    pub status: Status,
+    pub commands: Vec<(&'a str, &'a ServerResult)>,
+}
+
+/// Groups results by host, hosts in the order they first appear.
+pub fn by_host(results: &[(String, ServerResult)]) -> Vec<HostResults<'_>> {
+    let mut hosts: Vec<HostResults> = Vec::new();
+    for (command, result) in results {
+        let status = Status::of(result);
+        match hosts.iter_mut().find(|host| host.server == result.server) {
+            Some(host) => {
+                host.status = host.status.max(status);
+                host.commands.push((command, result));
+            }
+            None => hosts.push(HostResults {
+                server: &result.server,
+                status,
+                commands: vec![(command, result)],
+            }),
+        }
+    }
+    hosts
+}
+
+/// Counts of each status, in the order ok, changed, failed; skipped jobs
+/// aren't counted.
+pub fn tally(results: &[(String, ServerResult)]) -> (usize, usize, usize) {
+    results.iter().fold(
+        (0, 0, 0),
+        |(ok, changed, failed), (_, result)| match Status::of(result) {
+            Status::Ok => (ok + 1, changed, failed),
+            Status::Changed => (ok, changed + 1, failed),
+            Status::Failed => (ok, changed, failed + 1),
+            Status::Skipped => (ok, changed, failed),
+        },
+    )
+}
+
+/// Whether to color terminal output: only when stdout is a terminal and
+/// `NO_COLOR` is unset. Worked out once, on first use.
+pub fn color_enabled() -> bool {
+    static COLOR: OnceLock<bool> = OnceLock::new();
+    *COLOR.get_or_init(|| io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none())
+}
+
+/// Prints one line per host and command, followed by the totals.
+pub fn print_summary(results: &[(String, ServerResult)]) {
+    if results.is_empty() {
+        return;
+    }
+    println!();
+    print!("{}", format_summary(results, color_enabled()));
+}
+
+/// The summary as text, with or without terminal colors. When hosts ran
+/// several commands, each host gets a block with its overall status and
+/// its commands numbered in the order they ran.
+pub fn format_summary(results: &[(String, ServerResult)], color: bool) -> String {
+    let paint = |style: ansi_term::Colour, text: String| {
+        if color {
+            style.paint(text).to_string()
+        } else {
+            text
+        }
+    };
+    let hosts = by_host(results);
+    let grouped = hosts.len() < results.len();
+    let width = results
+        .iter()
+        .map(|(_, result)| result.server.len())
+        .max()
+        .unwrap_or_default();
+
+    let mut out = String::new();
+    out.push_str(tr(Msg::SummaryHeader));
+    out.push('\n');
+    out.push_str("-----------------------------\n");
+    for host in &hosts {
+        if grouped {
+            let status = host.status.paint(host.status.name().to_string(), color);
+            out.push_str(&format!("{}  {}\n", host.server, status));
+        }
+        let total = host.commands.len();
+        for (i, (command, result)) in host.commands.iter().enumerate() {
+            let label = if grouped {
+                format!("  [{}/{}]", i + 1, total)
+            } else {
+                format!("{:<width$}", host.server, width = width)
+            };
+            out.push_str(&format_line(&label, command, result, color));
+        }
+    }
+    let (ok, changed, failed) = tally(results);
+    out.push_str(&trf(
+        Msg::SummaryTotals,
+        &[
+            &paint(Green, ok.to_string()),
+            &paint(Yellow, changed.to_string()),
+            &paint(Red, failed.to_string()),
+        ],
+    ));
+    let skipped = results
+        .iter()
+        .filter(|(_, result)| Status::of(result) == Status::Skipped)
+        .count();
+    if skipped > 0 {
+        out.push_str(&trf(
+            Msg::SummarySkipped,
+            &[&paint(Purple, skipped.to_string())],
+        ));
+    }
+    out.push('\n');
+
+    // With several commands per host, also say how each host came out
+    // overall: as its worst result.
+    if grouped {
+        let count = |status| hosts.iter().filter(|host| host.status == status).count();
+        out.push_str(&trf(
+            Msg::SummaryHosts,
+            &[
+                &hosts.len(),
+                &paint(Green, count(Status::Ok).to_string()),
+                &paint(Yellow, count(Status::Changed).to_string()),
+                &paint(Red, count(Status::Failed).to_string()),
+            ],
+        ));
+        if count(Status::Skipped) > 0 {
+            out.push_str(&trf(
+                Msg::SummarySkipped,
+                &[&paint(Purple, count(Status::Skipped).to_string())],
+            ));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// One command's line: `label` (the host, or the command's number in its
+/// host's block), the status, the time, the command and any details.
+fn format_line(label: &str, command: &str, result: &ServerResult, color: bool) -> String {
+    let remote = result
+        .remote_time
+        .map(|t| {
+            format!(
+                "  (remote real {:.2}s, user {:.2}s, sys {:.2}s)",
+                t.real, t.user, t.sys
+            )
+        })
+        .unwrap_or_default();
+    let transfer = result
+        .transfer
+        .map(|t| {
+            format!(
+                "  ({}, {}/s{}{})",
+                format_bytes(t.bytes as f64),
+                format_bytes(t.bytes as f64 / result.duration.max(0.001)),
+                if t.resumed > 0 {
+                    format!(", resumed after {}", format_bytes(t.resumed as f64))
+                } else {
+                    String::new()
+                },
+                if t.verified { ", verified" } else { "" }
+            )
+        })
+        .unwrap_or_default();
+    let attempts = if result.attempts > 1 {
+        format!("  ({})", trf(Msg::SummaryAttempts, &[&result.attempts]))
+    } else {
+        String::new()
+    };
+    let cut = result
+        .cut
+        .map(|cut| format!("  ({})", cut.describe()))
+        .unwrap_or_default();
+    format!(
+        "{}  {}  {:>7.2}s  {}{}{}{}{}\n",
+        label,
+        Status::of(result).label(color),
+        result.duration,
+        command,
+        remote,
+        transfer,
+        attempts,
+        cut
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(success: bool, changed: bool) -> (String, ServerResult) {
+        (
+            "uptime".to_string(),
+            ServerResult {
+                server: "web01".to_string(),
+                duration: 0.5,
+                success,
+                changed,
+                ..Default::default()
+            },
+        )
+    }
+
+    #[test]
+    fn test_tally() {
+        let results = vec![
+            result(true, false),
+            result(true, true),
+            result(false, true),
+            result(false, false),
+        ];
+        assert_eq!(tally(&results), (1, 1, 2));
+        assert_eq!(Status::of(&results[2].1), Status::Failed);
+
+        let mut results = results;
+        results.push((
+            "uptime".to_string(),
+            ServerResult::skipped("web02", Cut::NotStarted),
+        ));
+        assert_eq!(tally(&results), (1, 1, 2));
+        let summary = format_summary(&results, false);
+        // web01 ran four commands, so the summary is grouped by host.
+        assert!(summary.contains("web01  failed\n  [1/4]  ok          0.50s  uptime\n"));
+        assert!(summary.contains(
+            "web02  skipped\n  [1/1]  skipped     0.00s  uptime  (not started, past --deadline)"
+        ));
+        assert!(summary.contains("1 ok, 1 changed, 2 failed, 1 skipped\n"));
+        // All of web01's commands count against it as a host.
+        assert!(summary.ends_with("2 host(s): 0 ok, 0 changed, 1 failed, 1 skipped\n"));
+    }
+}
@@ -0,0 +1,90 @@
+//! Host placeholders in commands: `{host}`, `{user}`, `{port}`, `{group}`
+//! and the host's own variables (`datacenter=fra1` in its spec, or its
+//! `vars` table) are replaced with the host's values before the command
+//! runs there, e.g. `russh "scp backup.tgz {backup_target}:"`.
+//!
+//! Only `{name}` with a name the host has a value for is replaced; other
+//! braces (`${HOME}`, `{1..3}`, awk's `{print $1}`) are left alone for the
+//! shell.
+
+use crate::audit::current_user;
+use crate::config::Host;
+
+/// The value of placeholder `name` on `host`: one of its variables, or a
+/// built-in.
+fn value(host: &Host, name: &str) -> Option<String> {
+    if let Some(value) = host.vars.get(name) {
+        return Some(value.clone());
+    }
+    match name {
+        "host" => Some(host.host.clone()),
+        "user" => Some(host.user.clone().unwrap_or_else(current_user)),
+        "port" => Some(host.port.unwrap_or(22).to_string()),
+        "group" => Some(String::new()),
+        _ => None,
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// `command` with the placeholders `host` has values for filled in.
+pub fn render(command: &str, host: &Host) -> String {
+    let mut rendered = String::with_capacity(command.len());
+    let mut rest = command;
+    while let Some(open) = rest.find('{') {
+        let after = &rest[open + 1..];
+        let replaced = after
+            .find('}')
+            .map(|close| &after[..close])
+            .filter(|name| is_name(name) && !rest[..open].ends_with('$'))
+            .and_then(|name| Some((name.len(), value(host, name)?)));
+        match replaced {
+            Some((len, value)) => {
+                rendered.push_str(&rest[..open]);
+                rendered.push_str(&value);
+                rest = &after[len + 1..];
+            }
+            None => {
+                rendered.push_str(&rest[..=open]);
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let host: Host = "db01 user=postgres port=2222 datacenter=fra1 backup_target=nas01"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            render("scp backup.tgz {backup_target}:{datacenter}/{host}", &host),
+            "scp backup.tgz nas01:fra1/db01"
+        );
+        assert_eq!(
+            render("echo {user}@{host}:{port}", &host),
+            "echo postgres@db01:2222"
+        );
+        assert_eq!(
+            render("echo ${HOME} {1..3} {missing} {} {host", &host),
+            "echo ${HOME} {1..3} {missing} {} {host"
+        );
+        assert_eq!(
+            render("awk '{print $1}' {host}.log", &host),
+            "awk '{print $1}' db01.log"
+        );
+        assert_eq!(render("echo {group}", &host), "echo ");
+    }
+}
@@ -0,0 +1,605 @@
+//! File transfers to and from hosts over the same per-host user, port and
+//! identity as the ssh transport. Single files are streamed through `ssh`
+//! so they can be resumed and verified (see `resume`); directories are
+//! copied with the system `scp` and checked against a manifest afterwards.
+//! With `--become` files go through a private staging directory on the
+//! host, and are moved into place (or read) with escalated privileges.
+
+mod resume;
+
+use self::resume::Manifest;
+use crate::cli::{
+    admit, display_mode, exec_options, inventory, report, schedule, sinks, Cli, PullArgs, PushArgs,
+};
+use crate::config::Host;
+use crate::crypto;
+use crate::errors::{AppError, Result};
+use crate::executor::execute_blocking_jobs;
+use crate::known_hosts;
+use crate::multiplex;
+use crate::ssh::{
+    agent_options, run_process_blocking, run_ssh_command_blocking, shell_quote, Event, ExecOptions,
+    Input, ServerResult, TransferStats,
+};
+use crate::warnings::{warn, WarningKind};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+/// `[user@]host:path`, bracketing IPv6 addresses so scp can split them.
+fn remote_spec(host: &Host, path: &str) -> String {
+    // The Windows OpenSSH server takes forward slashes, which need no
+    // escaping on the way through scp.
+    let path = if host.shell().is_windows() {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    };
+    let address = if host.host.contains(':') {
+        format!("[{}]", host.host)
+    } else {
+        host.host.clone()
+    };
+    match host.user.as_ref().filter(|u| !u.is_empty()) {
+        Some(user) => format!("{}@{}:{}", user, address, path),
+        None => format!("{}:{}", address, path),
+    }
+}
+
+/// Connection arguments shared by every scp invocation for `host`.
+fn scp_options(host: &Host) -> Vec<String> {
+    let mut args = vec!["-q".to_string(), "-B".to_string()];
+    if let Some(port) = host.port {
+        args.push("-P".to_string());
+        args.push(port.to_string());
+    }
+    if let Some(identity_file) = &host.identity_file {
+        args.push("-i".to_string());
+        args.push(identity_file.display().to_string());
+    }
+    // Older scp has no -J.
+    if let Some(jump) = host.jump() {
+        args.push("-o".to_string());
+        args.push(format!("ProxyJump={}", jump));
+    }
+    // Raw ssh options like `-p 22` mean something else to scp; only the
+    // `-o Key=Value` form is understood by both.
+    let mut skipped = Vec::new();
+    let mut options = host.option_args().into_iter();
+    while let Some(option) = options.next() {
+        if option == "-o" {
+            args.push(option);
+            args.extend(options.next());
+        } else if option.starts_with("-o") {
+            args.push(option);
+        } else {
+            skipped.push(option);
+        }
+    }
+    if !skipped.is_empty() {
+        warn(
+            WarningKind::HostSkipped,
+            format!(
+                "ssh options '{}' for {} are not passed to scp, use -o Key=Value instead",
+                skipped.join(" "),
+                host.host
+            ),
+        );
+    }
+    args.extend(agent_options(host));
+    args.extend(known_hosts::ssh_options(host));
+    args.extend(crypto::ssh_options(host));
+    args.extend(multiplex::ssh_options());
+    args
+}
+
+pub fn build_push_args(host: &Host, local: &Path, remote: &str) -> Vec<String> {
+    let mut args = scp_options(host);
+    if local.is_dir() {
+        args.push("-r".to_string());
+    }
+    args.push("--".to_string());
+    args.push(local.display().to_string());
+    args.push(remote_spec(host, remote));
+    args
+}
+
+/// The local side of a push, examined once for all hosts.
+pub struct Source {
+    path: PathBuf,
+    name: String,
+    kind: SourceKind,
+}
+
+enum SourceKind {
+    /// A regular file's size and sha256.
+    File(u64, String),
+    /// A directory's manifest and the total size of its files.
+    Dir(Manifest, u64),
+}
+
+impl Source {
+    /// The file or directory name the copy gets on the host.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The sha256 of the file, or of a directory's manifest, so an
+    /// approval covers the content and not just the path.
+    pub fn checksum(&self) -> String {
+        match &self.kind {
+            SourceKind::File(_, digest) => digest.clone(),
+            SourceKind::Dir(manifest, _) => {
+                let mut hasher = Sha256::new();
+                for (path, digest) in manifest {
+                    hasher.update(format!("{} {}\n", digest, path));
+                }
+                hasher
+                    .finalize()
+                    .iter()
+                    .map(|b| format!("{:02x}", b))
+                    .collect()
+            }
+        }
+    }
+
+    pub fn open(path: &Path) -> Result<Source> {
+        let unreadable =
+            |e: std::io::Error| AppError::Generic(format!("cannot read {}: {}", path.display(), e));
+        let kind = if path.is_dir() {
+            let (manifest, bytes) = resume::local_manifest(path).map_err(unreadable)?;
+            SourceKind::Dir(manifest, bytes)
+        } else {
+            let size = fs::metadata(path).map_err(unreadable)?.len();
+            SourceKind::File(size, resume::sha256_file(path).map_err(unreadable)?)
+        };
+        Ok(Source {
+            path: path.to_path_buf(),
+            name: path
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            kind,
+        })
+    }
+}
+
+/// Copies `source` to `remote` on `host`. Files are resumed and verified,
+/// directories copied with scp and verified afterwards. Windows hosts get
+/// a plain scp.
+pub fn push(
+    host: &Host,
+    source: &Source,
+    remote: &str,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    if host.shell().is_windows() {
+        return run_process_blocking(
+            host,
+            "scp",
+            &build_push_args(host, &source.path, remote),
+            Input::default(),
+            None,
+            tx,
+        );
+    }
+    let login = login_user(host);
+    let mut steps = Steps::default();
+    let staging = if options.escalates(host) {
+        match steps.stage(host, options, &tx) {
+            Some((staging, _)) => Some(staging),
+            None => return steps.finish(),
+        }
+    } else {
+        None
+    };
+    let target = staging.as_deref().unwrap_or(remote);
+    let copied = match &source.kind {
+        SourceKind::File(size, digest) => steps.run(resume::upload(
+            &login,
+            &source.path,
+            (*size, digest),
+            target,
+            options,
+            tx.clone(),
+        )),
+        SourceKind::Dir(manifest, bytes) => {
+            steps.run(run_process_blocking(
+                host,
+                "scp",
+                &build_push_args(host, &source.path, target),
+                Input::default(),
+                None,
+                tx.clone(),
+            )) && steps.run(with_stats(
+                resume::verify_pushed_dir(&login, &source.name, target, manifest, options),
+                *bytes,
+            ))
+        }
+    };
+    if let Some(staging) = staging {
+        if copied {
+            let staged = format!("{}/{}", staging, source.name);
+            steps.run(run_ssh_command_blocking(
+                host,
+                &format!(
+                    "cp -R {} {}; status=$?; rm -rf {}; exit $status",
+                    shell_quote(&staged),
+                    shell_quote(remote),
+                    shell_quote(&staging)
+                ),
+                options,
+                tx,
+            ));
+        } else {
+            steps.cleanup(host, options, &staging, tx);
+        }
+    }
+    steps.finish()
+}
+
+/// `host` for the steps that run as the login user, even where the host
+/// itself says `become = true`.
+pub fn login_user(host: &Host) -> Host {
+    Host {
+        escalate: Some(false),
+        ..host.clone()
+    }
+}
+
+/// The parts of an escalated transfer, reported as one result: the first
+/// failure, or the last step, with the time of all of them.
+#[derive(Default)]
+struct Steps {
+    duration: f64,
+    last: Option<ServerResult>,
+    transfer: Option<TransferStats>,
+}
+
+impl Steps {
+    /// Records a step; returns whether it succeeded.
+    fn run(&mut self, result: ServerResult) -> bool {
+        self.duration += result.duration;
+        self.transfer = result.transfer.or(self.transfer);
+        let success = result.success;
+        if self.last.as_ref().is_none_or(|last| last.success) {
+            self.last = Some(result);
+        }
+        success
+    }
+
+    /// Creates a private staging directory as the login user and returns
+    /// it along with that user's name.
+    fn stage(
+        &mut self,
+        host: &Host,
+        options: &ExecOptions,
+        tx: &Sender<Event>,
+    ) -> Option<(String, String)> {
+        let login = login_user(host);
+        let (quiet, _) = std::sync::mpsc::channel();
+        let result = run_ssh_command_blocking(&login, "id -un && mktemp -d", options, quiet);
+        if !result.success {
+            let _ = tx.send(Event::stderr(
+                &host.host,
+                "could not create a staging directory",
+            ));
+        }
+        let mut lines = result.output.lines().map(str::to_string);
+        let staged = lines.next().zip(lines.next());
+        self.run(ServerResult {
+            output: String::new(),
+            ..result
+        });
+        staged
+    }
+
+    fn cleanup(&mut self, host: &Host, options: &ExecOptions, staging: &str, tx: Sender<Event>) {
+        let login = login_user(host);
+        let command = format!("rm -rf {}", shell_quote(staging));
+        self.run(run_ssh_command_blocking(&login, &command, options, tx));
+    }
+
+    fn finish(self) -> ServerResult {
+        let mut result = self.last.unwrap_or_else(|| ServerResult {
+            error: Some("nothing was transferred".to_string()),
+            ..Default::default()
+        });
+        result.duration = self.duration;
+        result.transfer = self.transfer.map(|stats| TransferStats {
+            verified: stats.verified && result.success,
+            ..stats
+        });
+        result
+    }
+}
+
+/// Marks a directory copy whose verification step is `result`.
+fn with_stats(result: ServerResult, bytes: u64) -> ServerResult {
+    ServerResult {
+        changed: result.success,
+        transfer: Some(TransferStats {
+            bytes,
+            resumed: 0,
+            verified: result.success,
+        }),
+        ..result
+    }
+}
+
+/// `<local_dir>/<hostname>/`, with characters that can't appear in a
+/// directory name (IPv6 colons on Windows, path separators) replaced.
+pub fn host_dir(local_dir: &Path, host: &Host) -> PathBuf {
+    let name: String = host
+        .host
+        .chars()
+        .map(|c| {
+            if matches!(c, '/' | '\\' | ':') {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    local_dir.join(name)
+}
+
+pub fn build_pull_args(host: &Host, remote: &str, destination: &Path) -> Vec<String> {
+    let mut args = scp_options(host);
+    // -r so the remote path may be a file or a whole directory.
+    args.push("-r".to_string());
+    args.push("--".to_string());
+    args.push(remote_spec(host, remote));
+    args.push(destination.display().to_string());
+    args
+}
+
+/// Fetches `remote` from `host` into its own directory under `local_dir`.
+pub fn pull(
+    host: &Host,
+    remote: &str,
+    local_dir: &Path,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let destination = host_dir(local_dir, host);
+    if let Err(e) = fs::create_dir_all(&destination) {
+        let message = format!("could not create {}: {}", destination.display(), e);
+        let result = ServerResult {
+            server: host.host.clone(),
+            error: Some(message.clone()),
+            ..Default::default()
+        };
+        let _ = tx.send(Event::stderr(&host.host, message));
+        return result;
+    }
+    if host.shell().is_windows() {
+        return run_process_blocking(
+            host,
+            "scp",
+            &build_pull_args(host, remote, &destination),
+            Input::default(),
+            None,
+            tx,
+        );
+    }
+    let login = login_user(host);
+    let name = remote
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or(remote);
+    let mut steps = Steps::default();
+    let staging = if options.escalates(host) {
+        let Some((staging, user)) = steps.stage(host, options, &tx) else {
+            return steps.finish();
+        };
+        let readable = steps.run(run_ssh_command_blocking(
+            host,
+            &format!(
+                "cp -R {} {}/ && chown -R {} {}",
+                shell_quote(remote),
+                shell_quote(&staging),
+                shell_quote(&user),
+                shell_quote(&staging)
+            ),
+            options,
+            tx.clone(),
+        ));
+        if !readable {
+            steps.cleanup(host, options, &staging, tx);
+            return steps.finish();
+        }
+        Some(staging)
+    } else {
+        None
+    };
+    let source = match &staging {
+        Some(staging) => format!("{}/{}", staging, name),
+        None => remote.to_string(),
+    };
+    if resume::is_remote_dir(&login, &source, options) {
+        let (listed, manifest) = resume::remote_manifest(&login, &source, options);
+        if steps.run(listed)
+            && steps.run(run_process_blocking(
+                host,
+                "scp",
+                &build_pull_args(host, &source, &destination),
+                Input::default(),
+                None,
+                tx.clone(),
+            ))
+        {
+            steps.run(resume::verify_pulled_dir(
+                host,
+                &destination.join(name),
+                &manifest,
+            ));
+        }
+    } else {
+        steps.run(resume::download(
+            &login,
+            &source,
+            &destination.join(name),
+            options,
+            tx.clone(),
+        ));
+    }
+    if let Some(staging) = staging {
+        steps.cleanup(host, options, &staging, tx);
+    }
+    steps.finish()
+}
+
+/// `russh push`: copies a local file to every selected host.
+pub fn push_command(cli: &Cli, args: &PushArgs) -> Result<()> {
+    if !args.local.exists() {
+        return Err(AppError::File(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", args.local.display()),
+        )));
+    }
+    let (config, hosts) = inventory(cli)?;
+    let schedule = schedule(cli, &config)?;
+    let label: Arc<str> = format!("push {} {}", args.local.display(), args.remote).into();
+    let source = Arc::new(Source::open(&args.local)?);
+    // The approval covers what gets copied, not just its path.
+    let approved = format!("{} (sha256 {})", label, source.checksum());
+    let _lock = admit(cli, &config, &hosts, &[approved])?;
+    let jobs = hosts
+        .into_iter()
+        .map(|host| (Arc::new(host), Arc::clone(&label)))
+        .collect();
+    let remote = args.remote.clone();
+    let options = exec_options(cli, &config)?;
+    let sinks = sinks(cli, &config)?;
+    let results = execute_blocking_jobs(
+        jobs,
+        move |host, _, tx| push(host, &source, &remote, &options, tx),
+        Some(&sinks),
+        display_mode(cli, &config)?,
+        schedule,
+    );
+    report(cli, &results, sinks);
+    Ok(())
+}
+
+/// `russh pull`: fetches a file from every selected host into per-host
+/// directories.
+pub fn pull_command(cli: &Cli, args: &PullArgs) -> Result<()> {
+    let (config, hosts) = inventory(cli)?;
+    let schedule = schedule(cli, &config)?;
+    let label: Arc<str> = format!("pull {} {}", args.remote, args.local_dir.display()).into();
+    // What comes back isn't known until it's fetched, so the approval
+    // covers where it comes from and exactly where it goes.
+    let approved = format!(
+        "pull {} into {}",
+        args.remote,
+        std::path::absolute(&args.local_dir)?.display()
+    );
+    let _lock = admit(cli, &config, &hosts, &[approved])?;
+    let jobs = hosts
+        .into_iter()
+        .map(|host| (Arc::new(host), Arc::clone(&label)))
+        .collect();
+    let remote = args.remote.clone();
+    let local_dir = args.local_dir.clone();
+    let options = exec_options(cli, &config)?;
+    let sinks = sinks(cli, &config)?;
+    let results = execute_blocking_jobs(
+        jobs,
+        move |host, _, tx| pull(host, &remote, &local_dir, &options, tx),
+        Some(&sinks),
+        display_mode(cli, &config)?,
+        schedule,
+    );
+    report(cli, &results, sinks);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_checksum() {
+        let dir = std::env::temp_dir().join(format!("russh-source-{}", std::process::id()));
+        fs::create_dir_all(dir.join("etc")).unwrap();
+        fs::write(dir.join("etc/app.conf"), "hello\n").unwrap();
+        let file = Source::open(&dir.join("etc/app.conf")).unwrap();
+        assert_eq!(
+            file.checksum(),
+            "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03"
+        );
+        let before = Source::open(&dir).unwrap().checksum();
+        fs::write(dir.join("etc/app.conf"), "changed\n").unwrap();
+        assert_ne!(Source::open(&dir).unwrap().checksum(), before);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_build_push_args() {
+        let host: Host = "web01 user=deploy port=2222".parse().unwrap();
+        assert_eq!(
+            build_push_args(&host, Path::new("app.tar.gz"), "/tmp/"),
+            vec![
+                "-q",
+                "-B",
+                "-P",
+                "2222",
+                "--",
+                "app.tar.gz",
+                "deploy@web01:/tmp/"
+            ]
+        );
+        assert_eq!(
+            build_push_args(&Host::new("fe80::1"), Path::new("a"), "b"),
+            vec!["-q", "-B", "--", "a", "[fe80::1]:b"]
+        );
+        // Only -o options carry over to scp.
+        let host = Host {
+            options: Some("-4 -o ConnectTimeout=5".to_string()),
+            extra_options: vec!["-oLogLevel=ERROR".to_string()],
+            ..Host::new("web02")
+        };
+        assert_eq!(
+            build_push_args(&host, Path::new("a"), "b"),
+            vec![
+                "-q",
+                "-B",
+                "-o",
+                "ConnectTimeout=5",
+                "-oLogLevel=ERROR",
+                "--",
+                "a",
+                "web02:b"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_build_pull_args() {
+        let host: Host = "db01 user=ops".parse().unwrap();
+        let destination = host_dir(Path::new("logs"), &host);
+        assert_eq!(destination, Path::new("logs").join("db01"));
+        assert_eq!(
+            build_pull_args(&host, "/var/log/syslog", &destination),
+            vec![
+                "-q".to_string(),
+                "-B".to_string(),
+                "-r".to_string(),
+                "--".to_string(),
+                "ops@db01:/var/log/syslog".to_string(),
+                destination.display().to_string(),
+            ]
+        );
+        assert_eq!(
+            host_dir(Path::new("out"), &Host::new("fe80::1")),
+            Path::new("out").join("fe80__1")
+        );
+    }
+}
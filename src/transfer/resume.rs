@@ -0,0 +1,481 @@
+//! Resumable, checksummed copies of single files. The bytes are streamed
+//! through `ssh` into a partial file next to the destination; a broken
+//! connection leaves the partial file behind, and the next run continues
+//! from its end. The copy only takes the destination's name once its
+//! sha256 matches the source's.
+
+use crate::askpass;
+use crate::config::Host;
+use crate::preflight::format_bytes;
+use crate::ssh::{
+    build_ssh_args, local_env, run_ssh_command_blocking, shell_quote, Event, ExecOptions,
+    ServerResult, TransferStats,
+};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{self, Sender};
+use std::time::Instant;
+
+/// Appended to a destination's name while it is being written.
+pub const PARTIAL_SUFFIX: &str = ".russh-partial";
+
+/// Defines `h`, printing a file's sha256 with whichever of the usual tools
+/// the host has, or nothing if it has none.
+const REMOTE_SHA256: &str =
+    "h() { { sha256sum \"$1\" || shasum -a 256 \"$1\"; } 2>/dev/null | cut -d' ' -f1; }";
+
+/// sha256 of every file under a directory, keyed by `./relative/path`.
+pub type Manifest = BTreeMap<String, String>;
+
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 1024 * 1024];
+    loop {
+        match file.read(&mut buffer)? {
+            0 => break,
+            read => hasher.update(&buffer[..read]),
+        }
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect())
+}
+
+/// The manifest of a local directory, and the total size of its files.
+pub fn local_manifest(dir: &Path) -> io::Result<(Manifest, u64)> {
+    let mut manifest = Manifest::new();
+    let mut bytes = 0;
+    let mut pending = vec![(dir.to_path_buf(), ".".to_string())];
+    while let Some((path, relative)) = pending.pop() {
+        for entry in fs::read_dir(&path)? {
+            let entry = entry?;
+            let name = format!("{}/{}", relative, entry.file_name().to_string_lossy());
+            let kind = entry.file_type()?;
+            if kind.is_dir() {
+                pending.push((entry.path(), name));
+            } else if kind.is_file() {
+                bytes += entry.metadata()?.len();
+                manifest.insert(name, sha256_file(&entry.path())?);
+            }
+        }
+    }
+    Ok((manifest, bytes))
+}
+
+/// Shell printing the manifest of the directory in `$r`, one
+/// `<sha256> ./path` line per file.
+fn remote_manifest_script() -> String {
+    format!(
+        "{}; cd \"$r\" && find . -type f | while IFS= read -r f; do printf '%s %s\\n' \"$(h \"$f\")\" \"$f\"; done",
+        REMOTE_SHA256
+    )
+}
+
+fn parse_manifest<'a>(lines: impl Iterator<Item = &'a str>) -> Manifest {
+    lines
+        .filter_map(|line| line.split_once(' '))
+        .map(|(digest, path)| (path.to_string(), digest.to_string()))
+        .collect()
+}
+
+/// Checks that every file in `expected` is in `actual` with the same
+/// digest, describing the first few that aren't.
+pub fn compare(expected: &Manifest, actual: &Manifest) -> std::result::Result<(), String> {
+    let differing: Vec<&str> = expected
+        .iter()
+        .filter(|(path, digest)| actual.get(*path) != Some(digest))
+        .map(|(path, _)| path.as_str())
+        .collect();
+    match differing.len() {
+        0 => Ok(()),
+        count => Err(format!(
+            "checksum mismatch for {} file(s): {}{}",
+            count,
+            differing[..count.min(5)].join(", "),
+            if count > 5 { ", ..." } else { "" }
+        )),
+    }
+}
+
+/// `ssh` for streaming a file to or from `host`.
+fn ssh(host: &Host) -> Command {
+    let mut command = Command::new("ssh");
+    if let Some(vars) = local_env(host) {
+        command.env_clear().envs(vars);
+    }
+    askpass::apply(&mut command);
+    command
+}
+
+/// Where a push of `name` to `target` ends up: inside `target` if that is
+/// an existing directory, like scp does.
+fn resolve_target(target: &str, name: &str) -> String {
+    format!(
+        "t={}; [ -d \"$t\" ] && t=\"$t\"/{}",
+        shell_quote(target),
+        shell_quote(name)
+    )
+}
+
+/// The remote manifest of the directory `name` was pushed to under
+/// `target`, checked against the local one.
+pub fn verify_pushed_dir(
+    host: &Host,
+    name: &str,
+    target: &str,
+    expected: &Manifest,
+    options: &ExecOptions,
+) -> ServerResult {
+    let (quiet, _) = mpsc::channel();
+    let script = format!(
+        "{}; r=\"$t\"; {}",
+        resolve_target(target, name),
+        remote_manifest_script()
+    );
+    let mut result = run_ssh_command_blocking(host, &script, options, quiet);
+    if result.success {
+        let actual = parse_manifest(result.output.lines());
+        if let Err(message) = compare(expected, &actual) {
+            result.success = false;
+            result.error = Some(message);
+        }
+    }
+    ServerResult {
+        output: String::new(),
+        ..result
+    }
+}
+
+/// Checks a pulled directory against the manifest taken on the host.
+pub fn verify_pulled_dir(host: &Host, dir: &Path, expected: &Manifest) -> ServerResult {
+    let start = Instant::now();
+    let (outcome, bytes) = match local_manifest(dir) {
+        Ok((actual, bytes)) => (compare(expected, &actual), bytes),
+        Err(e) => (Err(format!("cannot read {}: {}", dir.display(), e)), 0),
+    };
+    ServerResult {
+        server: host.host.clone(),
+        error: outcome.as_ref().err().cloned(),
+        duration: start.elapsed().as_secs_f64(),
+        success: outcome.is_ok(),
+        changed: outcome.is_ok(),
+        transfer: Some(TransferStats {
+            bytes,
+            resumed: 0,
+            verified: outcome.is_ok(),
+        }),
+        ..Default::default()
+    }
+}
+
+/// Copies the regular file `local` (`size` bytes with sha256 `digest`) to
+/// `target` on `host`, continuing an earlier partial copy if there is one.
+pub fn upload(
+    host: &Host,
+    local: &Path,
+    (size, digest): (u64, &str),
+    target: &str,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let start = Instant::now();
+    let name = local
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let (quiet, _) = mpsc::channel();
+    let probe = run_ssh_command_blocking(
+        host,
+        &format!(
+            "{}; {{ wc -c < \"$t\"{}; }} 2>/dev/null || echo 0; printf '%s\\n' \"$t\"",
+            resolve_target(target, &name),
+            PARTIAL_SUFFIX
+        ),
+        options,
+        quiet,
+    );
+    let mut lines = probe.output.lines();
+    let (Some(offset), Some(target)) = (lines.next(), lines.next()) else {
+        return ServerResult {
+            output: String::new(),
+            ..probe
+        };
+    };
+    // A partial file longer than the source belongs to something else.
+    let offset = offset.trim().parse().unwrap_or(0);
+    let offset = if offset > size { 0 } else { offset };
+    if offset > 0 {
+        progress(
+            &tx,
+            host,
+            format!(
+                "resuming after {} of {}",
+                format_size(offset),
+                format_size(size)
+            ),
+        );
+    }
+
+    let script = format!(
+        "{}; t={}; p=\"$t\"{}; cat {} \"$p\" || exit 1; s=$(h \"$p\"); \
+         if [ -z \"$s\" ]; then echo 'neither sha256sum nor shasum is installed' >&2; exit 1; \
+         elif [ \"$s\" = {} ]; then mv -f \"$p\" \"$t\"; \
+         else rm -f \"$p\"; echo \"checksum mismatch, got $s\" >&2; exit 1; fi",
+        REMOTE_SHA256,
+        shell_quote(target),
+        PARTIAL_SUFFIX,
+        if offset == 0 { ">" } else { ">>" },
+        digest
+    );
+    let input = File::open(local).and_then(|mut file| {
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(file)
+    });
+    let outcome = input.and_then(|file| {
+        ssh(host)
+            .args(build_ssh_args(host, &script))
+            .stdin(Stdio::from(file))
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .output()
+    });
+    let stats = TransferStats {
+        bytes: size - offset,
+        resumed: offset,
+        verified: true,
+    };
+    finish(
+        host,
+        start,
+        outcome.map(|o| (o.status.success(), o.stderr)),
+        stats,
+        tx,
+    )
+}
+
+/// Fetches the regular file `remote` to `destination`, continuing an
+/// earlier partial download if there is one.
+pub fn download(
+    host: &Host,
+    remote: &str,
+    destination: &Path,
+    options: &ExecOptions,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let start = Instant::now();
+    let (quiet, _) = mpsc::channel();
+    let probe = run_ssh_command_blocking(
+        host,
+        &format!(
+            "{}; r={}; wc -c < \"$r\" && h \"$r\"",
+            REMOTE_SHA256,
+            shell_quote(remote)
+        ),
+        options,
+        quiet,
+    );
+    let mut lines = probe.output.lines();
+    let (Some(Ok(size)), Some(digest)) =
+        (lines.next().map(|l| l.trim().parse::<u64>()), lines.next())
+    else {
+        return ServerResult {
+            output: String::new(),
+            error: probe
+                .error
+                .clone()
+                .or_else(|| Some("neither sha256sum nor shasum is installed\n".to_string())),
+            success: false,
+            ..probe
+        };
+    };
+    let digest = digest.trim().to_string();
+
+    let partial = partial_path(destination);
+    let mut offset = fs::metadata(&partial).map(|m| m.len()).unwrap_or(0);
+    if offset > size {
+        offset = 0;
+    }
+    if offset > 0 {
+        progress(
+            &tx,
+            host,
+            format!(
+                "resuming after {} of {}",
+                format_size(offset),
+                format_size(size)
+            ),
+        );
+    }
+    let output = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&partial)
+        .and_then(|file| {
+            file.set_len(offset)?;
+            Ok(file)
+        });
+    let outcome = output.and_then(|file| {
+        let fetched = ssh(host)
+            .args(build_ssh_args(
+                host,
+                &format!("tail -c +{} {}", offset + 1, shell_quote(remote)),
+            ))
+            .stdin(Stdio::null())
+            .stdout(Stdio::from(file))
+            .stderr(Stdio::piped())
+            .output()?;
+        if !fetched.status.success() {
+            return Ok((false, fetched.stderr));
+        }
+        if sha256_file(&partial)? != digest {
+            fs::remove_file(&partial)?;
+            return Ok((false, b"checksum mismatch\n".to_vec()));
+        }
+        fs::rename(&partial, destination)?;
+        Ok((true, fetched.stderr))
+    });
+    let stats = TransferStats {
+        bytes: size - offset,
+        resumed: offset,
+        verified: true,
+    };
+    finish(host, start, outcome, stats, tx)
+}
+
+/// Lists the manifest of the remote directory `remote`; it is empty if
+/// the listing failed.
+pub fn remote_manifest(
+    host: &Host,
+    remote: &str,
+    options: &ExecOptions,
+) -> (ServerResult, Manifest) {
+    let (quiet, _) = mpsc::channel();
+    let script = format!("r={}; {}", shell_quote(remote), remote_manifest_script());
+    let result = run_ssh_command_blocking(host, &script, options, quiet);
+    let manifest = if result.success {
+        parse_manifest(result.output.lines())
+    } else {
+        Manifest::new()
+    };
+    (
+        ServerResult {
+            output: String::new(),
+            ..result
+        },
+        manifest,
+    )
+}
+
+/// Whether `remote` on `host` is a directory.
+pub fn is_remote_dir(host: &Host, remote: &str, options: &ExecOptions) -> bool {
+    let (quiet, _) = mpsc::channel();
+    let command = format!("[ -d {} ]", shell_quote(remote));
+    run_ssh_command_blocking(host, &command, options, quiet).success
+}
+
+fn partial_path(destination: &Path) -> PathBuf {
+    let mut name = destination.as_os_str().to_owned();
+    name.push(PARTIAL_SUFFIX);
+    PathBuf::from(name)
+}
+
+fn progress(tx: &Sender<Event>, host: &Host, line: String) {
+    let _ = tx.send(Event::stdout(&host.host, line));
+}
+
+/// The result of a streamed copy, from whether it succeeded and its stderr.
+fn finish(
+    host: &Host,
+    start: Instant,
+    outcome: io::Result<(bool, Vec<u8>)>,
+    stats: TransferStats,
+    tx: Sender<Event>,
+) -> ServerResult {
+    let duration = start.elapsed().as_secs_f64();
+    let (success, error) = match outcome {
+        Ok((success, stderr)) => (success, String::from_utf8_lossy(&stderr).to_string()),
+        Err(e) => (false, format!("{}\n", e)),
+    };
+    let line = if success {
+        format!(
+            "transferred {} in {:.1}s ({}/s), sha256 verified",
+            format_size(stats.bytes),
+            duration,
+            format_size((stats.bytes as f64 / duration.max(0.001)) as u64)
+        )
+    } else {
+        error.trim_end().to_string()
+    };
+    let _ = tx.send(match success {
+        true => Event::stdout(&host.host, line.clone()),
+        false => Event::stderr(&host.host, line.clone()),
+    });
+    ServerResult {
+        server: host.host.clone(),
+        output: if success {
+            format!("{}\n", line)
+        } else {
+            String::new()
+        },
+        error: Some(error).filter(|e| !e.is_empty()),
+        duration,
+        success,
+        changed: success && stats.bytes > 0,
+        transfer: Some(TransferStats {
+            verified: success,
+            ..stats
+        }),
+        ..Default::default()
+    }
+}
+
+fn format_size(bytes: u64) -> String {
+    format_bytes(bytes as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_manifests() {
+        let dir = std::env::temp_dir().join(format!("russh-manifest-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        fs::write(dir.join("a.txt"), "russh").unwrap();
+        fs::write(dir.join("sub").join("b.txt"), "").unwrap();
+        let (manifest, bytes) = local_manifest(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(bytes, 5);
+        assert_eq!(
+            manifest["./sub/b.txt"],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+
+        let remote = parse_manifest(
+            format!(
+                "{} ./a.txt\ne3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855 ./sub/b.txt\n000 ./extra\n",
+                manifest["./a.txt"]
+            )
+            .lines(),
+        );
+        assert_eq!(compare(&manifest, &remote), Ok(()));
+        let mut changed = remote.clone();
+        changed.insert("./a.txt".to_string(), "0".to_string());
+        changed.remove("./sub/b.txt");
+        assert_eq!(
+            compare(&manifest, &changed),
+            Err("checksum mismatch for 2 file(s): ./a.txt, ./sub/b.txt".to_string())
+        );
+        assert_eq!(
+            partial_path(Path::new("out/app.tar.gz")),
+            Path::new("out/app.tar.gz.russh-partial")
+        );
+    }
+}
@@ -0,0 +1,311 @@
+//! Full-screen dashboard for `--tui`: one pane of live output per server, a
+//! progress bar of finished jobs and a summary screen once the run is over.
+
+use crate::interrupt;
+use crate::ssh::{self, ServerResult};
+use crate::summary::{format_summary, tally};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+
+/// Lines kept per pane; older ones scroll away.
+const PANE_LINES: usize = 200;
+
+/// How often the screen is redrawn and the keyboard checked.
+const TICK: Duration = Duration::from_millis(50);
+
+#[derive(Default)]
+struct Pane {
+    lines: VecDeque<String>,
+    /// Jobs for this server that have finished, and how many of them failed.
+    finished: usize,
+    failed: usize,
+}
+
+/// Everything the dashboard shows, fed from the output and result channels.
+pub struct Dashboard {
+    servers: Vec<String>,
+    panes: HashMap<String, Pane>,
+    jobs: HashMap<String, usize>,
+    results: Vec<(String, ServerResult)>,
+    total: usize,
+}
+
+impl Dashboard {
+    /// `servers` lists the server of every job, in job order.
+    pub fn new(servers: Vec<String>) -> Dashboard {
+        let total = servers.len();
+        let mut jobs: HashMap<String, usize> = HashMap::new();
+        let mut unique = Vec::new();
+        for server in servers {
+            let count = jobs.entry(server.clone()).or_default();
+            if *count == 0 {
+                unique.push(server);
+            }
+            *count += 1;
+        }
+        Dashboard {
+            panes: unique
+                .iter()
+                .map(|s| (s.clone(), Pane::default()))
+                .collect(),
+            servers: unique,
+            jobs,
+            results: Vec::new(),
+            total,
+        }
+    }
+
+    pub fn line(&mut self, event: ssh::Event) {
+        let (server, line) = match event {
+            ssh::Event::StdoutLine { server, line } | ssh::Event::StderrLine { server, line } => {
+                (server, line)
+            }
+            ssh::Event::Completed { .. } => return,
+        };
+        let pane = self.panes.entry(server).or_default();
+        if pane.lines.len() == PANE_LINES {
+            pane.lines.pop_front();
+        }
+        pane.lines.push_back(line);
+    }
+
+    pub fn finish(&mut self, command: String, result: ServerResult) {
+        let pane = self.panes.entry(result.server.clone()).or_default();
+        pane.finished += 1;
+        if !result.success {
+            pane.failed += 1;
+        }
+        self.results.push((command, result));
+    }
+
+    pub fn progress(&self) -> (usize, usize) {
+        (self.results.len(), self.total)
+    }
+
+    fn pane_state(&self, server: &str) -> (&'static str, Color) {
+        let Some(pane) = self.panes.get(server) else {
+            return ("waiting", Color::Gray);
+        };
+        let jobs = self.jobs.get(server).copied().unwrap_or_default();
+        match (pane.finished, pane.failed) {
+            (_, failed) if failed > 0 => ("failed", Color::Red),
+            (finished, _) if finished >= jobs => ("done", Color::Green),
+            _ if pane.lines.is_empty() && pane.finished == 0 => ("waiting", Color::Gray),
+            _ => ("running", Color::Yellow),
+        }
+    }
+
+    fn render(&self, frame: &mut Frame, complete: bool) {
+        let [progress, body, help] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let (done, total) = self.progress();
+        let (ok, changed, failed) = tally(&self.results);
+        frame.render_widget(
+            Gauge::default()
+                .block(Block::bordered().title(" russh "))
+                .gauge_style(Style::default().fg(if failed > 0 {
+                    Color::Red
+                } else {
+                    Color::Green
+                }))
+                .ratio(if total == 0 {
+                    1.0
+                } else {
+                    done as f64 / total as f64
+                })
+                .label(format!(
+                    "{}/{} done - {} ok, {} changed, {} failed",
+                    done, total, ok, changed, failed
+                )),
+            progress,
+        );
+
+        if complete {
+            let summary = format_summary(&self.results, false);
+            frame.render_widget(
+                Paragraph::new(summary).block(Block::bordered().title(" Summary ")),
+                body,
+            );
+        } else {
+            self.render_panes(frame, body);
+        }
+
+        let hint = if complete {
+            "q: quit"
+        } else {
+            "q: leave the dashboard (the run continues)  ctrl-c: abort"
+        };
+        frame.render_widget(Paragraph::new(hint), help);
+    }
+
+    fn render_panes(&self, frame: &mut Frame, area: Rect) {
+        if self.servers.is_empty() {
+            return;
+        }
+        let columns = (self.servers.len() as f64).sqrt().ceil() as usize;
+        let rows = self.servers.len().div_ceil(columns);
+        let row_areas = Layout::vertical(vec![Constraint::Ratio(1, rows as u32); rows]).split(area);
+        for (row, servers) in self.servers.chunks(columns).enumerate() {
+            let cells = Layout::horizontal(vec![Constraint::Ratio(1, columns as u32); columns])
+                .split(row_areas[row]);
+            for (cell, server) in cells.iter().zip(servers) {
+                let (state, color) = self.pane_state(server);
+                let height = cell.height.saturating_sub(2) as usize;
+                let lines: Vec<Line> = self
+                    .panes
+                    .get(server)
+                    .map(|pane| {
+                        let skip = pane.lines.len().saturating_sub(height);
+                        pane.lines
+                            .iter()
+                            .skip(skip)
+                            .map(|l| Line::raw(l.as_str()))
+                            .collect()
+                    })
+                    .unwrap_or_default();
+                frame.render_widget(
+                    Paragraph::new(lines).block(
+                        Block::bordered()
+                            .title(format!(" {} - {} ", server, state))
+                            .border_style(Style::default().fg(color)),
+                    ),
+                    *cell,
+                );
+            }
+        }
+    }
+}
+
+/// What the user asked for with the keyboard.
+enum Key {
+    Quit,
+    Abort,
+}
+
+fn poll_key() -> io::Result<Option<Key>> {
+    if !event::poll(TICK)? {
+        return Ok(None);
+    }
+    let Event::Key(key) = event::read()? else {
+        return Ok(None);
+    };
+    if key.kind != KeyEventKind::Press {
+        return Ok(None);
+    }
+    Ok(match key.code {
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Key::Abort),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Key::Quit),
+        _ => None,
+    })
+}
+
+/// Shows the dashboard until the run is over and the user quits. Returns
+/// `false` if they left while jobs were still running, so the caller can
+/// print the rest of the output normally.
+pub fn run(
+    dashboard: &mut Dashboard,
+    lines: &Receiver<ssh::Event>,
+    results: &Receiver<(String, ServerResult)>,
+) -> io::Result<bool> {
+    let mut terminal = ratatui::try_init()?;
+    let outcome = event_loop(&mut terminal, dashboard, lines, results);
+    ratatui::restore();
+    outcome
+}
+
+fn event_loop(
+    terminal: &mut DefaultTerminal,
+    dashboard: &mut Dashboard,
+    lines: &Receiver<ssh::Event>,
+    results: &Receiver<(String, ServerResult)>,
+) -> io::Result<bool> {
+    let mut complete = false;
+    loop {
+        loop {
+            match lines.try_recv() {
+                Ok(line) => dashboard.line(line),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    complete = true;
+                    break;
+                }
+            }
+        }
+        while let Ok((command, result)) = results.try_recv() {
+            dashboard.finish(command, result);
+        }
+        terminal.draw(|frame| dashboard.render(frame, complete))?;
+        match poll_key()? {
+            Some(Key::Quit) => return Ok(complete),
+            Some(Key::Abort) if interrupt::interrupted() => {
+                ratatui::restore();
+                std::process::exit(interrupt::EXIT_CODE);
+            }
+            Some(Key::Abort) => interrupt::interrupt(),
+            None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    fn result(server: &str, success: bool) -> ServerResult {
+        ServerResult {
+            server: server.to_string(),
+            duration: 0.1,
+            success,
+            exit_code: Some(if success { 0 } else { 1 }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_dashboard_tracks_progress() {
+        let servers = ["web01", "web01", "web02"].map(String::from).to_vec();
+        let mut dashboard = Dashboard::new(servers);
+        for i in 0..PANE_LINES + 5 {
+            dashboard.line(ssh::Event::stdout("web01", format!("line {}", i)));
+        }
+        assert_eq!(dashboard.panes["web01"].lines.len(), PANE_LINES);
+        assert_eq!(dashboard.pane_state("web01").0, "running");
+        assert_eq!(dashboard.pane_state("web02").0, "waiting");
+
+        dashboard.finish("uptime".to_string(), result("web01", true));
+        dashboard.finish("df".to_string(), result("web01", true));
+        dashboard.finish("uptime".to_string(), result("web02", false));
+        assert_eq!(dashboard.progress(), (3, 3));
+        assert_eq!(dashboard.pane_state("web01").0, "done");
+        assert_eq!(dashboard.pane_state("web02").0, "failed");
+
+        let mut terminal = Terminal::new(TestBackend::new(80, 20)).unwrap();
+        terminal
+            .draw(|frame| dashboard.render(frame, true))
+            .unwrap();
+        let screen: String = terminal
+            .backend()
+            .buffer()
+            .content()
+            .iter()
+            .map(|cell| cell.symbol())
+            .collect();
+        assert!(screen.contains("3/3 done"));
+        assert!(screen.contains("Summary"));
+    }
+}
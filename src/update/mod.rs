@@ -0,0 +1,185 @@
+use crate::capability::{self, Capability};
+use crate::errors::{AppError, Result};
+use crate::http;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RELEASES_URL: &str = "https://api.github.com/repos/erictossell/russh/releases/latest";
+
+#[derive(Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+/// Name of the release asset built for this platform by the release workflow.
+fn asset_name() -> Option<&'static str> {
+    if cfg!(target_os = "linux") {
+        Some("russh-ubuntu-latest")
+    } else if cfg!(target_os = "macos") {
+        Some("russh-macos-latest")
+    } else if cfg!(target_os = "windows") {
+        Some("russh-windows-latest.exe")
+    } else {
+        None
+    }
+}
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .trim_start_matches('v')
+        .split(['.', '-', '+'])
+        .map_while(|part| part.parse().ok())
+        .collect()
+}
+
+/// Whether `candidate` is a newer release than `current`.
+fn is_newer(candidate: &str, current: &str) -> bool {
+    parse_version(candidate) > parse_version(current)
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The checksum file holds `<hex digest>  <file name>` as written by `sha256sum`.
+fn parse_checksum(contents: &str) -> Option<String> {
+    contents
+        .split_whitespace()
+        .next()
+        .filter(|digest| digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit()))
+        .map(|digest| digest.to_lowercase())
+}
+
+/// Checks GitHub for a newer release and, unless `check_only` is set,
+/// replaces the running binary with it.
+pub fn self_update(check_only: bool) -> Result<()> {
+    capability::check(Capability::SelfUpdate)?;
+    let current = env!("CARGO_PKG_VERSION");
+    let release: Release = serde_json::from_slice(&http::get(RELEASES_URL)?)
+        .map_err(|e| AppError::Generic(format!("unexpected release metadata: {}", e)))?;
+
+    if !is_newer(&release.tag_name, current) {
+        println!("russh {} is up to date.", current);
+        return Ok(());
+    }
+    println!(
+        "A newer release is available: {} (installed: {})",
+        release.tag_name, current
+    );
+    if check_only {
+        return Ok(());
+    }
+
+    let name = asset_name()
+        .ok_or_else(|| AppError::Generic("no release builds for this platform".to_string()))?;
+    let find_asset = |asset_name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == asset_name)
+            .ok_or_else(|| {
+                AppError::Generic(format!(
+                    "release {} has no asset named {}",
+                    release.tag_name, asset_name
+                ))
+            })
+    };
+    let binary = find_asset(name)?;
+    let checksum = find_asset(&format!("{}.sha256", name))?;
+
+    let expected = parse_checksum(&String::from_utf8_lossy(&http::get(
+        &checksum.browser_download_url,
+    )?))
+    .ok_or_else(|| AppError::Generic("malformed checksum file".to_string()))?;
+
+    let current_exe = env::current_exe()?;
+    let staged = staging_path(&current_exe);
+    // A failed or partial download leaves nothing behind either.
+    let downloaded =
+        http::download(&binary.browser_download_url, &staged).and_then(|()| Ok(fs::read(&staged)?));
+    let actual = match downloaded {
+        Ok(bytes) => sha256_hex(&bytes),
+        Err(e) => {
+            let _ = fs::remove_file(&staged);
+            return Err(e);
+        }
+    };
+    if actual != expected {
+        let _ = fs::remove_file(&staged);
+        return Err(AppError::Generic(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            name, expected, actual
+        )));
+    }
+
+    if let Err(e) = replace_binary(&staged, &current_exe) {
+        let _ = fs::remove_file(&staged);
+        return Err(e);
+    }
+    println!("Updated russh to {}.", release.tag_name);
+    Ok(())
+}
+
+/// The new binary is staged next to the current one so the final rename
+/// stays on the same filesystem and is atomic.
+fn staging_path(current_exe: &Path) -> PathBuf {
+    let dir = current_exe.parent().unwrap_or_else(|| Path::new("."));
+    dir.join(format!(".russh-update-{}", std::process::id()))
+}
+
+#[cfg(unix)]
+fn replace_binary(staged: &Path, current_exe: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(staged, fs::Permissions::from_mode(0o755))?;
+    fs::rename(staged, current_exe)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn replace_binary(staged: &Path, current_exe: &Path) -> Result<()> {
+    // A running executable can't be overwritten on Windows, but it can be moved.
+    let old = current_exe.with_extension("exe.old");
+    let _ = fs::remove_file(&old);
+    fs::rename(current_exe, &old)?;
+    if let Err(e) = fs::rename(staged, current_exe) {
+        let _ = fs::rename(&old, current_exe);
+        return Err(e.into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("v0.1.93", "0.1.92"));
+        assert!(is_newer("v0.2.0", "0.1.92"));
+        assert!(!is_newer("v0.1.92", "0.1.92"));
+        assert!(!is_newer("v0.1.9", "0.1.92"));
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let digest = sha256_hex(b"russh");
+        assert_eq!(
+            parse_checksum(&format!("{}  russh-ubuntu-latest\n", digest)),
+            Some(digest)
+        );
+        assert_eq!(parse_checksum("not-a-digest  russh"), None);
+    }
+}
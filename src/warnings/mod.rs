@@ -0,0 +1,103 @@
+use crate::i18n::{trf, Msg};
+use ansi_term::Color::Yellow;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::io::{self, IsTerminal};
+use std::sync::Mutex;
+
+/// What a warning is about, so consumers can group or filter them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WarningKind {
+    ConfigFallback,
+    HostSkipped,
+    DeprecatedKey,
+    Redaction,
+    Delivery,
+    Lock,
+    IgnoredOption,
+}
+
+impl fmt::Display for WarningKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            WarningKind::ConfigFallback => "config fallback",
+            WarningKind::HostSkipped => "host skipped",
+            WarningKind::DeprecatedKey => "deprecated key",
+            WarningKind::Redaction => "redaction",
+            WarningKind::Delivery => "delivery",
+            WarningKind::Lock => "lock",
+            WarningKind::IgnoredOption => "ignored option",
+        };
+        f.write_str(label)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+// Warnings are raised from anywhere (config loading, host resolution, worker
+// threads), so they are collected in one place and reported once at the end.
+static WARNINGS: Mutex<Vec<Warning>> = Mutex::new(Vec::new());
+
+pub fn warn(kind: WarningKind, message: impl Into<String>) {
+    let warning = Warning {
+        kind,
+        message: message.into(),
+    };
+    WARNINGS
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+        .push(warning);
+}
+
+/// Returns every warning collected so far, leaving the collector empty.
+pub fn take() -> Vec<Warning> {
+    std::mem::take(
+        &mut *WARNINGS
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()),
+    )
+}
+
+pub fn print_warnings(warnings: &[Warning]) {
+    if warnings.is_empty() {
+        return;
+    }
+    // Plain when stderr goes to a log file or cron mail.
+    let color = io::stderr().is_terminal() && env::var_os("NO_COLOR").is_none();
+    let paint = |text: String| match color {
+        true => Yellow.paint(text).to_string(),
+        false => text,
+    };
+    // stderr, so warnings never end up in machine-readable output.
+    eprintln!();
+    eprintln!("{}", paint(trf(Msg::WarningsHeader, &[&warnings.len()])));
+    eprintln!("-----------------------------");
+    for warning in warnings {
+        eprintln!(
+            "{} {}",
+            paint(format!("[{}]", warning.kind)),
+            warning.message
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_warn_and_take() {
+        warn(WarningKind::HostSkipped, "web01 listed twice");
+        let warnings = take();
+        assert!(warnings
+            .iter()
+            .any(|w| w.kind == WarningKind::HostSkipped && w.message == "web01 listed twice"));
+        assert!(take().iter().all(|w| w.message != "web01 listed twice"));
+    }
+}
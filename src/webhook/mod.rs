@@ -0,0 +1,120 @@
+use crate::errors::{AppError, Result};
+use crate::http;
+use crate::output::Record;
+use crate::sink::ResultSink;
+use crate::ssh::ServerResult;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::thread;
+use std::time::Duration;
+
+fn default_retries() -> u32 {
+    3
+}
+
+fn default_queue_size() -> usize {
+    64
+}
+
+fn default_timeout() -> u64 {
+    10
+}
+
+/// `[webhook]` config section: where to POST each host's result.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Attempts after the first failed POST, with exponential backoff.
+    #[serde(default = "default_retries")]
+    pub retries: u32,
+    /// Results waiting to be posted before workers block.
+    #[serde(default = "default_queue_size")]
+    pub queue_size: usize,
+    /// Per-request timeout in seconds.
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+}
+
+/// Posts each job's result as it completes. Delivery runs on the sink's
+/// own thread with a bounded queue (see [`crate::sink`]), so a slow
+/// endpoint slows workers down instead of buffering an unbounded number of
+/// results.
+pub struct Webhook {
+    config: WebhookConfig,
+}
+
+impl Webhook {
+    pub fn new(config: WebhookConfig) -> Webhook {
+        Webhook { config }
+    }
+}
+
+impl ResultSink for Webhook {
+    fn queue_size(&self) -> usize {
+        self.config.queue_size
+    }
+
+    fn result(&mut self, command: &str, result: &ServerResult) -> Result<()> {
+        let body = serde_json::to_vec(&Record { command, result }).map_err(|e| {
+            AppError::Generic(format!(
+                "could not encode webhook payload for {}: {}",
+                result.server, e
+            ))
+        })?;
+        let mut attempt = 0;
+        loop {
+            match http::post_json(&self.config.url, &body, self.config.timeout) {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.config.retries => {
+                    return Err(AppError::Generic(format!(
+                        "webhook for {} not delivered after {} attempts: {}",
+                        result.server,
+                        attempt + 1,
+                        e
+                    )));
+                }
+                Err(_) => {
+                    thread::sleep(backoff(attempt));
+                    attempt += 1;
+                }
+            }
+        }
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(500u64.saturating_mul(1 << attempt.min(6)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_webhook_config_defaults() {
+        let config: WebhookConfig = toml::from_str(r#"url = "http://localhost:9000/hook""#)
+            .expect("Failed to parse webhook config");
+        assert_eq!(config.retries, 3);
+        assert_eq!(config.queue_size, 64);
+        assert_eq!(config.timeout, 10);
+    }
+
+    #[test]
+    fn test_payload_includes_command() {
+        let result = ServerResult {
+            server: "web01".to_string(),
+            duration: 1.5,
+            success: true,
+            exit_code: Some(0),
+            ..Default::default()
+        };
+        let json = serde_json::to_value(Record {
+            command: "uptime",
+            result: &result,
+        })
+        .unwrap();
+        assert_eq!(json["command"], "uptime");
+        assert_eq!(json["server"], "web01");
+        assert_eq!(json["success"], true);
+    }
+}